@@ -0,0 +1,57 @@
+//! Checks the settings `ConfigBuilder` produces.
+//!
+//! Usage:
+//!   config [--file app.conf] [--set key=value]... [--explain-config]
+//!
+//! Settings come from the defaults, the file, `APP_*` environment
+//! variables and `--set` overrides, in that order. Invalid settings are
+//! reported and make it fail; `--explain-config` prints every setting
+//! with the layer it came from.
+
+use std::{env, process::ExitCode};
+
+use p01_derive_basics::ConfigBuilder;
+
+const USAGE: &str = "usage: config [--file app.conf] [--set key=value]... [--explain-config]";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let mut builder = ConfigBuilder::new();
+    let mut explain = false;
+    while let Some(arg) = args.next() {
+        if arg == "--explain-config" {
+            explain = true;
+            continue;
+        }
+        builder = match (arg.as_str(), args.next()) {
+            ("--file", Some(path)) => builder.with_file(path),
+            ("--set", Some(setting)) => match setting.split_once('=') {
+                Some((key, value)) => builder.with_override(key.trim(), value),
+                None => {
+                    eprintln!(
+                        "config: expected key=value after --set, found '{}'",
+                        setting
+                    );
+                    return ExitCode::FAILURE;
+                }
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        };
+    }
+
+    match builder.build() {
+        Ok(config) => {
+            if explain {
+                print!("{}", config.explain());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("config: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use std::{cell::Cell, fmt};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 pub struct Book {
     pub title: String,
@@ -104,7 +111,7 @@ impl Clone for TrackedDocument {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
@@ -122,6 +129,12 @@ impl ServerConfig {
     }
 }
 
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig::new("localhost", 8080, 1000)
+    }
+}
+
 /// A struct with manual Debug implementation.
 /// We'll hide the password field in debug output!
 pub struct UserCredentials {
@@ -147,13 +160,14 @@ impl fmt::Debug for UserCredentials {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AppSettings {
     pub debug_mode: bool, // Default: false
     pub log_level: u8,    // Default: 0
     pub app_name: String, // Default: "" (empty string)
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct DatabaseConf {
     pub host: String,
     pub port: u16,
@@ -176,6 +190,409 @@ impl Default for DatabaseConf {
     }
 }
 
+/// Every setting `ConfigBuilder` knows, as written in config files and
+/// overrides. `AppSettings` fields have no section.
+pub const CONFIG_KEYS: [&str; 10] = [
+    "server.host",
+    "server.port",
+    "server.max_conn",
+    "database.host",
+    "database.port",
+    "database.pool_size",
+    "database.timeout_seconds",
+    "debug_mode",
+    "log_level",
+    "app_name",
+];
+
+/// The environment variable for a setting: `server.port` is read from
+/// `APP_SERVER_PORT`.
+pub fn env_var(key: &str) -> String {
+    format!("APP_{}", key.replace('.', "_").to_uppercase())
+}
+
+/// The layer a setting's value came from. Later layers win: defaults,
+/// then the config file, then `APP_*` variables, then overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File { path: PathBuf, line: usize },
+    Env(String),
+    Override,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File { path, line } => write!(f, "{}:{}", path.display(), line),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Override => write!(f, "override"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io { path: PathBuf, source: io::Error },
+
+    /// A config file line that is neither `key = value`, `[section]`
+    /// nor a comment
+    Syntax {
+        path: PathBuf,
+        line: usize,
+        text: String,
+    },
+
+    /// A key that is not in `CONFIG_KEYS`
+    UnknownKey { key: String, from: Source },
+
+    /// A value that does not parse as the setting's type
+    InvalidValue {
+        key: String,
+        value: String,
+        reason: String,
+        from: Source,
+    },
+
+    /// A value that parses but fails validation
+    Invalid {
+        key: String,
+        reason: &'static str,
+        from: Source,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "cannot read {}: {}", path.display(), source)
+            }
+            ConfigError::Syntax { path, line, text } => write!(
+                f,
+                "{}:{}: expected `key = value`, found '{}'",
+                path.display(),
+                line,
+                text
+            ),
+            ConfigError::UnknownKey { key, from } => {
+                write!(f, "unknown setting '{}' (from {})", key, from)
+            }
+            ConfigError::InvalidValue {
+                key,
+                value,
+                reason,
+                from,
+            } => write!(
+                f,
+                "invalid value '{}' for '{}' (from {}): {}",
+                value, key, from, reason
+            ),
+            ConfigError::Invalid { key, reason, from } => {
+                write!(f, "'{}' {} (from {})", key, reason, from)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The settings `ConfigBuilder` produced, and where each one came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConf,
+    pub app: AppSettings,
+    sources: BTreeMap<&'static str, Source>,
+}
+
+impl Config {
+    fn defaults() -> Self {
+        Config {
+            server: ServerConfig::default(),
+            database: DatabaseConf::default(),
+            app: AppSettings::default(),
+            sources: CONFIG_KEYS.map(|key| (key, Source::Default)).into(),
+        }
+    }
+
+    /// The layer `key` was set by, or `None` for an unknown key.
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        self.sources.get(key)
+    }
+
+    /// The value of `key` as a config file would write it, with strings
+    /// quoted.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "server.host" => quote(&self.server.host),
+            "server.port" => self.server.port.to_string(),
+            "server.max_conn" => self.server.max_conn.to_string(),
+            "database.host" => quote(&self.database.host),
+            "database.port" => self.database.port.to_string(),
+            "database.pool_size" => self.database.pool_size.to_string(),
+            "database.timeout_seconds" => self.database.timeout_seconds.to_string(),
+            "debug_mode" => self.app.debug_mode.to_string(),
+            "log_level" => self.app.log_level.to_string(),
+            "app_name" => quote(&self.app.app_name),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// One line per setting with its value and source, as printed by
+    /// `--explain-config`, e.g. `server.port = 9000 (env APP_SERVER_PORT)`.
+    pub fn explain(&self) -> String {
+        CONFIG_KEYS
+            .iter()
+            .map(|key| {
+                let value = self.get(key).expect("every key has a value");
+                format!("{} = {} ({})\n", key, value, self.sources[key])
+            })
+            .collect()
+    }
+
+    /// Set `key` from its text in some layer, remembering the layer.
+    fn apply(&mut self, key: &str, value: &str, from: Source) -> Result<(), ConfigError> {
+        let Some(key) = CONFIG_KEYS.into_iter().find(|known| *known == key) else {
+            return Err(ConfigError::UnknownKey {
+                key: key.to_string(),
+                from,
+            });
+        };
+        let text = value.trim();
+        let parsed = match key {
+            "server.host" => unquote(text).map(|host| self.server.host = host),
+            "server.port" => parse(text).map(|port| self.server.port = port),
+            "server.max_conn" => parse(text).map(|n| self.server.max_conn = n),
+            "database.host" => unquote(text).map(|host| self.database.host = host),
+            "database.port" => parse(text).map(|port| self.database.port = port),
+            "database.pool_size" => parse(text).map(|n| self.database.pool_size = n),
+            "database.timeout_seconds" => parse(text).map(|n| self.database.timeout_seconds = n),
+            "debug_mode" => parse(text).map(|on| self.app.debug_mode = on),
+            "log_level" => parse(text).map(|level| self.app.log_level = level),
+            "app_name" => unquote(text).map(|name| self.app.app_name = name),
+            _ => unreachable!("'{}' is in CONFIG_KEYS but has no field", key),
+        };
+        if let Err(reason) = parsed {
+            return Err(ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: text.to_string(),
+                reason,
+                from,
+            });
+        }
+        self.sources.insert(key, from);
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let checks = [
+            ("server.port", self.server.port == 0, "must not be zero"),
+            ("database.port", self.database.port == 0, "must not be zero"),
+            (
+                "database.pool_size",
+                self.database.pool_size == 0,
+                "must be greater than zero",
+            ),
+        ];
+        match checks.into_iter().find(|&(_, failed, _)| failed) {
+            Some((key, _, reason)) => Err(ConfigError::Invalid {
+                key: key.to_string(),
+                reason,
+                from: self.sources[key].clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse<T: FromStr>(text: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    text.parse().map_err(|e: T::Err| e.to_string())
+}
+
+/// `text` in double quotes, with `"`, `\` and newlines escaped as
+/// `unquote` expects.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A string setting, either as is or in double quotes, where `\"`, `\\`
+/// and `\n` stand for a quote, a backslash and a newline.
+fn unquote(text: &str) -> Result<String, String> {
+    let Some(rest) = text.strip_prefix('"') else {
+        return Ok(text.to_string());
+    };
+    let mut chars = rest.chars();
+    let mut unquoted = String::new();
+    loop {
+        match chars.next() {
+            Some('"') if chars.as_str().is_empty() => return Ok(unquoted),
+            Some('"') => return Err("text after the closing quote".to_string()),
+            Some('\\') => match chars.next() {
+                Some('"') => unquoted.push('"'),
+                Some('\\') => unquoted.push('\\'),
+                Some('n') => unquoted.push('\n'),
+                Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                None => return Err("missing closing quote".to_string()),
+            },
+            Some(c) => unquoted.push(c),
+            None => return Err("missing closing quote".to_string()),
+        }
+    }
+}
+
+/// The `(line, key, value)` settings of a config file: `key = value`
+/// lines, where keys under a `[section]` header get its name as a prefix
+/// and `#` starts a comment line. Keys before any header, or under
+/// `[app]`, are the section-less `AppSettings` ones like `log_level`.
+fn parse_file(path: &Path, text: &str) -> Result<Vec<(usize, String, String)>, ConfigError> {
+    let mut section = String::new();
+    let mut settings = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = match name.trim() {
+                "app" => String::new(),
+                name => format!("{}.", name),
+            };
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(ConfigError::Syntax {
+                path: path.to_path_buf(),
+                line: index + 1,
+                text: line.to_string(),
+            });
+        };
+        let key = format!("{}{}", section, key.trim());
+        settings.push((index + 1, key, value.to_string()));
+    }
+    Ok(settings)
+}
+
+/// Looks an environment variable up by name.
+pub type EnvLookup = dyn Fn(&str) -> Option<String>;
+
+/// Builds a `Config` from layers, each overriding the one before:
+/// 1. `Default` impls of `ServerConfig`, `DatabaseConf` and `AppSettings`
+/// 2. a config file, if given
+/// 3. `APP_*` environment variables, named by `env_var`
+/// 4. overrides, as from the command line
+///
+/// The file holds `key = value` lines. `[server]` and `[database]` headers
+/// start those sections; `debug_mode`, `log_level` and `app_name` go
+/// before the first header or under `[app]`.
+///
+/// Environment variables are read through a function, which is the
+/// process environment unless replaced with `with_env`.
+pub struct ConfigBuilder {
+    file: Option<(PathBuf, Option<String>)>,
+    env: Box<EnvLookup>,
+    overrides: Vec<(String, String)>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder {
+            file: None,
+            env: Box::new(|var| std::env::var(var).ok()),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Read settings from the file at `path` when building.
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some((path.into(), None));
+        self
+    }
+
+    /// Use `contents` as the config file, reporting it as `path`.
+    pub fn with_file_contents(mut self, path: impl Into<PathBuf>, contents: &str) -> Self {
+        self.file = Some((path.into(), Some(contents.to_string())));
+        self
+    }
+
+    /// Look environment variables up with `env` instead of in the
+    /// process environment.
+    pub fn with_env(mut self, env: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        self.env = Box::new(env);
+        self
+    }
+
+    /// Set `key` to `value` over every other layer.
+    pub fn with_override(mut self, key: &str, value: &str) -> Self {
+        self.overrides.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn build(&self) -> Result<Config, ConfigError> {
+        let mut config = Config::defaults();
+
+        if let Some((path, contents)) = &self.file {
+            let text = match contents {
+                Some(text) => text.clone(),
+                None => fs::read_to_string(path).map_err(|source| ConfigError::Io {
+                    path: path.clone(),
+                    source,
+                })?,
+            };
+            for (line, key, value) in parse_file(path, &text)? {
+                let from = Source::File {
+                    path: path.clone(),
+                    line,
+                };
+                config.apply(&key, &value, from)?;
+            }
+        }
+
+        for key in CONFIG_KEYS {
+            let var = env_var(key);
+            if let Some(value) = (self.env)(&var) {
+                config.apply(key, &value, Source::Env(var))?;
+            }
+        }
+
+        for (key, value) in &self.overrides {
+            config.apply(key, value, Source::Override)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -335,4 +752,209 @@ mod tests {
         assert_eq!(custom_db.pool_size, 10); // from Default
         assert_eq!(custom_db.timeout_seconds, 30); // from Default
     }
+
+    /// An environment holding just `vars`, so tests never read the real one.
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'static {
+        let vars: BTreeMap<String, String> = vars
+            .iter()
+            .map(|&(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    const FILE: &str = "\
+# Production settings
+app_name = \"shop\"
+
+[server]
+host = 0.0.0.0
+port = 80
+
+[database]
+port = 6543
+pool_size = 20
+";
+
+    #[test]
+    fn test_config_defaults() {
+        let config = ConfigBuilder::new().with_env(env(&[])).build().unwrap();
+
+        assert_eq!(config.server, ServerConfig::default());
+        assert_eq!(config.database, DatabaseConf::default());
+        assert_eq!(config.app, AppSettings::default());
+        assert_eq!(config.source("server.port"), Some(&Source::Default));
+        assert_eq!(config.source("server.nope"), None);
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let config = ConfigBuilder::new()
+            .with_file_contents("app.conf", FILE)
+            .with_env(env(&[
+                ("APP_SERVER_PORT", "9000"),
+                ("APP_DATABASE_POOL_SIZE", "50"),
+                ("APP_DEBUG_MODE", "true"),
+                ("SERVER_PORT", "1"), // no APP_ prefix, so not ours
+            ]))
+            .with_override("database.pool_size", "5")
+            .build()
+            .unwrap();
+
+        // Each layer overrides the ones before it
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.max_conn, 1000);
+        assert_eq!(config.database.port, 6543);
+        assert_eq!(config.database.pool_size, 5);
+        assert!(config.app.debug_mode);
+        assert_eq!(config.app.app_name, "shop");
+
+        let file = |line| Source::File {
+            path: PathBuf::from("app.conf"),
+            line,
+        };
+        assert_eq!(config.source("app_name"), Some(&file(2)));
+        assert_eq!(config.source("server.host"), Some(&file(5)));
+        assert_eq!(
+            config.source("server.port"),
+            Some(&Source::Env("APP_SERVER_PORT".to_string()))
+        );
+        assert_eq!(config.source("database.pool_size"), Some(&Source::Override));
+        assert_eq!(config.source("log_level"), Some(&Source::Default));
+    }
+
+    #[test]
+    fn test_explain_config() {
+        let config = ConfigBuilder::new()
+            .with_file_contents("app.conf", FILE)
+            .with_env(env(&[("APP_LOG_LEVEL", "3")]))
+            .with_override("database.host", "db.internal")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            config.explain(),
+            "\
+server.host = \"0.0.0.0\" (app.conf:5)
+server.port = 80 (app.conf:6)
+server.max_conn = 1000 (default)
+database.host = \"db.internal\" (override)
+database.port = 6543 (app.conf:9)
+database.pool_size = 20 (app.conf:10)
+database.timeout_seconds = 30 (default)
+debug_mode = false (default)
+log_level = 3 (env APP_LOG_LEVEL)
+app_name = \"shop\" (app.conf:2)
+"
+        );
+    }
+
+    #[test]
+    fn test_app_section_and_quoting() {
+        let file = "\
+[server]
+port = 81
+[app]
+log_level = 2
+app_name = \"say \\\"hi\\\" \\\\ bye\"
+";
+        let config = ConfigBuilder::new()
+            .with_file_contents("app.conf", file)
+            .with_env(env(&[]))
+            .build()
+            .unwrap();
+        assert_eq!(config.server.port, 81);
+        assert_eq!(config.app.log_level, 2);
+        assert_eq!(config.app.app_name, r#"say "hi" \ bye"#);
+
+        // What `explain` prints reads back as the same value
+        let shown = config.get("app_name").unwrap();
+        assert_eq!(shown, r#""say \"hi\" \\ bye""#);
+        let again = ConfigBuilder::new()
+            .with_env(env(&[("APP_APP_NAME", &shown)]))
+            .build()
+            .unwrap();
+        assert_eq!(again.app.app_name, config.app.app_name);
+
+        for (text, reason) in [
+            (r#""a"b""#, "text after the closing quote"),
+            (r#""a\tb""#, r"unknown escape '\t'"),
+            (r#""ab\""#, "missing closing quote"),
+        ] {
+            assert_eq!(unquote(text), Err(reason.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let err = ConfigBuilder::new()
+            .with_env(env(&[("APP_SERVER_PORT", "0")]))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'server.port' must not be zero (from env APP_SERVER_PORT)"
+        );
+
+        let err = ConfigBuilder::new()
+            .with_file_contents("app.conf", "[database]\npool_size = 0")
+            .with_env(env(&[]))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'database.pool_size' must be greater than zero (from app.conf:2)"
+        );
+
+        // A later layer can fix what an earlier one got wrong
+        let config = ConfigBuilder::new()
+            .with_env(env(&[("APP_DATABASE_PORT", "0")]))
+            .with_override("database.port", "5433")
+            .build()
+            .unwrap();
+        assert_eq!(config.database.port, 5433);
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let build = |builder: ConfigBuilder| builder.with_env(env(&[])).build().unwrap_err();
+
+        let err = build(ConfigBuilder::new().with_override("server.prot", "80"));
+        assert_eq!(
+            err.to_string(),
+            "unknown setting 'server.prot' (from override)"
+        );
+
+        let err =
+            build(ConfigBuilder::new().with_file_contents("app.conf", "[server]\nport = 99999"));
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidValue { key, from: Source::File { line: 2, .. }, .. }
+                if key == "server.port"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid value '99999' for 'server.port' (from app.conf:2): \
+             number too large to fit in target type"
+        );
+
+        let err = build(ConfigBuilder::new().with_file_contents("app.conf", "\n\nverbose"));
+        assert_eq!(
+            err.to_string(),
+            "app.conf:3: expected `key = value`, found 'verbose'"
+        );
+
+        let err = build(ConfigBuilder::new().with_file("/nonexistent/app.conf"));
+        assert!(matches!(err, ConfigError::Io { .. }));
+        assert!(err.source().is_some());
+
+        let err = ConfigBuilder::new()
+            .with_env(env(&[("APP_DEBUG_MODE", "yes")]))
+            .build()
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("invalid value 'yes' for 'debug_mode' (from env APP_DEBUG_MODE)")
+        );
+    }
 }
@@ -185,6 +185,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_conversion)]
     fn test_reflexive_from() {
         // Every type implements From<T> for T (identity conversion)
         let email = Email::from("test@example.com");
@@ -1,5 +1,7 @@
 // ------------------------------
 
+#![allow(clippy::legacy_numeric_constants)]
+
 use std::{
     ops::{Deref, DerefMut},
     usize,
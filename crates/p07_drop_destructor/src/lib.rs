@@ -1,6 +1,10 @@
 // ------------------------------
 
-use std::{cell::Cell, rc::Rc, time::Instant};
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 pub struct LoggingResource {
     name: String,
@@ -40,6 +44,7 @@ impl Drop for LoggingResource {
 pub struct TimerGuard {
     name: String,
     start: Instant,
+    quiet: bool,
 }
 
 impl TimerGuard {
@@ -47,16 +52,32 @@ impl TimerGuard {
         println!("[TIMER START] {}", name);
         let name = name.to_string();
         let start = Instant::now();
-        TimerGuard { name, start }
+        let quiet = false;
+        TimerGuard { name, start, quiet }
+    }
+
+    // Same timer without the printing, for callers that read `elapsed`
+    pub fn quiet(name: &str) -> Self {
+        let name = name.to_string();
+        let start = Instant::now();
+        let quiet = true;
+        TimerGuard { name, start, quiet }
     }
 
     pub fn elapsed_ms(&self) -> u128 {
         self.start.elapsed().as_millis()
     }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
 }
 
 impl Drop for TimerGuard {
     fn drop(&mut self) {
+        if self.quiet {
+            return;
+        }
         println!(
             "[TIMER END] {} - took {:?}",
             self.name,
@@ -154,6 +175,14 @@ mod tests {
         }
         // Timer prints elapsed time here on drop
     }
+
+    #[test]
+    fn test_quiet_timer_guard() {
+        let timer = TimerGuard::quiet("silent");
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(timer.elapsed() >= std::time::Duration::from_millis(2));
+        // Nothing printed on drop
+    }
 }
 
 // ------------------------------
@@ -103,6 +103,7 @@ impl<'a, T> Iterator for CycleIter<'a, T> {
 // ----------------------
 
 #[cfg(test)]
+#[allow(clippy::legacy_numeric_constants)]
 mod tests {
     use core::str;
     use std::usize;
@@ -202,6 +203,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unnecessary_fold)]
     fn test_fold() {
         let total = Counter::new(5).fold(0, |acc, curr| acc + curr);
         assert_eq!(total, 15);
@@ -70,16 +70,19 @@ impl DynamicFilter {
 // --------------------------------
 
 /// Regular function — can be used as Fn
+#[allow(dead_code)]
 fn is_positive(x: i32) -> bool {
     x > 0
 }
 
 /// Function that takes a function pointer (not a closure)
+#[allow(dead_code)]
 fn apply_fn_ptr(f: fn(i32) -> bool, value: i32) -> bool {
     f(value)
 }
 
 /// Function that takes any Fn (closure OR function pointer)
+#[allow(dead_code)]
 fn apply_fn<F: Fn(i32) -> bool>(f: F, value: i32) -> bool {
     f(value)
 }
@@ -149,6 +149,7 @@ pub fn load_config(path: &str) -> std::result::Result<String, AppError> {
     Ok(content)
 }
 
+#[allow(clippy::manual_range_contains)]
 pub fn parse_port(s: &str) -> std::result::Result<u16, AppError> {
     let port: i32 = s.parse().map_err(|e| AppError::Parse {
         context: format!("invalid port: '{}'", s),
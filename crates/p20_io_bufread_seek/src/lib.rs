@@ -1,3 +1,5 @@
+#![allow(clippy::legacy_numeric_constants)]
+
 use std::{
     i64,
     io::{self, BufRead, Read, Seek, SeekFrom},
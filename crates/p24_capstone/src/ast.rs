@@ -0,0 +1,1128 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::{error::ParseError, exact::BigInt, lexer::Span, parser, units::Unit};
+
+// ------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    /// `start..end`, the ints from `start` up to but excluding `end`
+    Range,
+}
+
+impl BinOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Pow => "^",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::Range => "..",
+        }
+    }
+
+    /// Binding strength; higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 3,
+            BinOp::Range => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+            BinOp::Pow => 8,
+        }
+    }
+
+    /// `&&` and `||` skip their right operand when the left decides.
+    pub fn is_short_circuit(self) -> bool {
+        matches!(self, BinOp::And | BinOp::Or)
+    }
+
+    /// `^` groups to the right: `2 ^ 3 ^ 2 == 2 ^ (3 ^ 2)`
+    pub fn is_right_assoc(self) -> bool {
+        matches!(self, BinOp::Pow)
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+
+    /// Prefix operators bind tighter than `*` but looser than `^`,
+    /// so `-2 ^ 2 == -(2 ^ 2)`.
+    pub fn precedence(self) -> u8 {
+        7
+    }
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+// ------------------------------------------------
+/// A constant written in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    /// An integer too big for an `i64`, which only exact mode evaluates
+    BigInt(BigInt),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    /// A number followed by a unit, like `3 m`
+    Quantity(f64, &'static Unit),
+    /// A number followed directly by `i`, like `4i`
+    Imaginary(f64),
+}
+
+impl Literal {
+    /// The literal as a float, if it is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Literal::Int(n) => Some(*n as f64),
+            Literal::BigInt(n) => Some(n.to_f64()),
+            Literal::Float(n) => Some(*n),
+            Literal::Bool(_) | Literal::Str(_) | Literal::Quantity(..) | Literal::Imaginary(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Consistent with `==`: floats hash by value, so `0.0` and `-0.0` agree.
+impl Hash for Literal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        let float_bits = |n: f64| if n == 0.0 { 0 } else { n.to_bits() };
+        match self {
+            Literal::Int(n) => n.hash(state),
+            Literal::BigInt(n) => n.hash(state),
+            Literal::Float(n) => float_bits(*n).hash(state),
+            Literal::Bool(b) => b.hash(state),
+            Literal::Str(s) => s.hash(state),
+            Literal::Quantity(n, unit) => {
+                float_bits(*n).hash(state);
+                unit.hash(state);
+            }
+            Literal::Imaginary(n) => float_bits(*n).hash(state),
+        }
+    }
+}
+
+/// Prints the literal so it lexes back to the same one: floats keep a
+/// decimal point and strings are quoted.
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Int(n) => write!(f, "{}", n),
+            Literal::BigInt(n) => write!(f, "{}", n),
+            Literal::Float(n) => write_float(f, *n),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Str(s) => write_quoted(f, s),
+            // Whole amounts read better without `.0` and lex back the same
+            Literal::Quantity(n, unit) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                write!(f, "{} {}", *n as i64, unit.symbol)
+            }
+            Literal::Quantity(n, unit) => {
+                write_float(f, *n)?;
+                write!(f, " {}", unit.symbol)
+            }
+            // The lexer reads the digits before `i` as a float either way
+            Literal::Imaginary(n) => write!(f, "{}i", n),
+        }
+    }
+}
+
+/// Writes a float with `.0` when it would otherwise read as an integer.
+pub(crate) fn write_float(f: &mut Formatter<'_>, n: f64) -> std::fmt::Result {
+    if n.is_finite() && n.fract() == 0.0 {
+        write!(f, "{:.1}", n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+/// Writes `s` between double quotes with the lexer's escapes.
+pub(crate) fn write_quoted(f: &mut Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// ------------------------------------------------
+#[derive(Debug, Clone, Hash)]
+pub enum ExprKind {
+    Literal(Literal),
+    Var(String),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// `callee(args)` where the callee is not a plain name, as in
+    /// `make_adder(1)(2)` or `(|x| x * 2)(3)`
+    Apply {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `let name = value in body`
+    Let {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `|params| body`
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expr>,
+    },
+    /// `if cond then then_branch else else_branch`
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    /// `expr -> unit`
+    Convert {
+        expr: Box<Expr>,
+        unit: &'static Unit,
+    },
+    /// `{ name: value, ... }`, with each name at most once
+    Record {
+        fields: Vec<(String, Expr)>,
+    },
+    /// `expr.name`
+    Field {
+        expr: Box<Expr>,
+        name: String,
+    },
+    /// `match scrutinee { pattern => body, ... }`; the first arm whose
+    /// pattern matches is taken
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+}
+
+/// One `pattern => body` arm of a `match`.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    /// The pattern's source, for warnings about the arm
+    pub span: Span,
+}
+
+impl PartialEq for MatchArm {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern && self.body == other.body
+    }
+}
+
+impl Hash for MatchArm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state);
+        self.body.hash(state);
+    }
+}
+
+/// What a `match` arm compares its value against.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub enum Pattern {
+    /// `_`, matching anything
+    Wildcard,
+    /// A number, string or bool; numbers match equal numbers of any kind
+    Literal(Literal),
+    /// `start..end`, `start..` or `..end`: the numbers from `start` up to
+    /// but excluding `end`
+    Range {
+        start: Option<Literal>,
+        end: Option<Literal>,
+    },
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal(literal) => write!(f, "{}", literal),
+            Pattern::Range { start, end } => {
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, "..")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A node of the expression tree together with the source it came from.
+///
+/// Equality and hashing are structural: spans are ignored, so `"1+2"` and
+/// `"1 + 2"` parse to equal trees with equal hashes.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+
+    pub fn literal(literal: Literal) -> Self {
+        Expr::new(ExprKind::Literal(literal), Span::default())
+    }
+
+    pub fn int(value: i64) -> Self {
+        Expr::literal(Literal::Int(value))
+    }
+
+    pub fn float(value: f64) -> Self {
+        Expr::literal(Literal::Float(value))
+    }
+
+    pub fn var(name: impl Into<String>) -> Self {
+        Expr::new(ExprKind::Var(name.into()), Span::default())
+    }
+
+    pub fn unary(op: UnaryOp, operand: Expr) -> Self {
+        let span = operand.span;
+        let operand = Box::new(operand);
+        Expr::new(ExprKind::Unary { op, operand }, span)
+    }
+
+    pub fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Self {
+        let span = lhs.span.to(rhs.span);
+        let lhs = Box::new(lhs);
+        let rhs = Box::new(rhs);
+        Expr::new(ExprKind::Binary { op, lhs, rhs }, span)
+    }
+
+    pub fn call(name: impl Into<String>, args: Vec<Expr>) -> Self {
+        let name = name.into();
+        Expr::new(ExprKind::Call { name, args }, Span::default())
+    }
+
+    pub fn apply(callee: Expr, args: Vec<Expr>) -> Self {
+        let span = callee.span;
+        let callee = Box::new(callee);
+        Expr::new(ExprKind::Apply { callee, args }, span)
+    }
+
+    pub fn let_in(name: impl Into<String>, value: Expr, body: Expr) -> Self {
+        let name = name.into();
+        let span = value.span.to(body.span);
+        let value = Box::new(value);
+        let body = Box::new(body);
+        Expr::new(ExprKind::Let { name, value, body }, span)
+    }
+
+    pub fn lambda(params: Vec<String>, body: Expr) -> Self {
+        let span = body.span;
+        let body = Box::new(body);
+        Expr::new(ExprKind::Lambda { params, body }, span)
+    }
+
+    pub fn if_else(cond: Expr, then_branch: Expr, else_branch: Expr) -> Self {
+        let span = cond.span.to(else_branch.span);
+        let cond = Box::new(cond);
+        let then_branch = Box::new(then_branch);
+        let else_branch = Box::new(else_branch);
+        let kind = ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        };
+        Expr::new(kind, span)
+    }
+
+    pub fn convert(expr: Expr, unit: &'static Unit) -> Self {
+        let span = expr.span;
+        let expr = Box::new(expr);
+        Expr::new(ExprKind::Convert { expr, unit }, span)
+    }
+
+    pub fn record(fields: Vec<(String, Expr)>) -> Self {
+        Expr::new(ExprKind::Record { fields }, Span::default())
+    }
+
+    pub fn field(expr: Expr, name: impl Into<String>) -> Self {
+        let span = expr.span;
+        let expr = Box::new(expr);
+        let name = name.into();
+        Expr::new(ExprKind::Field { expr, name }, span)
+    }
+
+    pub fn match_on(scrutinee: Expr, arms: Vec<(Pattern, Expr)>) -> Self {
+        let span = scrutinee.span;
+        let scrutinee = Box::new(scrutinee);
+        let arms = arms
+            .into_iter()
+            .map(|(pattern, body)| MatchArm {
+                pattern,
+                body,
+                span: Span::default(),
+            })
+            .collect();
+        Expr::new(ExprKind::Match { scrutinee, arms }, span)
+    }
+
+    /// Replace free occurrences of the variable `name` with `value`; calls
+    /// by that name are left alone. Binders inside `self` must not capture
+    /// free variables of `value`.
+    pub fn substitute(&self, name: &str, value: &Expr) -> Expr {
+        let kind = match &self.kind {
+            ExprKind::Var(var) if var == name => value.kind.clone(),
+            ExprKind::Literal(_) | ExprKind::Var(_) => self.kind.clone(),
+            ExprKind::Unary { op, operand } => ExprKind::Unary {
+                op: *op,
+                operand: Box::new(operand.substitute(name, value)),
+            },
+            ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+                op: *op,
+                lhs: Box::new(lhs.substitute(name, value)),
+                rhs: Box::new(rhs.substitute(name, value)),
+            },
+            ExprKind::Call { name: callee, args } => ExprKind::Call {
+                name: callee.clone(),
+                args: args.iter().map(|arg| arg.substitute(name, value)).collect(),
+            },
+            ExprKind::Apply { callee, args } => ExprKind::Apply {
+                callee: Box::new(callee.substitute(name, value)),
+                args: args.iter().map(|arg| arg.substitute(name, value)).collect(),
+            },
+            ExprKind::Let {
+                name: bound,
+                value: bound_value,
+                body,
+            } => ExprKind::Let {
+                name: bound.clone(),
+                value: Box::new(bound_value.substitute(name, value)),
+                // An inner binding of the same name shadows ours
+                body: if bound == name {
+                    body.clone()
+                } else {
+                    Box::new(body.substitute(name, value))
+                },
+            },
+            ExprKind::Lambda { params, body } => ExprKind::Lambda {
+                params: params.clone(),
+                body: if params.iter().any(|param| param == name) {
+                    body.clone()
+                } else {
+                    Box::new(body.substitute(name, value))
+                },
+            },
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => ExprKind::If {
+                cond: Box::new(cond.substitute(name, value)),
+                then_branch: Box::new(then_branch.substitute(name, value)),
+                else_branch: Box::new(else_branch.substitute(name, value)),
+            },
+            ExprKind::Convert { expr, unit } => ExprKind::Convert {
+                expr: Box::new(expr.substitute(name, value)),
+                unit,
+            },
+            ExprKind::Record { fields } => ExprKind::Record {
+                fields: fields
+                    .iter()
+                    .map(|(field, expr)| (field.clone(), expr.substitute(name, value)))
+                    .collect(),
+            },
+            ExprKind::Field { expr, name: field } => ExprKind::Field {
+                expr: Box::new(expr.substitute(name, value)),
+                name: field.clone(),
+            },
+            ExprKind::Match { scrutinee, arms } => ExprKind::Match {
+                scrutinee: Box::new(scrutinee.substitute(name, value)),
+                arms: arms
+                    .iter()
+                    .map(|arm| MatchArm {
+                        body: arm.body.substitute(name, value),
+                        ..arm.clone()
+                    })
+                    .collect(),
+            },
+        };
+        Expr::new(kind, self.span)
+    }
+
+    /// Every variable, function, parameter and binding name in the tree.
+    pub fn names(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        match &self.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Var(name) => {
+                names.insert(name.clone());
+            }
+            ExprKind::Unary { operand, .. } => operand.collect_names(names),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                lhs.collect_names(names);
+                rhs.collect_names(names);
+            }
+            ExprKind::Call { name, args } => {
+                names.insert(name.clone());
+                args.iter().for_each(|arg| arg.collect_names(names));
+            }
+            ExprKind::Let { name, value, body } => {
+                names.insert(name.clone());
+                value.collect_names(names);
+                body.collect_names(names);
+            }
+            ExprKind::Lambda { params, body } => {
+                names.extend(params.iter().cloned());
+                body.collect_names(names);
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                cond.collect_names(names);
+                then_branch.collect_names(names);
+                else_branch.collect_names(names);
+            }
+            ExprKind::Convert { expr, .. } => expr.collect_names(names),
+            // Field names name no variable
+            ExprKind::Apply { .. }
+            | ExprKind::Record { .. }
+            | ExprKind::Field { .. }
+            | ExprKind::Match { .. } => self
+                .children()
+                .into_iter()
+                .for_each(|child| child.collect_names(names)),
+        }
+    }
+
+    /// Variables and functions referred to but not bound in the tree,
+    /// i.e. resolved in the scope it is evaluated in.
+    pub fn free_names(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        self.collect_free(&mut Vec::new(), &mut names);
+        names
+    }
+
+    fn collect_free<'a>(&'a self, bound: &mut Vec<&'a str>, names: &mut BTreeSet<String>) {
+        let mut refer = |name: &String, bound: &Vec<&str>| {
+            if !bound.contains(&name.as_str()) {
+                names.insert(name.clone());
+            }
+        };
+        match &self.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Var(name) => refer(name, bound),
+            ExprKind::Call { name, args } => {
+                refer(name, bound);
+                args.iter().for_each(|arg| arg.collect_free(bound, names));
+            }
+            ExprKind::Let { name, value, body } => {
+                value.collect_free(bound, names);
+                bound.push(name);
+                body.collect_free(bound, names);
+                bound.pop();
+            }
+            ExprKind::Lambda { params, body } => {
+                bound.extend(params.iter().map(String::as_str));
+                body.collect_free(bound, names);
+                bound.truncate(bound.len() - params.len());
+            }
+            _ => self
+                .children()
+                .into_iter()
+                .for_each(|child| child.collect_free(bound, names)),
+        }
+    }
+
+    /// Direct subexpressions in source order; a lambda's body counts, though
+    /// it only runs when the lambda is called.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Literal(_) | ExprKind::Var(_) => Vec::new(),
+            ExprKind::Unary { operand, .. } => vec![operand],
+            ExprKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            ExprKind::Call { args, .. } => args.iter().collect(),
+            ExprKind::Apply { callee, args } => std::iter::once(&**callee).chain(args).collect(),
+            ExprKind::Let { value, body, .. } => vec![value, body],
+            ExprKind::Lambda { body, .. } => vec![body],
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => vec![cond, then_branch, else_branch],
+            ExprKind::Convert { expr, .. } | ExprKind::Field { expr, .. } => vec![expr],
+            ExprKind::Record { fields } => fields.iter().map(|(_, expr)| expr).collect(),
+            ExprKind::Match { scrutinee, arms } => std::iter::once(&**scrutinee)
+                .chain(arms.iter().map(|arm| &arm.body))
+                .collect(),
+        }
+    }
+
+    /// Whether `var` occurs free, i.e. not shadowed by a `let` or parameter.
+    pub fn depends_on(&self, var: &str) -> bool {
+        match &self.kind {
+            ExprKind::Literal(_) => false,
+            ExprKind::Var(name) => name == var,
+            ExprKind::Unary { operand, .. } => operand.depends_on(var),
+            ExprKind::Binary { lhs, rhs, .. } => lhs.depends_on(var) || rhs.depends_on(var),
+            ExprKind::Call { name, args } => {
+                name == var || args.iter().any(|arg| arg.depends_on(var))
+            }
+            ExprKind::Let { name, value, body } => {
+                value.depends_on(var) || (name != var && body.depends_on(var))
+            }
+            ExprKind::Lambda { params, body } => {
+                !params.iter().any(|param| param == var) && body.depends_on(var)
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => cond.depends_on(var) || then_branch.depends_on(var) || else_branch.depends_on(var),
+            ExprKind::Convert { expr, .. } => expr.depends_on(var),
+            ExprKind::Apply { .. }
+            | ExprKind::Record { .. }
+            | ExprKind::Field { .. }
+            | ExprKind::Match { .. } => self.children().iter().any(|child| child.depends_on(var)),
+        }
+    }
+
+    /// Precedence of the node as printed; atoms never need parentheses.
+    pub(crate) fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExprKind::Literal(Literal::Int(n)) if *n < 0 => UnaryOp::Neg.precedence(),
+            ExprKind::Literal(Literal::BigInt(n)) if n.is_negative() => UnaryOp::Neg.precedence(),
+            ExprKind::Literal(
+                Literal::Float(n) | Literal::Quantity(n, _) | Literal::Imaginary(n),
+            ) if n.is_sign_negative() => UnaryOp::Neg.precedence(),
+            ExprKind::Literal(_)
+            | ExprKind::Var(_)
+            | ExprKind::Call { .. }
+            | ExprKind::Apply { .. }
+            | ExprKind::Record { .. }
+            | ExprKind::Field { .. }
+            | ExprKind::Match { .. } => u8::MAX,
+            ExprKind::Unary { op, .. } => op.precedence(),
+            ExprKind::Binary { op, .. } => op.precedence(),
+            // `let`, `if` and lambda bodies extend as far right as possible,
+            // and `->` binds more loosely than any operator
+            ExprKind::Let { .. }
+            | ExprKind::Lambda { .. }
+            | ExprKind::If { .. }
+            | ExprKind::Convert { .. } => 0,
+        }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.kind, &other.kind) {
+            (ExprKind::Literal(a), ExprKind::Literal(b)) => a == b,
+            (ExprKind::Var(a), ExprKind::Var(b)) => a == b,
+            (
+                ExprKind::Unary { op, operand },
+                ExprKind::Unary {
+                    op: op2,
+                    operand: operand2,
+                },
+            ) => op == op2 && operand == operand2,
+            (
+                ExprKind::Binary { op, lhs, rhs },
+                ExprKind::Binary {
+                    op: op2,
+                    lhs: lhs2,
+                    rhs: rhs2,
+                },
+            ) => op == op2 && lhs == lhs2 && rhs == rhs2,
+            (
+                ExprKind::Call { name, args },
+                ExprKind::Call {
+                    name: name2,
+                    args: args2,
+                },
+            ) => name == name2 && args == args2,
+            (
+                ExprKind::Apply { callee, args },
+                ExprKind::Apply {
+                    callee: callee2,
+                    args: args2,
+                },
+            ) => callee == callee2 && args == args2,
+            (
+                ExprKind::Let { name, value, body },
+                ExprKind::Let {
+                    name: name2,
+                    value: value2,
+                    body: body2,
+                },
+            ) => name == name2 && value == value2 && body == body2,
+            (
+                ExprKind::Lambda { params, body },
+                ExprKind::Lambda {
+                    params: params2,
+                    body: body2,
+                },
+            ) => params == params2 && body == body2,
+            (
+                ExprKind::If {
+                    cond,
+                    then_branch,
+                    else_branch,
+                },
+                ExprKind::If {
+                    cond: cond2,
+                    then_branch: then2,
+                    else_branch: else2,
+                },
+            ) => cond == cond2 && then_branch == then2 && else_branch == else2,
+            (
+                ExprKind::Convert { expr, unit },
+                ExprKind::Convert {
+                    expr: expr2,
+                    unit: unit2,
+                },
+            ) => expr == expr2 && unit == unit2,
+            (ExprKind::Record { fields }, ExprKind::Record { fields: fields2 }) => {
+                fields == fields2
+            }
+            (
+                ExprKind::Field { expr, name },
+                ExprKind::Field {
+                    expr: expr2,
+                    name: name2,
+                },
+            ) => expr == expr2 && name == name2,
+            (
+                ExprKind::Match { scrutinee, arms },
+                ExprKind::Match {
+                    scrutinee: scrutinee2,
+                    arms: arms2,
+                },
+            ) => scrutinee == scrutinee2 && arms == arms2,
+            _ => false,
+        }
+    }
+}
+
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+    }
+}
+
+/// Writes `(args)` after a callee.
+fn write_args(f: &mut Formatter<'_>, args: &[Expr]) -> std::fmt::Result {
+    write!(f, "(")?;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    write!(f, ")")
+}
+
+/// Writes `expr`, wrapped in parentheses when `parens` is set.
+fn write_operand(f: &mut Formatter<'_>, expr: &Expr, parens: bool) -> std::fmt::Result {
+    if parens {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Prints with the minimal parentheses needed to parse back to the same tree.
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExprKind::Literal(literal) => write!(f, "{}", literal),
+            ExprKind::Var(name) => write!(f, "{}", name),
+            ExprKind::Unary { op, operand } => {
+                write!(f, "{}", op)?;
+                write_operand(f, operand, operand.precedence() < op.precedence())
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let prec = op.precedence();
+                let lhs_parens =
+                    lhs.precedence() < prec || (lhs.precedence() == prec && op.is_right_assoc());
+                let rhs_parens =
+                    rhs.precedence() < prec || (rhs.precedence() == prec && !op.is_right_assoc());
+
+                write_operand(f, lhs, lhs_parens)?;
+                match op {
+                    BinOp::Range => write!(f, "{}", op)?,
+                    _ => write!(f, " {} ", op)?,
+                }
+                write_operand(f, rhs, rhs_parens)
+            }
+            ExprKind::Call { name, args } => {
+                write!(f, "{}", name)?;
+                write_args(f, args)
+            }
+            ExprKind::Apply { callee, args } => {
+                // A name would make it a `Call`, and a number is fine as is
+                write_operand(f, callee, callee.precedence() < u8::MAX)?;
+                write_args(f, args)
+            }
+            ExprKind::Let { name, value, body } => {
+                write!(f, "let {} = {} in {}", name, value, body)
+            }
+            ExprKind::Lambda { params, body } => {
+                write!(f, "|{}| {}", params.join(", "), body)
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                write!(f, "if {} then {} else {}", cond, then_branch, else_branch)
+            }
+            ExprKind::Convert { expr, unit } => {
+                // Only `let`, `if` and lambdas would swallow the arrow
+                let parens =
+                    expr.precedence() == 0 && !matches!(expr.kind, ExprKind::Convert { .. });
+                write_operand(f, expr, parens)?;
+                write!(f, " -> {}", unit.symbol)
+            }
+            ExprKind::Record { fields } if fields.is_empty() => write!(f, "{{}}"),
+            ExprKind::Record { fields } => {
+                write!(f, "{{ ")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                write!(f, " }}")
+            }
+            ExprKind::Field { expr, name } => {
+                // A number would take the dot as its decimal point
+                let parens =
+                    expr.precedence() < u8::MAX || matches!(expr.kind, ExprKind::Literal(_));
+                write_operand(f, expr, parens)?;
+                write!(f, ".{}", name)
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                write!(f, "match {} {{", scrutinee)?;
+                for (i, arm) in arms.iter().enumerate() {
+                    let sep = if i > 0 { "," } else { "" };
+                    write!(f, "{} {} => {}", sep, arm.pattern, arm.body)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parser::parse(s)
+    }
+}
+
+// ------------------------------------------------
+/// A line of input: a top-level assignment, a function definition or a
+/// bare expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// `name = value`, binding a global variable
+    Assign {
+        name: String,
+        value: Expr,
+    },
+    /// `fn name(params) = body`, defining a global function
+    FnDef {
+        name: String,
+        params: Vec<String>,
+        body: Expr,
+    },
+    Expr(Expr),
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Assign { name, value } => write!(f, "{} = {}", name, value),
+            Stmt::FnDef { name, params, body } => {
+                write!(f, "fn {}({}) = {}", name, params.join(", "), body)
+            }
+            Stmt::Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl FromStr for Stmt {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parser::parse_stmt(s)
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(src: &str) -> String {
+        let expr: Expr = src.parse().unwrap();
+        let printed = expr.to_string();
+        let reparsed: Expr = printed.parse().unwrap();
+        assert_eq!(expr, reparsed, "round trip changed the tree of {}", src);
+        printed
+    }
+
+    #[test]
+    fn test_display_minimal_parens() {
+        assert_eq!(roundtrip("1+2*3"), "1 + 2 * 3");
+        assert_eq!(roundtrip("(1+2)*3"), "(1 + 2) * 3");
+        assert_eq!(roundtrip("((x))"), "x");
+        assert_eq!(roundtrip("a - (b - c)"), "a - (b - c)");
+        assert_eq!(roundtrip("(a - b) - c"), "a - b - c");
+    }
+
+    #[test]
+    fn test_display_power_is_right_assoc() {
+        assert_eq!(roundtrip("2^3^2"), "2 ^ 3 ^ 2");
+        assert_eq!(roundtrip("(2^3)^2"), "(2 ^ 3) ^ 2");
+    }
+
+    #[test]
+    fn test_display_unary() {
+        assert_eq!(roundtrip("-x"), "-x");
+        assert_eq!(roundtrip("-(a + b)"), "-(a + b)");
+        assert_eq!(roundtrip("-2^2"), "-2 ^ 2");
+        assert_eq!(roundtrip("(-2)^2"), "(-2) ^ 2");
+        assert_eq!(roundtrip("a * -b"), "a * -b");
+        assert_eq!(roundtrip("a - -b"), "a - -b");
+    }
+
+    #[test]
+    fn test_display_calls() {
+        assert_eq!(roundtrip("max(1, 2+3)"), "max(1, 2 + 3)");
+        assert_eq!(roundtrip("f()"), "f()");
+        assert_eq!(roundtrip("sin(x)^2 + cos(x)^2"), "sin(x) ^ 2 + cos(x) ^ 2");
+    }
+
+    #[test]
+    fn test_display_let() {
+        assert_eq!(roundtrip("let x = 3 in x * x"), "let x = 3 in x * x");
+        assert_eq!(roundtrip("(let x = 1 in x) + 2"), "(let x = 1 in x) + 2");
+        assert_eq!(
+            roundtrip("let x = let y = 2 in y in x"),
+            "let x = let y = 2 in y in x"
+        );
+    }
+
+    #[test]
+    fn test_display_lambda() {
+        assert_eq!(roundtrip("|x, y| x + y"), "|x, y| x + y");
+        assert_eq!(roundtrip("||1"), "|| 1");
+        assert_eq!(roundtrip("(|x| x) + 1"), "(|x| x) + 1");
+        assert_eq!(roundtrip("map(xs, |x| x * 2)"), "map(xs, |x| x * 2)");
+    }
+
+    #[test]
+    fn test_stmt_roundtrip() {
+        let stmt: Stmt = "x=1+2".parse().unwrap();
+        assert_eq!(stmt.to_string(), "x = 1 + 2");
+        assert_eq!(stmt.to_string().parse::<Stmt>().unwrap(), stmt);
+
+        let stmt: Stmt = "fn hyp(a,b)=sqrt(a^2+b^2)".parse().unwrap();
+        assert_eq!(stmt.to_string(), "fn hyp(a, b) = sqrt(a ^ 2 + b ^ 2)");
+        assert_eq!(stmt.to_string().parse::<Stmt>().unwrap(), stmt);
+    }
+
+    #[test]
+    fn test_equality_ignores_spans() {
+        let a: Expr = "1+2".parse().unwrap();
+        let b: Expr = "  1   +   2".parse().unwrap();
+        assert_ne!(a.span, b.span);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_constructors() {
+        let built = Expr::binary(
+            BinOp::Mul,
+            Expr::int(2),
+            Expr::call("sqrt", vec![Expr::var("x")]),
+        );
+        let parsed: Expr = "2 * sqrt(x)".parse().unwrap();
+        assert_eq!(built, parsed);
+        assert_eq!(built.to_string(), "2 * sqrt(x)");
+    }
+
+    #[test]
+    fn test_children() {
+        let expr: Expr = "if f(x, 1) then -y else |z| z".parse().unwrap();
+        let children: Vec<String> = expr.children().iter().map(|e| e.to_string()).collect();
+        assert_eq!(children, ["f(x, 1)", "-y", "|z| z"]);
+        assert_eq!(expr.children()[0].children().len(), 2);
+        assert!(Expr::var("x").children().is_empty());
+    }
+
+    #[test]
+    fn test_free_names() {
+        let expr: Expr = "let y = f(x) in map(ys, |x| x + y + z)".parse().unwrap();
+        let names: Vec<String> = expr.free_names().into_iter().collect();
+        assert_eq!(names, ["f", "map", "x", "ys", "z"]);
+        let shadowed: Expr = "(let x = 1 in x) + x".parse().unwrap();
+        assert_eq!(shadowed.free_names().len(), 1);
+    }
+
+    #[test]
+    fn test_structural_hash() {
+        use std::hash::DefaultHasher;
+
+        let hash_of = |expr: &Expr| {
+            let mut hasher = DefaultHasher::new();
+            expr.hash(&mut hasher);
+            hasher.finish()
+        };
+        let hash = |src: &str| hash_of(&src.parse().unwrap());
+        assert_eq!(hash("1+2 * x"), hash("1 + (2 * x)"));
+        assert_ne!(hash("1 + 2"), hash("2 + 1"));
+        assert_ne!(hash("1 + 2"), hash("1.0 + 2"));
+        // Equal trees hash alike, even where the floats differ in sign bit
+        assert_eq!(Expr::float(0.0), Expr::float(-0.0));
+        assert_eq!(hash_of(&Expr::float(0.0)), hash_of(&Expr::float(-0.0)));
+    }
+
+    #[test]
+    fn test_negative_literal_display() {
+        // Synthetic trees may hold negative literals; they print like a negation
+        let expr = Expr::binary(BinOp::Pow, Expr::int(-2), Expr::float(2.0));
+        assert_eq!(expr.to_string(), "(-2) ^ 2.0");
+    }
+
+    #[test]
+    fn test_display_literals() {
+        assert_eq!(roundtrip("1 + 2.0 * 1.5"), "1 + 2.0 * 1.5");
+        assert_eq!(roundtrip("1e3"), "1000.0");
+        assert_eq!(roundtrip("true != false"), "true != false");
+        assert_eq!(
+            roundtrip(r#""say \"hi\"\n" + "\\""#),
+            r#""say \"hi\"\n" + "\\""#
+        );
+    }
+
+    #[test]
+    fn test_display_logic_and_comparisons() {
+        assert_eq!(roundtrip("a < b && !(c || d)"), "a < b && !(c || d)");
+        assert_eq!(roundtrip("(a || b) && c"), "(a || b) && c");
+        assert_eq!(roundtrip("a + 1 >= b * 2 == c"), "a + 1 >= b * 2 == c");
+        assert_eq!(roundtrip("!a == b"), "!a == b");
+        assert_eq!(roundtrip("-x < 0"), "-x < 0");
+    }
+
+    #[test]
+    fn test_display_quantities() {
+        assert_eq!(roundtrip("3 m + 2.5 km"), "3 m + 2.5 km");
+        assert_eq!(roundtrip("1e20 m"), "100000000000000000000.0 m");
+        assert_eq!(roundtrip("(20 C -> F) + 1 F"), "(20 C -> F) + 1 F");
+        assert_eq!(roundtrip("a -> cm -> m"), "a -> cm -> m");
+        assert_eq!(
+            roundtrip("(let x = 1 m in x) -> cm"),
+            "(let x = 1 m in x) -> cm"
+        );
+        assert_eq!(
+            roundtrip("let x = 1 m in x -> cm"),
+            "let x = 1 m in x -> cm"
+        );
+    }
+
+    #[test]
+    fn test_display_if() {
+        assert_eq!(
+            roundtrip("if x > 0 then x else -x"),
+            "if x > 0 then x else -x"
+        );
+        assert_eq!(
+            roundtrip("(if a then 1 else 2) + 3"),
+            "(if a then 1 else 2) + 3"
+        );
+        assert_eq!(
+            roundtrip("if a then if b then 1 else 2 else 3"),
+            "if a then if b then 1 else 2 else 3"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crate::{
+    env::Bindings, error::EvalError, eval::Interpreter, memo::MemoCache,
+    registry::FunctionRegistry, trace::Tracer, value::Value, vm::CompiledExpr,
+};
+
+/// Chunks each thread's share of a batch is split into, so threads that
+/// finish early take over work from slower ones.
+const CHUNKS_PER_THREAD: usize = 8;
+
+// ------------------------------------------------
+// Workers share the compiled expression and the registry behind it by
+// reference, so both must be `Send + Sync`; these fail to compile if a
+// change ever makes them otherwise.
+fn _assert_send_sync<T: Send + Sync>() {}
+
+fn _static_assertions() {
+    _assert_send_sync::<CompiledExpr>();
+    _assert_send_sync::<FunctionRegistry>();
+    _assert_send_sync::<Bindings>();
+    _assert_send_sync::<Value>();
+    _assert_send_sync::<EvalError>();
+
+    // Interpreters may be cloned onto other threads too, with what they share
+    _assert_send_sync::<Interpreter>();
+    _assert_send_sync::<MemoCache>();
+    _assert_send_sync::<Tracer>();
+}
+
+// ------------------------------------------------
+/// Shards the rows of each batch across a fixed number of threads to
+/// evaluate a `CompiledExpr` over them at once.
+///
+/// The threads are scoped to one `eval_batch` call: each call spawns them,
+/// they take chunks of rows in turn until none are left, and the call
+/// joins them before returning. That lets them borrow the expression and
+/// rows instead of copying them, and costs one spawn per thread per batch,
+/// which is small beside a batch big enough to be worth sharding. Rows are
+/// evaluated independently, each under the expression's `EvalLimits`, and
+/// results come back in row order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopedBatch {
+    threads: NonZeroUsize,
+}
+
+impl ScopedBatch {
+    /// Shard each batch across `threads` threads; zero is taken as one.
+    pub fn new(threads: usize) -> Self {
+        ScopedBatch {
+            threads: NonZeroUsize::new(threads).unwrap_or(NonZeroUsize::MIN),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.get()
+    }
+
+    /// Evaluate `expr` once per row, as `CompiledExpr::eval` would.
+    pub fn eval_batch(
+        &self,
+        expr: &CompiledExpr,
+        rows: &[Bindings],
+    ) -> Vec<Result<Value, EvalError>> {
+        let threads = self.threads().min(rows.len());
+        if threads <= 1 {
+            return rows.iter().map(|row| expr.eval(row)).collect();
+        }
+
+        let chunk_len = rows.len().div_ceil(threads * CHUNKS_PER_THREAD);
+        let chunks: Vec<&[Bindings]> = rows.chunks(chunk_len).collect();
+        let next = AtomicUsize::new(0);
+        let done = Mutex::new(Vec::with_capacity(chunks.len()));
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(chunk) = chunks.get(index) else {
+                            break;
+                        };
+                        let results: Vec<_> = chunk.iter().map(|row| expr.eval(row)).collect();
+                        done.lock()
+                            .expect("no worker panics holding the lock")
+                            .push((index, results));
+                    }
+                });
+            }
+        });
+
+        let mut done = done.into_inner().expect("every worker has finished");
+        done.sort_unstable_by_key(|&(index, _)| index);
+        done.into_iter().flat_map(|(_, results)| results).collect()
+    }
+}
+
+/// One thread per available CPU, counted once per process.
+impl Default for ScopedBatch {
+    fn default() -> Self {
+        static THREADS: OnceLock<usize> = OnceLock::new();
+        let threads =
+            THREADS.get_or_init(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+        ScopedBatch::new(*threads)
+    }
+}
+
+/// Evaluate `expr` once per row, sharded by `ScopedBatch::default()`.
+pub fn eval_batch(expr: &CompiledExpr, rows: &[Bindings]) -> Vec<Result<Value, EvalError>> {
+    ScopedBatch::default().eval_batch(expr, rows)
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{limits::EvalLimits, parser::parse, vm::compile};
+
+    fn rows(count: i64) -> Vec<Bindings> {
+        (0..count)
+            .map(|i| {
+                let mut row = Bindings::new();
+                row.insert("price".to_string(), Value::Int(i));
+                row.insert("qty".to_string(), Value::Int(i % 7));
+                row
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_sequential_evaluation() {
+        let code = compile(&parse("price * 10 / qty + sum(map([1, 2], |k| k * qty))").unwrap());
+        let rows = rows(1000);
+        let expected: Vec<_> = rows.iter().map(|row| code.eval(row)).collect();
+        for threads in [1, 3, 8] {
+            let results = ScopedBatch::new(threads).eval_batch(&code, &rows);
+            assert_eq!(results, expected, "with {} threads", threads);
+        }
+        // Every seventh row divides by zero, and keeps its place
+        assert!(expected[7].is_err());
+        assert_eq!(expected[8], Ok(Value::Float(83.0)));
+    }
+
+    #[test]
+    fn test_small_and_empty_batches() {
+        let code = compile(&parse("price + 1").unwrap());
+        let batch = ScopedBatch::new(16);
+        assert!(batch.eval_batch(&code, &[]).is_empty());
+        assert_eq!(
+            batch.eval_batch(&code, &rows(2)),
+            [Ok(Value::Int(1)), Ok(Value::Int(2))]
+        );
+        assert_eq!(ScopedBatch::new(0).threads(), 1);
+        assert!(ScopedBatch::default().threads() >= 1);
+    }
+
+    #[test]
+    fn test_registry_and_limits_per_row() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut registry = FunctionRegistry::new();
+        registry.register("discount", move |price: i64| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            if price > 100 { 0.9 } else { 1.0 }
+        });
+        let code = compile(
+            &parse("price * discount(price) + fold(range(0, qty), 0, |a, b| a + b)").unwrap(),
+        )
+        .with_registry(registry);
+
+        // The fewest steps a row runs in; rows folding longer ranges need more
+        let needed = |row: &Bindings| {
+            (1..)
+                .find(|&steps| {
+                    let limited = code
+                        .clone()
+                        .with_limits(EvalLimits::none().max_steps(steps));
+                    limited.eval(row).is_ok()
+                })
+                .unwrap()
+        };
+        let rows = rows(500);
+        let budget = needed(&rows[4]);
+        let expected: Vec<bool> = rows.iter().map(|row| needed(row) > budget).collect();
+        assert!(expected.contains(&true) && expected.contains(&false));
+
+        calls.store(0, Ordering::Relaxed);
+        let code = code.with_limits(EvalLimits::none().max_steps(budget));
+        let results = eval_batch(&code, &rows);
+        assert_eq!(calls.load(Ordering::Relaxed), 500);
+        assert_eq!(results[200], Ok(Value::Float(180.0 + 6.0)));
+        // Steps are counted per row, so a row fails only if it alone needs
+        // more than the budget
+        let failed: Vec<bool> = results.iter().map(Result::is_err).collect();
+        assert_eq!(failed, expected);
+    }
+}
@@ -0,0 +1,65 @@
+//! Calculator driver for the capstone evaluator.
+//!
+//! Usage:
+//!   calc                 interactive session on stdin
+//!   calc script.expr     evaluate every line of a file
+//!
+//! Options go first: `--dump-optimized` prints each statement after
+//! optimization, and `--exact` computes with exact rationals.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader, IsTerminal},
+    process::ExitCode,
+};
+
+use p24_capstone::{exact::NumericMode, repl::Repl};
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut repl = Repl::new();
+    while let Some(option) = args.first().filter(|arg| arg.starts_with("--")) {
+        repl = match option.as_str() {
+            "--dump-optimized" => repl.dump_optimized(true),
+            "--exact" => repl.numeric_mode(NumericMode::Exact),
+            _ => break,
+        };
+        args.remove(0);
+    }
+    let mut stdout = io::stdout().lock();
+
+    let result = match args.as_slice() {
+        [] => {
+            let stdin = io::stdin();
+            let interactive = stdin.is_terminal();
+            let failures = repl.interactive(interactive).run(stdin.lock(), &mut stdout);
+            // Mistakes typed at the prompt don't make the session fail
+            if interactive {
+                failures.map(|_| 0)
+            } else {
+                failures
+            }
+        }
+        [path] => match File::open(path) {
+            Ok(file) => repl.run(BufReader::new(file), &mut stdout),
+            Err(e) => {
+                eprintln!("calc: cannot open '{}': {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("usage: calc [--dump-optimized] [--exact] [script.expr]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("calc: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
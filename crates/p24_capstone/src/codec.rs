@@ -0,0 +1,799 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, Read, Write},
+};
+
+use p19_io_read_write::CntWriter;
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Pattern, UnaryOp},
+    error::CodecError,
+    lexer::Span,
+    parser::MAX_NESTING,
+    units::{self, Unit},
+};
+
+// ------------------------------------------------
+/// First bytes of every saved expression.
+pub const MAGIC: [u8; 4] = *b"CALC";
+
+/// Version of the encoding; bump it whenever the payload layout changes.
+pub const VERSION: u16 = 2;
+
+/// Magic, version, kind, payload length and checksum.
+const HEADER_LEN: usize = 4 + 2 + 1 + 4 + 4;
+
+/// Longer payloads are rejected instead of allocated.
+const MAX_PAYLOAD: u32 = 64 << 20;
+
+/// Deeper trees are rejected instead of decoded, which recurses once per
+/// level. Twice what the parser accepts leaves room for optimized and
+/// derived trees.
+const MAX_DEPTH: usize = 2 * MAX_NESTING;
+
+const BIN_OPS: [BinOp; 15] = [
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mul,
+    BinOp::Div,
+    BinOp::Rem,
+    BinOp::Pow,
+    BinOp::Eq,
+    BinOp::Ne,
+    BinOp::Lt,
+    BinOp::Le,
+    BinOp::Gt,
+    BinOp::Ge,
+    BinOp::And,
+    BinOp::Or,
+    BinOp::Range,
+];
+
+const UNARY_OPS: [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+
+/// What a saved file holds, recorded in its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Expr,
+    Compiled,
+}
+
+impl Kind {
+    fn tag(self) -> u8 {
+        match self {
+            Kind::Expr => b'E',
+            Kind::Compiled => b'C',
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Kind> {
+        match tag {
+            b'E' => Some(Kind::Expr),
+            b'C' => Some(Kind::Compiled),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Expr => write!(f, "an expression tree"),
+            Kind::Compiled => write!(f, "compiled bytecode"),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// CRC-32 (IEEE) of `bytes`, bit by bit; payloads are small enough that a
+/// lookup table would not pay for itself.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Write the header and `payload`, returning the bytes written as counted
+/// by p19's `CntWriter`.
+pub(crate) fn write_framed<W: Write>(kind: Kind, payload: &[u8], writer: W) -> io::Result<usize> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|&len| len <= MAX_PAYLOAD)
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "expression too large to save")
+        })?;
+
+    let mut out = CntWriter::new(writer);
+    out.write_all(&MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&[kind.tag()])?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(&crc32(payload).to_le_bytes())?;
+    out.write_all(payload)?;
+    out.flush()?;
+    Ok(out.bytes_written())
+}
+
+/// Read a header and its payload, checking everything the header records.
+pub(crate) fn read_framed<R: Read>(expected: Kind, mut reader: R) -> Result<Vec<u8>, CodecError> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let field = |range: std::ops::Range<usize>| &header[range];
+
+    let found: [u8; 4] = field(0..4).try_into().unwrap();
+    if found != MAGIC {
+        return Err(CodecError::BadMagic { found });
+    }
+    let version = u16::from_le_bytes(field(4..6).try_into().unwrap());
+    if version != VERSION {
+        return Err(CodecError::UnsupportedVersion {
+            found: version,
+            supported: VERSION,
+        });
+    }
+    let kind = Kind::from_tag(header[6]).ok_or_else(|| CodecError::Corrupt {
+        offset: 6,
+        reason: format!("unknown kind {:#04x}", header[6]),
+    })?;
+    if kind != expected {
+        return Err(CodecError::WrongKind {
+            expected,
+            found: kind,
+        });
+    }
+    let len = u32::from_le_bytes(field(7..11).try_into().unwrap());
+    if len > MAX_PAYLOAD {
+        return Err(CodecError::Corrupt {
+            offset: 7,
+            reason: format!("payload of {} bytes is too large", len),
+        });
+    }
+    let checksum = u32::from_le_bytes(field(11..15).try_into().unwrap());
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    let found = crc32(&payload);
+    if found != checksum {
+        return Err(CodecError::ChecksumMismatch {
+            expected: checksum,
+            found,
+        });
+    }
+    Ok(payload)
+}
+
+// ------------------------------------------------
+/// Builds a payload: little-endian integers, length-prefixed strings and
+/// lists, and one tag byte per node.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    pub(crate) fn bool(&mut self, b: bool) {
+        self.u8(b as u8);
+    }
+
+    /// A length, count or index.
+    pub(crate) fn usize(&mut self, n: usize) {
+        let n = u32::try_from(n).expect("counts in a saved expression fit in 32 bits");
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub(crate) fn i64(&mut self, n: i64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+
+    pub(crate) fn f64(&mut self, n: f64) {
+        self.bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    pub(crate) fn strs(&mut self, items: &[String]) {
+        self.usize(items.len());
+        for item in items {
+            self.str(item);
+        }
+    }
+
+    pub(crate) fn span(&mut self, span: Span) {
+        self.usize(span.start);
+        self.usize(span.end);
+    }
+
+    /// Units are stored by symbol and looked up again when read.
+    pub(crate) fn unit(&mut self, unit: &Unit) {
+        self.str(unit.symbol);
+    }
+
+    pub(crate) fn bin_op(&mut self, op: BinOp) {
+        let index = BIN_OPS.iter().position(|&o| o == op).unwrap();
+        self.u8(index as u8);
+    }
+
+    pub(crate) fn unary_op(&mut self, op: UnaryOp) {
+        let index = UNARY_OPS.iter().position(|&o| o == op).unwrap();
+        self.u8(index as u8);
+    }
+
+    pub(crate) fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::Int(n) => {
+                self.u8(0);
+                self.i64(*n);
+            }
+            Literal::Float(n) => {
+                self.u8(1);
+                self.f64(*n);
+            }
+            Literal::Bool(b) => {
+                self.u8(2);
+                self.bool(*b);
+            }
+            Literal::Str(s) => {
+                self.u8(3);
+                self.str(s);
+            }
+            Literal::Quantity(n, unit) => {
+                self.u8(4);
+                self.f64(*n);
+                self.unit(unit);
+            }
+            Literal::Imaginary(n) => {
+                self.u8(5);
+                self.f64(*n);
+            }
+            Literal::BigInt(n) => {
+                self.u8(6);
+                self.str(&n.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.u8(0),
+            Pattern::Literal(literal) => {
+                self.u8(1);
+                self.literal(literal);
+            }
+            Pattern::Range { start, end } => {
+                self.u8(2);
+                self.bound(start.as_ref());
+                self.bound(end.as_ref());
+            }
+        }
+    }
+
+    fn bound(&mut self, bound: Option<&Literal>) {
+        self.bool(bound.is_some());
+        if let Some(literal) = bound {
+            self.literal(literal);
+        }
+    }
+
+    pub(crate) fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                self.u8(0);
+                self.literal(literal);
+            }
+            ExprKind::Var(name) => {
+                self.u8(1);
+                self.str(name);
+            }
+            ExprKind::Unary { op, operand } => {
+                self.u8(2);
+                self.unary_op(*op);
+                self.expr(operand);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.u8(3);
+                self.bin_op(*op);
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Call { name, args } => {
+                self.u8(4);
+                self.str(name);
+                self.usize(args.len());
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Let { name, value, body } => {
+                self.u8(5);
+                self.str(name);
+                self.expr(value);
+                self.expr(body);
+            }
+            ExprKind::Lambda { params, body } => {
+                self.u8(6);
+                self.strs(params);
+                self.expr(body);
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.u8(7);
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            ExprKind::Convert { expr, unit } => {
+                self.u8(8);
+                self.expr(expr);
+                self.unit(unit);
+            }
+            ExprKind::Record { fields } => {
+                self.u8(9);
+                self.usize(fields.len());
+                for (name, value) in fields {
+                    self.str(name);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field { expr, name } => {
+                self.u8(10);
+                self.expr(expr);
+                self.str(name);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.u8(11);
+                self.expr(scrutinee);
+                self.usize(arms.len());
+                for arm in arms {
+                    self.pattern(&arm.pattern);
+                    self.span(arm.span);
+                    self.expr(&arm.body);
+                }
+            }
+            ExprKind::Apply { callee, args } => {
+                self.u8(12);
+                self.expr(callee);
+                self.usize(args.len());
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+        }
+        self.span(expr.span);
+    }
+}
+
+/// Reads back what `Encoder` wrote, failing with the file offset of the
+/// first value that makes no sense.
+#[derive(Debug)]
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Where the value read last starts
+    start: usize,
+    /// Expressions being decoded, counting the current one
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Decoder {
+            bytes,
+            pos: 0,
+            start: 0,
+            depth: 0,
+        }
+    }
+
+    /// Fail unless the whole payload was read.
+    pub(crate) fn finish(mut self) -> Result<(), CodecError> {
+        self.start = self.pos;
+        match self.bytes.len() - self.pos {
+            0 => Ok(()),
+            extra => Err(self.corrupt(format!("{} unread bytes after the end", extra))),
+        }
+    }
+
+    pub(crate) fn corrupt(&self, reason: impl Into<String>) -> CodecError {
+        CodecError::Corrupt {
+            offset: HEADER_LEN + self.start,
+            reason: reason.into(),
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let bytes = self.take_slice(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+        self.start = self.pos;
+        if self.bytes.len() - self.pos < len {
+            return Err(self.corrupt("payload ends in the middle of a value"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, CodecError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            n => Err(self.corrupt(format!("{} is not a bool", n))),
+        }
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, CodecError> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+
+    pub(crate) fn i64(&mut self) -> Result<i64, CodecError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, CodecError> {
+        Ok(f64::from_bits(u64::from_le_bytes(self.take()?)))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, CodecError> {
+        let len = self.usize()?;
+        let bytes = self.take_slice(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupt("string is not UTF-8"))
+    }
+
+    pub(crate) fn strings(&mut self) -> Result<Vec<String>, CodecError> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.string()).collect()
+    }
+
+    pub(crate) fn span(&mut self) -> Result<Span, CodecError> {
+        let start = self.usize()?;
+        let end = self.usize()?;
+        if start > end {
+            return Err(self.corrupt(format!("span {}..{} ends before it starts", start, end)));
+        }
+        Ok(Span::new(start, end))
+    }
+
+    pub(crate) fn unit(&mut self) -> Result<&'static Unit, CodecError> {
+        let symbol = self.string()?;
+        units::lookup(&symbol).ok_or_else(|| self.corrupt(format!("unknown unit `{}`", symbol)))
+    }
+
+    pub(crate) fn bin_op(&mut self) -> Result<BinOp, CodecError> {
+        let index = self.u8()?;
+        BIN_OPS
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| self.corrupt(format!("unknown binary operator {}", index)))
+    }
+
+    pub(crate) fn unary_op(&mut self) -> Result<UnaryOp, CodecError> {
+        let index = self.u8()?;
+        UNARY_OPS
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| self.corrupt(format!("unknown unary operator {}", index)))
+    }
+
+    pub(crate) fn literal(&mut self) -> Result<Literal, CodecError> {
+        let literal = match self.u8()? {
+            0 => Literal::Int(self.i64()?),
+            1 => Literal::Float(self.f64()?),
+            2 => Literal::Bool(self.bool()?),
+            3 => Literal::Str(self.string()?),
+            4 => Literal::Quantity(self.f64()?, self.unit()?),
+            5 => Literal::Imaginary(self.f64()?),
+            6 => {
+                let digits = self.string()?;
+                let n = digits
+                    .parse()
+                    .map_err(|_| self.corrupt(format!("invalid integer '{}'", digits)))?;
+                Literal::BigInt(n)
+            }
+            tag => return Err(self.corrupt(format!("unknown literal tag {}", tag))),
+        };
+        Ok(literal)
+    }
+
+    pub(crate) fn pattern(&mut self) -> Result<Pattern, CodecError> {
+        let pattern = match self.u8()? {
+            0 => Pattern::Wildcard,
+            1 => Pattern::Literal(self.literal()?),
+            2 => Pattern::Range {
+                start: self.bound()?,
+                end: self.bound()?,
+            },
+            tag => return Err(self.corrupt(format!("unknown pattern tag {}", tag))),
+        };
+        Ok(pattern)
+    }
+
+    fn bound(&mut self) -> Result<Option<Literal>, CodecError> {
+        match self.bool()? {
+            true => self.literal().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn boxed(&mut self) -> Result<Box<Expr>, CodecError> {
+        self.expr().map(Box::new)
+    }
+
+    pub(crate) fn expr(&mut self) -> Result<Expr, CodecError> {
+        if self.depth == MAX_DEPTH {
+            let reason = format!("expressions nested more than {} deep", MAX_DEPTH);
+            return Err(self.corrupt(reason));
+        }
+        self.depth += 1;
+        let expr = self.node();
+        self.depth -= 1;
+        expr
+    }
+
+    /// One expression and, through `expr`, its children.
+    fn node(&mut self) -> Result<Expr, CodecError> {
+        let kind = match self.u8()? {
+            0 => ExprKind::Literal(self.literal()?),
+            1 => ExprKind::Var(self.string()?),
+            2 => ExprKind::Unary {
+                op: self.unary_op()?,
+                operand: self.boxed()?,
+            },
+            3 => ExprKind::Binary {
+                op: self.bin_op()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            4 => {
+                let name = self.string()?;
+                let len = self.usize()?;
+                let args = (0..len).map(|_| self.expr()).collect::<Result<_, _>>()?;
+                ExprKind::Call { name, args }
+            }
+            5 => ExprKind::Let {
+                name: self.string()?,
+                value: self.boxed()?,
+                body: self.boxed()?,
+            },
+            6 => ExprKind::Lambda {
+                params: self.strings()?,
+                body: self.boxed()?,
+            },
+            7 => ExprKind::If {
+                cond: self.boxed()?,
+                then_branch: self.boxed()?,
+                else_branch: self.boxed()?,
+            },
+            8 => ExprKind::Convert {
+                expr: self.boxed()?,
+                unit: self.unit()?,
+            },
+            9 => {
+                let len = self.usize()?;
+                let fields = (0..len)
+                    .map(|_| Ok((self.string()?, self.expr()?)))
+                    .collect::<Result<_, CodecError>>()?;
+                ExprKind::Record { fields }
+            }
+            10 => ExprKind::Field {
+                expr: self.boxed()?,
+                name: self.string()?,
+            },
+            11 => {
+                let scrutinee = self.boxed()?;
+                let len = self.usize()?;
+                let arms = (0..len)
+                    .map(|_| {
+                        let pattern = self.pattern()?;
+                        let span = self.span()?;
+                        let body = self.expr()?;
+                        Ok(MatchArm {
+                            pattern,
+                            body,
+                            span,
+                        })
+                    })
+                    .collect::<Result<_, CodecError>>()?;
+                ExprKind::Match { scrutinee, arms }
+            }
+            12 => {
+                let callee = self.boxed()?;
+                let len = self.usize()?;
+                let args = (0..len).map(|_| self.expr()).collect::<Result<_, _>>()?;
+                ExprKind::Apply { callee, args }
+            }
+            tag => return Err(self.corrupt(format!("unknown expression tag {}", tag))),
+        };
+        Ok(Expr::new(kind, self.span()?))
+    }
+}
+
+// ------------------------------------------------
+impl Expr {
+    /// Save the tree, spans included, in the versioned binary format;
+    /// returns the number of bytes written.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<usize> {
+        let mut encoder = Encoder::new();
+        encoder.expr(self);
+        write_framed(Kind::Expr, &encoder.finish(), writer)
+    }
+
+    /// Load a tree saved by `write_to`, rejecting anything truncated,
+    /// corrupted or written by another version.
+    pub fn read_from<R: Read>(reader: R) -> Result<Expr, CodecError> {
+        let payload = read_framed(Kind::Expr, reader)?;
+        let mut decoder = Decoder::new(&payload);
+        let expr = decoder.expr()?;
+        decoder.finish()?;
+        Ok(expr)
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(src: &str) -> Vec<u8> {
+        let expr: Expr = src.parse().unwrap();
+        let mut bytes = Vec::new();
+        let written = expr.write_to(&mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+        bytes
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for src in [
+            "1 + 2 * -x",
+            "let f = |a, b| a ^ b in f(2, 10) % 7",
+            "if x >= 1.5 && !done then \"yes\\n\" else \"no\"",
+            "3 m + 50 cm -> km",
+            "max(1, 2.25, sqrt(x)) == 2 || false",
+            "{ price: p, qty: {} }.price * match q { 0 => 1, -2..5.5 => 2, ..-9 => 3, _ => 4 }",
+            "(3 + 4i) * -2.5i / sqrt(-1)",
+            "123456789012345678901234567890 % 7",
+            "make_adder(1)(2) + (|x| x)(3).y",
+        ] {
+            let expr: Expr = src.parse().unwrap();
+            let loaded = Expr::read_from(save(src).as_slice()).unwrap();
+            assert_eq!(loaded, expr, "{}", src);
+            assert_eq!(loaded.to_string(), expr.to_string());
+        }
+
+        let loaded = Expr::read_from(save("a +  b").as_slice()).unwrap();
+        let ExprKind::Binary { rhs, .. } = &loaded.kind else {
+            panic!("expected a binary node");
+        };
+        assert_eq!(rhs.span, Span::new(5, 6), "spans survive for diagnostics");
+    }
+
+    #[test]
+    fn test_header_errors() {
+        let bytes = save("x + 1");
+
+        let mut other = bytes.clone();
+        other[..4].copy_from_slice(b"PK\x03\x04");
+        assert!(matches!(
+            Expr::read_from(other.as_slice()),
+            Err(CodecError::BadMagic { found }) if &found == b"PK\x03\x04"
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4] = 3;
+        assert_eq!(
+            Expr::read_from(newer.as_slice()).unwrap_err().to_string(),
+            "saved with format version 3, but only version 2 can be read"
+        );
+
+        let mut compiled = bytes.clone();
+        compiled[6] = b'C';
+        assert_eq!(
+            Expr::read_from(compiled.as_slice())
+                .unwrap_err()
+                .to_string(),
+            "expected an expression tree, found compiled bytecode"
+        );
+
+        assert!(matches!(
+            Expr::read_from(&bytes[..bytes.len() - 1]),
+            Err(CodecError::Truncated)
+        ));
+        assert!(matches!(
+            Expr::read_from(&bytes[..3]),
+            Err(CodecError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let mut bytes = save("price * qty");
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x40;
+        assert!(matches!(
+            Expr::read_from(bytes.as_slice()),
+            Err(CodecError::ChecksumMismatch { .. })
+        ));
+
+        // A consistent checksum over a payload that does not decode
+        let mut encoder = Encoder::new();
+        encoder.u8(1);
+        encoder.str("x");
+        encoder.u8(9);
+        let mut bytes = Vec::new();
+        write_framed(Kind::Expr, &encoder.finish(), &mut bytes).unwrap();
+        assert_eq!(
+            Expr::read_from(bytes.as_slice()).unwrap_err().to_string(),
+            "corrupt saved expression at byte 21: payload ends in the middle of a value"
+        );
+
+        let mut encoder = Encoder::new();
+        encoder.literal(&Literal::Int(1));
+        let mut bytes = Vec::new();
+        write_framed(
+            Kind::Expr,
+            &[&[0xff][..], &encoder.finish()].concat(),
+            &mut bytes,
+        )
+        .unwrap();
+        assert_eq!(
+            Expr::read_from(bytes.as_slice()).unwrap_err().to_string(),
+            "corrupt saved expression at byte 15: unknown expression tag 255"
+        );
+
+        // Nesting deep enough to exhaust the stack is refused up front
+        let mut encoder = Encoder::new();
+        for _ in 0..500_000 {
+            encoder.u8(2);
+            encoder.unary_op(UnaryOp::Neg);
+        }
+        let mut bytes = Vec::new();
+        write_framed(Kind::Expr, &encoder.finish(), &mut bytes).unwrap();
+        let err = Expr::read_from(bytes.as_slice()).unwrap_err();
+        assert!(matches!(err, CodecError::Corrupt { .. }));
+        assert!(
+            err.to_string()
+                .ends_with("expressions nested more than 200 deep"),
+            "{}",
+            err
+        );
+    }
+}
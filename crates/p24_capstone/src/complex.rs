@@ -0,0 +1,375 @@
+use std::{
+    fmt::{Display, Formatter},
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
+// ------------------------------------------------
+/// A complex number `re + im·i`, with p14's operator set: `+`, `-`, `*`
+/// and `/` between complex numbers by value or by reference, with real
+/// scalars on either side, the assigning forms of each, and `-`.
+///
+/// There is no `%`, since complex numbers have no ordering to round by.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    /// The imaginary unit
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    /// The number at distance `r` from zero and angle `theta` in radians
+    /// from the positive real axis.
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Complex {
+            re: r * theta.cos(),
+            im: r * theta.sin(),
+        }
+    }
+
+    /// Distance and angle, as `from_polar` takes them.
+    pub fn to_polar(self) -> (f64, f64) {
+        (self.abs(), self.arg())
+    }
+
+    /// Magnitude
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Angle from the positive real axis, in `(-pi, pi]`
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    /// Complex conjugate
+    pub fn conj(self) -> Self {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.re == 0.0 && self.im == 0.0
+    }
+
+    /// The principal square root, whose real part is never negative.
+    pub fn sqrt(self) -> Self {
+        if self.im == 0.0 {
+            return match self.re {
+                re if re >= 0.0 => Complex::new(re.sqrt(), self.im),
+                re => Complex::new(0.0, (-re).sqrt().copysign(self.im)),
+            };
+        }
+        // Half-angle formulas, which avoid cancellation in `r - re`
+        let r = self.abs();
+        let re = ((r + self.re) / 2.0).sqrt();
+        let im = ((r - self.re) / 2.0).sqrt().copysign(self.im);
+        Complex::new(re, im)
+    }
+
+    pub fn exp(self) -> Self {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    /// The principal natural logarithm.
+    pub fn ln(self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    /// `self` to a whole power, by repeated squaring, so small powers are
+    /// as exact as multiplying out.
+    pub fn powi(self, n: i32) -> Self {
+        let mut result = Complex::new(1.0, 0.0);
+        let mut base = self;
+        let mut exp = n.unsigned_abs();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            exp >>= 1;
+        }
+        if n < 0 { 1.0 / result } else { result }
+    }
+
+    /// The principal value of `self` to the power `exp`.
+    pub fn powc(self, exp: Complex) -> Self {
+        if exp.im == 0.0 && exp.re.fract() == 0.0 && exp.re.abs() <= i32::MAX as f64 {
+            return self.powi(exp.re as i32);
+        }
+        if self.is_zero() {
+            // 0 to a positive power, and nothing sensible otherwise
+            return match exp.re > 0.0 {
+                true => Complex::default(),
+                false => Complex::new(f64::NAN, f64::NAN),
+            };
+        }
+        (exp * self.ln()).exp()
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self {
+        Complex { re, im: 0.0 }
+    }
+}
+
+/// `3 + 4i`, `0.5 - 2i`, which lex back to the same number.
+impl Display for Complex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+        write!(f, "{} {} {}i", self.re, sign, self.im.abs())
+    }
+}
+
+// ------------------------------------------------
+// Complex + Complex
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re + rhs.re,
+            im: self.im + rhs.im,
+        }
+    }
+}
+
+// Complex - Complex
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re - rhs.re,
+            im: self.im - rhs.im,
+        }
+    }
+}
+
+// Complex * Complex
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Complex {
+            re: self.re * rhs.re - self.im * rhs.im,
+            im: self.re * rhs.im + self.im * rhs.re,
+        }
+    }
+}
+
+// Complex / Complex, by the conjugate of the divisor
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self::Output {
+        let norm = rhs.re * rhs.re + rhs.im * rhs.im;
+        Complex {
+            re: (self.re * rhs.re + self.im * rhs.im) / norm,
+            im: (self.im * rhs.re - self.re * rhs.im) / norm,
+        }
+    }
+}
+
+// -Complex
+impl Neg for Complex {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Complex {
+            re: -self.re,
+            im: -self.im,
+        }
+    }
+}
+
+impl Neg for &Complex {
+    type Output = Complex;
+    fn neg(self) -> Self::Output {
+        -*self
+    }
+}
+
+/// `&a op &b`, `a op &b` and `&a op b` for an operator between two
+/// complex numbers, in terms of `a op b`.
+macro_rules! forward_ref_binop {
+    ($imp:ident, $method:ident) => {
+        impl $imp for &Complex {
+            type Output = Complex;
+            fn $method(self, rhs: Self) -> Self::Output {
+                $imp::$method(*self, *rhs)
+            }
+        }
+
+        impl $imp<&Complex> for Complex {
+            type Output = Complex;
+            fn $method(self, rhs: &Complex) -> Self::Output {
+                $imp::$method(self, *rhs)
+            }
+        }
+
+        impl $imp<Complex> for &Complex {
+            type Output = Complex;
+            fn $method(self, rhs: Complex) -> Self::Output {
+                $imp::$method(*self, rhs)
+            }
+        }
+    };
+}
+
+forward_ref_binop!(Add, add);
+forward_ref_binop!(Sub, sub);
+forward_ref_binop!(Mul, mul);
+forward_ref_binop!(Div, div);
+
+/// `a op x` and `x op a` for a real scalar `x`, as if it were `x + 0i`.
+macro_rules! scalar_binop {
+    ($imp:ident, $method:ident) => {
+        impl $imp<f64> for Complex {
+            type Output = Complex;
+            fn $method(self, rhs: f64) -> Self::Output {
+                $imp::$method(self, Complex::from(rhs))
+            }
+        }
+
+        impl $imp<Complex> for f64 {
+            type Output = Complex;
+            fn $method(self, rhs: Complex) -> Self::Output {
+                $imp::$method(Complex::from(self), rhs)
+            }
+        }
+    };
+}
+
+scalar_binop!(Add, add);
+scalar_binop!(Sub, sub);
+scalar_binop!(Mul, mul);
+scalar_binop!(Div, div);
+
+/// `a op= b` for a complex or real `b`, in terms of `a op b`.
+macro_rules! assign_op {
+    ($imp:ident, $method:ident, $op:ident, $op_method:ident) => {
+        impl $imp for Complex {
+            fn $method(&mut self, rhs: Self) {
+                *self = $op::$op_method(*self, rhs);
+            }
+        }
+
+        impl $imp<f64> for Complex {
+            fn $method(&mut self, rhs: f64) {
+                *self = $op::$op_method(*self, rhs);
+            }
+        }
+    };
+}
+
+assign_op!(AddAssign, add_assign, Add, add);
+assign_op!(SubAssign, sub_assign, Sub, sub);
+assign_op!(MulAssign, mul_assign, Mul, mul);
+assign_op!(DivAssign, div_assign, Div, div);
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    use super::*;
+
+    fn close(a: Complex, b: Complex) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    #[test]
+    #[allow(clippy::op_ref)]
+    fn test_arithmetic() {
+        let a = Complex::new(3.0, 4.0);
+        let b = Complex::new(1.0, -2.0);
+        assert_eq!(a + b, Complex::new(4.0, 2.0));
+        assert_eq!(a - b, Complex::new(2.0, 6.0));
+        assert_eq!(a * b, Complex::new(11.0, -2.0));
+        assert_eq!(a / b, Complex::new(-1.0, 2.0));
+        assert_eq!(-a, Complex::new(-3.0, -4.0));
+        assert_eq!(Complex::I * Complex::I, Complex::new(-1.0, 0.0));
+
+        // By reference, in every combination
+        assert_eq!(&a + &b, a + b);
+        assert_eq!(a - &b, a - b);
+        assert_eq!(&a * b, a * b);
+        assert_eq!(&a / &b, a / b);
+        assert_eq!(-&a, -a);
+    }
+
+    #[test]
+    fn test_scalars_and_assign_ops() {
+        let a = Complex::new(3.0, 4.0);
+        assert_eq!(a * 2.0, Complex::new(6.0, 8.0));
+        assert_eq!(2.0 * a, Complex::new(6.0, 8.0));
+        assert_eq!(a / 2.0, Complex::new(1.5, 2.0));
+        assert_eq!(1.0 - a, Complex::new(-2.0, -4.0));
+        assert_eq!(a + 1.0, Complex::new(4.0, 4.0));
+
+        let mut z = a;
+        z += Complex::I;
+        assert_eq!(z, Complex::new(3.0, 5.0));
+        z -= 3.0;
+        assert_eq!(z, Complex::new(0.0, 5.0));
+        z *= Complex::I;
+        assert_eq!(z, Complex::new(-5.0, 0.0));
+        z /= -5.0;
+        assert_eq!(z, Complex::new(1.0, -0.0));
+        z /= Complex::I;
+        assert_eq!(z, Complex::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn test_polar_and_functions() {
+        let a = Complex::new(3.0, 4.0);
+        assert_eq!(a.abs(), 5.0);
+        assert_eq!(a.conj(), Complex::new(3.0, -4.0));
+        assert_eq!(Complex::new(-1.0, 0.0).arg(), PI);
+        assert_eq!(Complex::I.arg(), FRAC_PI_2);
+
+        let (r, theta) = a.to_polar();
+        assert!(close(Complex::from_polar(r, theta), a));
+        assert!(close(Complex::from_polar(2.0, PI), Complex::new(-2.0, 0.0)));
+
+        assert_eq!(Complex::from(-4.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_eq!(Complex::new(-4.0, -0.0).sqrt(), Complex::new(0.0, -2.0));
+        assert_eq!(Complex::new(-7.0, 24.0).sqrt(), Complex::new(3.0, 4.0));
+        assert_eq!(Complex::I.exp().abs(), 1.0);
+        assert!(close((Complex::I * PI).exp(), Complex::from(-1.0)));
+        assert!(close(a.ln().exp(), a));
+    }
+
+    #[test]
+    fn test_powers() {
+        let a = Complex::new(3.0, 4.0);
+        assert_eq!(a.powi(2), Complex::new(-7.0, 24.0));
+        assert_eq!(a.powi(0), Complex::new(1.0, 0.0));
+        assert!(close(a.powi(-1), Complex::new(0.12, -0.16)));
+        assert_eq!(a.powc(Complex::from(2.0)), a.powi(2));
+        assert!(close(
+            Complex::from(-1.0).powc(Complex::from(0.5)),
+            Complex::I
+        ));
+        assert!(close(
+            Complex::I.powc(Complex::I),
+            Complex::from((-FRAC_PI_2).exp())
+        ));
+        assert_eq!(
+            Complex::default().powc(Complex::new(0.5, 1.0)),
+            Complex::default()
+        );
+        assert!(Complex::default().powc(Complex::I).re.is_nan());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Complex::new(3.0, 4.0).to_string(), "3 + 4i");
+        assert_eq!(Complex::new(0.5, -2.0).to_string(), "0.5 - 2i");
+        assert_eq!(Complex::new(-1.0, 0.0).to_string(), "-1 + 0i");
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::HashSet;
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, UnaryOp},
+    error::DeriveError,
+    optimize::Optimizer,
+};
+
+// ------------------------------------------------
+impl Expr {
+    /// Symbolic derivative with respect to `var`, simplified.
+    ///
+    /// Panics if the expression contains something without a derivative
+    /// rule; use `try_derive` to handle that case.
+    pub fn derive(&self, var: &str) -> Expr {
+        self.try_derive(var).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Symbolic derivative with respect to `var`, simplified.
+    ///
+    /// Builtins are assumed not to be shadowed by user functions.
+    pub fn try_derive(&self, var: &str) -> Result<Expr, DeriveError> {
+        let mut names = self.names();
+        names.insert(var.to_string());
+        let derivative = Deriver { names }.derive(self, var)?;
+        Ok(simplify(derivative))
+    }
+}
+
+struct Deriver {
+    /// Names already in use, so renamed `let` bindings stay unique
+    names: HashSet<String>,
+}
+
+impl Deriver {
+    fn fresh(&mut self, base: &str) -> String {
+        let name = (1..)
+            .map(|i| format!("{}{}", base, i))
+            .find(|name| !self.names.contains(name))
+            .expect("some suffix is unused");
+        self.names.insert(name.clone());
+        name
+    }
+
+    fn derive(&mut self, expr: &Expr, var: &str) -> Result<Expr, DeriveError> {
+        let derivative = match &expr.kind {
+            ExprKind::Literal(
+                Literal::Int(_) | Literal::BigInt(_) | Literal::Float(_) | Literal::Imaginary(_),
+            ) => num(0.0),
+            ExprKind::Literal(Literal::Bool(_) | Literal::Str(_)) => {
+                return Err(unsupported("a non-numeric literal", expr));
+            }
+            ExprKind::Literal(Literal::Quantity(..)) | ExprKind::Convert { .. } => {
+                return Err(unsupported("a quantity with units", expr));
+            }
+            ExprKind::Record { .. } => return Err(unsupported("a record", expr)),
+            ExprKind::Field { .. } => return Err(unsupported("a field access", expr)),
+            ExprKind::Match { .. } => return Err(unsupported("a match", expr)),
+            ExprKind::Var(name) => num(if name == var { 1.0 } else { 0.0 }),
+            ExprKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            } => neg(self.derive(operand, var)?),
+            ExprKind::Unary {
+                op: UnaryOp::Not, ..
+            } => return Err(unsupported("a logical operator", expr)),
+            ExprKind::Binary { op, .. } if op.is_short_circuit() => {
+                return Err(unsupported("a logical operator", expr));
+            }
+            ExprKind::Binary {
+                op: BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge,
+                ..
+            } => return Err(unsupported("a comparison", expr)),
+            ExprKind::Binary {
+                op: BinOp::Range, ..
+            } => return Err(unsupported("a range", expr)),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (u, v) = (&**lhs, &**rhs);
+                let du = self.derive(u, var)?;
+                match op {
+                    BinOp::Add => add(du, self.derive(v, var)?),
+                    BinOp::Sub => sub(du, self.derive(v, var)?),
+                    BinOp::Mul => {
+                        let dv = self.derive(v, var)?;
+                        add(mul(du, v.clone()), mul(u.clone(), dv))
+                    }
+                    BinOp::Div => {
+                        let dv = self.derive(v, var)?;
+                        let numerator = sub(mul(du, v.clone()), mul(u.clone(), dv));
+                        div(numerator, pow(v.clone(), num(2.0)))
+                    }
+                    BinOp::Pow => self.power(expr, u, v, du, var)?,
+                    // `u % c` only shifts `u` by multiples of `c`
+                    BinOp::Rem if !v.depends_on(var) => du,
+                    BinOp::Rem => return Err(unsupported("remainder by a variable", expr)),
+                    _ => unreachable!("comparisons and logic are rejected above"),
+                }
+            }
+            ExprKind::Call { name, args } => match args.as_slice() {
+                [u] => {
+                    let du = self.derive(u, var)?;
+                    chain(name, u, du).ok_or_else(|| DeriveError::UnknownDerivative {
+                        name: name.clone(),
+                        span: expr.span,
+                    })?
+                }
+                _ => {
+                    return Err(DeriveError::UnknownDerivative {
+                        name: name.clone(),
+                        span: expr.span,
+                    });
+                }
+            },
+            ExprKind::Let { name, value, body } => self.let_in(name, value, body, var)?,
+            ExprKind::Lambda { .. } => return Err(unsupported("a lambda", expr)),
+            ExprKind::Apply { .. } => return Err(unsupported("a call of a function value", expr)),
+            // Piecewise: differentiate each branch, keeping the condition
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let then_branch = self.derive(then_branch, var)?;
+                let else_branch = self.derive(else_branch, var)?;
+                if then_branch == else_branch {
+                    then_branch
+                } else {
+                    Expr::if_else((**cond).clone(), then_branch, else_branch)
+                }
+            }
+        };
+        Ok(derivative)
+    }
+
+    /// `u ^ v`, with the simpler rules when only one side depends on `var`.
+    fn power(
+        &mut self,
+        expr: &Expr,
+        u: &Expr,
+        v: &Expr,
+        du: Expr,
+        var: &str,
+    ) -> Result<Expr, DeriveError> {
+        let dv = self.derive(v, var)?;
+        let ln_u = || call("ln", u.clone());
+
+        let derivative = if !v.depends_on(var) {
+            // v * u^(v - 1) * u'
+            let exponent = sub(v.clone(), num(1.0));
+            mul(mul(v.clone(), pow(u.clone(), exponent)), du)
+        } else if !u.depends_on(var) {
+            // u^v * ln(u) * v'
+            mul(mul(expr.clone(), ln_u()), dv)
+        } else {
+            // u^v * (v' * ln(u) + v * u' / u)
+            let inner = add(mul(dv, ln_u()), div(mul(v.clone(), du), u.clone()));
+            mul(expr.clone(), inner)
+        };
+        Ok(derivative)
+    }
+
+    /// Chain rule through a binding: `d/dx (let n = v in b)` is
+    /// `let n = v in db/dx + db/dn * dv/dx`.
+    fn let_in(
+        &mut self,
+        name: &str,
+        value: &Expr,
+        body: &Expr,
+        var: &str,
+    ) -> Result<Expr, DeriveError> {
+        // Rename the binding if it would hide `var` or names used in `dv/dx`
+        let (name, body) = if name == var || value.depends_on(name) {
+            let fresh = self.fresh(name);
+            let renamed = body.substitute(name, &Expr::var(fresh.clone()));
+            (fresh, renamed)
+        } else {
+            (name.to_string(), body.clone())
+        };
+
+        let direct = self.derive(&body, var)?;
+        let through = mul(self.derive(&body, &name)?, self.derive(value, var)?);
+        let derivative = add(direct, through);
+
+        if derivative.depends_on(&name) {
+            Ok(Expr::let_in(name, value.clone(), derivative))
+        } else {
+            Ok(derivative)
+        }
+    }
+}
+
+fn unsupported(what: &str, expr: &Expr) -> DeriveError {
+    DeriveError::Unsupported {
+        what: what.to_string(),
+        span: expr.span,
+    }
+}
+
+/// Derivative of the one-argument builtin `name` applied to `u`.
+fn chain(name: &str, u: &Expr, du: Expr) -> Option<Expr> {
+    let u = || u.clone();
+    let derivative = match name {
+        "sin" => mul(call("cos", u()), du),
+        "cos" => neg(mul(call("sin", u()), du)),
+        "tan" => div(du, pow(call("cos", u()), num(2.0))),
+        "exp" => mul(call("exp", u()), du),
+        "ln" => div(du, u()),
+        "log10" => div(du, mul(u(), call("ln", num(10.0)))),
+        "sqrt" => div(du, mul(num(2.0), call("sqrt", u()))),
+        _ => return None,
+    };
+    Some(derivative)
+}
+
+// ------------------------------------------------
+/// Upper bound on rounds of `simplify`.
+const MAX_ROUNDS: usize = 8;
+
+/// Fold what the constructors below cannot see, like the `-1 - 1` left in
+/// the exponent of `x ^ -1`, with the optimizer's constant folding and
+/// identity passes, then rebuild with the constructors until nothing
+/// changes.
+fn simplify(mut expr: Expr) -> Expr {
+    let optimizer = Optimizer::new()
+        .strength_reduction(false)
+        .common_subexpressions(false);
+    for _ in 0..MAX_ROUNDS {
+        let next = rebuild(&optimizer.optimize(&expr));
+        if next == expr {
+            break;
+        }
+        expr = next;
+    }
+    expr
+}
+
+/// `expr` with its arithmetic rebuilt bottom-up by the constructors below.
+fn rebuild(expr: &Expr) -> Expr {
+    match &expr.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => neg(rebuild(operand)),
+        ExprKind::Binary { op, lhs, rhs } => {
+            let (a, b) = (rebuild(lhs), rebuild(rhs));
+            match op {
+                BinOp::Add => add(a, b),
+                BinOp::Sub => sub(a, b),
+                BinOp::Mul => mul(a, b),
+                BinOp::Div => div(a, b),
+                BinOp::Pow => pow(a, b),
+                _ => Expr::binary(*op, a, b),
+            }
+        }
+        ExprKind::Call { name, args } => Expr::call(name, args.iter().map(rebuild).collect()),
+        ExprKind::Let { name, value, body } => {
+            Expr::let_in(name.clone(), rebuild(value), rebuild(body))
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => Expr::if_else((**cond).clone(), rebuild(then_branch), rebuild(else_branch)),
+        _ => expr.clone(),
+    }
+}
+
+// ------------------------------------------------
+// Constructors that simplify as they build, so derivatives stay readable.
+
+/// Whole numbers become int literals, so `2 * x` keeps the type of `x`.
+fn num(n: f64) -> Expr {
+    const EXACT: f64 = (1u64 << 53) as f64;
+    if n.fract() == 0.0 && n.abs() < EXACT {
+        Expr::int(n as i64)
+    } else {
+        Expr::float(n)
+    }
+}
+
+fn as_number(expr: &Expr) -> Option<f64> {
+    match &expr.kind {
+        ExprKind::Literal(literal) => literal.as_f64(),
+        _ => None,
+    }
+}
+
+fn is(expr: &Expr, value: f64) -> bool {
+    as_number(expr) == Some(value)
+}
+
+fn negated(expr: &Expr) -> Option<&Expr> {
+    match &expr.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => Some(operand),
+        _ => None,
+    }
+}
+
+fn call(name: &str, arg: Expr) -> Expr {
+    Expr::call(name, vec![arg])
+}
+
+fn neg(a: Expr) -> Expr {
+    if let Some(n) = as_number(&a) {
+        return num(-n);
+    }
+    match negated(&a) {
+        Some(inner) => inner.clone(),
+        None => Expr::unary(UnaryOp::Neg, a),
+    }
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    match (as_number(&a), as_number(&b)) {
+        (Some(x), Some(y)) => num(x + y),
+        (Some(0.0), _) => b,
+        (_, Some(0.0)) => a,
+        (_, Some(y)) if y < 0.0 => Expr::binary(BinOp::Sub, a, num(-y)),
+        _ => match negated(&b) {
+            Some(inner) => sub(a, inner.clone()),
+            None => Expr::binary(BinOp::Add, a, b),
+        },
+    }
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    match (as_number(&a), as_number(&b)) {
+        (Some(x), Some(y)) => num(x - y),
+        (_, Some(0.0)) => a,
+        (Some(0.0), _) => neg(b),
+        _ if a == b => num(0.0),
+        // `u + v - v` and `v + u - v` are `u`
+        _ => match (&a.kind, negated(&b)) {
+            (
+                ExprKind::Binary {
+                    op: BinOp::Add,
+                    lhs,
+                    rhs,
+                },
+                _,
+            ) if **rhs == b => (**lhs).clone(),
+            (
+                ExprKind::Binary {
+                    op: BinOp::Add,
+                    lhs,
+                    rhs,
+                },
+                _,
+            ) if **lhs == b => (**rhs).clone(),
+            (_, Some(inner)) => add(a.clone(), inner.clone()),
+            _ => Expr::binary(BinOp::Sub, a, b),
+        },
+    }
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    if is(&a, 0.0) || is(&b, 0.0) {
+        return num(0.0);
+    }
+    if is(&a, 1.0) {
+        return b;
+    }
+    if is(&b, 1.0) {
+        return a;
+    }
+    if let (Some(x), Some(y)) = (as_number(&a), as_number(&b)) {
+        return num(x * y);
+    }
+    // Constants go first: `x * 2` becomes `2 * x`
+    if as_number(&b).is_some() {
+        return mul(b, a);
+    }
+    if let Some(inner) = negated(&a) {
+        return neg(mul(inner.clone(), b));
+    }
+    if let Some(inner) = negated(&b) {
+        return neg(mul(a, inner.clone()));
+    }
+    if is(&a, -1.0) {
+        return neg(b);
+    }
+    // `2 * (3 * x)` becomes `6 * x`
+    if let (
+        Some(x),
+        ExprKind::Binary {
+            op: BinOp::Mul,
+            lhs,
+            rhs,
+        },
+    ) = (as_number(&a), &b.kind)
+        && let Some(y) = as_number(lhs)
+    {
+        return mul(num(x * y), (**rhs).clone());
+    }
+    // `u / v * v` and `v * (u / v)` are `u`
+    match (&a.kind, &b.kind) {
+        (
+            ExprKind::Binary {
+                op: BinOp::Div,
+                lhs,
+                rhs,
+            },
+            _,
+        ) if **rhs == b => (**lhs).clone(),
+        (
+            _,
+            ExprKind::Binary {
+                op: BinOp::Div,
+                lhs,
+                rhs,
+            },
+        ) if **rhs == a => (**lhs).clone(),
+        _ => Expr::binary(BinOp::Mul, a, b),
+    }
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    match (as_number(&a), as_number(&b)) {
+        // `/` always yields a float
+        (Some(x), Some(y)) if y != 0.0 => Expr::float(x / y),
+        (_, Some(1.0)) => a,
+        (Some(x), _) if x == 0.0 && !is(&b, 0.0) => num(0.0),
+        (_, None) if a == b => num(1.0),
+        _ => match negated(&a) {
+            Some(inner) => neg(div(inner.clone(), b)),
+            None => Expr::binary(BinOp::Div, a, b),
+        },
+    }
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    match (as_number(&a), as_number(&b)) {
+        (_, Some(0.0)) => num(1.0),
+        (_, Some(1.0)) => a,
+        (Some(x), Some(y)) if x.powf(y).is_finite() => num(x.powf(y)),
+        _ => Expr::binary(BinOp::Pow, a, b),
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{env::Bindings, eval::Interpreter, value::Value};
+
+    fn d(src: &str, var: &str) -> String {
+        let expr: Expr = src.parse().unwrap();
+        expr.derive(var).to_string()
+    }
+
+    #[test]
+    fn test_basic_rules() {
+        assert_eq!(d("42", "x"), "0");
+        assert_eq!(d("x", "x"), "1");
+        assert_eq!(d("y", "x"), "0");
+        assert_eq!(d("3 * x ^ 2 + 2 * x + 1", "x"), "6 * x + 2");
+        assert_eq!(d("-x", "x"), "-1");
+        assert_eq!(d("x * y", "y"), "x");
+    }
+
+    #[test]
+    fn test_product_and_quotient() {
+        assert_eq!(d("x * sin(x)", "x"), "sin(x) + x * cos(x)");
+        assert_eq!(d("1 / x", "x"), "-1 / x ^ 2");
+        assert_eq!(d("x / (x + 1)", "x"), "1 / (x + 1) ^ 2");
+        assert_eq!(d("(1 + x) / x", "x"), "(x - (1 + x)) / x ^ 2");
+        assert_eq!(d("x / x", "x"), "0");
+        assert_eq!(d("ln(x) / x", "x"), "(1 - ln(x)) / x ^ 2");
+    }
+
+    #[test]
+    fn test_powers() {
+        assert_eq!(d("x ^ 3", "x"), "3 * x ^ 2");
+        assert_eq!(d("2 ^ x", "x"), "2 ^ x * ln(2)");
+        assert_eq!(d("x ^ x", "x"), "x ^ x * (ln(x) + 1)");
+        // Constant exponents are folded
+        assert_eq!(d("x ^ -1", "x"), "-x ^ (-2)");
+        assert_eq!(d("x ^ (1 / 2)", "x"), "0.5 * x ^ (-0.5)");
+        assert_eq!(d("x ^ (-1 + 1)", "x"), "0");
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        assert_eq!(d("sin(x)", "x"), "cos(x)");
+        assert_eq!(d("cos(x)", "x"), "-sin(x)");
+        assert_eq!(d("exp(2 * x)", "x"), "2 * exp(2 * x)");
+        assert_eq!(d("ln(x)", "x"), "1 / x");
+        assert_eq!(
+            d("sin(x) * cos(x)", "x"),
+            "cos(x) * cos(x) - sin(x) * sin(x)"
+        );
+        assert_eq!(d("sqrt(x)", "x"), "1 / (2 * sqrt(x))");
+    }
+
+    #[test]
+    fn test_cost_formula_sensitivity() {
+        let cost = "price * qty * (1 + rate) + shipping";
+        assert_eq!(d(cost, "rate"), "price * qty");
+        assert_eq!(d(cost, "qty"), "price * (1 + rate)");
+        assert_eq!(d(cost, "shipping"), "1");
+    }
+
+    #[test]
+    fn test_let_bindings() {
+        assert_eq!(d("let y = x ^ 2 in y * 3", "x"), "6 * x");
+        // The binding survives when the derivative still uses it
+        assert_eq!(d("let y = 2 * x in y ^ 2", "x"), "let y = 2 * x in 4 * y");
+        // A binding that shadows `var` is renamed
+        assert_eq!(d("let x = x * x in x + 1", "x"), "x + x");
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let expr: Expr = "max(x, 1)".parse().unwrap();
+        let err = expr.try_derive("x").unwrap_err();
+        assert_eq!(err.to_string(), "no derivative rule for function 'max'");
+        assert_eq!(err.span().start, 0);
+
+        let expr: Expr = "x % x".parse().unwrap();
+        assert!(matches!(
+            expr.try_derive("x"),
+            Err(DeriveError::Unsupported { .. })
+        ));
+        let expr: Expr = "|y| x".parse().unwrap();
+        assert_eq!(
+            expr.try_derive("x").unwrap_err().to_string(),
+            "cannot differentiate a lambda"
+        );
+        let expr: Expr = "x * 3 m -> cm".parse().unwrap();
+        assert_eq!(
+            expr.try_derive("x").unwrap_err().to_string(),
+            "cannot differentiate a quantity with units"
+        );
+        let expr: Expr = "x * match x { 0 => 1, _ => r.k }".parse().unwrap();
+        assert_eq!(
+            expr.try_derive("x").unwrap_err().to_string(),
+            "cannot differentiate a match"
+        );
+    }
+
+    #[test]
+    fn test_piecewise() {
+        assert_eq!(
+            d("if x > 0 then x ^ 2 else -x", "x"),
+            "if x > 0 then 2 * x else -1"
+        );
+        assert_eq!(d("if x > 0 then 2 * x else x + x", "x"), "2");
+
+        let expr: Expr = "x < 1".parse().unwrap();
+        assert_eq!(
+            expr.try_derive("x").unwrap_err().to_string(),
+            "cannot differentiate a comparison"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no derivative rule for function 'floor'")]
+    fn test_derive_panics_without_rule() {
+        let expr: Expr = "floor(x)".parse().unwrap();
+        expr.derive("x");
+    }
+
+    /// Compare against a central finite difference at a few points.
+    #[test]
+    fn test_matches_numeric_derivative() {
+        let sources = [
+            "x ^ 3 - 2 * x",
+            "sin(x) * exp(x)",
+            "ln(x) / x",
+            "sqrt(x * x + 1)",
+            "x ^ x",
+            "tan(x) + cos(2 * x)",
+            "let y = x * x in y / (1 + y)",
+            "2 ^ x * log10(x)",
+        ];
+        let h = 1e-6;
+        for src in sources {
+            let expr: Expr = src.parse().unwrap();
+            let derivative = expr.derive("x");
+            for x in [0.3, 1.1, 2.5] {
+                let at = |x: f64| -> f64 {
+                    let row: Bindings = [("x".to_string(), Value::Float(x))].into();
+                    match Interpreter::with_bindings(&row).eval(&expr).unwrap() {
+                        Value::Float(n) => n,
+                        other => panic!("expected a number, got {}", other),
+                    }
+                };
+                let numeric = (at(x + h) - at(x - h)) / (2.0 * h);
+                let row: Bindings = [("x".to_string(), Value::Float(x))].into();
+                let Value::Float(symbolic) =
+                    Interpreter::with_bindings(&row).eval(&derivative).unwrap()
+                else {
+                    panic!("expected a number");
+                };
+                assert!(
+                    (numeric - symbolic).abs() < 1e-4 * (1.0 + symbolic.abs()),
+                    "d/dx {} at {}: {} vs {}",
+                    src,
+                    x,
+                    symbolic,
+                    numeric
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{
+    error::{CalcError, EvalError, ParseError, TypeError},
+    lexer::Span,
+    pattern::MatchWarning,
+};
+
+// ------------------------------------------------
+/// An error message tied to a span of source text.
+///
+/// `render` shows the offending line with carets under the span:
+///
+/// ```text
+/// 1 + * 2
+///     ^ expected operand
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Full message, e.g. "parse error: expected operand, found `*`"
+    pub message: String,
+    /// Short text printed after the carets
+    pub label: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, label: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            label: label.into(),
+            span,
+        }
+    }
+
+    /// The source line containing the span, and a caret line under it.
+    pub fn render(&self, src: &str) -> String {
+        let start = self.span.start.min(src.len());
+        let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let line = &src[line_start..line_end];
+
+        // Keep tabs so the carets line up however the terminal expands them
+        let padding: String = src[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = self.span.end.clamp(start, line_end);
+        let width = src[start..end].chars().count().max(1);
+
+        format!("{}\n{}{} {}", line, padding, "^".repeat(width), self.label)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        Diagnostic::new(err.to_string(), err.label(), err.span())
+    }
+}
+
+impl From<&EvalError> for Diagnostic {
+    fn from(err: &EvalError) -> Self {
+        Diagnostic::new(err.to_string(), err.label(), err.span())
+    }
+}
+
+/// Type errors are found before evaluation, so they get their own prefix.
+impl From<&TypeError> for Diagnostic {
+    fn from(err: &TypeError) -> Self {
+        Diagnostic::new(format!("type error: {}", err), err.label(), err.span())
+    }
+}
+
+impl From<&CalcError> for Diagnostic {
+    fn from(err: &CalcError) -> Self {
+        Diagnostic::new(err.to_string(), err.label(), err.span())
+    }
+}
+
+impl From<&MatchWarning> for Diagnostic {
+    fn from(warning: &MatchWarning) -> Self {
+        Diagnostic::new(
+            format!("warning: {}", warning),
+            warning.label(),
+            warning.span,
+        )
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval::eval, parser::parse};
+
+    #[test]
+    fn test_render_parse_error() {
+        let src = "1 + * 2";
+        let err = parse(src).unwrap_err();
+        let diagnostic = Diagnostic::from(&err);
+        assert_eq!(diagnostic.render(src), "1 + * 2\n    ^ expected operand");
+        assert_eq!(diagnostic.to_string(), "expected operand, found `*`");
+    }
+
+    #[test]
+    fn test_render_wide_span() {
+        let src = "2 * foo + 1";
+        let err = eval(src).unwrap_err();
+        let diagnostic = Diagnostic::from(&err);
+        assert_eq!(diagnostic.render(src), "2 * foo + 1\n    ^^^ not defined");
+        assert_eq!(
+            diagnostic.message,
+            "evaluation error: unknown variable 'foo'"
+        );
+    }
+
+    #[test]
+    fn test_render_at_end_of_input() {
+        let src = "(1 + 2";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&err).render(src),
+            "(1 + 2\n      ^ expected `)`"
+        );
+    }
+
+    #[test]
+    fn test_render_picks_the_right_line() {
+        let src = "x = 1\ny = x +\t*";
+        let diagnostic = Diagnostic::new("oops", "here", Span::new(14, 15));
+        assert_eq!(diagnostic.render(src), "y = x +\t*\n       \t^ here");
+    }
+
+    #[test]
+    fn test_render_non_ascii() {
+        let src = "é + §";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&err).render(src),
+            "é + §\n    ^ unexpected character"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::value::Value;
+
+// ------------------------------------------------
+/// Variable values supplied from outside, e.g. one row of inputs to a formula.
+pub type Bindings = HashMap<String, Value>;
+
+/// One lexical scope: its own bindings plus a link to the enclosing scope.
+///
+/// Parents are shared through `Arc`, so a nested scope is cheap to create
+/// and can outlive the evaluation step that made it.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    vars: HashMap<String, Value>,
+    parent: Option<Arc<Environment>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            vars: HashMap::new(),
+            parent: None,
+        }
+    }
+
+    /// A new, empty scope nested inside `parent`.
+    pub fn with_parent(parent: Arc<Environment>) -> Self {
+        Environment {
+            vars: HashMap::new(),
+            parent: Some(parent),
+        }
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Environment>> {
+        self.parent.as_ref()
+    }
+
+    /// Bind `name` in this scope, shadowing any outer binding.
+    pub fn define(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.vars.insert(name.into(), value.into());
+    }
+
+    /// Look `name` up here, then in each enclosing scope in turn.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self.vars.get(name) {
+            Some(value) => Some(value),
+            None => self.parent.as_ref()?.get(name),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Bindings of this scope only, sorted by name.
+    pub fn locals(&self) -> Vec<(&str, &Value)> {
+        let mut vars: Vec<(&str, &Value)> = self
+            .vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+
+    /// Number of scopes from here to the outermost one.
+    pub fn depth(&self) -> usize {
+        1 + self.parent.as_ref().map_or(0, |parent| parent.depth())
+    }
+
+    /// Remove the bindings of this scope; parents are untouched.
+    pub fn clear(&mut self) {
+        self.vars.clear();
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_define_and_get() {
+        let mut env = Environment::new();
+        env.define("x", 1.0);
+        assert_eq!(env.get("x"), Some(&Value::Float(1.0)));
+        assert_eq!(env.get("y"), None);
+    }
+
+    #[test]
+    fn test_parent_chain_lookup() {
+        let mut global = Environment::new();
+        global.define("x", 1.0);
+        global.define("y", 2.0);
+
+        let mut inner = Environment::with_parent(Arc::new(global));
+        inner.define("x", 10.0);
+
+        // Shadowed in the inner scope, visible through the parent otherwise
+        assert_eq!(inner.get("x"), Some(&Value::Float(10.0)));
+        assert_eq!(inner.get("y"), Some(&Value::Float(2.0)));
+        assert_eq!(inner.parent().unwrap().get("x"), Some(&Value::Float(1.0)));
+        assert_eq!(inner.depth(), 2);
+    }
+
+    #[test]
+    fn test_locals_sorted() {
+        let mut env = Environment::new();
+        env.define("b", 2.0);
+        env.define("a", 1.0);
+        let names: Vec<&str> = env.locals().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        env.clear();
+        assert!(env.locals().is_empty());
+    }
+}
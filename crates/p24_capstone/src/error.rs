@@ -0,0 +1,722 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    io,
+    num::ParseFloatError,
+};
+
+use crate::{codec::Kind, lexer::Span, limits::Limit, sheet::CellRef, typecheck::Type};
+
+// ------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A character that cannot start any token
+    UnexpectedChar { ch: char, span: Span },
+
+    /// Digits that do not form a valid number
+    InvalidNumber {
+        text: String,
+        span: Span,
+        source: ParseFloatError,
+    },
+
+    /// String literal without its closing quote
+    UnterminatedString { span: Span },
+
+    /// Backslash followed by a character with no escape meaning
+    InvalidEscape { ch: char, span: Span },
+
+    /// Name after `->` that is not a known unit
+    UnknownUnit { name: String, span: Span },
+
+    /// A token that does not fit the grammar at this point
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+
+    /// Input ended while more was expected
+    UnexpectedEof { expected: String, span: Span },
+
+    /// Record literal giving the same field twice
+    DuplicateField { name: String, span: Span },
+
+    /// Expression nested deeper than the parser's `max_nesting`
+    TooDeep { max: usize, span: Span },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedChar { span, .. }
+            | ParseError::InvalidNumber { span, .. }
+            | ParseError::UnterminatedString { span }
+            | ParseError::InvalidEscape { span, .. }
+            | ParseError::UnknownUnit { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::UnexpectedEof { span, .. }
+            | ParseError::DuplicateField { span, .. }
+            | ParseError::TooDeep { span, .. } => *span,
+        }
+    }
+
+    /// Short description for the caret line of a diagnostic.
+    pub fn label(&self) -> String {
+        match self {
+            ParseError::UnexpectedChar { .. } => "unexpected character".to_string(),
+            ParseError::InvalidNumber { .. } => "invalid number".to_string(),
+            ParseError::UnterminatedString { .. } => "missing closing `\"`".to_string(),
+            ParseError::InvalidEscape { .. } => "unknown escape".to_string(),
+            ParseError::UnknownUnit { .. } => "not a unit".to_string(),
+            ParseError::UnexpectedToken { expected, .. }
+            | ParseError::UnexpectedEof { expected, .. } => format!("expected {}", expected),
+            ParseError::DuplicateField { .. } => "given again here".to_string(),
+            ParseError::TooDeep { .. } => "nested too deeply".to_string(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { ch, .. } => {
+                write!(f, "unexpected character '{}'", ch)
+            }
+            ParseError::InvalidNumber { text, .. } => {
+                write!(f, "invalid number '{}'", text)
+            }
+            ParseError::UnterminatedString { .. } => write!(f, "unterminated string"),
+            ParseError::InvalidEscape { ch, .. } => {
+                write!(f, "invalid escape sequence '\\{}'", ch)
+            }
+            ParseError::UnknownUnit { name, .. } => write!(f, "unknown unit '{}'", name),
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            ParseError::UnexpectedEof { expected, .. } => {
+                write!(f, "expected {}, found end of input", expected)
+            }
+            ParseError::DuplicateField { name, .. } => {
+                write!(f, "field '{}' is given more than once", name)
+            }
+            ParseError::TooDeep { max, .. } => {
+                write!(f, "expression nested more than {} deep", max)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::InvalidNumber { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// ------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// Identifier not bound to any value
+    UnknownVariable { name: String, span: Span },
+
+    /// Call to a function that does not exist
+    UnknownFunction { name: String, span: Span },
+
+    /// Function called with the wrong number of arguments
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+
+    /// Division or remainder with a zero divisor
+    DivisionByZero { span: Span },
+
+    /// Integer arithmetic or conversion whose result does not fit in an `i64`
+    IntegerOverflow { span: Span },
+
+    /// Quantities whose dimensions do not fit the operation, like `3 m + 2 s`
+    DimensionMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+
+    /// Operand or argument of the wrong type
+    TypeMismatch {
+        expected: String,
+        found: String,
+        span: Span,
+    },
+
+    /// Evaluation ran past one of its `EvalLimits`
+    LimitExceeded { limit: Limit, span: Span },
+
+    /// Argument of the right type but outside what the function accepts
+    InvalidArgument {
+        name: String,
+        reason: String,
+        span: Span,
+    },
+
+    /// Sequence without an end passed to a function that reads all of it
+    InfiniteSequence { name: String, span: Span },
+
+    /// Field access on a record without that field
+    NoField { name: String, span: Span },
+
+    /// `match` whose arms all failed to match the value, which is printed
+    NoMatch { value: String, span: Span },
+}
+
+impl EvalError {
+    pub fn span(&self) -> Span {
+        match self {
+            EvalError::UnknownVariable { span, .. }
+            | EvalError::UnknownFunction { span, .. }
+            | EvalError::ArityMismatch { span, .. }
+            | EvalError::DivisionByZero { span }
+            | EvalError::IntegerOverflow { span }
+            | EvalError::DimensionMismatch { span, .. }
+            | EvalError::TypeMismatch { span, .. }
+            | EvalError::LimitExceeded { span, .. }
+            | EvalError::InvalidArgument { span, .. }
+            | EvalError::InfiniteSequence { span, .. }
+            | EvalError::NoField { span, .. }
+            | EvalError::NoMatch { span, .. } => *span,
+        }
+    }
+
+    /// Short description for the caret line of a diagnostic.
+    pub fn label(&self) -> String {
+        match self {
+            EvalError::UnknownVariable { .. } => "not defined".to_string(),
+            EvalError::UnknownFunction { .. } => "no such function".to_string(),
+            EvalError::ArityMismatch { expected, .. } => {
+                format!("expected {} argument(s)", expected)
+            }
+            EvalError::DivisionByZero { .. } => "divisor is zero".to_string(),
+            EvalError::IntegerOverflow { .. } => "does not fit in 64 bits".to_string(),
+            EvalError::DimensionMismatch { found, .. } => format!("has dimension {}", found),
+            EvalError::TypeMismatch { expected, .. } => format!("expected {}", expected),
+            EvalError::LimitExceeded { .. } => "stopped here".to_string(),
+            EvalError::InvalidArgument { reason, .. } => reason.clone(),
+            EvalError::InfiniteSequence { .. } => "this never ends".to_string(),
+            EvalError::NoField { .. } => "no such field".to_string(),
+            EvalError::NoMatch { .. } => "no arm matches".to_string(),
+        }
+    }
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::UnknownVariable { name, .. } => {
+                write!(f, "unknown variable '{}'", name)
+            }
+            EvalError::UnknownFunction { name, .. } => {
+                write!(f, "unknown function '{}'", name)
+            }
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                write!(
+                    f,
+                    "function '{}' expects {} argument(s), got {}",
+                    name, expected, found
+                )
+            }
+            EvalError::DivisionByZero { .. } => write!(f, "division by zero"),
+            EvalError::IntegerOverflow { .. } => write!(f, "integer overflow"),
+            EvalError::DimensionMismatch {
+                expected, found, ..
+            } => {
+                write!(
+                    f,
+                    "dimension mismatch: expected {}, found {}",
+                    expected, found
+                )
+            }
+            EvalError::TypeMismatch {
+                expected, found, ..
+            } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, found)
+            }
+            EvalError::LimitExceeded { limit, .. } => {
+                write!(f, "evaluation limit exceeded: {}", limit)
+            }
+            EvalError::InvalidArgument { name, reason, .. } => {
+                write!(f, "invalid argument to '{}': {}", name, reason)
+            }
+            EvalError::InfiniteSequence { name, .. } => write!(
+                f,
+                "'{}' needs a sequence with an end; limit it with take()",
+                name
+            ),
+            EvalError::NoField { name, .. } => write!(f, "record has no field '{}'", name),
+            EvalError::NoMatch { value, .. } => write!(f, "no match arm for {}", value),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+// ------------------------------------------------
+/// An expression that has no symbolic derivative rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeriveError {
+    /// Call to a function without a known derivative
+    UnknownDerivative { name: String, span: Span },
+
+    /// Construct the differentiator does not handle
+    Unsupported { what: String, span: Span },
+}
+
+impl DeriveError {
+    pub fn span(&self) -> Span {
+        match self {
+            DeriveError::UnknownDerivative { span, .. } | DeriveError::Unsupported { span, .. } => {
+                *span
+            }
+        }
+    }
+}
+
+impl Display for DeriveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeriveError::UnknownDerivative { name, .. } => {
+                write!(f, "no derivative rule for function '{}'", name)
+            }
+            DeriveError::Unsupported { what, .. } => write!(f, "cannot differentiate {}", what),
+        }
+    }
+}
+
+impl Error for DeriveError {}
+
+// ------------------------------------------------
+/// A problem found by the type checker before evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// A value whose type differs from the one its context needs
+    Mismatch {
+        expected: Type,
+        found: Type,
+        span: Span,
+    },
+
+    /// Operator or builtin applied to types it has no meaning for
+    NoOperator {
+        op: &'static str,
+        operands: Vec<Type>,
+        span: Span,
+    },
+
+    /// A type that would have to contain itself, as in `|f| f(f)`
+    InfiniteType {
+        span: Span,
+    },
+
+    UnknownVariable {
+        name: String,
+        span: Span,
+    },
+
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+
+    /// `.name` on a record type without that field
+    NoField {
+        name: String,
+        record: Type,
+        span: Span,
+    },
+}
+
+impl TypeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::Mismatch { span, .. }
+            | TypeError::NoOperator { span, .. }
+            | TypeError::InfiniteType { span }
+            | TypeError::UnknownVariable { span, .. }
+            | TypeError::UnknownFunction { span, .. }
+            | TypeError::ArityMismatch { span, .. }
+            | TypeError::NoField { span, .. } => *span,
+        }
+    }
+
+    /// Short description for the caret line of a diagnostic.
+    pub fn label(&self) -> String {
+        match self {
+            TypeError::Mismatch { expected, .. } => format!("expected {}", expected),
+            TypeError::NoOperator { .. } => "not defined for these types".to_string(),
+            TypeError::InfiniteType { .. } => "would contain itself".to_string(),
+            TypeError::UnknownVariable { .. } => "not defined".to_string(),
+            TypeError::UnknownFunction { .. } => "no such function".to_string(),
+            TypeError::ArityMismatch { expected, .. } => {
+                format!("expected {} argument(s)", expected)
+            }
+            TypeError::NoField { .. } => "no such field".to_string(),
+        }
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeError::Mismatch {
+                expected, found, ..
+            } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, found)
+            }
+            TypeError::NoOperator { op, operands, .. } => {
+                let operands: Vec<String> = operands.iter().map(Type::to_string).collect();
+                write!(f, "`{}` does not apply to {}", op, operands.join(" and "))
+            }
+            TypeError::InfiniteType { .. } => write!(f, "recursive type"),
+            TypeError::UnknownVariable { name, .. } => {
+                write!(f, "unknown variable '{}'", name)
+            }
+            TypeError::UnknownFunction { name, .. } => {
+                write!(f, "unknown function '{}'", name)
+            }
+            TypeError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                write!(
+                    f,
+                    "function '{}' expects {} argument(s), got {}",
+                    name, expected, found
+                )
+            }
+            TypeError::NoField { name, record, .. } => {
+                write!(f, "{} has no field '{}'", record, name)
+            }
+        }
+    }
+}
+
+impl Error for TypeError {}
+
+// ------------------------------------------------
+/// A rejected spreadsheet edit, or the reason a cell has no value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SheetError {
+    /// Formula text that does not parse
+    Parse {
+        cell: CellRef,
+        errors: Vec<ParseError>,
+    },
+
+    /// Cell or reference outside the grid
+    OutOfRange { cell: CellRef },
+
+    /// Formula that would depend on itself; `path` starts and ends at the
+    /// edited cell and each step refers to the next
+    Cycle { path: Vec<CellRef> },
+
+    /// Formula that failed to evaluate
+    Eval { cell: CellRef, source: EvalError },
+
+    /// Formula reading a cell that has no value because of an error
+    BadInput { cell: CellRef, input: CellRef },
+}
+
+impl Display for SheetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SheetError::Parse { cell, errors } => {
+                write!(f, "cannot parse the formula in {}", cell)?;
+                match errors.as_slice() {
+                    [] => Ok(()),
+                    [err] => write!(f, ": {}", err),
+                    [err, rest @ ..] => write!(f, ": {} (and {} more)", err, rest.len()),
+                }
+            }
+            SheetError::OutOfRange { cell } => write!(f, "cell {} is outside the sheet", cell),
+            SheetError::Cycle { path } => {
+                write!(f, "reference cycle: ")?;
+                for (i, cell) in path.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}", cell)?;
+                }
+                Ok(())
+            }
+            SheetError::Eval { cell, source } => write!(f, "{}: {}", cell, source),
+            SheetError::BadInput { cell, input } => {
+                write!(f, "{} depends on {}, which has an error", cell, input)
+            }
+        }
+    }
+}
+
+impl Error for SheetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SheetError::Parse { errors, .. } => errors.first().map(|e| e as &(dyn Error + 'static)),
+            SheetError::Eval { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// ------------------------------------------------
+/// A saved expression that cannot be loaded.
+#[derive(Debug)]
+pub enum CodecError {
+    /// Reading failed for a reason other than running out of input
+    Io(io::Error),
+
+    /// Input ends before the header or payload is complete
+    Truncated,
+
+    /// Input does not start with the format's magic bytes
+    BadMagic { found: [u8; 4] },
+
+    /// Written by a different version of the format
+    UnsupportedVersion { found: u16, supported: u16 },
+
+    /// Holds a different kind of data than was asked for
+    WrongKind { expected: Kind, found: Kind },
+
+    /// Payload does not match the checksum in the header
+    ChecksumMismatch { expected: u32, found: u32 },
+
+    /// Payload that passed the checksum but does not decode; `offset`
+    /// counts from the start of the file
+    Corrupt { offset: usize, reason: String },
+
+    /// Bytecode that decodes but could misbehave when run
+    InvalidCode { at: usize, reason: String },
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Io(err) => write!(f, "cannot read saved expression: {}", err),
+            CodecError::Truncated => write!(f, "saved expression is truncated"),
+            CodecError::BadMagic { found } => {
+                write!(f, "not a saved expression (starts with {:02x?})", found)
+            }
+            CodecError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "saved with format version {}, but only version {} can be read",
+                found, supported
+            ),
+            CodecError::WrongKind { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            CodecError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: header says {:08x}, payload has {:08x}",
+                expected, found
+            ),
+            CodecError::Corrupt { offset, reason } => {
+                write!(f, "corrupt saved expression at byte {}: {}", offset, reason)
+            }
+            CodecError::InvalidCode { at, reason } => {
+                write!(f, "invalid bytecode at instruction {}: {}", at, reason)
+            }
+        }
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CodecError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => CodecError::Truncated,
+            _ => CodecError::Io(err),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// Any failure while turning source text into a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcError {
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl CalcError {
+    pub fn span(&self) -> Span {
+        match self {
+            CalcError::Parse(e) => e.span(),
+            CalcError::Eval(e) => e.span(),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            CalcError::Parse(e) => e.label(),
+            CalcError::Eval(e) => e.label(),
+        }
+    }
+}
+
+impl Display for CalcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalcError::Parse(e) => write!(f, "parse error: {}", e),
+            CalcError::Eval(e) => write!(f, "evaluation error: {}", e),
+        }
+    }
+}
+
+impl Error for CalcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalcError::Parse(e) => Some(e),
+            CalcError::Eval(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for CalcError {
+    fn from(err: ParseError) -> Self {
+        CalcError::Parse(err)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(err: EvalError) -> Self {
+        CalcError::Eval(err)
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_display() {
+        let err = ParseError::UnexpectedToken {
+            expected: "operand".to_string(),
+            found: "`*`".to_string(),
+            span: Span::new(4, 5),
+        };
+        assert_eq!(err.to_string(), "expected operand, found `*`");
+        assert_eq!(err.span(), Span::new(4, 5));
+
+        let err = ParseError::UnexpectedEof {
+            expected: "`)`".to_string(),
+            span: Span::new(3, 3),
+        };
+        assert_eq!(err.to_string(), "expected `)`, found end of input");
+    }
+
+    #[test]
+    fn test_eval_error_display() {
+        let err = EvalError::ArityMismatch {
+            name: "sqrt".to_string(),
+            expected: 1,
+            found: 2,
+            span: Span::new(0, 10),
+        };
+        assert_eq!(
+            err.to_string(),
+            "function 'sqrt' expects 1 argument(s), got 2"
+        );
+
+        let err = EvalError::LimitExceeded {
+            limit: Limit::Depth(50),
+            span: Span::new(0, 6),
+        };
+        assert_eq!(
+            err.to_string(),
+            "evaluation limit exceeded: evaluation nested more than 50 deep"
+        );
+    }
+
+    #[test]
+    fn test_calc_error_source() {
+        let err: CalcError = EvalError::DivisionByZero {
+            span: Span::new(2, 3),
+        }
+        .into();
+        assert_eq!(err.to_string(), "evaluation error: division by zero");
+        assert_eq!(err.span(), Span::new(2, 3));
+
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "division by zero");
+        assert!(source.source().is_none());
+    }
+
+    #[test]
+    fn test_walk_error_chain() {
+        let source = "1e".parse::<f64>().unwrap_err();
+        let err: CalcError = ParseError::InvalidNumber {
+            text: "1e".to_string(),
+            span: Span::new(0, 2),
+            source,
+        }
+        .into();
+
+        let mut current: Option<&(dyn Error + 'static)> = Some(&err);
+        let mut messages = Vec::new();
+        while let Some(err) = current {
+            messages.push(err.to_string());
+            current = err.source();
+        }
+
+        assert_eq!(
+            messages,
+            vec![
+                "parse error: invalid number '1e'",
+                "invalid number '1e'",
+                "invalid float literal",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let err = ParseError::UnexpectedEof {
+            expected: "operand".to_string(),
+            span: Span::new(3, 3),
+        };
+        assert_eq!(err.label(), "expected operand");
+
+        let err: CalcError = EvalError::UnknownVariable {
+            name: "x".to_string(),
+            span: Span::new(0, 1),
+        }
+        .into();
+        assert_eq!(err.label(), "not defined");
+    }
+}
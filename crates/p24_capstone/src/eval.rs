@@ -0,0 +1,2532 @@
+use std::{cmp::Ordering, collections::BTreeMap, sync::Arc};
+
+use p14_operator_arithmetic::Vec2;
+use p23_extend_sum::Money;
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Pattern, Stmt, UnaryOp},
+    complex::Complex,
+    env::{Bindings, Environment},
+    error::{CalcError, EvalError},
+    exact::{BigInt, MAX_POW_BITS, NumericMode, Rational},
+    lexer::Span,
+    limits::{EvalLimits, Meter},
+    memo::{Keyer, MemoCache, MemoKey},
+    parser::Parser,
+    registry::FunctionRegistry,
+    seq::{self, Items, Seq},
+    trace::Observer,
+    units::{Dim, Quantity, Unit},
+    value::{Lambda, Value},
+};
+
+// ------------------------------------------------
+/// Named constants available in every expression.
+const CONSTANTS: &[(&str, f64)] = &[("pi", std::f64::consts::PI), ("e", std::f64::consts::E)];
+
+/// Local scopes active during evaluation; `None` at the top level.
+type Scope<'a> = Option<&'a Arc<Environment>>;
+
+/// Tree-walking interpreter holding the global variables and functions.
+///
+/// Lookup goes innermost `let` scope first, then globals, then the
+/// builtin constants. Globals are looked up when used rather than captured,
+/// so a function may call itself or one defined after it.
+///
+/// Each call to `eval`, `exec`, `call` or `call_function` is one
+/// evaluation as far as the `EvalLimits` are concerned.
+#[derive(Debug, Clone, Default)]
+pub struct Interpreter {
+    globals: Environment,
+    limits: EvalLimits,
+    registry: Arc<FunctionRegistry>,
+    mode: NumericMode,
+    observer: Option<Arc<dyn Observer>>,
+    memo: Option<Arc<MemoCache>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            globals: Environment::new(),
+            limits: EvalLimits::default(),
+            registry: Arc::default(),
+            mode: NumericMode::default(),
+            observer: None,
+            memo: None,
+        }
+    }
+
+    /// Evaluate number literals in `mode`; variables keep the values they
+    /// already have.
+    pub fn with_mode(mut self, mode: NumericMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> NumericMode {
+        self.mode
+    }
+
+    /// Report every node evaluated to `observer`, e.g. a `Tracer`.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Remember the results of user function calls and of `eval` in
+    /// `memo`, which clones of this interpreter go on sharing.
+    pub fn with_memo(mut self, memo: Arc<MemoCache>) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    pub fn memo(&self) -> Option<&Arc<MemoCache>> {
+        self.memo.as_ref()
+    }
+
+    /// Enforce `limits` on every evaluation from now on.
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> EvalLimits {
+        self.limits
+    }
+
+    /// Make the host functions in `registry` callable by name.
+    pub fn with_registry(mut self, registry: impl Into<Arc<FunctionRegistry>>) -> Self {
+        self.registry = registry.into();
+        self
+    }
+
+    pub fn registry(&self) -> &Arc<FunctionRegistry> {
+        &self.registry
+    }
+
+    /// An interpreter whose globals start out as `bindings`.
+    pub fn with_bindings(bindings: &Bindings) -> Self {
+        let mut interp = Interpreter::new();
+        for (name, value) in bindings {
+            interp.set_var(name.clone(), value.clone());
+        }
+        interp
+    }
+
+    pub fn globals(&self) -> &Environment {
+        &self.globals
+    }
+
+    pub fn set_var(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.globals.define(name, value);
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// All global variables, sorted by name.
+    pub fn vars(&self) -> Vec<(&str, &Value)> {
+        self.globals.locals()
+    }
+
+    pub fn clear(&mut self) {
+        self.globals.clear();
+    }
+
+    /// Parse and evaluate an expression in one step.
+    pub fn eval_str(&self, src: &str) -> Result<Value, CalcError> {
+        let expr = Parser::new(src)
+            .with_max_nesting(self.limits.nesting())
+            .parse()?;
+        Ok(self.eval(&expr)?)
+    }
+
+    /// Parse and execute a statement, which may assign a global.
+    pub fn exec_str(&mut self, src: &str) -> Result<Value, CalcError> {
+        let stmt = Parser::new(src)
+            .with_max_nesting(self.limits.nesting())
+            .parse_stmt()?;
+        Ok(self.exec(&stmt)?)
+    }
+
+    /// Execute a statement; assignments and definitions yield the new value.
+    pub fn exec(&mut self, stmt: &Stmt) -> Result<Value, EvalError> {
+        match stmt {
+            Stmt::Assign { name, value } => {
+                let value = self.eval(value)?;
+                self.globals.define(name.clone(), value.clone());
+                Ok(value)
+            }
+            Stmt::FnDef { name, params, body } => {
+                let lambda = Lambda {
+                    name: Some(name.clone()),
+                    params: params.clone(),
+                    body: body.clone(),
+                    captured: None,
+                };
+                let value = Value::Function(Arc::new(lambda));
+                self.globals.define(name.clone(), value.clone());
+                Ok(value)
+            }
+            Stmt::Expr(expr) => self.eval(expr),
+        }
+    }
+
+    pub fn eval(&self, expr: &Expr) -> Result<Value, EvalError> {
+        let meter = Meter::new(self.limits);
+        let key = self.memo_key(|keyer| keyer.expr_key(expr));
+        self.memoized(key, &meter, || self.eval_in(expr, None, &meter))
+    }
+
+    /// Call the global function `name`, e.g. one defined with `fn`.
+    pub fn call_function(&self, name: &str, args: Vec<Value>) -> Result<Value, EvalError> {
+        let span = Span::default();
+        match self.globals.get(name) {
+            Some(value) => self.call(value.as_function(span)?, args, span),
+            None => Err(EvalError::UnknownFunction {
+                name: name.to_string(),
+                span,
+            }),
+        }
+    }
+
+    /// Apply a user function: parameters are bound in a fresh scope nested
+    /// inside the one the function captured.
+    pub fn call(&self, lambda: &Lambda, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+        self.call_in(lambda, args, span, &Meter::new(self.limits))
+    }
+
+    /// `call` as part of an evaluation already being metered.
+    pub(crate) fn call_in(
+        &self,
+        lambda: &Lambda,
+        args: Vec<Value>,
+        span: Span,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let name = lambda.name.as_deref().unwrap_or("<lambda>");
+        expect_arity(name, &args, lambda.params.len(), span)?;
+        let key = self.memo_key(|keyer| keyer.call_key(lambda, &args));
+        self.memoized(key, meter, || {
+            let _depth = meter.enter(span)?;
+            let mut env = match &lambda.captured {
+                Some(parent) => Environment::with_parent(Arc::clone(parent)),
+                None => Environment::new(),
+            };
+            for (param, arg) in lambda.params.iter().zip(args) {
+                env.define(param.clone(), arg);
+            }
+            self.eval_in(&lambda.body, Some(&Arc::new(env)), meter)
+        })
+    }
+
+    /// A key for the memo cache, if there is a cache.
+    fn memo_key(&self, key: impl FnOnce(&mut Keyer<'_>) -> Option<MemoKey>) -> Option<MemoKey> {
+        self.memo.as_ref()?;
+        key(&mut Keyer::new(&self.globals, self.mode))
+    }
+
+    /// The cached result for `key`, or `eval`'s, stored unless it failed
+    /// or called a host function.
+    fn memoized(
+        &self,
+        key: Option<MemoKey>,
+        meter: &Meter,
+        eval: impl FnOnce() -> Result<Value, EvalError>,
+    ) -> Result<Value, EvalError> {
+        let (Some(memo), Some(key)) = (&self.memo, key) else {
+            return eval();
+        };
+        if let Some(value) = memo.get(&key) {
+            return Ok(value);
+        }
+        let host_calls = meter.host_calls();
+        let value = eval()?;
+        if meter.host_calls() == host_calls {
+            memo.insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    fn eval_in(&self, expr: &Expr, scope: Scope<'_>, meter: &Meter) -> Result<Value, EvalError> {
+        let _depth = meter.enter(expr.span)?;
+        let Some(observer) = &self.observer else {
+            return self.eval_node(expr, scope, meter);
+        };
+        observer.enter(expr);
+        let result = self.eval_node(expr, scope, meter);
+        observer.exit(expr, result.as_ref());
+        result
+    }
+
+    /// Evaluate one node. The bulkier cases live in their own methods, as
+    /// every level of nesting pays for this function's stack frame.
+    fn eval_node(&self, expr: &Expr, scope: Scope<'_>, meter: &Meter) -> Result<Value, EvalError> {
+        meter.step(expr.span)?;
+        match &expr.kind {
+            ExprKind::Literal(Literal::BigInt(n)) => self.mode.big_int(n, expr.span),
+            ExprKind::Literal(literal) => Ok(self.mode.number(Value::from(literal))),
+            ExprKind::Var(name) => self.lookup(name, expr.span, scope),
+            ExprKind::Unary { op, operand } => {
+                let value = self.eval_in(operand, scope, meter)?;
+                apply_unary(*op, &value, operand.span)
+            }
+            ExprKind::Binary { op, lhs, rhs } if op.is_short_circuit() => {
+                let l = self.eval_in(lhs, scope, meter)?.as_bool(lhs.span)?;
+                // `false && _` and `true || _` never look at the right side
+                if l == (*op == BinOp::Or) {
+                    return Ok(Value::Bool(l));
+                }
+                Ok(Value::Bool(
+                    self.eval_in(rhs, scope, meter)?.as_bool(rhs.span)?,
+                ))
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                // Both operands are evaluated before either is type-checked
+                let l = self.eval_in(lhs, scope, meter)?;
+                let r = self.eval_in(rhs, scope, meter)?;
+                let value = apply_binary(*op, &l, &r, [lhs.span, rhs.span, expr.span])?;
+                meter.checked(value, expr.span)
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval_in(cond, scope, meter)?.as_bool(cond.span)? {
+                    self.eval_in(then_branch, scope, meter)
+                } else {
+                    self.eval_in(else_branch, scope, meter)
+                }
+            }
+            ExprKind::Convert { expr, unit } => {
+                convert(&self.eval_in(expr, scope, meter)?, unit, expr.span)
+            }
+            ExprKind::Call { name, args } => self.eval_call(name, args, expr.span, scope, meter),
+            ExprKind::Apply { callee, args } => {
+                self.eval_apply(callee, args, expr.span, scope, meter)
+            }
+            ExprKind::Let { name, value, body } => self.eval_let(name, value, body, scope, meter),
+            ExprKind::Lambda { params, body } => {
+                let lambda = Lambda {
+                    name: None,
+                    params: params.clone(),
+                    body: (**body).clone(),
+                    captured: scope.cloned(),
+                };
+                Ok(Value::Function(Arc::new(lambda)))
+            }
+            ExprKind::Record { fields } => self.eval_record(fields, scope, meter),
+            ExprKind::Field { expr: record, name } => {
+                let record_value = self.eval_in(record, scope, meter)?;
+                field(&record_value, name, record.span, expr.span)
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.eval_match(scrutinee, arms, expr.span, scope, meter)
+            }
+        }
+    }
+
+    fn eval_call(
+        &self,
+        name: &str,
+        args: &[Expr],
+        span: Span,
+        scope: Scope<'_>,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let args = args
+            .iter()
+            .map(|arg| self.eval_in(arg, scope, meter))
+            .collect::<Result<Vec<Value>, EvalError>>()?;
+
+        let value = match self.resolve(name, scope) {
+            Some(callee) => self.call_in(callee.as_function(span)?, args, span, meter)?,
+            None => self.call_builtin(name, args, span, meter)?,
+        };
+        meter.checked(value, span)
+    }
+
+    fn eval_apply(
+        &self,
+        callee: &Expr,
+        args: &[Expr],
+        span: Span,
+        scope: Scope<'_>,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let function = self.eval_in(callee, scope, meter)?;
+        let args = args
+            .iter()
+            .map(|arg| self.eval_in(arg, scope, meter))
+            .collect::<Result<Vec<Value>, EvalError>>()?;
+        let value = self.call_in(function.as_function(span)?, args, span, meter)?;
+        meter.checked(value, span)
+    }
+
+    fn eval_let(
+        &self,
+        name: &str,
+        value: &Expr,
+        body: &Expr,
+        scope: Scope<'_>,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let value = self.eval_in(value, scope, meter)?;
+        let mut env = match scope {
+            Some(parent) => Environment::with_parent(Arc::clone(parent)),
+            None => Environment::new(),
+        };
+        env.define(name.to_string(), value);
+        self.eval_in(body, Some(&Arc::new(env)), meter)
+    }
+
+    fn eval_record(
+        &self,
+        fields: &[(String, Expr)],
+        scope: Scope<'_>,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let fields = fields
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), self.eval_in(value, scope, meter)?)))
+            .collect::<Result<BTreeMap<String, Value>, EvalError>>()?;
+        Ok(Value::Record(Arc::new(fields)))
+    }
+
+    fn eval_match(
+        &self,
+        scrutinee: &Expr,
+        arms: &[MatchArm],
+        span: Span,
+        scope: Scope<'_>,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        let value = self.eval_in(scrutinee, scope, meter)?;
+        match arms
+            .iter()
+            .find(|arm| matches_pattern(&arm.pattern, &value))
+        {
+            Some(arm) => self.eval_in(&arm.body, scope, meter),
+            None => Err(EvalError::NoMatch {
+                value: value.to_string(),
+                span,
+            }),
+        }
+    }
+
+    /// Find a variable in the local scopes or globals.
+    fn resolve<'a>(&'a self, name: &str, scope: Scope<'a>) -> Option<&'a Value> {
+        scope
+            .and_then(|env| env.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn lookup(&self, name: &str, span: Span, scope: Scope<'_>) -> Result<Value, EvalError> {
+        if let Some(value) = self.resolve(name, scope) {
+            return Ok(value.clone());
+        }
+        CONSTANTS
+            .iter()
+            .find(|(constant, _)| *constant == name)
+            .map(|&(_, value)| Value::Float(value))
+            .ok_or_else(|| EvalError::UnknownVariable {
+                name: name.to_string(),
+                span,
+            })
+    }
+
+    /// List and higher-order builtins first, then the numeric table, then
+    /// the registry.
+    pub(crate) fn call_builtin(
+        &self,
+        name: &str,
+        args: Vec<Value>,
+        span: Span,
+        meter: &Meter,
+    ) -> Result<Value, EvalError> {
+        match name {
+            _ if self.mode == NumericMode::Exact
+                && (EXACT_BUILTINS.contains(&name) || INEXACT_BUILTINS.contains(&name))
+                && args.iter().all(is_exact) =>
+            {
+                exact_builtin(name, &args, span)
+            }
+            "list" => Ok(Value::list(args)),
+            "len" => {
+                expect_arity(name, &args, 1, span)?;
+                let known = match &args[0] {
+                    Value::Str(s) => Some(s.chars().count()),
+                    Value::List(items) => Some(items.len()),
+                    Value::Seq(seq) => seq.known_len(),
+                    _ => None,
+                };
+                let len = match known {
+                    Some(len) => len,
+                    None => self
+                        .stream(name, &args[0], span, meter)?
+                        .try_fold(0_usize, |len, item| item.map(|_| len + 1))?,
+                };
+                Ok(self.mode.number(Value::Int(len as i64)))
+            }
+            "range" => {
+                // `range(end)`, `range(start, end)` or `range(start, end, step)`
+                if !(1..=3).contains(&args.len()) {
+                    expect_arity(name, &args, 3, span)?;
+                }
+                let ints = args
+                    .iter()
+                    .map(|arg| arg.as_int(span))
+                    .collect::<Result<Vec<i64>, EvalError>>()?;
+                let (start, end, step) = match ints[..] {
+                    [end] => (0, end, 1),
+                    [start, end] => (start, end, 1),
+                    [start, end, step] => (start, end, step),
+                    _ => unreachable!("arity is checked above"),
+                };
+                if step == 0 {
+                    return Err(EvalError::InvalidArgument {
+                        name: name.to_string(),
+                        reason: "step must not be zero".to_string(),
+                        span,
+                    });
+                }
+                Ok(Value::seq(Seq::Range { start, end, step }))
+            }
+            "fib" => {
+                expect_arity(name, &args, 0, span)?;
+                Ok(Value::seq(Seq::Fib))
+            }
+            "take" => {
+                expect_arity(name, &args, 2, span)?;
+                let n = usize::try_from(args[1].as_int(span)?).map_err(|_| {
+                    EvalError::InvalidArgument {
+                        name: name.to_string(),
+                        reason: "count must not be negative".to_string(),
+                        span,
+                    }
+                })?;
+                match &args[0] {
+                    Value::Seq(seq) => Ok(Value::seq(Seq::Take(Arc::clone(seq), n))),
+                    other => {
+                        let items = other.as_list(span)?;
+                        Ok(Value::list(items[..n.min(items.len())].to_vec()))
+                    }
+                }
+            }
+            "collect" => {
+                expect_arity(name, &args, 1, span)?;
+                Ok(Value::List(self.items(name, &args[0], span, meter)?))
+            }
+            "money" => {
+                expect_arity(name, &args, 1, span)?;
+                let cents = float_to_int((args[0].as_number(span)? * 100.0).round(), span)?;
+                Ok(Value::Money(Money::new(cents)))
+            }
+            "int" => {
+                expect_arity(name, &args, 1, span)?;
+                match &args[0] {
+                    Value::Int(n) => Ok(Value::Int(*n)),
+                    Value::Rational(r) => Ok(Value::from(Rational::integer(r.trunc()))),
+                    other => {
+                        let n = float_to_int(other.as_number(span)?, span)?;
+                        Ok(self.mode.number(Value::Int(n)))
+                    }
+                }
+            }
+            "float" => {
+                expect_arity(name, &args, 1, span)?;
+                Ok(Value::Float(args[0].as_number(span)?))
+            }
+            "vec" => {
+                expect_arity(name, &args, 2, span)?;
+                let (x, y) = (args[0].as_number(span)?, args[1].as_number(span)?);
+                Ok(Value::Vector(Vec2::new(x, y)))
+            }
+            "dot" => {
+                expect_arity(name, &args, 2, span)?;
+                let (a, b) = (args[0].as_vector(span)?, args[1].as_vector(span)?);
+                Ok(Value::Float(a.dot(b)))
+            }
+            "length" => {
+                expect_arity(name, &args, 1, span)?;
+                Ok(Value::Float(args[0].as_vector(span)?.length()))
+            }
+            "normalize" => {
+                expect_arity(name, &args, 1, span)?;
+                Ok(Value::Vector(args[0].as_vector(span)?.normalize()))
+            }
+            "sqrt" => {
+                expect_arity(name, &args, 1, span)?;
+                // The square root of a negative real is imaginary
+                match &args[0] {
+                    Value::Complex(c) => Ok(Value::Complex(c.sqrt())),
+                    other => match other.as_number(span)? {
+                        n if n < 0.0 => Ok(Value::Complex(Complex::from(n).sqrt())),
+                        n => Ok(Value::Float(n.sqrt())),
+                    },
+                }
+            }
+            "abs" if !matches!(args.as_slice(), [Value::Complex(_)]) => {
+                expect_arity(name, &args, 1, span)?;
+                match &args[0] {
+                    Value::Int(n) => n
+                        .checked_abs()
+                        .map(Value::Int)
+                        .ok_or(EvalError::IntegerOverflow { span }),
+                    Value::Rational(r) => Ok(Value::from(r.abs())),
+                    other => Ok(Value::Float(other.as_number(span)?.abs())),
+                }
+            }
+            "abs" | "arg" | "re" | "im" => {
+                expect_arity(name, &args, 1, span)?;
+                let z = as_complex(&args[0], span)?;
+                let part = match name {
+                    "abs" => z.abs(),
+                    "arg" => z.arg(),
+                    "re" => z.re,
+                    _ => z.im,
+                };
+                Ok(Value::Float(part))
+            }
+            "conj" => {
+                expect_arity(name, &args, 1, span)?;
+                match &args[0] {
+                    Value::Complex(c) => Ok(Value::Complex(c.conj())),
+                    // Reals are their own conjugates
+                    other => other.as_number(span).map(|_| other.clone()),
+                }
+            }
+            "polar" => {
+                expect_arity(name, &args, 1, span)?;
+                let (r, theta) = as_complex(&args[0], span)?.to_polar();
+                Ok(Value::record([
+                    ("r".to_string(), Value::Float(r)),
+                    ("theta".to_string(), Value::Float(theta)),
+                ]))
+            }
+            "rect" => {
+                expect_arity(name, &args, 2, span)?;
+                let (r, theta) = (args[0].as_number(span)?, args[1].as_number(span)?);
+                Ok(Value::Complex(Complex::from_polar(r, theta)))
+            }
+            "sum" | "product" => {
+                expect_arity(name, &args, 1, span)?;
+                let op = if name == "sum" {
+                    BinOp::Add
+                } else {
+                    BinOp::Mul
+                };
+                seq::total(op, self.stream(name, &args[0], span, meter)?, span)
+            }
+            // Lazy on sequences, eager on lists
+            "map" | "filter" if matches!(args.first(), Some(Value::Seq(_))) => {
+                expect_arity(name, &args, 2, span)?;
+                let (Value::Seq(seq), f) = (&args[0], args[1].as_function(span)?) else {
+                    unreachable!("matched above")
+                };
+                let (seq, f) = (Arc::clone(seq), Arc::clone(f));
+                let lazy = match name {
+                    "map" => Seq::Map(seq, f),
+                    _ => Seq::Filter(seq, f),
+                };
+                Ok(Value::seq(lazy))
+            }
+            "map" => {
+                expect_arity(name, &args, 2, span)?;
+                let f = args[1].as_function(span)?;
+                let mapped = args[0]
+                    .as_list(span)?
+                    .iter()
+                    .map(|item| self.call_in(f, vec![item.clone()], span, meter))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                Ok(Value::list(mapped))
+            }
+            "filter" => {
+                expect_arity(name, &args, 2, span)?;
+                let f = args[1].as_function(span)?;
+                let mut kept = Vec::new();
+                for item in args[0].as_list(span)? {
+                    if self
+                        .call_in(f, vec![item.clone()], span, meter)?
+                        .as_bool(span)?
+                    {
+                        kept.push(item.clone());
+                    }
+                }
+                Ok(Value::list(kept))
+            }
+            "fold" => {
+                expect_arity(name, &args, 3, span)?;
+                let f = args[2].as_function(span)?;
+                self.stream(name, &args[0], span, meter)?
+                    .try_fold(args[1].clone(), |acc, item| {
+                        self.call_in(f, vec![acc, item?], span, meter)
+                    })
+            }
+            _ => {
+                // Never a builtin's name, so the order does not matter
+                if let Some(native) = self.registry.get(name) {
+                    meter.host_call();
+                    return native.call(&args, span);
+                }
+                let nums = args
+                    .iter()
+                    .map(|arg| arg.as_number(span))
+                    .collect::<Result<Vec<f64>, EvalError>>();
+                // Report unknown names and arity before argument types
+                let (arity, _) =
+                    numeric_builtin(name).ok_or_else(|| EvalError::UnknownFunction {
+                        name: name.to_string(),
+                        span,
+                    })?;
+                expect_arity(name, &args, arity, span)?;
+                call_builtin(name, &nums?, span).map(Value::Float)
+            }
+        }
+    }
+}
+
+impl Interpreter {
+    /// The items of a list, or of a sequence, which is run to the end for
+    /// them.
+    fn items(
+        &self,
+        name: &str,
+        value: &Value,
+        span: Span,
+        meter: &Meter,
+    ) -> Result<Arc<Vec<Value>>, EvalError> {
+        match value {
+            Value::Seq(seq) => Ok(Arc::new(seq.collect(name, self, span, meter)?)),
+            Value::List(items) => Ok(Arc::clone(items)),
+            other => Err(other.mismatch("list", span)),
+        }
+    }
+
+    /// The items of a list, or of a sequence with an end, one at a time,
+    /// for functions that read each item once and keep none of them.
+    fn stream<'a>(
+        &'a self,
+        name: &str,
+        value: &'a Value,
+        span: Span,
+        meter: &'a Meter,
+    ) -> Result<Items<'a>, EvalError> {
+        match value {
+            Value::Seq(seq) => {
+                seq.expect_finite(name, span)?;
+                Ok(seq.items(self, span, meter))
+            }
+            Value::List(items) => Ok(Box::new(items.iter().cloned().map(Ok))),
+            other => Err(other.mismatch("list", span)),
+        }
+    }
+}
+
+/// Evaluate `src` with no variables defined.
+pub fn eval(src: &str) -> Result<Value, CalcError> {
+    Interpreter::new().eval_str(src)
+}
+
+pub(crate) fn expect_arity(
+    name: &str,
+    args: &[Value],
+    expected: usize,
+    span: Span,
+) -> Result<(), EvalError> {
+    if args.len() == expected {
+        return Ok(());
+    }
+    Err(EvalError::ArityMismatch {
+        name: name.to_string(),
+        expected,
+        found: args.len(),
+        span,
+    })
+}
+
+// ------------------------------------------------
+/// `-` on numbers (checked for ints), vectors and quantities, `!` on
+/// bools. Errors point at the operand's `span`.
+pub fn apply_unary(op: UnaryOp, operand: &Value, span: Span) -> Result<Value, EvalError> {
+    match (op, operand) {
+        (UnaryOp::Neg, Value::Int(n)) => n
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or(EvalError::IntegerOverflow { span }),
+        (UnaryOp::Neg, Value::Rational(r)) => Ok(Value::from(-&**r)),
+        (UnaryOp::Neg, Value::Vector(v)) => Ok(Value::Vector(-*v)),
+        (UnaryOp::Neg, Value::Complex(c)) => Ok(Value::Complex(-c)),
+        (UnaryOp::Neg, Value::Quantity(q)) => Ok(Value::Quantity(Quantity {
+            value: -q.value,
+            ..*q
+        })),
+        (UnaryOp::Neg, Value::Money(m)) => m
+            .cents()
+            .checked_neg()
+            .map(|cents| Value::Money(Money::new(cents)))
+            .ok_or(EvalError::IntegerOverflow { span }),
+        (UnaryOp::Neg, other) => Ok(Value::Float(-other.as_number(span)?)),
+        (UnaryOp::Not, other) => Ok(Value::Bool(!other.as_bool(span)?)),
+    }
+}
+
+/// Apply `op` to evaluated operands. `spans` holds the left operand, the
+/// right operand and the whole expression, for error reporting.
+///
+/// Coercion between ints and floats:
+/// - int with int stays int, and overflow is an error rather than wrapping
+/// - `/` always divides as floats, so `7 / 2 == 3.5`
+/// - `int ^ int` with a negative exponent is a float
+/// - an int mixed with a float is widened to a float first
+/// - `<`, `<=`, `>` and `>=` compare two numbers (as floats unless both
+///   are ints) or two strings
+/// - `==` and `!=` follow `Value::equals`, so `1 == 1.0`
+///
+/// `+` also joins two strings; `&&` and `||` take bools, and `..` two ints.
+/// Vectors are covered by `vector_binary`, quantities by `quantity_binary`,
+/// money by `money_binary`, complex numbers by `complex_binary` and
+/// rationals by `rational_binary`.
+pub fn apply_binary(
+    op: BinOp,
+    lhs: &Value,
+    rhs: &Value,
+    spans: [Span; 3],
+) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    match op {
+        BinOp::Eq => Ok(Value::Bool(lhs.equals(rhs))),
+        BinOp::Ne => Ok(Value::Bool(!lhs.equals(rhs))),
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+            let ordering = compare(lhs, rhs, lhs_span, rhs_span)?;
+            // NaN is unordered, so every comparison with it is false
+            let holds = ordering.is_some_and(|ordering| match op {
+                BinOp::Lt => ordering.is_lt(),
+                BinOp::Le => ordering.is_le(),
+                BinOp::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            });
+            Ok(Value::Bool(holds))
+        }
+        BinOp::And | BinOp::Or => {
+            let (l, r) = (lhs.as_bool(lhs_span)?, rhs.as_bool(rhs_span)?);
+            Ok(Value::Bool(if op == BinOp::And { l && r } else { l || r }))
+        }
+        BinOp::Range => {
+            let (start, end) = (lhs.as_int(lhs_span)?, rhs.as_int(rhs_span)?);
+            Ok(Value::seq(Seq::Range {
+                start,
+                end,
+                step: 1,
+            }))
+        }
+        BinOp::Add if matches!(lhs, Value::Str(_)) => match (lhs, rhs) {
+            (Value::Str(a), Value::Str(b)) => Ok(Value::from(format!("{}{}", a, b))),
+            _ => Err(rhs.mismatch("string", rhs_span)),
+        },
+        _ => match (lhs, rhs) {
+            (Value::Vector(_), _) | (_, Value::Vector(_)) => vector_binary(op, lhs, rhs, spans),
+            (Value::Quantity(_), _) | (_, Value::Quantity(_)) => {
+                quantity_binary(op, lhs, rhs, spans)
+            }
+            (Value::Money(_), _) | (_, Value::Money(_)) => money_binary(op, lhs, rhs, spans),
+            (Value::Complex(_), _) | (_, Value::Complex(_)) => complex_binary(op, lhs, rhs, spans),
+            (Value::Rational(_), _) | (_, Value::Rational(_)) => {
+                rational_binary(op, lhs, rhs, spans)
+            }
+            (Value::Int(a), Value::Int(b)) => int_binary(op, *a, *b, span),
+            _ => {
+                let (l, r) = (lhs.as_number(lhs_span)?, rhs.as_number(rhs_span)?);
+                float_binary(op, l, r, span).map(Value::Float)
+            }
+        },
+    }
+}
+
+/// The field `name` of a record; `record` is the span of the record
+/// expression and `span` that of the whole access.
+pub(crate) fn field(
+    value: &Value,
+    name: &str,
+    record: Span,
+    span: Span,
+) -> Result<Value, EvalError> {
+    match value {
+        Value::Record(fields) => fields.get(name).cloned().ok_or_else(|| EvalError::NoField {
+            name: name.to_string(),
+            span,
+        }),
+        other => Err(other.mismatch("record", record)),
+    }
+}
+
+/// Whether `value` matches a `match` arm's pattern. Literals match as `==`
+/// would; ranges hold numbers from their start up to but excluding their
+/// end. A value of another type than the pattern's simply does not match.
+pub(crate) fn matches_pattern(pattern: &Pattern, value: &Value) -> bool {
+    match pattern {
+        Pattern::Wildcard => true,
+        Pattern::Literal(literal) => value.equals(&Value::from(literal)),
+        Pattern::Range { start, end } => {
+            if !matches!(value, Value::Int(_) | Value::Float(_) | Value::Rational(_)) {
+                return false;
+            }
+            let holds = |bound: &Literal, holds: fn(Ordering) -> bool| {
+                let span = Span::default();
+                compare(value, &Value::from(bound), span, span)
+                    .is_ok_and(|ordering| ordering.is_some_and(holds))
+            };
+            start
+                .as_ref()
+                .is_none_or(|start| holds(start, Ordering::is_ge))
+                && end.as_ref().is_none_or(|end| holds(end, Ordering::is_lt))
+        }
+    }
+}
+
+fn compare(
+    lhs: &Value,
+    rhs: &Value,
+    lhs_span: Span,
+    rhs_span: Span,
+) -> Result<Option<Ordering>, EvalError> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => Ok(Some(a.cmp(b))),
+        (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+        (Value::Str(_), other) => Err(other.mismatch("string", rhs_span)),
+        (Value::Money(a), Value::Money(b)) => Ok(Some(a.cents().cmp(&b.cents()))),
+        (Value::Money(_), other) => Err(other.mismatch("money", rhs_span)),
+        (other, Value::Money(_)) => Err(other.mismatch("money", lhs_span)),
+        (Value::Quantity(_), _) | (_, Value::Quantity(_)) => {
+            let l = as_quantity(lhs, lhs_span)?;
+            let r = as_quantity(rhs, rhs_span)?;
+            expect_dim(&r, l.dim, rhs_span)?;
+            Ok(l.si().partial_cmp(&r.si()))
+        }
+        (Value::Rational(_) | Value::Int(_), Value::Rational(_) | Value::Int(_)) => {
+            Ok(Some(as_rational(lhs).cmp(&as_rational(rhs))))
+        }
+        _ => {
+            let (l, r) = (lhs.as_number(lhs_span)?, rhs.as_number(rhs_span)?);
+            Ok(l.partial_cmp(&r))
+        }
+    }
+}
+
+/// Arithmetic with at least one vector operand, delegating to `Vec2`:
+/// `+`, `-` and `*` element-wise between two vectors, and `*` or `/` by a
+/// number.
+fn vector_binary(
+    op: BinOp,
+    lhs: &Value,
+    rhs: &Value,
+    spans: [Span; 3],
+) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    let result = match (op, lhs, rhs) {
+        (BinOp::Add, Value::Vector(a), Value::Vector(b)) => a + b,
+        (BinOp::Sub, Value::Vector(a), Value::Vector(b)) => *a - *b,
+        (BinOp::Mul, Value::Vector(a), Value::Vector(b)) => *a * *b,
+        (BinOp::Mul, Value::Vector(v), n) => *v * n.as_number(rhs_span)?,
+        (BinOp::Mul, n, Value::Vector(v)) => n.as_number(lhs_span)? * *v,
+        (BinOp::Div, Value::Vector(v), n) => match n.as_number(rhs_span)? {
+            0.0 => return Err(EvalError::DivisionByZero { span }),
+            n => *v / n,
+        },
+        // Report the operand that keeps the operator from applying
+        (BinOp::Add | BinOp::Sub, Value::Vector(_), other) => {
+            return Err(other.mismatch("vector", rhs_span));
+        }
+        (BinOp::Add | BinOp::Sub, other, Value::Vector(_)) => {
+            return Err(other.mismatch("vector", lhs_span));
+        }
+        (_, Value::Vector(_), _) => return Err(lhs.mismatch("number", lhs_span)),
+        _ => return Err(rhs.mismatch("number", rhs_span)),
+    };
+    Ok(Value::Vector(result))
+}
+
+/// Money adds to and subtracts from money and scales by ints, all in
+/// whole cents; p23's `Money` has no fractions of a cent to round.
+fn money_binary(op: BinOp, lhs: &Value, rhs: &Value, spans: [Span; 3]) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    let cents = match (op, lhs, rhs) {
+        (BinOp::Add, Value::Money(a), Value::Money(b)) => a.cents().checked_add(b.cents()),
+        (BinOp::Sub, Value::Money(a), Value::Money(b)) => a.cents().checked_sub(b.cents()),
+        (BinOp::Mul, Value::Money(m), n) => m.cents().checked_mul(n.as_int(rhs_span)?),
+        (BinOp::Mul, n, Value::Money(m)) => m.cents().checked_mul(n.as_int(lhs_span)?),
+        (BinOp::Add | BinOp::Sub, Value::Money(_), other) => {
+            return Err(other.mismatch("money", rhs_span));
+        }
+        (BinOp::Add | BinOp::Sub | BinOp::Mul, other, _) => {
+            return Err(other.mismatch("money", lhs_span));
+        }
+        (_, Value::Money(_), _) => return Err(lhs.mismatch("number", lhs_span)),
+        _ => return Err(rhs.mismatch("number", rhs_span)),
+    };
+    cents
+        .map(|cents| Value::Money(Money::new(cents)))
+        .ok_or(EvalError::IntegerOverflow { span })
+}
+
+/// Arithmetic with at least one quantity operand. Plain numbers count as
+/// dimensionless, and a dimensionless result is a plain float.
+///
+/// `+`, `-` and `%` need matching dimensions and answer in the left
+/// operand's unit, so `1 km + 250 m` is `1.25 km`. Scaling by a number
+/// keeps the unit; `*` and `/` between quantities and `^` with an int
+/// exponent work in SI base units.
+///
+/// Temperatures in `C` or `F` are measured from an offset zero, so the
+/// only arithmetic on them is `-`, giving the difference in `K`; anything
+/// else needs them converted to `K` first, where the answer is unambiguous.
+fn quantity_binary(
+    op: BinOp,
+    lhs: &Value,
+    rhs: &Value,
+    spans: [Span; 3],
+) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    let l = as_quantity(lhs, lhs_span)?;
+    let nonzero = |n: f64| match n {
+        0.0 => Err(EvalError::DivisionByZero { span }),
+        n => Ok(n),
+    };
+
+    let absolute = |q: &Quantity, span: Span| match q.has_offset() {
+        true => Err(EvalError::InvalidArgument {
+            name: op.symbol().to_string(),
+            reason: format!("{} is on an offset scale; convert it to K first", q),
+            span,
+        }),
+        false => Ok(()),
+    };
+
+    let result = match op {
+        BinOp::Pow => {
+            absolute(&l, lhs_span)?;
+            let n = rhs.as_int(rhs_span)?;
+            let n = i32::try_from(n).map_err(|_| EvalError::IntegerOverflow { span })?;
+            if n < 0 && l.si() == 0.0 {
+                return Err(EvalError::DivisionByZero { span });
+            }
+            Quantity::from_si(l.si().powi(n), l.dim.pow(n))
+        }
+        _ => {
+            let r = as_quantity(rhs, rhs_span)?;
+            if op == BinOp::Sub && (l.has_offset() || r.has_offset()) {
+                expect_dim(&r, l.dim, rhs_span)?;
+                return Ok(Value::from(Quantity::from_si(l.si() - r.si(), l.dim)));
+            }
+            absolute(&l, lhs_span)?;
+            absolute(&r, rhs_span)?;
+            match op {
+                BinOp::Add | BinOp::Sub | BinOp::Rem => {
+                    expect_dim(&r, l.dim, rhs_span)?;
+                    let (a, b) = (l.value, r.value_like(&l));
+                    let value = match op {
+                        BinOp::Add => a + b,
+                        BinOp::Sub => a - b,
+                        _ => a % nonzero(b)?,
+                    };
+                    Quantity { value, ..l }
+                }
+                BinOp::Mul if r.dim.is_none() => Quantity {
+                    value: l.value * r.si(),
+                    ..l
+                },
+                BinOp::Mul if l.dim.is_none() => Quantity {
+                    value: l.si() * r.value,
+                    ..r
+                },
+                BinOp::Div if r.dim.is_none() => Quantity {
+                    value: l.value / nonzero(r.si())?,
+                    ..l
+                },
+                BinOp::Mul => Quantity::from_si(l.si() * r.si(), l.dim * r.dim),
+                BinOp::Div => Quantity::from_si(l.si() / nonzero(r.si())?, l.dim / r.dim),
+                _ => unreachable!("apply_binary handles comparisons and logic"),
+            }
+        }
+    };
+    Ok(Value::from(result))
+}
+
+/// A quantity, or a number as a dimensionless one.
+fn as_quantity(value: &Value, span: Span) -> Result<Quantity, EvalError> {
+    match value {
+        Value::Quantity(q) => Ok(*q),
+        other => Ok(Quantity::from_si(other.as_number(span)?, Dim::NONE)),
+    }
+}
+
+fn expect_dim(q: &Quantity, expected: Dim, span: Span) -> Result<(), EvalError> {
+    if q.dim == expected {
+        return Ok(());
+    }
+    Err(EvalError::DimensionMismatch {
+        expected: expected.to_string(),
+        found: q.dim.to_string(),
+        span,
+    })
+}
+
+/// `value -> unit`: the same amount in another unit of its dimension.
+/// Errors point at the converted expression's `span`.
+pub fn convert(value: &Value, unit: &'static Unit, span: Span) -> Result<Value, EvalError> {
+    let q = as_quantity(value, span)?;
+    expect_dim(&q, unit.dim, span)?;
+    Ok(Value::Quantity(Quantity::new(q.value_in(unit), unit)))
+}
+
+/// Arithmetic with at least one complex operand, delegating to `Complex`;
+/// a real operand counts as `x + 0i`. `^` takes the principal value, and
+/// there is no `%`, as complex numbers have no ordering to round by.
+fn complex_binary(
+    op: BinOp,
+    lhs: &Value,
+    rhs: &Value,
+    spans: [Span; 3],
+) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    let (l, r) = (as_complex(lhs, lhs_span)?, as_complex(rhs, rhs_span)?);
+    let result = match op {
+        BinOp::Add => l + r,
+        BinOp::Sub => l - r,
+        BinOp::Mul => l * r,
+        BinOp::Div if r.is_zero() => return Err(EvalError::DivisionByZero { span }),
+        BinOp::Div => l / r,
+        BinOp::Pow => l.powc(r),
+        BinOp::Rem if matches!(lhs, Value::Complex(_)) => {
+            return Err(lhs.mismatch("number", lhs_span));
+        }
+        BinOp::Rem => return Err(rhs.mismatch("number", rhs_span)),
+        _ => unreachable!("apply_binary handles comparisons and logic"),
+    };
+    Ok(Value::Complex(result))
+}
+
+/// A complex number, or a real one as `x + 0i`.
+fn as_complex(value: &Value, span: Span) -> Result<Complex, EvalError> {
+    match value {
+        Value::Complex(c) => Ok(*c),
+        other => Ok(Complex::from(other.as_number(span)?)),
+    }
+}
+
+/// Exact arithmetic when both operands are ints or rationals, and one is
+/// a rational. `/` and `%` stay exact, as does `^` with a whole exponent
+/// up to `MAX_POW_BITS`. A fractional exponent has no exact answer, so it
+/// is refused like `sqrt` is; a float operand gives a float.
+fn rational_binary(
+    op: BinOp,
+    lhs: &Value,
+    rhs: &Value,
+    spans: [Span; 3],
+) -> Result<Value, EvalError> {
+    let [lhs_span, rhs_span, span] = spans;
+    let inexact = || {
+        let (l, r) = (lhs.as_number(lhs_span)?, rhs.as_number(rhs_span)?);
+        float_binary(op, l, r, span).map(Value::Float)
+    };
+    if !is_exact(lhs) || !is_exact(rhs) {
+        return inexact();
+    }
+
+    let (l, r) = (as_rational(lhs), as_rational(rhs));
+    let result = match op {
+        BinOp::Add => &l + &r,
+        BinOp::Sub => &l - &r,
+        BinOp::Mul => &l * &r,
+        BinOp::Div => l
+            .checked_div(&r)
+            .ok_or(EvalError::DivisionByZero { span })?,
+        BinOp::Rem => l
+            .checked_rem(&r)
+            .ok_or(EvalError::DivisionByZero { span })?,
+        BinOp::Pow => {
+            if !r.is_integer() {
+                return Err(EvalError::InvalidArgument {
+                    name: "^".to_string(),
+                    reason: "no exact answer; try float(x) ^ y instead".to_string(),
+                    span,
+                });
+            }
+            // Powers of 0, 1 and -1 stay small whatever the exponent
+            let growth = l.bits().saturating_sub(1);
+            let exp = match r.numer().to_i64() {
+                Some(exp) => exp,
+                // Past `i64` only the exponent's sign and parity matter
+                None if growth == 0 => {
+                    let two = BigInt::from(2);
+                    let (_, parity) = r.numer().div_rem(&two).expect("two is not zero");
+                    let parity = parity.to_i64().expect("a remainder of two fits");
+                    if r.numer().is_negative() {
+                        parity - 2
+                    } else {
+                        parity + 2
+                    }
+                }
+                None => return Err(EvalError::IntegerOverflow { span }),
+            };
+            if growth.saturating_mul(exp.unsigned_abs()) > MAX_POW_BITS {
+                return Err(EvalError::IntegerOverflow { span });
+            }
+            l.pow(exp).ok_or(EvalError::DivisionByZero { span })?
+        }
+        _ => unreachable!("apply_binary handles comparisons and logic"),
+    };
+    Ok(Value::from(result))
+}
+
+fn is_exact(value: &Value) -> bool {
+    matches!(value, Value::Int(_) | Value::Rational(_))
+}
+
+/// A numeric builtin of ints and rationals in exact mode. Those in
+/// `EXACT_BUILTINS` give exact answers; the rest have none, so they refuse
+/// rather than quietly rounding, and `float(x)` opts in.
+fn exact_builtin(name: &str, args: &[Value], span: Span) -> Result<Value, EvalError> {
+    let arity = if matches!(name, "min" | "max") { 2 } else { 1 };
+    expect_arity(name, args, arity, span)?;
+    if INEXACT_BUILTINS.contains(&name) {
+        return Err(EvalError::InvalidArgument {
+            name: name.to_string(),
+            reason: format!("no exact answer; try {}(float(x)) instead", name),
+            span,
+        });
+    }
+    let x = as_rational(&args[0]);
+    let result = match name {
+        "abs" => x.abs(),
+        "re" => x,
+        "im" => Rational::from(0),
+        "floor" => Rational::integer(x.floor()),
+        "ceil" => Rational::integer(x.ceil()),
+        "round" => Rational::integer(x.round()),
+        "min" => x.min(as_rational(&args[1])),
+        "max" => x.max(as_rational(&args[1])),
+        _ => unreachable!("{} is not an exact builtin", name),
+    };
+    Ok(Value::from(result))
+}
+
+/// An int or a rational as a rational.
+fn as_rational(value: &Value) -> Rational {
+    match value {
+        Value::Int(n) => Rational::from(*n),
+        Value::Rational(r) => (**r).clone(),
+        other => unreachable!("{} is not exact", other.type_name()),
+    }
+}
+
+/// Checked arithmetic on two ints.
+fn int_binary(op: BinOp, lhs: i64, rhs: i64, span: Span) -> Result<Value, EvalError> {
+    let result = match op {
+        BinOp::Add => lhs.checked_add(rhs),
+        BinOp::Sub => lhs.checked_sub(rhs),
+        BinOp::Mul => lhs.checked_mul(rhs),
+        BinOp::Rem if rhs == 0 => return Err(EvalError::DivisionByZero { span }),
+        // Only `i64::MIN % -1` overflows
+        BinOp::Rem => lhs.checked_rem(rhs),
+        BinOp::Div => return float_binary(op, lhs as f64, rhs as f64, span).map(Value::Float),
+        BinOp::Pow if rhs < 0 => {
+            return float_binary(op, lhs as f64, rhs as f64, span).map(Value::Float);
+        }
+        BinOp::Pow => match lhs {
+            // These stay in range for any exponent
+            0 | 1 => Some(if rhs == 0 { 1 } else { lhs }),
+            -1 => Some(if rhs % 2 == 0 { 1 } else { -1 }),
+            _ => u32::try_from(rhs).ok().and_then(|exp| lhs.checked_pow(exp)),
+        },
+        _ => unreachable!("apply_binary handles comparisons and logic"),
+    };
+    result
+        .map(Value::Int)
+        .ok_or(EvalError::IntegerOverflow { span })
+}
+
+fn float_binary(op: BinOp, lhs: f64, rhs: f64, span: Span) -> Result<f64, EvalError> {
+    let value = match op {
+        BinOp::Add => lhs + rhs,
+        BinOp::Sub => lhs - rhs,
+        BinOp::Mul => lhs * rhs,
+        BinOp::Div | BinOp::Rem if rhs == 0.0 => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        BinOp::Div => lhs / rhs,
+        BinOp::Rem => lhs % rhs,
+        // A negative power of zero divides by it, as `1 / 0` does
+        BinOp::Pow if lhs == 0.0 && rhs < 0.0 => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        BinOp::Pow => lhs.powf(rhs),
+        _ => unreachable!("apply_binary handles comparisons and logic"),
+    };
+    Ok(value)
+}
+
+/// `int(x)` truncates toward zero; NaN, infinities and floats outside the
+/// `i64` range do not fit.
+fn float_to_int(n: f64, span: Span) -> Result<i64, EvalError> {
+    // `i64::MIN as f64` is exactly -2^63; 2^63 itself is out of range
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    let truncated = n.trunc();
+    if (-LIMIT..LIMIT).contains(&truncated) {
+        Ok(truncated as i64)
+    } else {
+        Err(EvalError::IntegerOverflow { span })
+    }
+}
+
+pub type BuiltinFn = fn(&[f64]) -> f64;
+
+/// Builtins handled by `Interpreter::call_builtin` before the numeric table.
+const LIST_BUILTINS: &[&str] = &[
+    "list",
+    "len",
+    "int",
+    "float",
+    "vec",
+    "dot",
+    "length",
+    "normalize",
+    "sum",
+    "product",
+    "map",
+    "filter",
+    "fold",
+    "range",
+    "fib",
+    "take",
+    "collect",
+    "money",
+    "sqrt",
+    "abs",
+    "arg",
+    "conj",
+    "re",
+    "im",
+    "polar",
+    "rect",
+];
+
+/// Builtins exact mode answers exactly when given ints and rationals.
+const EXACT_BUILTINS: &[&str] = &["abs", "re", "im", "floor", "ceil", "round", "min", "max"];
+
+/// Builtins with no exact answer, which exact mode refuses ints and
+/// rationals.
+const INEXACT_BUILTINS: &[&str] = &["sqrt", "arg", "sin", "cos", "tan", "exp", "ln", "log10"];
+
+/// Numeric builtin functions as `(name, arity, implementation)`.
+const BUILTINS: &[(&str, usize, BuiltinFn)] = &[
+    ("sin", 1, |a| a[0].sin()),
+    ("cos", 1, |a| a[0].cos()),
+    ("tan", 1, |a| a[0].tan()),
+    ("exp", 1, |a| a[0].exp()),
+    ("ln", 1, |a| a[0].ln()),
+    ("log10", 1, |a| a[0].log10()),
+    ("floor", 1, |a| a[0].floor()),
+    ("ceil", 1, |a| a[0].ceil()),
+    ("round", 1, |a| a[0].round()),
+    ("min", 2, |a| a[0].min(a[1])),
+    ("max", 2, |a| a[0].max(a[1])),
+];
+
+/// Whether `name` is a builtin function of any kind.
+pub fn is_builtin(name: &str) -> bool {
+    LIST_BUILTINS.contains(&name) || numeric_builtin(name).is_some()
+}
+
+/// Builtins besides the numeric ones that run no other code: none of them
+/// calls a function or reads a sequence, whose lazy `map` might.
+const PURE_BUILTINS: &[&str] = &[
+    "list",
+    "int",
+    "float",
+    "vec",
+    "dot",
+    "length",
+    "normalize",
+    "range",
+    "fib",
+    "take",
+    "money",
+    "sqrt",
+    "abs",
+    "arg",
+    "conj",
+    "re",
+    "im",
+    "polar",
+    "rect",
+];
+
+/// Whether calls to the builtin `name` with equal arguments always give
+/// equal results, as they can never reach a host function.
+pub(crate) fn is_pure_builtin(name: &str) -> bool {
+    PURE_BUILTINS.contains(&name) || numeric_builtin(name).is_some()
+}
+
+/// Arity and implementation of the numeric builtin `name`.
+pub(crate) fn numeric_builtin(name: &str) -> Option<(usize, BuiltinFn)> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|&(_, arity, func)| (arity, func))
+}
+
+pub fn call_builtin(name: &str, args: &[f64], span: Span) -> Result<f64, EvalError> {
+    let Some((arity, func)) = numeric_builtin(name) else {
+        return Err(EvalError::UnknownFunction {
+            name: name.to_string(),
+            span,
+        });
+    };
+
+    if args.len() != arity {
+        return Err(EvalError::ArityMismatch {
+            name: name.to_string(),
+            expected: arity,
+            found: args.len(),
+            span,
+        });
+    }
+
+    Ok(func(args))
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ints(values: &[i64]) -> Value {
+        Value::list(values.iter().map(|&n| Value::Int(n)).collect())
+    }
+
+    fn approx(value: Value, expected: f64) -> bool {
+        matches!(value, Value::Float(n) if (n - expected).abs() < 1e-12)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(eval("7 % 4").unwrap(), 3);
+        assert_eq!(eval("2 ^ 3 ^ 2").unwrap(), 512);
+        assert_eq!(eval("-2 ^ 2").unwrap(), -4);
+    }
+
+    #[test]
+    fn test_int_float_coercion() {
+        // int with int stays int
+        assert_eq!(eval("2 + 3 * 4 - 1").unwrap(), Value::Int(13));
+        assert_eq!(eval("-7 % 3").unwrap(), Value::Int(-1));
+        assert_eq!(eval("2 ^ 10").unwrap(), Value::Int(1024));
+        // ...except `/`, and powers with a negative exponent
+        assert_eq!(eval("7 / 2").unwrap(), Value::Float(3.5));
+        assert_eq!(eval("6 / 3").unwrap(), Value::Float(2.0));
+        assert_eq!(eval("2 ^ -1").unwrap(), Value::Float(0.5));
+        for src in ["0 ^ -1", "0.0 ^ -2", "-0.0 ^ -0.5", "(0 m) ^ -1"] {
+            assert!(
+                matches!(
+                    eval(src).unwrap_err(),
+                    CalcError::Eval(EvalError::DivisionByZero { .. })
+                ),
+                "{}",
+                src
+            );
+        }
+        assert_eq!(eval("0 ^ 0").unwrap(), Value::Int(1));
+        assert_eq!(eval("0.0 ^ 0.5").unwrap(), Value::Float(0.0));
+        // Mixing with a float widens the int
+        assert_eq!(eval("1 + 0.5").unwrap(), Value::Float(1.5));
+        assert_eq!(eval("2.0 * 3").unwrap(), Value::Float(6.0));
+        assert_eq!(eval("7.5 % 2").unwrap(), Value::Float(1.5));
+        // Numeric builtins take and return floats, but `abs` keeps an int
+        assert_eq!(eval("sqrt(9)").unwrap(), Value::Float(3.0));
+        assert_eq!(eval("abs(-3)").unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_explicit_conversions() {
+        assert_eq!(eval("int(3.9)").unwrap(), Value::Int(3));
+        assert_eq!(eval("int(-3.9)").unwrap(), Value::Int(-3));
+        assert_eq!(eval("int(7)").unwrap(), Value::Int(7));
+        assert_eq!(eval("float(7)").unwrap(), Value::Float(7.0));
+        assert_eq!(eval("int(7 / 2) + 1").unwrap(), Value::Int(4));
+
+        for src in ["int(1e19)", "int(0 * 1e400)", "int(-1e400)"] {
+            let err = eval(src).unwrap_err();
+            assert!(
+                matches!(err, CalcError::Eval(EvalError::IntegerOverflow { .. })),
+                "{}",
+                src
+            );
+        }
+        assert_eq!(eval("int(-9223372036854775807 - 1.0)").unwrap(), i64::MIN);
+
+        let err = eval("float(true)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected number, found bool"
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow_is_an_error() {
+        for src in [
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "4294967296 * 4294967296",
+            "2 ^ 63",
+            "3 ^ 4294967296",
+            "-(-9223372036854775807 - 1)",
+            "(-9223372036854775807 - 1) % -1",
+        ] {
+            let err = eval(src).unwrap_err();
+            assert!(
+                matches!(err, CalcError::Eval(EvalError::IntegerOverflow { .. })),
+                "{}: {:?}",
+                src,
+                err
+            );
+        }
+        let err = eval("1 + 2 ^ 70").unwrap_err();
+        assert_eq!(err.to_string(), "evaluation error: integer overflow");
+        assert_eq!(err.span(), Span::new(4, 10));
+
+        // Bases that cannot grow are fine with any exponent
+        assert_eq!(eval("1 ^ 10000000000").unwrap(), 1);
+        assert_eq!(eval("(-1) ^ 10000000001").unwrap(), -1);
+        // Floats overflow to infinity as before
+        assert_eq!(eval("2.0 ^ 2000").unwrap(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval("1 < 2").unwrap(), true);
+        assert_eq!(eval("2 <= 2 && 3 > 2 && 3 >= 4").unwrap(), false);
+        assert_eq!(eval("1 == 1.0").unwrap(), true);
+        assert_eq!(eval("0.1 + 0.2 != 0.3").unwrap(), true);
+        assert_eq!(eval("1 < 1.5").unwrap(), true);
+        assert_eq!(eval(r#""abc" < "abd""#).unwrap(), true);
+        assert_eq!(eval("list(1, 2) == list(1.0, 2)").unwrap(), true);
+        // Different types are never equal, but only ordering is an error
+        assert_eq!(eval(r#"1 == "1""#).unwrap(), false);
+        assert_eq!(eval("true != 1").unwrap(), true);
+        let err = eval(r#""a" < 1"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected string, found int"
+        );
+        assert_eq!(err.span(), Span::new(6, 7));
+        // NaN is unordered
+        assert_eq!(
+            eval("let nan = 0 * 1e400 in nan < 1 || nan >= 1").unwrap(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_logic_short_circuits() {
+        assert_eq!(eval("!true || !false").unwrap(), true);
+        assert_eq!(eval("false && 1 / 0 > 0").unwrap(), false);
+        assert_eq!(eval("true || nope").unwrap(), true);
+        assert!(eval("true && nope").is_err());
+
+        // Numbers are not truthy
+        let err = eval("1 && true").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected bool, found int"
+        );
+        assert_eq!(err.span(), Span::new(0, 1));
+        assert!(eval("!0").is_err());
+        assert!(eval("true && 1").is_err());
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        let mut interp = Interpreter::new();
+        interp
+            .exec_str("fn fact(n) = if n <= 1 then 1 else n * fact(n - 1)")
+            .unwrap();
+        assert_eq!(interp.eval_str("fact(20)").unwrap(), 2432902008176640000);
+        assert!(matches!(
+            interp.eval_str("fact(21)"),
+            Err(CalcError::Eval(EvalError::IntegerOverflow { .. }))
+        ));
+
+        // Only the chosen branch runs
+        assert_eq!(eval("if 1 > 2 then 1 / 0 else 5").unwrap(), 5);
+        let err = eval("if 1 then 2 else 3").unwrap_err();
+        assert_eq!(err.span(), Span::new(3, 4));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(eval(r#""tax" + "es""#).unwrap(), Value::from("taxes"));
+        assert_eq!(eval(r#"len("héllo")"#).unwrap(), 5);
+        assert!(eval(r#""a" + 1"#).is_err());
+        assert!(eval(r#"1 + "a""#).is_err());
+        assert_eq!(
+            eval(r#"if "b" > "a" then "yes" else "no""#)
+                .unwrap()
+                .to_string(),
+            r#""yes""#
+        );
+    }
+
+    #[test]
+    fn test_vectors() {
+        let v = |x, y| Value::Vector(Vec2::new(x, y));
+        assert_eq!(eval("vec(1, 2) + vec(3, 4)").unwrap(), v(4.0, 6.0));
+        assert_eq!(eval("vec(1, 2) - vec(3, 4)").unwrap(), v(-2.0, -2.0));
+        assert_eq!(eval("vec(1, 2) * vec(3, 4)").unwrap(), v(3.0, 8.0));
+        assert_eq!(eval("2 * vec(1, 2) * 1.5").unwrap(), v(3.0, 6.0));
+        assert_eq!(eval("vec(3, 4) / 2").unwrap(), v(1.5, 2.0));
+        assert_eq!(eval("-vec(1, -2)").unwrap(), v(-1.0, 2.0));
+        assert_eq!(eval("dot(vec(1, 2), vec(3, 4))").unwrap(), 11.0);
+        assert_eq!(eval("length(vec(3, 4))").unwrap(), 5.0);
+        assert_eq!(eval("normalize(vec(0, 2))").unwrap(), v(0.0, 1.0));
+        assert_eq!(eval("normalize(vec(0, 0))").unwrap(), v(0.0, 0.0));
+        assert_eq!(eval("vec(1, 2) == vec(1.0, 2.0)").unwrap(), true);
+
+        // Centre a 200x50 box in an 800x600 frame
+        let mut interp = Interpreter::new();
+        interp.exec_str("frame = vec(800, 600)").unwrap();
+        interp
+            .exec_str("fn centre(size) = (frame - size) / 2")
+            .unwrap();
+        let origin = interp.eval_str("centre(vec(200, 50))").unwrap();
+        assert_eq!(origin.to_string(), "vec(300.0, 275.0)");
+    }
+
+    #[test]
+    fn test_quantities() {
+        let show = |src: &str| eval(src).unwrap().to_string();
+        assert_eq!(show("3 m + 2 m"), "5 m");
+        assert_eq!(show("1 km + 250 m"), "1.25 km");
+        assert_eq!(show("20 C - 5 C"), "15 K");
+        assert_eq!(show("2 * 3 kg * 2"), "12 kg");
+        assert_eq!(show("-(3 s) % 2 s"), "-1 s");
+        assert_eq!(show("10 m / 4 s"), "2.5 m/s");
+        assert_eq!(show("2 kg * 3 m / (1 s) ^ 2"), "6 N");
+        assert_eq!(show("3 N * 2 m"), "6 J");
+        assert_eq!(show("(2 m) ^ 3"), "8 m^3");
+        assert_eq!(show("(2 s) ^ -1"), "0.5 1/s");
+        // Dimensions cancel out to a plain number
+        assert_eq!(eval("1 km / 1 m").unwrap(), 1000.0);
+
+        assert_eq!(eval("1 km == 1000 m").unwrap(), true);
+        assert_eq!(eval("1 km == 1000").unwrap(), false);
+        assert_eq!(eval("90 s < 2 min && 0 C > 31 F").unwrap(), true);
+    }
+
+    #[test]
+    fn test_temperature_arithmetic() {
+        let show = |src: &str| eval(src).unwrap().to_string();
+        let kelvin = |src: &str| match eval(src).unwrap() {
+            Value::Quantity(q) => q.value_in(crate::units::lookup("K").unwrap()),
+            other => panic!("{} is not a quantity", other),
+        };
+        let approx = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        // Differences agree whatever scale either side is on
+        assert!(approx(kelvin("20 C - 5 F"), 35.0));
+        assert!(approx(kelvin("5 F - 20 C"), -35.0));
+        assert!(approx(kelvin("(20 C -> K) - (5 F -> K)"), 35.0));
+        assert!(approx(kelvin("68 F - 20 C"), 0.0));
+        assert!(approx(kelvin("20 C - 5 K"), 288.15));
+        assert_eq!(show("(20 C -> K) * 2"), "586.3 K");
+
+        for src in [
+            "20 C + 5 F",
+            "5 F + 20 C",
+            "20 C + 5 C",
+            "20 C * 2",
+            "2 * 20 C",
+        ] {
+            assert!(
+                matches!(
+                    eval(src).unwrap_err(),
+                    CalcError::Eval(EvalError::InvalidArgument { .. })
+                ),
+                "{}",
+                src
+            );
+        }
+        let err = eval("1 K + 20 C").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: invalid argument to '+': \
+             20 C is on an offset scale; convert it to K first"
+        );
+        assert_eq!(err.span(), Span::new(6, 10));
+        assert!(eval("20 C / 2 C").is_err());
+        assert!(eval("(20 C) ^ 2").is_err());
+        assert!(eval("20 C - 5 m").is_err());
+    }
+
+    #[test]
+    fn test_unit_conversion() {
+        let show = |src: &str| eval(src).unwrap().to_string();
+        assert_eq!(show("20 C -> F"), "68 F");
+        assert_eq!(show("212 F -> C"), "100 C");
+        assert_eq!(show("0 C -> K"), "273.15 K");
+        assert_eq!(show("1.5 km + 20 m -> m"), "1520 m");
+        assert_eq!(show("90 min -> h"), "1.5 h");
+        assert_eq!(show("10 m / 4 s * 2 s -> cm"), "500 cm");
+
+        let mut interp = Interpreter::new();
+        interp.exec_str("fn to_f(t) = t -> F").unwrap();
+        assert_eq!(interp.eval_str("to_f(-40 C)").unwrap().to_string(), "-40 F");
+    }
+
+    #[test]
+    fn test_dimension_errors() {
+        let err = eval("3 m + 2 s").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: dimension mismatch: expected m, found s"
+        );
+        assert_eq!(err.span(), Span::new(6, 9));
+        assert_eq!(err.label(), "has dimension s");
+
+        let err = eval("3 m + 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: dimension mismatch: expected m, found dimensionless"
+        );
+        let err = eval("(10 m / 2 s) -> km").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: dimension mismatch: expected m, found m/s"
+        );
+        assert_eq!(err.span(), Span::new(0, 12));
+
+        assert!(matches!(
+            eval("5 -> m").unwrap_err(),
+            CalcError::Eval(EvalError::DimensionMismatch { .. })
+        ));
+        assert!(eval("1 kg < 1 s").is_err());
+        assert!(eval("2 ^ (1 m)").is_err());
+        assert!(eval("(1 m) ^ 0.5").is_err());
+        assert!(eval("sqrt(4 m)").is_err());
+        assert!(matches!(
+            eval("1 m / 0").unwrap_err(),
+            CalcError::Eval(EvalError::DivisionByZero { .. })
+        ));
+    }
+
+    #[test]
+    fn test_vector_errors() {
+        let err = eval("vec(1, 2) + 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected vector, found int"
+        );
+        assert_eq!(err.span(), Span::new(12, 13));
+
+        let err = eval("2 / vec(1, 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected number, found vector"
+        );
+        assert_eq!(err.span(), Span::new(4, 13));
+
+        assert_eq!(eval("vec(1, 2) ^ 2").unwrap_err().span(), Span::new(0, 9));
+        assert_eq!(
+            eval("vec(1, 2) < vec(2, 3)").unwrap_err().span(),
+            Span::new(0, 9)
+        );
+        assert!(matches!(
+            eval("vec(1, 2) / 0").unwrap_err(),
+            CalcError::Eval(EvalError::DivisionByZero { .. })
+        ));
+        assert!(eval("length(3)").is_err());
+        assert!(eval(r#"vec("x", 1)"#).is_err());
+        assert!(matches!(
+            eval("vec(1)").unwrap_err(),
+            CalcError::Eval(EvalError::ArityMismatch { expected: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_builtins() {
+        assert_eq!(eval("sqrt(16)").unwrap(), 4.0);
+        assert_eq!(eval("max(3, 7) - min(3, 7)").unwrap(), 4.0);
+        assert!(approx(eval("sin(pi / 2)").unwrap(), 1.0));
+        assert!(approx(eval("ln(e)").unwrap(), 1.0));
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut interp = Interpreter::new();
+        interp.set_var("x", 3.0);
+        interp.set_var("y", 4.0);
+        assert_eq!(interp.eval_str("sqrt(x^2 + y^2)").unwrap(), 5.0);
+
+        let names: Vec<&str> = interp.vars().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["x", "y"]);
+        interp.clear();
+        assert!(interp.get_var("x").is_none());
+    }
+
+    #[test]
+    fn test_let_bindings() {
+        assert_eq!(eval("let x = 3 in x * x").unwrap(), 9);
+        assert_eq!(eval("let x = 2 in let y = x + 1 in x * y").unwrap(), 6);
+    }
+
+    #[test]
+    fn test_let_shadowing() {
+        assert_eq!(eval("let x = 1 in let x = x + 10 in x").unwrap(), 11);
+        // Inner binding does not leak out of its body
+        assert_eq!(eval("(let x = 5 in x) + (let y = 1 in y)").unwrap(), 6);
+        assert_eq!(eval("let pi = 3 in pi").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_let_scope_does_not_leak() {
+        let err = eval("(let x = 1 in x) + x").unwrap_err();
+        assert_eq!(
+            err,
+            CalcError::Eval(EvalError::UnknownVariable {
+                name: "x".to_string(),
+                span: Span::new(19, 20),
+            })
+        );
+    }
+
+    #[test]
+    fn test_assignment() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.exec_str("x = 5").unwrap(), 5);
+        assert_eq!(interp.exec_str("y = x * 2").unwrap(), 10);
+        assert_eq!(interp.exec_str("x = x + y").unwrap(), 15);
+
+        // A let binding shadows the global without changing it
+        assert_eq!(interp.exec_str("let x = 1 in x + y").unwrap(), 11);
+        assert_eq!(interp.get_var("x"), Some(&Value::Int(15)));
+    }
+
+    #[test]
+    fn test_failed_assignment_keeps_old_value() {
+        let mut interp = Interpreter::new();
+        interp.exec_str("x = 1").unwrap();
+        assert!(interp.exec_str("x = nope").is_err());
+        assert_eq!(interp.get_var("x"), Some(&Value::Int(1)));
+    }
+
+    #[test]
+    fn test_fn_definition() {
+        let mut interp = Interpreter::new();
+        interp.exec_str("fn sq(x) = x * x").unwrap();
+        interp
+            .exec_str("fn hyp(a, b) = sqrt(sq(a) + sq(b))")
+            .unwrap();
+        assert_eq!(interp.eval_str("hyp(3, 4)").unwrap(), 5.0);
+
+        // Globals are read when the function runs, not when it is defined
+        interp.exec_str("fn taxed(p) = p * (1 + rate)").unwrap();
+        interp.exec_str("rate = 0.5").unwrap();
+        assert_eq!(interp.eval_str("taxed(10)").unwrap(), 15.0);
+        assert_eq!(interp.call_function("taxed", vec![4.into()]).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_calls_function_defined_later() {
+        let mut interp = Interpreter::new();
+        interp
+            .exec_str("fn fact(n) = fold(range_to(n), 1, |a, b| a * b)")
+            .unwrap();
+        interp
+            .exec_str("fn range_to(n) = map(list(1, 2, 3, 4, 5), |i| i)")
+            .unwrap();
+        assert_eq!(interp.eval_str("fact(5)").unwrap(), 120);
+    }
+
+    #[test]
+    fn test_lambda_values() {
+        assert_eq!(eval("let add = |x, y| x + y in add(2, 3)").unwrap(), 5);
+        assert_eq!(eval("let k = || 7 in k()").unwrap(), 7);
+
+        let mut interp = Interpreter::new();
+        interp.exec_str("double = |x| x * 2").unwrap();
+        assert_eq!(interp.eval_str("double(double(3))").unwrap(), 12);
+    }
+
+    #[test]
+    fn test_closures_capture_scope() {
+        let mut interp = Interpreter::new();
+        // `n` is gone once the let body finishes, but the lambda keeps it
+        interp.exec_str("add5 = let n = 5 in |x| x + n").unwrap();
+        assert_eq!(interp.eval_str("add5(10)").unwrap(), 15);
+
+        // Functions returning functions
+        interp.exec_str("fn adder(n) = |x| x + n").unwrap();
+        assert_eq!(
+            interp.eval_str("let inc = adder(1) in inc(41)").unwrap(),
+            42
+        );
+        // ...called straight away
+        assert_eq!(interp.eval_str("adder(1)(2)").unwrap(), 3);
+        assert_eq!(interp.eval_str("(|x, y| x + y)(1, 2)").unwrap(), 3);
+        assert_eq!(interp.eval_str("{ f: adder(3) }.f(4)").unwrap(), 7);
+        assert!(matches!(
+            interp.eval_str("(1)(2)"),
+            Err(CalcError::Eval(EvalError::TypeMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_lambda_params_shadow_captures() {
+        assert_eq!(
+            eval("let x = 1 in let f = |x| x * 10 in f(5) + x").unwrap(),
+            51
+        );
+    }
+
+    #[test]
+    fn test_map_filter_fold() {
+        assert_eq!(
+            eval("map(list(1, 2, 3), |x| x * x)").unwrap(),
+            ints(&[1, 4, 9])
+        );
+        assert_eq!(
+            eval("filter(list(1, 2, 3, 4, 5), |x| x % 2 == 1)").unwrap(),
+            ints(&[1, 3, 5])
+        );
+        assert_eq!(
+            eval("fold(list(1, 2, 3, 4), 0, |acc, x| acc + x)").unwrap(),
+            10
+        );
+        assert_eq!(eval("len(list())").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sum() {
+        assert_eq!(eval("sum(list(1, 2, 3))").unwrap(), 6);
+        assert_eq!(eval("sum(list(1, 2.5))").unwrap(), 3.5);
+        assert_eq!(eval("sum(list())").unwrap(), 0);
+        assert_eq!(
+            eval("sum(list(vec(1, 2), vec(3, 4)))").unwrap().to_string(),
+            "vec(4.0, 6.0)"
+        );
+        assert!(matches!(
+            eval("sum(list(9223372036854775807, 1))").unwrap_err(),
+            CalcError::Eval(EvalError::IntegerOverflow { .. })
+        ));
+        assert!(eval("sum(list(1, true))").is_err());
+        assert!(eval("sum(3)").is_err());
+    }
+
+    #[test]
+    fn test_sequences() {
+        assert_eq!(eval("[1, 2, 3]").unwrap(), ints(&[1, 2, 3]));
+        assert_eq!(eval("sum(1..101)").unwrap(), 5050);
+        assert_eq!(eval("product(1..6)").unwrap(), 120);
+        assert_eq!(
+            eval("collect(range(0, 20, 5))").unwrap(),
+            ints(&[0, 5, 10, 15])
+        );
+        assert_eq!(eval("collect(range(3))").unwrap(), ints(&[0, 1, 2]));
+        assert_eq!(eval("len(10..0)").unwrap(), 0);
+        assert_eq!(eval("sum(take(fib(), 10))").unwrap(), 88);
+        assert_eq!(eval("take([1, 2, 3], 5)").unwrap(), ints(&[1, 2, 3]));
+        assert_eq!(eval("product([1.5, 2, 4])").unwrap(), 12.0);
+        assert_eq!(eval("fold(1..5, 0, |acc, x| acc * 10 + x)").unwrap(), 1234);
+
+        // Mapping and filtering an endless sequence is fine until it is read
+        let evens = "take(filter(map(fib(), |n| n * 2), |n| n % 4 == 0), 4)";
+        assert_eq!(
+            eval(&format!("collect({})", evens)).unwrap(),
+            ints(&[0, 4, 16, 68])
+        );
+        assert_eq!(
+            eval("take(fib(), 3)").unwrap().to_string(),
+            "take(fib(), 3)"
+        );
+        assert_eq!(eval("2..5").unwrap().to_string(), "2..5");
+
+        let err = eval("sum(map(fib(), |n| n))").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: 'sum' needs a sequence with an end; limit it with take()"
+        );
+        assert_eq!(err.span(), Span::new(0, 22));
+        assert_eq!(
+            eval("range(0, 10, 0)").unwrap_err().to_string(),
+            "evaluation error: invalid argument to 'range': step must not be zero"
+        );
+        assert!(eval("take(fib(), -1)").is_err());
+        assert!(eval("1.5..3").is_err());
+        assert!(
+            eval("sum(take(fib(), 100))").is_err(),
+            "past the last i64 term"
+        );
+    }
+
+    #[test]
+    fn test_sequences_respect_limits() {
+        let interp = Interpreter::new().with_limits(EvalLimits::none().max_steps(1000));
+        let expr: Expr = "len(filter(take(fib(), 90), |n| n < 0))".parse().unwrap();
+        assert!(interp.eval(&expr).is_ok());
+        let expr: Expr = "sum(1..1000000000000)".parse().unwrap();
+        assert!(matches!(
+            interp.eval(&expr),
+            Err(EvalError::LimitExceeded { .. })
+        ));
+        // Lengths of ranges are worked out, not counted
+        let expr: Expr = "len(1..1000000000000) + len(take(range(9, 0, -2), 3))"
+            .parse()
+            .unwrap();
+        assert_eq!(interp.eval(&expr).unwrap(), 999_999_999_999 + 3);
+
+        // Aggregations read items one at a time instead of building a list
+        let interp = Interpreter::new().with_limits(EvalLimits::none().max_len(10));
+        let read = |src: &str| interp.eval(&src.parse().unwrap());
+        assert_eq!(read("sum(1..1001)").unwrap(), 500_500);
+        assert_eq!(
+            read("product(map(1..21, |n| n))").unwrap(),
+            2_432_902_008_176_640_000_i64
+        );
+        assert_eq!(read("fold(1..101, 0, |acc, n| acc + 1)").unwrap(), 100);
+        assert_eq!(read("len(filter(1..101, |n| n % 2 == 0))").unwrap(), 50);
+        assert!(matches!(
+            read("collect(1..101)"),
+            Err(EvalError::LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_exact_mode() {
+        let interp = Interpreter::new().with_mode(NumericMode::Exact);
+        let exact = |src: &str| interp.eval_str(src).map(|value| value.to_string());
+        assert_eq!(exact("1/3 + 1/6").unwrap(), "1/2");
+        assert_eq!(exact("0.1 * 3 - 1/5").unwrap(), "1/10");
+        assert_eq!(exact("0.1 + 0.2 == 0.3").unwrap(), "true");
+        assert_eq!(exact("2 ^ 100").unwrap(), "1267650600228229401496703205376");
+        assert_eq!(
+            exact("123456789012345678901234567890 / 3").unwrap(),
+            "41152263004115226300411522630"
+        );
+        assert_eq!(exact("(2/3) ^ -2 + -7 % 2").unwrap(), "5/4");
+        assert_eq!(
+            exact("product(1..31)").unwrap(),
+            "265252859812191058636308480000000"
+        );
+        assert_eq!(exact("int(-7/2) + len(\"abc\") / 2").unwrap(), "-3/2");
+        assert_eq!(exact("1/3 < 0.34 && 1 == 1.0").unwrap(), "true");
+        assert_eq!(exact("money(2.5) * 3").unwrap(), "money(7.50)");
+        assert_eq!(exact("collect(range(0, 1, 1/2 * 2))").unwrap(), "[0]");
+        assert_eq!(exact("round(7/2) + round(-7/2)").unwrap(), "0");
+        assert_eq!(exact("abs(-1/3)").unwrap(), "1/3");
+        assert_eq!(exact("max(1/3, 1/4) - min(1/3, 1/4)").unwrap(), "1/12");
+        assert_eq!(exact("floor(-7/2) + ceil(7/2)").unwrap(), "0");
+        assert_eq!(
+            exact("floor(10 ^ 30 + 1/2)").unwrap(),
+            "1".to_string() + &"0".repeat(30)
+        );
+
+        // Floats only on request, or where no exact answer exists
+        assert_eq!(interp.eval_str("float(1/8)").unwrap(), 0.125);
+        assert!(approx(
+            interp.eval_str("sqrt(float(2)) * 2").unwrap(),
+            8_f64.sqrt()
+        ));
+        assert!(approx(
+            interp.eval_str("sin(float(1/2))").unwrap(),
+            0.5_f64.sin()
+        ));
+        assert_eq!(interp.eval_str("float(4) ^ 0.5").unwrap(), 2.0);
+        assert_eq!(exact("3 m ^ 2").unwrap(), "9 m^2");
+
+        let err = |src: &str| interp.eval_str(src).unwrap_err();
+        assert!(matches!(
+            err("1 / (1/2 - 0.5)"),
+            CalcError::Eval(EvalError::DivisionByZero { .. })
+        ));
+        assert!(matches!(
+            err("0 ^ -1"),
+            CalcError::Eval(EvalError::DivisionByZero { .. })
+        ));
+        assert!(matches!(
+            err("10 ^ 100000"),
+            CalcError::Eval(EvalError::IntegerOverflow { .. })
+        ));
+        assert_eq!(exact("1 ^ 100000000 + (-1) ^ 100000001").unwrap(), "0");
+        assert_eq!(exact("(-1) ^ (10 ^ 30 + 1) + 1 ^ -(10 ^ 30)").unwrap(), "0");
+        assert!(matches!(
+            err("2 ^ 10 ^ 30"),
+            CalcError::Eval(EvalError::IntegerOverflow { .. })
+        ));
+        assert_eq!(
+            err("if 1/2 then 1 else 2").to_string(),
+            "evaluation error: type mismatch: expected bool, found rational"
+        );
+        assert_eq!(
+            err("1..2.5").to_string(),
+            "evaluation error: type mismatch: expected int, found rational"
+        );
+        assert_eq!(
+            err("sqrt(2)").to_string(),
+            "evaluation error: invalid argument to 'sqrt': \
+             no exact answer; try sqrt(float(x)) instead"
+        );
+        assert_eq!(
+            err("2 ^ (1/2)").to_string(),
+            "evaluation error: invalid argument to '^': \
+             no exact answer; try float(x) ^ y instead"
+        );
+        assert!(matches!(
+            err("4 ^ 0.5"),
+            CalcError::Eval(EvalError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            err("ln(1/2)"),
+            CalcError::Eval(EvalError::InvalidArgument { .. })
+        ));
+
+        // Float mode is unchanged, and still limited to 64-bit ints
+        assert!(approx(eval("1/3 + 1/6").unwrap(), 0.5));
+        assert!(matches!(
+            eval("123456789012345678901234567890 / 3"),
+            Err(CalcError::Eval(EvalError::IntegerOverflow { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_money() {
+        assert_eq!(
+            eval("sum([money(19.99), money(0.01), money(5)])")
+                .unwrap()
+                .to_string(),
+            "money(25.00)"
+        );
+        // Cents stay exact where floats drift
+        assert_eq!(
+            eval("sum(map(1..11, |i| money(0.1)))").unwrap().to_string(),
+            "money(1.00)"
+        );
+        assert_eq!(
+            eval("money(2.5) * 3 - money(0.5)").unwrap().to_string(),
+            "money(7.00)"
+        );
+        assert_eq!(eval("-money(1.25)").unwrap().to_string(), "money(-1.25)");
+        assert_eq!(eval("money(1) < money(1.01)").unwrap(), true);
+        assert_eq!(eval("money(1) == money(1.00)").unwrap(), true);
+
+        assert_eq!(
+            eval("money(1) * 1.5").unwrap_err().to_string(),
+            "evaluation error: type mismatch: expected int, found float"
+        );
+        assert!(eval("money(1) + 1").is_err());
+        assert!(eval("product([money(1), money(2)])").is_err());
+        assert!(eval("money(1) / 2").is_err());
+    }
+
+    #[test]
+    fn test_complex() {
+        let complex = |src: &str| eval(src).unwrap().to_string();
+        assert_eq!(complex("3 + 4i"), "3 + 4i");
+        assert_eq!(complex("(3 + 4i) * (1 - 2i)"), "11 - 2i");
+        assert_eq!(complex("(3 + 4i) / (1 - 2i)"), "-1 + 2i");
+        assert_eq!(complex("-(1 + 1i) + 2"), "1 - 1i");
+        assert_eq!(complex("1i ^ 2"), "-1 + 0i");
+        assert_eq!(complex("sqrt(-4)"), "0 + 2i");
+        assert_eq!(complex("sqrt(-7 + 24i)"), "3 + 4i");
+        assert_eq!(complex("conj(3 + 4i)"), "3 - 4i");
+        assert_eq!(eval("sqrt(16)").unwrap(), Value::Float(4.0));
+        assert_eq!(eval("abs(3 - 4i)").unwrap(), 5.0);
+        // Real arguments keep their own type, and ints stay checked
+        assert_eq!(eval("abs(-3)").unwrap(), 3);
+        assert_eq!(eval("abs(-2.5)").unwrap(), 2.5);
+        assert!(matches!(
+            eval("abs(-9223372036854775807 - 1)"),
+            Err(CalcError::Eval(EvalError::IntegerOverflow { .. }))
+        ));
+        assert_eq!(eval("arg(-1)").unwrap(), std::f64::consts::PI);
+        assert_eq!(eval("re(2 - 5i) + im(2 - 5i)").unwrap(), -3.0);
+        assert_eq!(eval("conj(2)").unwrap(), 2);
+        assert_eq!(eval("(1 + 1i) * (1 - 1i) == 2").unwrap(), true);
+        assert_eq!(eval("1i == 1").unwrap(), false);
+
+        let z = eval("let p = polar(1 + 1i) in rect(p.r, p.theta)").unwrap();
+        let Value::Complex(z) = z else {
+            panic!("expected a complex number, got {}", z);
+        };
+        assert!((z - Complex::new(1.0, 1.0)).abs() < 1e-12);
+        assert_eq!(
+            eval("polar(-2)").unwrap(),
+            Value::record([
+                ("r".to_string(), Value::Float(2.0)),
+                ("theta".to_string(), Value::Float(std::f64::consts::PI)),
+            ])
+        );
+
+        assert!(matches!(
+            eval("1 / 0i").unwrap_err(),
+            CalcError::Eval(EvalError::DivisionByZero { .. })
+        ));
+        assert_eq!(
+            eval("(1 + 1i) % 2").unwrap_err().to_string(),
+            "evaluation error: type mismatch: expected number, found complex"
+        );
+        assert!(eval("1i < 2").is_err());
+        assert!(eval("abs(vec(3, 4))").is_err());
+    }
+
+    #[test]
+    fn test_higher_order_with_named_functions() {
+        let mut interp = Interpreter::new();
+        interp.exec_str("fn sq(x) = x * x").unwrap();
+        interp.exec_str("prices = list(10, 20, 30)").unwrap();
+        interp
+            .exec_str("fn with_tax(rate) = |p| p * (1 + rate)")
+            .unwrap();
+
+        assert_eq!(
+            interp.eval_str("map(prices, sq)").unwrap(),
+            ints(&[100, 400, 900])
+        );
+        assert!(approx(
+            interp
+                .eval_str("fold(map(prices, with_tax(0.5)), 0, |a, b| a + b)")
+                .unwrap(),
+            90.0
+        ));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let err = eval("let f = |x| x in f(1, 2)").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::ArityMismatch {
+                expected: 1,
+                found: 2,
+                ..
+            })
+        ));
+
+        let err = eval("let f = 3 in f(1)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected function, found int"
+        );
+
+        let err = eval("map(1, |x| x)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected list, found int"
+        );
+
+        let err = eval("list(1) + 1").unwrap_err();
+        assert_eq!(err.span(), Span::new(0, 7));
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let mut registry = FunctionRegistry::new();
+        registry
+            .register("clamp", |x: f64, lo: f64, hi: f64| x.clamp(lo, hi))
+            .register("shout", |s: String| s.to_uppercase() + "!");
+        let mut interp = Interpreter::new().with_registry(registry);
+
+        assert_eq!(interp.eval_str("clamp(15, 0, 10)").unwrap(), 10.0);
+        assert_eq!(
+            interp.eval_str(r#"shout("hi")"#).unwrap(),
+            Value::from("HI!")
+        );
+        assert_eq!(
+            interp
+                .eval_str("map(list(-5, 5), |x| clamp(x, 0, 1))")
+                .unwrap(),
+            Value::list(vec![Value::Float(0.0), Value::Float(1.0)])
+        );
+
+        let err = interp.eval_str("clamp(1, 2)").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::ArityMismatch {
+                expected: 3,
+                found: 2,
+                ..
+            })
+        ));
+        let err = interp.eval_str("1 + shout(42)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: type mismatch: expected string, found int"
+        );
+        assert_eq!(err.span(), Span::new(4, 13));
+
+        // User functions shadow registered ones
+        interp.exec_str("fn shout(s) = s").unwrap();
+        assert_eq!(
+            interp.eval_str(r#"shout("hi")"#).unwrap(),
+            Value::from("hi")
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        use crate::limits::Limit;
+        use std::time::Duration;
+
+        let mut interp = Interpreter::new().with_limits(EvalLimits::none().max_steps(10_000));
+        interp
+            .exec_str("fn fib(n) = if n < 2 then n else fib(n - 1) + fib(n - 2)")
+            .unwrap();
+        assert_eq!(interp.eval_str("fib(10)").unwrap(), 55);
+        let err = interp.eval_str("fib(40)").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::LimitExceeded {
+                limit: Limit::Steps(10_000),
+                ..
+            })
+        ));
+        // Each evaluation starts with a fresh budget
+        assert_eq!(interp.eval_str("fib(10)").unwrap(), 55);
+
+        // Untrusted formulas are also kept shallow when parsed
+        let deep = format!("{}1{}", "(".repeat(80), ")".repeat(80));
+        assert_eq!(Interpreter::new().eval_str(&deep).unwrap(), 1);
+        let err = Interpreter::new()
+            .with_limits(EvalLimits::untrusted())
+            .eval_str(&deep)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Parse(crate::error::ParseError::TooDeep { max: 64, .. })
+        ));
+
+        let mut interp = Interpreter::new().with_limits(EvalLimits::none().max_depth(50));
+        interp
+            .exec_str("fn down(n) = if n == 0 then 0 else down(n - 1)")
+            .unwrap();
+        // Each level is three frames: the call, its `if` and the recursive call
+        assert_eq!(interp.eval_str("down(15)").unwrap(), 0);
+        let err = interp.eval_str("down(16)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "evaluation error: evaluation limit exceeded: evaluation nested more than 50 deep"
+        );
+        assert_eq!(err.span(), Span::new(40, 41));
+
+        // Nesting inside a function counts as well as the calls themselves
+        let nested = format!("{}deep(n - 1){}", "(1 + ".repeat(30), ")".repeat(30));
+        interp
+            .exec_str(&format!("fn deep(n) = if n == 0 then 0 else {}", nested))
+            .unwrap();
+        assert_eq!(interp.eval_str("deep(1)").unwrap(), 30);
+        assert!(matches!(
+            interp.eval_str("deep(2)").unwrap_err(),
+            CalcError::Eval(EvalError::LimitExceeded {
+                limit: Limit::Depth(50),
+                ..
+            })
+        ));
+
+        let interp = Interpreter::new().with_limits(EvalLimits::none().max_len(3));
+        assert_eq!(
+            interp.eval_str(r#""ab" + "c""#).unwrap(),
+            Value::from("abc")
+        );
+        let err = interp.eval_str(r#"len("ab" + "cd")"#).unwrap_err();
+        assert_eq!(err.span(), Span::new(4, 15));
+        assert!(interp.eval_str("list(1, 2, 3, 4)").is_err());
+        assert!(
+            interp
+                .eval_str("map(list(1, 2, 3), |x| list(x, x))")
+                .is_ok()
+        );
+
+        let mut interp =
+            Interpreter::new().with_limits(EvalLimits::none().max_time(Duration::ZERO));
+        interp
+            .exec_str("fn spin(n) = if n == 0 then 0 else spin(n - 1)")
+            .unwrap();
+        assert!(matches!(
+            interp.eval_str("spin(1000)").unwrap_err(),
+            CalcError::Eval(EvalError::LimitExceeded {
+                limit: Limit::Time(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_default_limits_stop_runaway_recursion() {
+        use crate::limits::{DEFAULT_MAX_DEPTH, Limit};
+
+        // Runaway recursion, with and without nesting inside each call and
+        // through higher-order builtins, on a default-sized thread stack
+        let nested = format!("{}g(n - 1){}", "(1 + ".repeat(30), ")".repeat(30));
+        let defs = [
+            "fn f(n) = f(n)".to_string(),
+            format!("fn g(n) = if n == 0 then 0 else {}", nested),
+            "fn h(n) = if n == 0 then list(0) else map(list(1), |x| h(n - 1))".to_string(),
+            "fn down(n) = if n == 0 then 0 else down(n - 1)".to_string(),
+        ];
+        for limits in [EvalLimits::default(), EvalLimits::untrusted()] {
+            let defs = defs.clone();
+            let errors = std::thread::spawn(move || {
+                let mut interp = Interpreter::new().with_limits(limits);
+                for def in &defs {
+                    interp.exec_str(def).unwrap();
+                }
+                assert_eq!(interp.eval_str("down(20)").unwrap(), 0);
+                ["f(1)", "g(1000)", "h(1000)", "down(5000)"].map(|src| interp.eval_str(src))
+            })
+            .join()
+            .unwrap();
+            let max = limits.max_depth.unwrap();
+            for err in errors {
+                assert!(matches!(
+                    err,
+                    Err(CalcError::Eval(EvalError::LimitExceeded {
+                        limit: Limit::Depth(depth),
+                        ..
+                    })) if depth == max
+                ));
+            }
+        }
+        assert_eq!(EvalLimits::default().max_depth, Some(DEFAULT_MAX_DEPTH));
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = eval("1 + foo").unwrap_err();
+        assert_eq!(
+            err,
+            CalcError::Eval(EvalError::UnknownVariable {
+                name: "foo".to_string(),
+                span: Span::new(4, 7),
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_function_and_arity() {
+        let err = eval("nope(1)").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::UnknownFunction { .. })
+        ));
+
+        let err = eval("sqrt(1, 2)").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::ArityMismatch {
+                expected: 1,
+                found: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let err = eval("1 / (2 - 2)").unwrap_err();
+        assert_eq!(
+            err,
+            CalcError::Eval(EvalError::DivisionByZero {
+                span: Span::new(0, 11),
+            })
+        );
+        assert!(eval("5 % 0").is_err());
+    }
+
+    #[test]
+    fn test_records() {
+        let order = eval("{ price: 10, qty: 3 }").unwrap();
+        assert_eq!(order.to_string(), "{ price: 10, qty: 3 }");
+        assert_eq!(eval("{ price: 10, qty: 3 }.qty").unwrap(), 3);
+        assert_eq!(
+            eval("let o = { price: 2.5, qty: 4 } in o.price * o.qty").unwrap(),
+            10.0
+        );
+        assert_eq!(eval("{ a: { b: true } }.a.b").unwrap(), true);
+        assert_eq!(eval("{ a: 1, b: 2 } == { b: 2.0, a: 1 }").unwrap(), true);
+        assert_eq!(
+            eval("map([1, 2], |n| { n: n, sq: n * n }.sq)").unwrap(),
+            Value::list(vec![1.into(), 4.into()])
+        );
+
+        let err = eval("{ a: 1 }.b").unwrap_err();
+        assert_eq!(
+            err,
+            CalcError::Eval(EvalError::NoField {
+                name: "b".to_string(),
+                span: Span::new(0, 10),
+            })
+        );
+        assert_eq!(err.to_string(), "evaluation error: record has no field 'b'");
+        let err = eval("(1 + 2).a").unwrap_err();
+        assert!(matches!(
+            err,
+            CalcError::Eval(EvalError::TypeMismatch { span, .. }) if span == Span::new(0, 7)
+        ));
+    }
+
+    #[test]
+    fn test_match() {
+        let mut interp = Interpreter::new();
+        interp
+            .exec_str(
+                r#"fn size(n) = match n { 0 => "none", 1..10 => "few", 10.. => "many", _ => "less" }"#,
+            )
+            .unwrap();
+        let sizes: Vec<String> = ["0", "0.0", "3", "9.99", "10", "1e9", "-1"]
+            .iter()
+            .map(|n| {
+                interp
+                    .eval_str(&format!("size({})", n))
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                "\"none\"", "\"none\"", "\"few\"", "\"few\"", "\"many\"", "\"many\"", "\"less\""
+            ]
+        );
+        assert_eq!(
+            eval(r#"match "b" { "a" => 1, true => 2, 1..5 => 3, _ => 4 }"#).unwrap(),
+            4
+        );
+        assert_eq!(eval("match 1 < 2 { true => 1, false => 0 }").unwrap(), 1);
+
+        // Exact numbers match ranges exactly
+        let exact = Interpreter::new().with_mode(NumericMode::Exact);
+        let result = exact.eval_str("match 1/3 { ..0.5 => 1, _ => 2 }").unwrap();
+        assert_eq!(result.to_string(), "1");
+
+        let err = eval("match 5 { 0 => 1, ..5 => 2 }").unwrap_err();
+        assert_eq!(
+            err,
+            CalcError::Eval(EvalError::NoMatch {
+                value: "5".to_string(),
+                span: Span::new(0, 28),
+            })
+        );
+        assert_eq!(err.to_string(), "evaluation error: no match arm for 5");
+    }
+
+    #[test]
+    fn test_parse_errors_pass_through() {
+        let err = eval("1 +").unwrap_err();
+        assert!(matches!(err, CalcError::Parse(_)));
+    }
+}
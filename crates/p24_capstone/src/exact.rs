@@ -0,0 +1,758 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Formatter},
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+use crate::{error::EvalError, lexer::Span, value::Value};
+
+// ------------------------------------------------
+/// Largest power, in bits, that `^` builds exactly before giving up with
+/// an overflow error rather than running for minutes.
+pub(crate) const MAX_POW_BITS: u64 = 1 << 16;
+
+/// How number literals evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NumericMode {
+    /// Ints are `i64`, and `/` gives floats
+    #[default]
+    Float,
+    /// Numbers are `Rational`s: integers of any size, and fractions kept
+    /// exact in lowest terms. Decimals mean the decimal written, so `0.1`
+    /// is exactly `1/10`. Floats only come from `float(x)`; functions
+    /// like `sqrt` that have no exact answer need a float to work on.
+    Exact,
+}
+
+impl NumericMode {
+    /// A number literal, or an int produced by a builtin, as this mode
+    /// represents it; other values pass through.
+    pub fn number(self, value: Value) -> Value {
+        match (self, &value) {
+            (NumericMode::Exact, Value::Int(n)) => Value::from(Rational::from(*n)),
+            (NumericMode::Exact, Value::Float(n)) => {
+                Rational::from_decimal(*n).map_or(value, Value::from)
+            }
+            _ => value,
+        }
+    }
+
+    /// An integer literal too big for an `i64`, which float mode has no
+    /// room for.
+    pub fn big_int(self, n: &BigInt, span: Span) -> Result<Value, EvalError> {
+        match self {
+            NumericMode::Exact => Ok(Value::from(Rational::integer(n.clone()))),
+            NumericMode::Float => Err(EvalError::IntegerOverflow { span }),
+        }
+    }
+}
+
+impl Display for NumericMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumericMode::Float => write!(f, "float"),
+            NumericMode::Exact => write!(f, "exact"),
+        }
+    }
+}
+
+impl FromStr for NumericMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "float" => Ok(NumericMode::Float),
+            "exact" => Ok(NumericMode::Exact),
+            other => Err(format!(
+                "unknown numeric mode '{}' (expected 'float' or 'exact')",
+                other
+            )),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// An integer of any size: a sign and base-2^32 digits, least significant
+/// first.
+///
+/// Digits never end in a zero and zero is never negative, so each value
+/// has exactly one representation and derived equality is numeric.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut digits: Vec<u32>) -> Self {
+        trim(&mut digits);
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// Bits needed for the magnitude; zero needs none.
+    pub fn bits(&self) -> u64 {
+        bits(&self.digits)
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = i128::from(
+            self.digits
+                .iter()
+                .rev()
+                .fold(0_u64, |acc, &digit| (acc << 32) | u64::from(digit)),
+        );
+        i64::try_from(if self.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    /// The nearest float for up to about 1000 bits; beyond that, infinity.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &digit| acc * 4_294_967_296.0 + f64::from(digit));
+        if self.negative { -magnitude } else { magnitude }
+    }
+
+    /// Truncating division, so the remainder has the sign of `self` as
+    /// with `i64`; `None` when dividing by zero.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_digits(&self.digits, &divisor.digits);
+        Some((
+            BigInt::from_parts(self.negative != divisor.negative, quotient),
+            BigInt::from_parts(self.negative, remainder),
+        ))
+    }
+
+    /// Greatest common divisor, never negative.
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.digits.clone(), other.digits.clone());
+        while !b.is_empty() {
+            let (_, remainder) = div_rem_digits(&a, &b);
+            a = b;
+            b = remainder;
+        }
+        BigInt::from_parts(false, a)
+    }
+
+    pub fn pow(&self, mut exp: u64) -> BigInt {
+        let mut base = self.clone();
+        let mut result = BigInt::from(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// Shift the magnitude left.
+    fn shl(&self, shift: u64) -> BigInt {
+        let (words, bits) = ((shift / 32) as usize, (shift % 32) as u32);
+        let mut digits = vec![0; words];
+        let mut carry = 0;
+        for &digit in &self.digits {
+            match bits {
+                0 => digits.push(digit),
+                _ => {
+                    digits.push((digit << bits) | carry);
+                    carry = digit >> (32 - bits);
+                }
+            }
+        }
+        digits.push(carry);
+        BigInt::from_parts(self.negative, digits)
+    }
+
+    /// Shift the magnitude right, rounding toward zero.
+    fn shr(&self, shift: u64) -> BigInt {
+        let (words, bits) = ((shift / 32) as usize, (shift % 32) as u32);
+        let Some(kept) = self.digits.get(words..) else {
+            return BigInt::default();
+        };
+        let digits = match bits {
+            0 => kept.to_vec(),
+            _ => (0..kept.len())
+                .map(|i| {
+                    let high = kept.get(i + 1).map_or(0, |&next| next << (32 - bits));
+                    (kept[i] >> bits) | high
+                })
+                .collect(),
+        };
+        BigInt::from_parts(self.negative, digits)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let magnitude = n.unsigned_abs();
+        BigInt::from_parts(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+/// Decimal digits with an optional leading `-`.
+impl FromStr for BigInt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("'{}' is not an integer", s));
+        }
+        let mut magnitude = Vec::new();
+        for b in digits.bytes() {
+            mul_small_add(&mut magnitude, 10, u32::from(b - b'0'));
+        }
+        Ok(BigInt::from_parts(negative, magnitude))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Nine decimal digits at a time, least significant first
+        let mut rest = self.digits.clone();
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            chunks.push(div_small(&mut rest, CHUNK));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(top) = chunks.next() {
+            write!(f, "{}", top)?;
+        }
+        chunks.try_for_each(|chunk| write!(f, "{:09}", chunk))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: &BigInt) -> BigInt {
+        if self.negative == rhs.negative {
+            return BigInt::from_parts(self.negative, add_digits(&self.digits, &rhs.digits));
+        }
+        // Opposite signs: the larger magnitude wins and keeps its sign
+        let (larger, smaller) = match cmp_digits(&self.digits, &rhs.digits) {
+            Ordering::Less => (rhs, self),
+            _ => (self, rhs),
+        };
+        let mut digits = larger.digits.clone();
+        sub_assign_digits(&mut digits, &smaller.digits);
+        BigInt::from_parts(larger.negative, digits)
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: &BigInt) -> BigInt {
+        self + &-rhs
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != rhs.negative,
+            mul_digits(&self.digits, &rhs.digits),
+        )
+    }
+}
+
+// ------------------------------------------------
+// Magnitudes: little-endian base-2^32 digits without trailing zeros.
+
+fn trim(digits: &mut Vec<u32>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn bits(digits: &[u32]) -> u64 {
+    digits.last().map_or(0, |top| {
+        digits.len() as u64 * 32 - u64::from(top.leading_zeros())
+    })
+}
+
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0_u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let total = u64::from(digit) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        sum.push(total as u32);
+        carry = total >> 32;
+    }
+    if carry > 0 {
+        sum.push(carry as u32);
+    }
+    sum
+}
+
+/// `a -= b`, where `a >= b`.
+fn sub_assign_digits(a: &mut Vec<u32>, b: &[u32]) {
+    let mut borrow = false;
+    for (i, digit) in a.iter_mut().enumerate() {
+        let (diff, under) = digit.overflowing_sub(b.get(i).copied().unwrap_or(0));
+        let (diff, under_again) = diff.overflowing_sub(u32::from(borrow));
+        *digit = diff;
+        borrow = under || under_again;
+    }
+    debug_assert!(!borrow, "subtracted a larger magnitude");
+    trim(a);
+}
+
+/// Schoolbook multiplication; operands here stay small enough for it.
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut product = vec![0_u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0_u64;
+        for (j, &y) in b.iter().enumerate() {
+            // At most (2^32 - 1)^2 + 2 * (2^32 - 1), which fits
+            let total = u64::from(x) * u64::from(y) + u64::from(product[i + j]) + carry;
+            product[i + j] = total as u32;
+            carry = total >> 32;
+        }
+        product[i + b.len()] = carry as u32;
+    }
+    trim(&mut product);
+    product
+}
+
+/// `a = a * factor + add`.
+fn mul_small_add(a: &mut Vec<u32>, factor: u32, add: u32) {
+    let mut carry = u64::from(add);
+    for digit in a.iter_mut() {
+        let total = u64::from(*digit) * u64::from(factor) + carry;
+        *digit = total as u32;
+        carry = total >> 32;
+    }
+    if carry > 0 {
+        a.push(carry as u32);
+    }
+}
+
+/// `a /= divisor`, returning the remainder.
+fn div_small(a: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0_u64;
+    for digit in a.iter_mut().rev() {
+        let current = (remainder << 32) | u64::from(*digit);
+        *digit = (current / u64::from(divisor)) as u32;
+        remainder = current % u64::from(divisor);
+    }
+    trim(a);
+    remainder as u32
+}
+
+/// Quotient and remainder by shift-and-subtract, one quotient bit at a
+/// time; `b` is not zero.
+fn div_rem_digits(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_digits(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, *divisor);
+        return (quotient, BigInt::from(i64::from(remainder)).digits);
+    }
+
+    let shift = bits(a) - bits(b);
+    let mut divisor = BigInt::from_parts(false, b.to_vec()).shl(shift).digits;
+    let mut remainder = a.to_vec();
+    let mut quotient = vec![0_u32; (shift / 32) as usize + 1];
+    for bit in (0..=shift).rev() {
+        if cmp_digits(&remainder, &divisor) != Ordering::Less {
+            sub_assign_digits(&mut remainder, &divisor);
+            quotient[(bit / 32) as usize] |= 1 << (bit % 32);
+        }
+        divisor = BigInt::from_parts(false, divisor).shr(1).digits;
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+// ------------------------------------------------
+/// A fraction of `BigInt`s in lowest terms with a positive denominator,
+/// so derived equality is numeric; integers have denominator 1.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numer: BigInt,
+    denom: BigInt,
+}
+
+impl Rational {
+    /// `numer / denom` in lowest terms; `None` if `denom` is zero.
+    pub fn new(numer: BigInt, denom: BigInt) -> Option<Rational> {
+        if denom.is_zero() {
+            return None;
+        }
+        let gcd = numer.gcd(&denom);
+        let divide = |n: &BigInt| n.div_rem(&gcd).expect("the gcd of a nonzero is nonzero").0;
+        let (numer, denom) = (divide(&numer), divide(&denom));
+        Some(match denom.is_negative() {
+            true => Rational {
+                numer: -&numer,
+                denom: -&denom,
+            },
+            false => Rational { numer, denom },
+        })
+    }
+
+    pub fn integer(n: BigInt) -> Rational {
+        Rational {
+            numer: n,
+            denom: BigInt::from(1),
+        }
+    }
+
+    /// The exact value of the shortest decimal that prints as `n`, so
+    /// `0.1` is `1/10` rather than the binary fraction nearest it; `None`
+    /// for NaN and infinities.
+    pub fn from_decimal(n: f64) -> Option<Rational> {
+        if !n.is_finite() {
+            return None;
+        }
+        // Floats display without an exponent, e.g. `0.0000001`
+        let text = n.to_string();
+        let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+        let numer: BigInt = format!("{}{}", whole, fraction).parse().ok()?;
+        let denom = BigInt::from(10).pow(fraction.len() as u64);
+        Rational::new(numer, denom)
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer.is_zero()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom == BigInt::from(1)
+    }
+
+    /// Bits in the larger of numerator and denominator.
+    pub fn bits(&self) -> u64 {
+        self.numer.bits().max(self.denom.bits())
+    }
+
+    /// Rounded toward zero.
+    pub fn trunc(&self) -> BigInt {
+        let (quotient, _) = self
+            .numer
+            .div_rem(&self.denom)
+            .expect("denominator is positive");
+        quotient
+    }
+
+    /// Rounded toward negative infinity.
+    pub fn floor(&self) -> BigInt {
+        match self.numer.is_negative() && !self.is_integer() {
+            true => &self.trunc() - &BigInt::from(1),
+            false => self.trunc(),
+        }
+    }
+
+    /// Rounded toward positive infinity.
+    pub fn ceil(&self) -> BigInt {
+        match !self.numer.is_negative() && !self.is_integer() {
+            true => &self.trunc() + &BigInt::from(1),
+            false => self.trunc(),
+        }
+    }
+
+    /// Rounded to the nearest integer, halves away from zero as with
+    /// `f64::round`.
+    pub fn round(&self) -> BigInt {
+        let half = Rational {
+            numer: BigInt::from(1),
+            denom: BigInt::from(2),
+        };
+        match self.numer.is_negative() {
+            true => (self - &half).trunc(),
+            false => (self + &half).trunc(),
+        }
+    }
+
+    pub fn abs(&self) -> Rational {
+        match self.numer.is_negative() {
+            true => -self,
+            false => self.clone(),
+        }
+    }
+
+    /// The integer value, if it is one that fits an `i64`.
+    pub fn to_i64(&self) -> Option<i64> {
+        self.is_integer().then(|| self.numer.to_i64()).flatten()
+    }
+
+    /// The nearest float, the one conversion that loses exactness.
+    pub fn to_f64(&self) -> f64 {
+        // Drop low bits of both sides alike, so huge ones don't overflow
+        let shift = self.bits().saturating_sub(1000);
+        self.numer.shr(shift).to_f64() / self.denom.shr(shift).to_f64()
+    }
+
+    /// `None` when dividing by zero.
+    pub fn checked_div(&self, rhs: &Rational) -> Option<Rational> {
+        Rational::new(&self.numer * &rhs.denom, &self.denom * &rhs.numer)
+    }
+
+    /// Remainder after truncating division, with the sign of `self` as
+    /// with ints; `None` when dividing by zero.
+    pub fn checked_rem(&self, rhs: &Rational) -> Option<Rational> {
+        let quotient = Rational::integer(self.checked_div(rhs)?.trunc());
+        Some(self - &(rhs * &quotient))
+    }
+
+    /// `self ^ exp`; `None` for zero to a negative power.
+    pub fn pow(&self, exp: i64) -> Option<Rational> {
+        let magnitude = exp.unsigned_abs();
+        let (numer, denom) = (self.numer.pow(magnitude), self.denom.pow(magnitude));
+        match exp < 0 {
+            true => Rational::new(denom, numer),
+            // Powers of coprime numbers stay coprime
+            false => Some(Rational { numer, denom }),
+        }
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(n: i64) -> Self {
+        Rational::integer(BigInt::from(n))
+    }
+}
+
+/// `7`, or `-1/3` for a fraction.
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.is_integer() {
+            true => write!(f, "{}", self.numer),
+            false => write!(f, "{}/{}", self.numer, self.denom),
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Denominators are positive, so cross-multiplying keeps the order
+        (&self.numer * &other.denom).cmp(&(&other.numer * &self.denom))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numer: -&self.numer,
+            denom: self.denom.clone(),
+        }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, rhs: &Rational) -> Rational {
+        let numer = &(&self.numer * &rhs.denom) + &(&rhs.numer * &self.denom);
+        Rational::new(numer, &self.denom * &rhs.denom).expect("denominators are nonzero")
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, rhs: &Rational) -> Rational {
+        self + &-rhs
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, rhs: &Rational) -> Rational {
+        Rational::new(&self.numer * &rhs.numer, &self.denom * &rhs.denom)
+            .expect("denominators are nonzero")
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    fn ratio(numer: i64, denom: i64) -> Rational {
+        Rational::new(BigInt::from(numer), BigInt::from(denom)).unwrap()
+    }
+
+    #[test]
+    fn test_bigint_arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        let (q, r) = b.div_rem(&a).unwrap();
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            ("-8".into(), "-9000000000900000000090".into())
+        );
+        assert_eq!(&(&q * &a) + &r, b);
+        assert!(a.div_rem(&BigInt::default()).is_none());
+
+        assert_eq!(
+            BigInt::from(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(big("-0"), BigInt::default());
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!((&BigInt::from(i64::MAX) + &BigInt::from(1)).to_i64(), None);
+        assert!(big("-5") < big("3") && big("-5") < big("-4"));
+        assert!("12a".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn test_bigint_gcd_and_shifts() {
+        let a = &BigInt::from(2).pow(80) * &BigInt::from(3);
+        let b = &BigInt::from(2).pow(70) * &BigInt::from(5);
+        assert_eq!(a.gcd(&b), BigInt::from(2).pow(70));
+        assert_eq!(BigInt::from(-12).gcd(&BigInt::from(18)), BigInt::from(6));
+        assert_eq!(BigInt::from(1).shl(65).shr(64), BigInt::from(2));
+        assert_eq!(
+            BigInt::from(2).pow(64).to_f64(),
+            18_446_744_073_709_551_616.0
+        );
+    }
+
+    #[test]
+    fn test_rationals_stay_reduced() {
+        assert_eq!(&ratio(1, 3) + &ratio(1, 6), ratio(1, 2));
+        assert_eq!(ratio(2, -4).to_string(), "-1/2");
+        assert_eq!(ratio(0, -7), Rational::from(0));
+        assert_eq!((&ratio(3, 4) * &ratio(4, 3)).to_string(), "1");
+        assert_eq!(ratio(7, 2).checked_rem(&ratio(1, 1)), Some(ratio(1, 2)));
+        assert_eq!(ratio(-7, 2).checked_rem(&ratio(2, 1)), Some(ratio(-3, 2)));
+        assert_eq!(ratio(-7, 2).trunc(), BigInt::from(-3));
+        assert_eq!(ratio(-7, 2).floor(), BigInt::from(-4));
+        assert_eq!(ratio(7, 2).ceil(), BigInt::from(4));
+        assert_eq!(ratio(-7, 2).ceil(), BigInt::from(-3));
+        assert_eq!(ratio(7, 2).round(), BigInt::from(4));
+        assert_eq!(ratio(-7, 2).round(), BigInt::from(-4));
+        assert_eq!(ratio(-1, 3).round(), BigInt::from(0));
+        assert_eq!(ratio(-1, 3).abs(), ratio(1, 3));
+        assert!(ratio(1, 2).checked_div(&Rational::from(0)).is_none());
+        assert_eq!(ratio(2, 3).pow(-2), Some(ratio(9, 4)));
+        assert_eq!(Rational::from(0).pow(-1), None);
+        assert!(ratio(1, 3) < ratio(1, 2) && ratio(-1, 2) < ratio(-1, 3));
+    }
+
+    #[test]
+    fn test_decimals_and_floats() {
+        assert_eq!(Rational::from_decimal(0.1), Some(ratio(1, 10)));
+        assert_eq!(Rational::from_decimal(-2.5), Some(ratio(-5, 2)));
+        assert_eq!(Rational::from_decimal(1e-7), Some(ratio(1, 10_000_000)));
+        assert_eq!(Rational::from_decimal(f64::NAN), None);
+        assert_eq!(ratio(1, 3).to_f64(), 1.0 / 3.0);
+
+        let huge = Rational::new(BigInt::from(10).pow(400), BigInt::from(3).pow(800)).unwrap();
+        let expected = (400.0 * 10_f64.ln() - 800.0 * 3_f64.ln()).exp();
+        assert!((huge.to_f64() / expected - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mode_converts_literals() {
+        let exact = NumericMode::Exact;
+        assert_eq!(exact.number(Value::Int(3)), Value::from(Rational::from(3)));
+        assert_eq!(exact.number(Value::Float(0.25)), Value::from(ratio(1, 4)));
+        assert_eq!(exact.number(Value::Bool(true)), Value::Bool(true));
+        assert_eq!(NumericMode::Float.number(Value::Int(3)), Value::Int(3));
+        assert_eq!("exact".parse(), Ok(NumericMode::Exact));
+        assert!("fast".parse::<NumericMode>().is_err());
+    }
+}
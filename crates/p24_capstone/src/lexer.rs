@@ -0,0 +1,664 @@
+use std::{
+    fmt::{Display, Formatter},
+    iter::Peekable,
+    str::CharIndices,
+};
+
+use crate::{error::ParseError, exact::BigInt};
+
+// ------------------------------------------------
+/// Byte range `start..end` into the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+// ------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Int(i64),
+    /// Digits too many for an `i64`
+    BigInt(BigInt),
+    Float(f64),
+    /// A number directly followed by `i`
+    Imaginary(f64),
+    Str(String),
+    Ident(String),
+    Let,
+    In,
+    Fn,
+    If,
+    Then,
+    Else,
+    Match,
+    True,
+    False,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Dot,
+    DotDot,
+    Colon,
+    Arrow,
+    Assign,
+    FatArrow,
+    Pipe,
+    EqEq,
+    NotEq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    AndAnd,
+    OrOr,
+    Bang,
+    /// Where the lexer rejected a literal, left by the parser so the
+    /// literal still counts as an operand
+    Invalid,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Int(n) => write!(f, "number `{}`", n),
+            TokenKind::BigInt(n) => write!(f, "number `{}`", n),
+            TokenKind::Float(n) => write!(f, "number `{}`", n),
+            TokenKind::Imaginary(n) => write!(f, "number `{}i`", n),
+            TokenKind::Str(s) => write!(f, "string {:?}", s),
+            TokenKind::Ident(name) => write!(f, "identifier `{}`", name),
+            TokenKind::Let => write!(f, "`let`"),
+            TokenKind::In => write!(f, "`in`"),
+            TokenKind::Fn => write!(f, "`fn`"),
+            TokenKind::If => write!(f, "`if`"),
+            TokenKind::Then => write!(f, "`then`"),
+            TokenKind::Else => write!(f, "`else`"),
+            TokenKind::Match => write!(f, "`match`"),
+            TokenKind::True => write!(f, "`true`"),
+            TokenKind::False => write!(f, "`false`"),
+            TokenKind::Plus => write!(f, "`+`"),
+            TokenKind::Minus => write!(f, "`-`"),
+            TokenKind::Star => write!(f, "`*`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::Percent => write!(f, "`%`"),
+            TokenKind::Caret => write!(f, "`^`"),
+            TokenKind::LParen => write!(f, "`(`"),
+            TokenKind::RParen => write!(f, "`)`"),
+            TokenKind::LBracket => write!(f, "`[`"),
+            TokenKind::RBracket => write!(f, "`]`"),
+            TokenKind::LBrace => write!(f, "`{{`"),
+            TokenKind::RBrace => write!(f, "`}}`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Dot => write!(f, "`.`"),
+            TokenKind::DotDot => write!(f, "`..`"),
+            TokenKind::Colon => write!(f, "`:`"),
+            TokenKind::Arrow => write!(f, "`->`"),
+            TokenKind::Assign => write!(f, "`=`"),
+            TokenKind::FatArrow => write!(f, "`=>`"),
+            TokenKind::Pipe => write!(f, "`|`"),
+            TokenKind::EqEq => write!(f, "`==`"),
+            TokenKind::NotEq => write!(f, "`!=`"),
+            TokenKind::Lt => write!(f, "`<`"),
+            TokenKind::Le => write!(f, "`<=`"),
+            TokenKind::Gt => write!(f, "`>`"),
+            TokenKind::Ge => write!(f, "`>=`"),
+            TokenKind::AndAnd => write!(f, "`&&`"),
+            TokenKind::OrOr => write!(f, "`||`"),
+            TokenKind::Bang => write!(f, "`!`"),
+            TokenKind::Invalid => write!(f, "invalid literal"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Token { kind, span }
+    }
+}
+
+// ------------------------------------------------
+/// Turns source text into a stream of spanned tokens.
+///
+/// The lexer is an `Iterator`, so a whole input can be tokenized with
+/// `collect::<Result<Vec<_>, _>>()`.
+pub struct Lexer<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        let chars = src.char_indices().peekable();
+        Lexer { src, chars }
+    }
+
+    /// Byte offset of the next unread character.
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |&(i, _)| i)
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// The character after the next one, without consuming anything.
+    fn peek_second(&self) -> Option<char> {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        ahead.next().map(|(_, c)| c)
+    }
+
+    fn eat_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek_char().is_some_and(&pred) {
+            self.chars.next();
+        }
+    }
+
+    /// Digits alone make an integer; a fraction or exponent makes a float.
+    fn number(&mut self, start: usize) -> Result<Token, ParseError> {
+        self.eat_while(|c| c.is_ascii_digit());
+        let mut is_float = self.src[start..].starts_with('.');
+
+        // Only treat '.' as a decimal point when a digit follows
+        if self.peek_char() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            self.chars.next();
+            self.eat_while(|c| c.is_ascii_digit());
+            is_float = true;
+        }
+
+        // Exponent: e10, e+3, e-7
+        if matches!(self.peek_char(), Some('e' | 'E')) {
+            let mut ahead = self.chars.clone();
+            ahead.next();
+            let next = ahead.next().map(|(_, c)| c);
+            let after = ahead.next().map(|(_, c)| c);
+            let has_exponent = match next {
+                Some(c) if c.is_ascii_digit() => true,
+                Some('+' | '-') => after.is_some_and(|c| c.is_ascii_digit()),
+                _ => false,
+            };
+            if has_exponent {
+                is_float = true;
+                self.chars.next();
+                if matches!(self.peek_char(), Some('+' | '-')) {
+                    self.chars.next();
+                }
+                self.eat_while(|c| c.is_ascii_digit());
+            }
+        }
+
+        let end = self.offset();
+        let text = &self.src[start..end];
+
+        // An `i` ending the number makes it imaginary: `4i`, `2.5i`
+        let mut ahead = self.chars.clone();
+        if ahead.next().is_some_and(|(_, c)| c == 'i')
+            && !ahead
+                .next()
+                .is_some_and(|(_, c)| c.is_alphanumeric() || c == '_')
+        {
+            self.chars.next();
+            let span = Span::new(start, end + 1);
+            return text
+                .parse::<f64>()
+                .map(|n| Token::new(TokenKind::Imaginary(n), span))
+                .map_err(|source| ParseError::InvalidNumber {
+                    text: text.to_string(),
+                    span,
+                    source,
+                });
+        }
+
+        let span = Span::new(start, end);
+        if !is_float {
+            // Whether a bigger one is usable depends on the numeric mode
+            let kind = match text.parse::<i64>() {
+                Ok(n) => TokenKind::Int(n),
+                Err(_) => TokenKind::BigInt(text.parse().expect("digits make a BigInt")),
+            };
+            return Ok(Token::new(kind, span));
+        }
+        text.parse::<f64>()
+            .map(|n| Token::new(TokenKind::Float(n), span))
+            .map_err(|source| ParseError::InvalidNumber {
+                text: text.to_string(),
+                span,
+                source,
+            })
+    }
+
+    /// A double-quoted string; the opening quote is already consumed.
+    ///
+    /// After a bad escape the rest of the string is still consumed, so
+    /// lexing resumes after the closing quote.
+    fn string(&mut self, start: usize) -> Result<Token, ParseError> {
+        let mut text = String::new();
+        let mut bad_escape = None;
+        loop {
+            let Some((i, c)) = self.chars.next() else {
+                return Err(ParseError::UnterminatedString {
+                    span: Span::new(start, self.src.len()),
+                });
+            };
+            match c {
+                '"' => break,
+                '\\' => match self.chars.next() {
+                    Some((_, 'n')) => text.push('\n'),
+                    Some((_, 't')) => text.push('\t'),
+                    Some((_, c @ ('"' | '\\'))) => text.push(c),
+                    Some((j, c)) => {
+                        bad_escape.get_or_insert(ParseError::InvalidEscape {
+                            ch: c,
+                            span: Span::new(i, j + c.len_utf8()),
+                        });
+                    }
+                    None => {
+                        return Err(ParseError::UnterminatedString {
+                            span: Span::new(start, self.src.len()),
+                        });
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+
+        match bad_escape {
+            Some(err) => Err(err),
+            None => Ok(Token::new(
+                TokenKind::Str(text),
+                Span::new(start, self.offset()),
+            )),
+        }
+    }
+
+    fn ident(&mut self, start: usize) -> Token {
+        self.eat_while(|c| c.is_alphanumeric() || c == '_');
+        let end = self.offset();
+        let kind = match &self.src[start..end] {
+            "let" => TokenKind::Let,
+            "in" => TokenKind::In,
+            "fn" => TokenKind::Fn,
+            "if" => TokenKind::If,
+            "then" => TokenKind::Then,
+            "else" => TokenKind::Else,
+            "match" => TokenKind::Match,
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            name => TokenKind::Ident(name.to_string()),
+        };
+        Token::new(kind, Span::new(start, end))
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.eat_while(char::is_whitespace);
+
+        let (start, ch) = self.chars.next()?;
+        let single = |kind| Some(Ok(Token::new(kind, Span::new(start, start + 1))));
+        let double = |kind| Some(Ok(Token::new(kind, Span::new(start, start + 2))));
+
+        match ch {
+            '+' => single(TokenKind::Plus),
+            '-' if self.peek_char() == Some('>') => {
+                self.chars.next();
+                double(TokenKind::Arrow)
+            }
+            '-' => single(TokenKind::Minus),
+            '*' => single(TokenKind::Star),
+            '/' => single(TokenKind::Slash),
+            '%' => single(TokenKind::Percent),
+            '^' => single(TokenKind::Caret),
+            '(' => single(TokenKind::LParen),
+            ')' => single(TokenKind::RParen),
+            '[' => single(TokenKind::LBracket),
+            ']' => single(TokenKind::RBracket),
+            '{' => single(TokenKind::LBrace),
+            '}' => single(TokenKind::RBrace),
+            ',' => single(TokenKind::Comma),
+            '.' if self.peek_char() == Some('.') => {
+                self.chars.next();
+                double(TokenKind::DotDot)
+            }
+            ':' => single(TokenKind::Colon),
+            '=' | '!' | '<' | '>' if self.peek_char() == Some('=') => {
+                self.chars.next();
+                double(match ch {
+                    '=' => TokenKind::EqEq,
+                    '!' => TokenKind::NotEq,
+                    '<' => TokenKind::Le,
+                    _ => TokenKind::Ge,
+                })
+            }
+            '&' if self.peek_char() == Some('&') => {
+                self.chars.next();
+                double(TokenKind::AndAnd)
+            }
+            '|' if self.peek_char() == Some('|') => {
+                self.chars.next();
+                double(TokenKind::OrOr)
+            }
+            '=' if self.peek_char() == Some('>') => {
+                self.chars.next();
+                double(TokenKind::FatArrow)
+            }
+            '=' => single(TokenKind::Assign),
+            '!' => single(TokenKind::Bang),
+            '<' => single(TokenKind::Lt),
+            '>' => single(TokenKind::Gt),
+            '|' => single(TokenKind::Pipe),
+            '"' => Some(self.string(start)),
+            c if c.is_ascii_digit() => Some(self.number(start)),
+            '.' if self.peek_char().is_some_and(|c| c.is_ascii_digit()) => Some(self.number(start)),
+            '.' => single(TokenKind::Dot),
+            c if c.is_alphabetic() || c == '_' => Some(Ok(self.ident(start))),
+            c => Some(Err(ParseError::UnexpectedChar {
+                ch: c,
+                span: Span::new(start, start + c.len_utf8()),
+            })),
+        }
+    }
+}
+
+/// Tokenize the whole input, stopping at the first lexical error.
+pub fn tokenize(src: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(src).collect()
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        tokenize(src).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_operators_and_parens() {
+        assert_eq!(
+            kinds("+ - * / % ^ ( ) , :"),
+            vec![
+                TokenKind::Plus,
+                TokenKind::Minus,
+                TokenKind::Star,
+                TokenKind::Slash,
+                TokenKind::Percent,
+                TokenKind::Caret,
+                TokenKind::LParen,
+                TokenKind::RParen,
+                TokenKind::Comma,
+                TokenKind::Colon,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(
+            kinds("42 3.5 .25 1e3 2.5E-2"),
+            vec![
+                TokenKind::Int(42),
+                TokenKind::Float(3.5),
+                TokenKind::Float(0.25),
+                TokenKind::Float(1000.0),
+                TokenKind::Float(0.025),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_imaginary_numbers() {
+        assert_eq!(
+            kinds("3 + 4i 2.5i 1e3i 99999999999999999999i"),
+            vec![
+                TokenKind::Int(3),
+                TokenKind::Plus,
+                TokenKind::Imaginary(4.0),
+                TokenKind::Imaginary(2.5),
+                TokenKind::Imaginary(1000.0),
+                TokenKind::Imaginary(1e20),
+            ]
+        );
+        // Only a lone `i` counts; `4 i` and `4in` leave it a name
+        assert_eq!(
+            kinds("4 i 4in"),
+            vec![
+                TokenKind::Int(4),
+                TokenKind::Ident("i".to_string()),
+                TokenKind::Int(4),
+                TokenKind::In,
+            ]
+        );
+        assert_eq!(tokenize("12i").unwrap()[0].span, Span::new(0, 3));
+    }
+
+    #[test]
+    fn test_integer_too_large() {
+        assert_eq!(kinds("9223372036854775807"), vec![TokenKind::Int(i64::MAX)]);
+        assert_eq!(
+            kinds("9223372036854775808"),
+            vec![TokenKind::BigInt("9223372036854775808".parse().unwrap())]
+        );
+    }
+
+    #[test]
+    fn test_comparison_and_logic_operators() {
+        assert_eq!(
+            kinds("== != < <= > >= && || ! = | ->"),
+            vec![
+                TokenKind::EqEq,
+                TokenKind::NotEq,
+                TokenKind::Lt,
+                TokenKind::Le,
+                TokenKind::Gt,
+                TokenKind::Ge,
+                TokenKind::AndAnd,
+                TokenKind::OrOr,
+                TokenKind::Bang,
+                TokenKind::Assign,
+                TokenKind::Pipe,
+                TokenKind::Arrow,
+            ]
+        );
+        assert_eq!(
+            kinds("a->b - >"),
+            vec![
+                TokenKind::Ident("a".to_string()),
+                TokenKind::Arrow,
+                TokenKind::Ident("b".to_string()),
+                TokenKind::Minus,
+                TokenKind::Gt,
+            ]
+        );
+        let err = tokenize("a & b").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedChar { ch: '&', .. }));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            kinds(r#""hi" "a \"b\" \\ \n""#),
+            vec![
+                TokenKind::Str("hi".to_string()),
+                TokenKind::Str("a \"b\" \\ \n".to_string()),
+            ]
+        );
+        let tokens = tokenize(r#"x + "é""#).unwrap();
+        assert_eq!(tokens[2].span, Span::new(4, 8));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let err = tokenize(r#"1 + "abc"#).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::UnterminatedString {
+                span: Span::new(4, 8)
+            }
+        );
+
+        // Lexing picks up again after the string with the bad escape
+        let results: Vec<_> = Lexer::new(r#""a\qb" + 1"#).collect();
+        assert_eq!(
+            results[0],
+            Err(ParseError::InvalidEscape {
+                ch: 'q',
+                span: Span::new(2, 4),
+            })
+        );
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn test_number_without_fraction_digits() {
+        // "1." is not a float literal; the dot is left for field access
+        assert_eq!(kinds("1."), vec![TokenKind::Int(1), TokenKind::Dot]);
+
+        // ...so it can start a range
+        assert_eq!(
+            kinds("1..10 [x]"),
+            vec![
+                TokenKind::Int(1),
+                TokenKind::DotDot,
+                TokenKind::Int(10),
+                TokenKind::LBracket,
+                TokenKind::Ident("x".to_string()),
+                TokenKind::RBracket,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_records_and_match() {
+        assert_eq!(
+            kinds("match r.x { _ => {} }"),
+            vec![
+                TokenKind::Match,
+                TokenKind::Ident("r".to_string()),
+                TokenKind::Dot,
+                TokenKind::Ident("x".to_string()),
+                TokenKind::LBrace,
+                TokenKind::Ident("_".to_string()),
+                TokenKind::FatArrow,
+                TokenKind::LBrace,
+                TokenKind::RBrace,
+                TokenKind::RBrace,
+            ]
+        );
+        assert_eq!(
+            kinds("a.5 = >"),
+            vec![
+                TokenKind::Ident("a".to_string()),
+                TokenKind::Float(0.5),
+                TokenKind::Assign,
+                TokenKind::Gt,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            kinds("x _tmp sin2"),
+            vec![
+                TokenKind::Ident("x".to_string()),
+                TokenKind::Ident("_tmp".to_string()),
+                TokenKind::Ident("sin2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            kinds("let x = 1 in lettuce fn | if then else true false"),
+            vec![
+                TokenKind::Let,
+                TokenKind::Ident("x".to_string()),
+                TokenKind::Assign,
+                TokenKind::Int(1),
+                TokenKind::In,
+                TokenKind::Ident("lettuce".to_string()),
+                TokenKind::Fn,
+                TokenKind::Pipe,
+                TokenKind::If,
+                TokenKind::Then,
+                TokenKind::Else,
+                TokenKind::True,
+                TokenKind::False,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("12 + foo").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            vec![Span::new(0, 2), Span::new(3, 4), Span::new(5, 8)]
+        );
+    }
+
+    #[test]
+    fn test_unexpected_char() {
+        let err = tokenize("1 + $").unwrap_err();
+        match err {
+            ParseError::UnexpectedChar { ch, span } => {
+                assert_eq!(ch, '$');
+                assert_eq!(span, Span::new(4, 5));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_span_merge() {
+        let a = Span::new(2, 4);
+        let b = Span::new(7, 9);
+        assert_eq!(a.to(b), Span::new(2, 9));
+        assert_eq!(b.to(a), Span::new(2, 9));
+        assert_eq!(a.len(), 2);
+    }
+}
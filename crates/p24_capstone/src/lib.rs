@@ -1,14 +1,80 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
+//! Phase 11: Capstone — Expression Evaluator
+//!
+//! Ties the earlier phases together into a small language:
+//! 1. `Lexer` is an `Iterator` of spanned tokens
+//! 2. `Parser` is a precedence-climbing parser producing an `Expr` tree
+//! 3. `Expr` implements `Display` and `FromStr`, so printing and parsing round-trip
+//! 4. `Interpreter` walks the tree with `let` scopes chained through `Environment`
+//! 5. Lambdas are `Value`s that capture their scope, like closures capture variables
+//! 6. `CompiledExpr` compiles once to bytecode run on p10's `Stack`, for hot formulas
+//! 7. Errors carry spans; `Diagnostic` renders them with carets under the source
+//! 8. `Optimizer` runs toggleable rewriting passes that keep results unchanged
+//! 9. `Expr::derive` differentiates symbolically and simplifies the result
+//! 10. `Value::Vector` wraps p14's `Vec2`, whose operators do the vector arithmetic
+//! 11. `Sheet` keeps formulas in p15's `Grid2D` and recomputes dependents in order
+//! 12. Quantities like `3 m` carry dimensions; `20 C -> F` converts via p03's `From` impls
+//! 13. `EvalLimits` bound steps, call depth, value length and time in both backends
+//! 14. `FunctionRegistry` marshals arguments into typed Rust closures via `std::any::Any` downcasts
+//! 15. `TypeChecker` infers types Hindley–Milner style and reports every error before evaluation
+//! 16. `write_to`/`read_from` save trees and bytecode in a checksummed, versioned format
+//! 17. `Seq` keeps ranges and `fib()` lazy; `sum`/`product` total via p23's `Sum`/`Product`
+//! 18. `NumericMode::Exact` computes with hand-written `BigInt`s and reduced `Rational`s
+//! 19. `Tracer` observes evaluation, timing each node with p07's `TimerGuard`
+//! 20. `Expr::latex`/`Expr::mathml` typeset formulas with the fewest parentheses
+//! 21. `MemoCache` remembers pure calls under p02-style `Hash`/`Eq` keys of tree and arguments
+//! 22. `ScopedBatch` shards rows across threads, relying on p21-style `Send + Sync` assertions
+//! 23. Records and `match` on literals and ranges, with warnings for missed values and dead arms
+//! 24. `Complex` numbers like `3 + 4i`, with p14's operators by value, by reference and assigning
+//! 25. `Repl` drives the interpreter from any `BufRead`
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub mod ast;
+pub mod batch;
+pub mod codec;
+pub mod complex;
+pub mod derive;
+pub mod diagnostic;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod exact;
+pub mod lexer;
+pub mod limits;
+pub mod memo;
+pub mod optimize;
+pub mod parser;
+pub mod pattern;
+pub mod registry;
+pub mod render;
+pub mod repl;
+pub mod seq;
+pub mod sheet;
+pub mod trace;
+pub mod typecheck;
+pub mod units;
+pub mod value;
+pub mod vm;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub use ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Pattern, Stmt, UnaryOp};
+pub use batch::{ScopedBatch, eval_batch};
+pub use complex::Complex;
+pub use diagnostic::Diagnostic;
+pub use env::{Bindings, Environment};
+pub use error::{CalcError, CodecError, DeriveError, EvalError, ParseError, SheetError, TypeError};
+pub use eval::{Interpreter, eval};
+pub use exact::{BigInt, NumericMode, Rational};
+pub use lexer::{Lexer, Span, Token, TokenKind};
+pub use limits::{DEFAULT_MAX_DEPTH, EvalLimits, Limit};
+pub use memo::{ExprKey, MemoCache, MemoKey, MemoStats, ValueKey};
+pub use optimize::Optimizer;
+pub use parser::{MAX_NESTING, Parser, parse, parse_all, parse_stmt, parse_stmt_all};
+pub use pattern::{MatchWarning, MatchWarningKind};
+pub use registry::{FunctionRegistry, IntoNative, NativeFn};
+pub use render::{Latex, MathMl};
+pub use repl::Repl;
+pub use seq::Seq;
+pub use sheet::{Cell, CellRef, Content, Formula, Sheet};
+pub use trace::{Observer, Profile, ProfileEntry, TraceNode, Tracer};
+pub use typecheck::{Type, TypeChecker};
+pub use units::{Dim, Quantity, Unit};
+pub use value::{Lambda, Value};
+pub use vm::{CompiledExpr, Op, compile};
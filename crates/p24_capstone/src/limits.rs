@@ -0,0 +1,282 @@
+use std::{
+    cell::Cell,
+    fmt::{Display, Formatter},
+    time::{Duration, Instant},
+};
+
+use crate::{error::EvalError, lexer::Span, parser::MAX_NESTING, value::Value};
+
+// ------------------------------------------------
+/// How often, in steps, the wall clock is consulted.
+const CLOCK_INTERVAL: u64 = 256;
+
+/// Evaluator frames allowed by default: small enough that runaway
+/// recursion like `fn f(n) = f(n)` stops before it exhausts the 2 MiB
+/// stack of a spawned thread, even in debug builds.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Resource limits on a single evaluation; `None` means unlimited.
+///
+/// Both backends enforce them the same way: a step is one tree node
+/// visited or one instruction run, and length applies to every string or
+/// list a step produces. Depth counts the evaluator's own frames, one per
+/// function call and one per tree node being evaluated inside another, so
+/// it bounds stack use however a formula nests or recurses. The bytecode
+/// backend runs its top level without recursing, so only its calls count.
+/// The default limits only depth, to `DEFAULT_MAX_DEPTH`; deeper recursion
+/// needs a thread with a bigger stack and a `max_depth` to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalLimits {
+    pub max_steps: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub max_len: Option<usize>,
+    /// Wall-clock budget, measured from the start of each evaluation
+    pub max_time: Option<Duration>,
+    /// How deeply source text may nest when parsed from a string; the
+    /// parser's `MAX_NESTING` applies either way
+    pub max_nesting: Option<usize>,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits::none().max_depth(DEFAULT_MAX_DEPTH)
+    }
+}
+
+impl EvalLimits {
+    /// No limits at all, not even on depth.
+    pub fn none() -> Self {
+        EvalLimits {
+            max_steps: None,
+            max_depth: None,
+            max_len: None,
+            max_time: None,
+            max_nesting: None,
+        }
+    }
+
+    /// Limits for formulas from untrusted users: generous for anything a
+    /// spreadsheet needs, but small enough to keep a worker responsive.
+    pub fn untrusted() -> Self {
+        EvalLimits {
+            max_steps: Some(1_000_000),
+            max_depth: Some(100),
+            max_len: Some(100_000),
+            max_time: Some(Duration::from_millis(250)),
+            max_nesting: Some(64),
+        }
+    }
+
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn max_len(mut self, len: usize) -> Self {
+        self.max_len = Some(len);
+        self
+    }
+
+    pub fn max_time(mut self, time: Duration) -> Self {
+        self.max_time = Some(time);
+        self
+    }
+
+    pub fn max_nesting(mut self, nesting: usize) -> Self {
+        self.max_nesting = Some(nesting);
+        self
+    }
+
+    /// The nesting to give a parser: `max_nesting`, but never more than
+    /// `MAX_NESTING`.
+    pub(crate) fn nesting(&self) -> usize {
+        self.max_nesting
+            .map_or(MAX_NESTING, |max| max.min(MAX_NESTING))
+    }
+}
+
+/// Which limit an evaluation ran into, with the configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps(u64),
+    Depth(usize),
+    Len(usize),
+    Time(Duration),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Steps(max) => write!(f, "more than {} steps", max),
+            Limit::Depth(max) => write!(f, "evaluation nested more than {} deep", max),
+            Limit::Len(max) => write!(f, "a value longer than {}", max),
+            Limit::Time(max) => write!(f, "running longer than {:?}", max),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// Usage so far of one evaluation, checked against its limits.
+///
+/// Counters sit in `Cell`s so the evaluators can keep taking `&self`; a
+/// meter lives for one top-level evaluation and is never shared.
+#[derive(Debug)]
+pub(crate) struct Meter {
+    limits: EvalLimits,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    deadline: Option<Instant>,
+    /// Host functions called so far, whose results may not be repeatable
+    host_calls: Cell<u64>,
+}
+
+impl Meter {
+    pub(crate) fn new(limits: EvalLimits) -> Self {
+        Meter {
+            limits,
+            steps: Cell::new(0),
+            depth: Cell::new(0),
+            deadline: limits.max_time.map(|time| Instant::now() + time),
+            host_calls: Cell::new(0),
+        }
+    }
+
+    fn exceeded(limit: Limit, span: Span) -> EvalError {
+        EvalError::LimitExceeded { limit, span }
+    }
+
+    /// Count one step, failing once the step or time budget is used up.
+    pub(crate) fn step(&self, span: Span) -> Result<(), EvalError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        if let Some(max) = self.limits.max_steps
+            && steps > max
+        {
+            return Err(Self::exceeded(Limit::Steps(max), span));
+        }
+        if let Some(deadline) = self.deadline
+            && steps.is_multiple_of(CLOCK_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            let max = self.limits.max_time.unwrap_or_default();
+            return Err(Self::exceeded(Limit::Time(max), span));
+        }
+        Ok(())
+    }
+
+    /// Note a call to a host function.
+    pub(crate) fn host_call(&self) {
+        self.host_calls.set(self.host_calls.get() + 1);
+    }
+
+    pub(crate) fn host_calls(&self) -> u64 {
+        self.host_calls.get()
+    }
+
+    /// Enter a function call or tree node; the depth drops again when the
+    /// guard does.
+    pub(crate) fn enter(&self, span: Span) -> Result<DepthGuard<'_>, EvalError> {
+        let depth = self.depth.get() + 1;
+        if let Some(max) = self.limits.max_depth
+            && depth > max
+        {
+            return Err(Self::exceeded(Limit::Depth(max), span));
+        }
+        self.depth.set(depth);
+        Ok(DepthGuard { meter: self })
+    }
+
+    /// Fail if a string or list of `len` items would be too long.
+    pub(crate) fn check_len(&self, len: usize, span: Span) -> Result<(), EvalError> {
+        match self.limits.max_len {
+            Some(max) if len > max => Err(Self::exceeded(Limit::Len(max), span)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pass `value` through if its length is within the limit.
+    pub(crate) fn checked(&self, value: Value, span: Span) -> Result<Value, EvalError> {
+        match &value {
+            Value::Str(s) => self.check_len(s.chars().count(), span)?,
+            Value::List(items) => self.check_len(items.len(), span)?,
+            _ => {}
+        }
+        Ok(value)
+    }
+}
+
+/// Leaves a function call when dropped, on success and error paths alike.
+pub(crate) struct DepthGuard<'a> {
+    meter: &'a Meter,
+}
+
+impl Drop for DepthGuard<'_> {
+    fn drop(&mut self) {
+        self.meter.depth.set(self.meter.depth.get() - 1);
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_and_depth() {
+        let meter = Meter::new(EvalLimits::none().max_steps(2).max_depth(1));
+        let span = Span::default();
+        assert!(meter.step(span).is_ok());
+        assert!(meter.step(span).is_ok());
+        assert_eq!(
+            meter.step(span),
+            Err(EvalError::LimitExceeded {
+                limit: Limit::Steps(2),
+                span
+            })
+        );
+
+        {
+            let _outer = meter.enter(span).unwrap();
+            assert!(meter.enter(span).is_err());
+        }
+        assert!(meter.enter(span).is_ok(), "the guard restores the depth");
+    }
+
+    #[test]
+    fn test_lengths() {
+        let meter = Meter::new(EvalLimits::none().max_len(3));
+        let span = Span::default();
+        assert!(meter.checked(Value::Str("abc".into()), span).is_ok());
+        assert!(meter.checked(Value::Str("abcd".into()), span).is_err());
+        assert!(
+            meter
+                .checked(Value::list(vec![Value::Int(1); 4]), span)
+                .is_err()
+        );
+        assert!(meter.checked(Value::Int(12345), span).is_ok());
+    }
+
+    #[test]
+    fn test_only_depth_limited_by_default() {
+        let meter = Meter::new(EvalLimits::default());
+        let span = Span::default();
+        for _ in 0..10_000 {
+            meter.step(span).unwrap();
+        }
+        let guards: Vec<_> = (0..DEFAULT_MAX_DEPTH)
+            .map(|_| meter.enter(span).unwrap())
+            .collect();
+        assert!(meter.enter(span).is_err());
+        drop(guards);
+        assert!(meter.check_len(usize::MAX, span).is_ok());
+
+        let meter = Meter::new(EvalLimits::none());
+        let _guards: Vec<_> = (0..10_000).map(|_| meter.enter(span).unwrap()).collect();
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Display, Formatter},
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    ast::Expr,
+    env::Environment,
+    exact::{NumericMode, Rational},
+    units::Dim,
+    value::{Lambda, Value},
+};
+
+/// Entries a `MemoCache::default()` holds before evicting.
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+// ------------------------------------------------
+/// A value as part of a `MemoKey`: equal keys mean interchangeable values.
+///
+/// Floats compare by bit pattern. Functions are keyed by their structure
+/// and by the keys of whatever their free names refer to, so redefining a
+/// function or a global it reads gives calls a new key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ValueKey {
+    Int(i64),
+    Float(u64),
+    Rational(Rational),
+    Complex(u64, u64),
+    Bool(bool),
+    Str(Arc<str>),
+    Vector(u64, u64),
+    Quantity {
+        value: u64,
+        dim: Dim,
+        unit: Option<&'static str>,
+    },
+    Money(i64),
+    List(Vec<ValueKey>),
+    /// Field names and keys, in name order
+    Record(Vec<(String, ValueKey)>),
+    Function {
+        /// The function as a lambda expression
+        code: ExprKey,
+        /// Keys of the free names, in name order
+        free: Vec<ValueKey>,
+    },
+    /// A function already being keyed further out, by its depth from the
+    /// outermost, as when `fib` refers to itself
+    Recursive(usize),
+    /// A name bound to nothing: a constant, builtin or host function
+    Unbound,
+}
+
+/// An expression as part of a key, hashed and compared by its structure
+/// with `Expr`'s own `Hash` and `==`, so spans make no difference.
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub struct ExprKey(pub Expr);
+
+/// `Expr`'s `==` only fails to be reflexive for a NaN literal, whose
+/// results then just never hit.
+impl Eq for ExprKey {}
+
+/// What a cached result was computed from, like p02's `CacheKey`: an
+/// expression or function, compared in full rather than by a hash alone,
+/// and the values it depended on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemoKey {
+    /// The expression, or the function called as a lambda expression
+    pub expr: ExprKey,
+    pub mode: NumericMode,
+    /// Arguments of a call, then the keys of the free names, in name order
+    pub args: Vec<ValueKey>,
+}
+
+/// Builds keys against one interpreter's globals; a name that a function
+/// does not bind is looked up in the scope it captured, then the globals,
+/// as when it runs.
+pub(crate) struct Keyer<'a> {
+    globals: &'a Environment,
+    mode: NumericMode,
+    /// Functions being keyed, outermost first
+    stack: Vec<*const Lambda>,
+}
+
+impl<'a> Keyer<'a> {
+    pub(crate) fn new(globals: &'a Environment, mode: NumericMode) -> Self {
+        Keyer {
+            globals,
+            mode,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Key of evaluating `expr` at the top level; `None` if something it
+    /// refers to has no key.
+    pub(crate) fn expr_key(&mut self, expr: &Expr) -> Option<MemoKey> {
+        let args = self.free_keys(expr.free_names(), None)?;
+        Some(MemoKey {
+            expr: ExprKey(expr.clone()),
+            mode: self.mode,
+            args,
+        })
+    }
+
+    /// Key of calling `lambda` with `args`.
+    pub(crate) fn call_key(&mut self, lambda: &Lambda, args: &[Value]) -> Option<MemoKey> {
+        let mut keys = args
+            .iter()
+            .map(|arg| self.value_key(arg))
+            .collect::<Option<Vec<ValueKey>>>()?;
+        let ValueKey::Function { code, free } = self.function_key(lambda)? else {
+            unreachable!("a function keyed at the outermost level is not recursive");
+        };
+        keys.extend(free);
+        Some(MemoKey {
+            expr: code,
+            mode: self.mode,
+            args: keys,
+        })
+    }
+
+    fn value_key(&mut self, value: &Value) -> Option<ValueKey> {
+        Some(match value {
+            Value::Int(n) => ValueKey::Int(*n),
+            Value::Float(n) => ValueKey::Float(n.to_bits()),
+            Value::Rational(r) => ValueKey::Rational((**r).clone()),
+            Value::Complex(c) => ValueKey::Complex(c.re.to_bits(), c.im.to_bits()),
+            Value::Bool(b) => ValueKey::Bool(*b),
+            Value::Str(s) => ValueKey::Str(Arc::clone(s)),
+            Value::Vector(v) => ValueKey::Vector(v.x.to_bits(), v.y.to_bits()),
+            Value::Quantity(q) => ValueKey::Quantity {
+                value: q.value.to_bits(),
+                dim: q.dim,
+                unit: q.unit.map(|unit| unit.symbol),
+            },
+            Value::Money(m) => ValueKey::Money(m.cents()),
+            Value::List(items) => ValueKey::List(
+                items
+                    .iter()
+                    .map(|item| self.value_key(item))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Record(fields) => ValueKey::Record(
+                fields
+                    .iter()
+                    .map(|(name, value)| Some((name.clone(), self.value_key(value)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            // Sequences may be endless; they are never keyed
+            Value::Seq(_) => return None,
+            Value::Function(lambda) => self.function_key(lambda)?,
+        })
+    }
+
+    fn function_key(&mut self, lambda: &Lambda) -> Option<ValueKey> {
+        let this = lambda as *const Lambda;
+        if let Some(level) = self.stack.iter().position(|&outer| outer == this) {
+            return Some(ValueKey::Recursive(level));
+        }
+        self.stack.push(this);
+        let mut names = lambda.body.free_names();
+        names.retain(|name| !lambda.params.contains(name));
+        let free = self.free_keys(names, lambda.captured.as_deref());
+        self.stack.pop();
+        let code = Expr::lambda(lambda.params.clone(), lambda.body.clone());
+        Some(ValueKey::Function {
+            code: ExprKey(code),
+            free: free?,
+        })
+    }
+
+    fn free_keys(
+        &mut self,
+        names: impl IntoIterator<Item = String>,
+        captured: Option<&Environment>,
+    ) -> Option<Vec<ValueKey>> {
+        let globals = self.globals;
+        names
+            .into_iter()
+            .map(|name| {
+                match captured
+                    .and_then(|env| env.get(&name))
+                    .or_else(|| globals.get(&name))
+                {
+                    Some(value) => self.value_key(value),
+                    None => Some(ValueKey::Unbound),
+                }
+            })
+            .collect()
+    }
+}
+
+// ------------------------------------------------
+/// Counts of a `MemoCache`'s lookups and contents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for newer ones
+    pub evictions: u64,
+    pub entries: usize,
+}
+
+impl MemoStats {
+    /// Share of lookups that hit, from 0 to 1; 0 before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl Display for MemoStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit rate), {} entries, {} evicted",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0,
+            self.entries,
+            self.evictions
+        )
+    }
+}
+
+/// Results of pure evaluations, shared by every interpreter given it.
+///
+/// An interpreter with a cache looks up each call to a user function and
+/// each top-level `eval` before running it, keyed by `MemoKey`. Other
+/// subtrees are not looked up on their own: keying one costs about as much
+/// as evaluating it, so only calls, which may hide any amount of work, and
+/// whole expressions, like a spreadsheet's formulas, are worth it. Results
+/// that called a host function from the `FunctionRegistry` are never
+/// stored, since those may differ from call to call, and neither are
+/// errors. At most `capacity` results are kept; the oldest goes first.
+pub struct MemoCache {
+    capacity: usize,
+    state: Mutex<MemoState>,
+}
+
+#[derive(Default)]
+struct MemoState {
+    entries: HashMap<MemoKey, Value>,
+    /// Keys in the order they were stored, oldest first
+    order: VecDeque<MemoKey>,
+    stats: MemoStats,
+}
+
+impl MemoCache {
+    pub fn new(capacity: usize) -> Self {
+        MemoCache {
+            capacity,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoState> {
+        // Every update leaves the state consistent, even one cut short
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.state().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The stored result for `key`, counting a hit or a miss.
+    pub fn get(&self, key: &MemoKey) -> Option<Value> {
+        let mut state = self.state();
+        let value = state.entries.get(key).cloned();
+        match value {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1,
+        }
+        value
+    }
+
+    /// Store `value` for `key`, evicting the oldest entry if full.
+    pub fn insert(&self, key: MemoKey, value: Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state();
+        if state.entries.insert(key.clone(), value).is_some() {
+            // Computed twice at once, by two threads; keep its place
+            return;
+        }
+        state.order.push_back(key);
+        while state.entries.len() > self.capacity {
+            let Some(oldest) = state.order.pop_front() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+            state.stats.evictions += 1;
+        }
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        let state = self.state();
+        MemoStats {
+            entries: state.entries.len(),
+            ..state.stats
+        }
+    }
+
+    /// Drop every entry; the counts carry on.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.entries.clear();
+        state.order.clear();
+    }
+}
+
+impl Default for MemoCache {
+    fn default() -> Self {
+        MemoCache::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Debug for MemoCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoCache")
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{eval::Interpreter, limits::EvalLimits, registry::FunctionRegistry};
+
+    const FIB: &str = "fn fib(n) = if n < 2 then n else fib(n - 1) + fib(n - 2)";
+
+    fn memoized() -> (Interpreter, Arc<MemoCache>) {
+        let memo = Arc::new(MemoCache::default());
+        (Interpreter::new().with_memo(Arc::clone(&memo)), memo)
+    }
+
+    #[test]
+    fn test_memoized_fib() {
+        let limits = EvalLimits::none().max_steps(10_000);
+        let mut plain = Interpreter::new().with_limits(limits);
+        plain.exec_str(FIB).unwrap();
+        assert!(
+            plain.eval_str("fib(30)").is_err(),
+            "exponential without a cache"
+        );
+
+        let (interp, memo) = memoized();
+        let mut interp = interp.with_limits(limits);
+        interp.exec_str(FIB).unwrap();
+        assert_eq!(interp.eval_str("fib(30)").unwrap(), 832_040);
+        let stats = memo.stats();
+        assert_eq!(
+            stats.misses, 32,
+            "each of fib(0..=30) and the expression once"
+        );
+        assert_eq!(stats.hits, 28);
+
+        // The whole expression is remembered too
+        assert_eq!(interp.eval_str("fib(30)").unwrap(), 832_040);
+        assert_eq!(memo.stats().hits, 29);
+    }
+
+    #[test]
+    fn test_changes_to_globals_change_keys() {
+        let (mut interp, _) = memoized();
+        interp.exec_str("rate = 2").unwrap();
+        interp.exec_str("fn scale(x) = x * rate").unwrap();
+        interp.exec_str("fn total(x) = scale(x) + 1").unwrap();
+        assert_eq!(interp.eval_str("total(3)").unwrap(), 7);
+
+        interp.exec_str("rate = 3").unwrap();
+        assert_eq!(interp.eval_str("total(3)").unwrap(), 10);
+        interp.exec_str("fn scale(x) = x - rate").unwrap();
+        assert_eq!(interp.eval_str("total(3)").unwrap(), 1);
+        interp.exec_str("rate = 2").unwrap();
+        interp.exec_str("fn scale(x) = x * rate").unwrap();
+        assert_eq!(interp.eval_str("total(3)").unwrap(), 7);
+    }
+
+    #[test]
+    fn test_closures_are_keyed_by_what_they_capture() {
+        let (mut interp, memo) = memoized();
+        interp.exec_str("fn adder(n) = |x| x + n").unwrap();
+        interp.exec_str("add1 = adder(1)").unwrap();
+        interp.exec_str("add2 = adder(2)").unwrap();
+        assert_eq!(interp.eval_str("add1(5)").unwrap(), 6);
+        assert_eq!(interp.eval_str("add2(5)").unwrap(), 7);
+        assert_eq!(
+            interp.eval_str("map([5, 1], |x| add1(x) * 10)").unwrap(),
+            Value::list(vec![Value::Int(60), Value::Int(20)])
+        );
+        assert_eq!(memo.stats().hits, 1, "add1(5) again inside the lambda");
+    }
+
+    #[test]
+    fn test_host_calls_are_not_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut registry = FunctionRegistry::new();
+        registry.register("tick", move || {
+            counter.fetch_add(1, Ordering::SeqCst) as i64
+        });
+        let (interp, memo) = memoized();
+        let mut interp = interp.with_registry(registry);
+        interp.exec_str("fn next(x) = tick() + x").unwrap();
+        interp.exec_str("fn double(x) = x * 2").unwrap();
+
+        assert_eq!(interp.eval_str("next(0)").unwrap(), 0);
+        assert_eq!(interp.eval_str("next(0)").unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(interp.eval_str("double(next(0))").unwrap(), 4);
+        assert_eq!(memo.stats().hits, 0);
+        assert_eq!(memo.len(), 1, "only double(2) is pure");
+    }
+
+    #[test]
+    fn test_errors_and_sequences_are_not_cached() {
+        let (mut interp, memo) = memoized();
+        interp.exec_str("fn inv(x) = 1 / x").unwrap();
+        assert!(interp.eval_str("inv(0)").is_err());
+        assert!(interp.eval_str("inv(0)").is_err());
+        assert_eq!(memo.stats().hits, 0);
+        interp.exec_str("fn total(s) = sum(s)").unwrap();
+        assert_eq!(interp.eval_str("total(1..4)").unwrap(), 6);
+        assert_eq!(memo.len(), 1, "the whole expression, but not the call");
+    }
+
+    #[test]
+    fn test_bounded() {
+        let memo = Arc::new(MemoCache::new(2));
+        let mut interp = Interpreter::new().with_memo(Arc::clone(&memo));
+        interp.exec_str("fn sq(x) = x * x").unwrap();
+        for src in ["sq(1)", "sq(2)", "sq(3)", "sq(3)"] {
+            interp.eval_str(src).unwrap();
+        }
+        let stats = memo.stats();
+        assert_eq!(stats.entries, 2);
+        assert!(stats.evictions > 0);
+        assert_eq!(stats.hits, 1);
+        assert!(stats.to_string().starts_with("1 hits, "));
+        memo.clear();
+        assert!(memo.is_empty());
+        assert_eq!(memo.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_keys_compare_whole_expressions() {
+        let globals = Environment::new();
+        let key = |src: &str| {
+            let expr: Expr = src.parse().unwrap();
+            Keyer::new(&globals, NumericMode::Float)
+                .expr_key(&expr)
+                .unwrap()
+        };
+        assert_eq!(key("1 + 2"), key("1  +  (2)"), "spans do not matter");
+        assert_ne!(key("1 + 2"), key("2 + 1"));
+        assert_eq!(key("1 + 2").expr, ExprKey("1 + 2".parse().unwrap()));
+
+        // A stored key must equal the one looked up, not just share its hash
+        let memo = MemoCache::default();
+        memo.insert(key("1 + 2"), Value::Int(3));
+        assert_eq!(memo.get(&key("1 + 2")), Some(Value::Int(3)));
+        assert_eq!(memo.get(&key("2 + 1")), None);
+    }
+
+    #[test]
+    fn test_modes_have_separate_entries() {
+        let (mut interp, _) = memoized();
+        interp.exec_str("fn half(x) = x / 2").unwrap();
+        assert_eq!(interp.eval_str("half(1)").unwrap(), 0.5);
+        let exact = interp.clone().with_mode(NumericMode::Exact);
+        assert_eq!(exact.eval_str("half(1)").unwrap().to_string(), "1/2");
+    }
+}
@@ -0,0 +1,1208 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Stmt, UnaryOp},
+    eval::{apply_binary, apply_unary, convert, is_pure_builtin, matches_pattern},
+    exact::NumericMode,
+    value::Value,
+};
+
+// ------------------------------------------------
+/// Upper bound on rounds of the rewriting passes.
+const MAX_ROUNDS: usize = 16;
+
+/// Pipeline of rewriting passes over `Expr`, each of which can be switched
+/// off on its own.
+///
+/// Rewrites keep results and errors, including division by zero and
+/// integer overflow, the same whatever the variables hold. Identity
+/// elimination and strength reduction only touch operands known to be
+/// numbers, since `x * 1` fails for a list and `x + x` joins strings that
+/// `2 * x` rejects: literals, arithmetic on them, `let`s bound to them and
+/// the variables passed to `with_vars`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimizer {
+    constant_folding: bool,
+    identity_elimination: bool,
+    strength_reduction: bool,
+    common_subexpressions: bool,
+    vars: Scope,
+    /// Every name passed to `with_vars`, any of which may shadow a builtin
+    globals: HashSet<String>,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Optimizer {
+    /// All passes enabled.
+    pub fn new() -> Self {
+        Optimizer {
+            constant_folding: true,
+            identity_elimination: true,
+            strength_reduction: true,
+            common_subexpressions: true,
+            vars: Scope::new(),
+            globals: HashSet::new(),
+        }
+    }
+
+    pub fn constant_folding(mut self, enabled: bool) -> Self {
+        self.constant_folding = enabled;
+        self
+    }
+
+    pub fn identity_elimination(mut self, enabled: bool) -> Self {
+        self.identity_elimination = enabled;
+        self
+    }
+
+    pub fn strength_reduction(mut self, enabled: bool) -> Self {
+        self.strength_reduction = enabled;
+        self
+    }
+
+    pub fn common_subexpressions(mut self, enabled: bool) -> Self {
+        self.common_subexpressions = enabled;
+        self
+    }
+
+    /// Values the free variables hold when the optimized code runs, so
+    /// that numeric rewrites apply to them. Lambda bodies may run after
+    /// the variables change and do not rely on these. A builtin's name
+    /// among them is taken as redefined, so its calls are not shared.
+    pub fn with_vars<'a>(mut self, vars: impl IntoIterator<Item = (&'a str, &'a Value)>) -> Self {
+        let vars: Vec<(&str, &Value)> = vars.into_iter().collect();
+        self.globals = vars.iter().map(|(name, _)| name.to_string()).collect();
+        self.vars = vars
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_string(), Known::of(value)?)))
+            .collect();
+        self
+    }
+
+    /// The passes that keep results unchanged in `mode`. Folding and
+    /// strength reduction compute as in float mode, where `1 / 3` is a
+    /// float, so exact mode goes without them.
+    pub fn for_mode(self, mode: NumericMode) -> Self {
+        match mode {
+            NumericMode::Float => self,
+            NumericMode::Exact => self.constant_folding(false).strength_reduction(false),
+        }
+    }
+
+    /// Run the local passes until nothing changes, then share repeated
+    /// subexpressions.
+    pub fn optimize(&self, expr: &Expr) -> Expr {
+        self.optimize_in(expr, &self.vars)
+    }
+
+    fn optimize_in(&self, expr: &Expr, vars: &Scope) -> Expr {
+        let mut expr = expr.clone();
+        for _ in 0..MAX_ROUNDS {
+            let mut next = expr.clone();
+            if self.constant_folding {
+                next = fold_constants(&next);
+            }
+            if self.identity_elimination {
+                next = rewrite(&next, vars, &drop_identity);
+            }
+            if self.strength_reduction {
+                next = rewrite(&next, vars, &cheapen);
+            }
+            if next == expr {
+                break;
+            }
+            expr = next;
+        }
+
+        if self.common_subexpressions {
+            expr = share_repeats(&expr, &self.globals);
+        }
+        expr
+    }
+
+    pub fn optimize_stmt(&self, stmt: &Stmt) -> Stmt {
+        match stmt {
+            Stmt::Assign { name, value } => Stmt::Assign {
+                name: name.clone(),
+                value: self.optimize(value),
+            },
+            // The body runs whenever the function is called
+            Stmt::FnDef { name, params, body } => Stmt::FnDef {
+                name: name.clone(),
+                params: params.clone(),
+                body: self.optimize_in(body, &Scope::new()),
+            },
+            Stmt::Expr(expr) => Stmt::Expr(self.optimize(expr)),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// What an expression is known to produce, if it produces anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Known {
+    Int,
+    /// An int, a float or, in exact mode, a rational
+    Number,
+    Bool,
+}
+
+/// What the variables in scope are known to hold.
+type Scope = HashMap<String, Known>;
+
+impl Known {
+    fn of(value: &Value) -> Option<Known> {
+        match value {
+            Value::Int(_) => Some(Known::Int),
+            Value::Float(_) | Value::Rational(_) => Some(Known::Number),
+            Value::Bool(_) => Some(Known::Bool),
+            _ => None,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        self != Known::Bool
+    }
+
+    /// The result of arithmetic on `self` and `other`.
+    fn arithmetic(self, other: Known) -> Option<Known> {
+        match (self, other) {
+            (Known::Int, Known::Int) => Some(Known::Int),
+            (l, r) if l.is_number() && r.is_number() => Some(Known::Number),
+            _ => None,
+        }
+    }
+}
+
+/// What `expr` produces when it does not fail.
+fn known(expr: &Expr, scope: &Scope) -> Option<Known> {
+    match &expr.kind {
+        ExprKind::Literal(Literal::Int(_)) => Some(Known::Int),
+        ExprKind::Literal(Literal::Float(_)) => Some(Known::Number),
+        ExprKind::Literal(Literal::Bool(_)) => Some(Known::Bool),
+        ExprKind::Var(name) => scope.get(name).copied(),
+        ExprKind::Unary { op, operand } => {
+            let operand = known(operand, scope)?;
+            match op {
+                UnaryOp::Neg => Some(operand).filter(|k| k.is_number()),
+                UnaryOp::Not => Some(operand).filter(|k| *k == Known::Bool),
+            }
+        }
+        ExprKind::Binary { op, lhs, rhs } => match op {
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                Some(Known::Bool)
+            }
+            BinOp::And | BinOp::Or => Some(Known::Bool),
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Rem => {
+                known(lhs, scope)?.arithmetic(known(rhs, scope)?)
+            }
+            // `/` makes a float of ints, and `^` does for negative powers
+            BinOp::Div | BinOp::Pow => known(lhs, scope)?
+                .arithmetic(known(rhs, scope)?)
+                .map(|_| Known::Number),
+            BinOp::Range => None,
+        },
+        ExprKind::Let { name, value, body } => known(body, &bind(scope, name, value)),
+        ExprKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => match (known(then_branch, scope)?, known(else_branch, scope)?) {
+            (Known::Bool, Known::Bool) => Some(Known::Bool),
+            (l, r) => l.arithmetic(r),
+        },
+        _ => None,
+    }
+}
+
+/// `scope` inside the body of `let name = value`.
+fn bind(scope: &Scope, name: &str, value: &Expr) -> Scope {
+    let mut inner = scope.clone();
+    match known(value, scope) {
+        Some(k) => inner.insert(name.to_string(), k),
+        None => inner.remove(name),
+    };
+    inner
+}
+
+/// Rebuild `expr` bottom-up, applying `rule` to each node after its
+/// children. `rule` also gets what the variables in scope hold.
+fn rewrite(expr: &Expr, scope: &Scope, rule: &impl Fn(Expr, &Scope) -> Expr) -> Expr {
+    let kind = match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => expr.kind.clone(),
+        ExprKind::Unary { op, operand } => ExprKind::Unary {
+            op: *op,
+            operand: Box::new(rewrite(operand, scope, rule)),
+        },
+        ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+            op: *op,
+            lhs: Box::new(rewrite(lhs, scope, rule)),
+            rhs: Box::new(rewrite(rhs, scope, rule)),
+        },
+        ExprKind::Call { name, args } => ExprKind::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| rewrite(arg, scope, rule)).collect(),
+        },
+        ExprKind::Apply { callee, args } => ExprKind::Apply {
+            callee: Box::new(rewrite(callee, scope, rule)),
+            args: args.iter().map(|arg| rewrite(arg, scope, rule)).collect(),
+        },
+        ExprKind::Let { name, value, body } => ExprKind::Let {
+            name: name.clone(),
+            value: Box::new(rewrite(value, scope, rule)),
+            body: Box::new(rewrite(body, &bind(scope, name, value), rule)),
+        },
+        ExprKind::Lambda { params, body } => ExprKind::Lambda {
+            params: params.clone(),
+            // A lambda may be called after the variables it sees change
+            body: Box::new(rewrite(body, &Scope::new(), rule)),
+        },
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => ExprKind::If {
+            cond: Box::new(rewrite(cond, scope, rule)),
+            then_branch: Box::new(rewrite(then_branch, scope, rule)),
+            else_branch: Box::new(rewrite(else_branch, scope, rule)),
+        },
+        ExprKind::Convert { expr: inner, unit } => ExprKind::Convert {
+            expr: Box::new(rewrite(inner, scope, rule)),
+            unit,
+        },
+        ExprKind::Record { fields } => ExprKind::Record {
+            fields: fields
+                .iter()
+                .map(|(name, value)| (name.clone(), rewrite(value, scope, rule)))
+                .collect(),
+        },
+        ExprKind::Field { expr: inner, name } => ExprKind::Field {
+            expr: Box::new(rewrite(inner, scope, rule)),
+            name: name.clone(),
+        },
+        ExprKind::Match { scrutinee, arms } => ExprKind::Match {
+            scrutinee: Box::new(rewrite(scrutinee, scope, rule)),
+            arms: arms
+                .iter()
+                .map(|arm| MatchArm {
+                    body: rewrite(&arm.body, scope, rule),
+                    ..arm.clone()
+                })
+                .collect(),
+        },
+    };
+    rule(Expr::new(kind, expr.span), scope)
+}
+
+/// Whether `expr` is the int literal `value`. Float literals do not count:
+/// `x * 1.0` turns an int `x` into a float.
+fn is_int(expr: &Expr, value: i64) -> bool {
+    matches!(expr.kind, ExprKind::Literal(Literal::Int(n)) if n == value)
+}
+
+fn literal(expr: &Expr) -> Option<Value> {
+    match &expr.kind {
+        // Its value depends on the numeric mode
+        ExprKind::Literal(Literal::BigInt(_)) => None,
+        ExprKind::Literal(literal) => Some(Value::from(literal)),
+        _ => None,
+    }
+}
+
+/// The literal that evaluates to `value`, if there is one.
+fn to_literal(value: Value) -> Option<Literal> {
+    match value {
+        Value::Int(n) => Some(Literal::Int(n)),
+        Value::Float(n) if n.is_finite() => Some(Literal::Float(n)),
+        // Only a purely imaginary number is written as one literal
+        Value::Complex(c) if c.re == 0.0 && c.re.is_sign_positive() && c.im.is_finite() => {
+            Some(Literal::Imaginary(c.im))
+        }
+        Value::Bool(b) => Some(Literal::Bool(b)),
+        Value::Str(s) => Some(Literal::Str(s.to_string())),
+        Value::Quantity(q) if q.value.is_finite() => {
+            q.unit.map(|unit| Literal::Quantity(q.value, unit))
+        }
+        _ => None,
+    }
+}
+
+// ------------------------------------------------
+/// Evaluate operators on literals, pick the branch of an `if` whose
+/// condition is a literal and the arm of a `match` on a literal, and
+/// inline `let`s bound to literals.
+///
+/// Operations that fail, like `1 / 0` or an integer overflow, or that
+/// overflow to a non-finite float are left for the evaluator.
+pub fn fold_constants(expr: &Expr) -> Expr {
+    rewrite(expr, &Scope::new(), &|node, _| {
+        let folded = match &node.kind {
+            ExprKind::Unary { op, operand } => {
+                literal(operand).and_then(|v| apply_unary(*op, &v, operand.span).ok())
+            }
+            // `false && _` and `true || _` are decided by the left side alone
+            ExprKind::Binary { op, lhs, .. }
+                if op.is_short_circuit() && literal(lhs) == Some(Value::Bool(*op == BinOp::Or)) =>
+            {
+                literal(lhs)
+            }
+            ExprKind::Binary { op, lhs, rhs } => match (literal(lhs), literal(rhs)) {
+                (Some(l), Some(r)) => {
+                    apply_binary(*op, &l, &r, [lhs.span, rhs.span, node.span]).ok()
+                }
+                _ => None,
+            },
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => match literal(cond) {
+                Some(Value::Bool(true)) => return (**then_branch).clone(),
+                Some(Value::Bool(false)) => return (**else_branch).clone(),
+                _ => None,
+            },
+            // With no matching arm the error is left for the evaluator
+            ExprKind::Match { scrutinee, arms } => {
+                let arm = literal(scrutinee)
+                    .and_then(|v| arms.iter().find(|arm| matches_pattern(&arm.pattern, &v)));
+                match arm {
+                    Some(arm) => return arm.body.clone(),
+                    None => None,
+                }
+            }
+            ExprKind::Convert { expr, unit } => {
+                literal(expr).and_then(|v| convert(&v, unit, expr.span).ok())
+            }
+            // A call by the bound name would fail on the literal; leave it to do so
+            ExprKind::Let { name, value, body }
+                if matches!(value.kind, ExprKind::Literal(_)) && !calls(body, name) =>
+            {
+                return fold_constants(&body.substitute(name, value));
+            }
+            _ => None,
+        };
+
+        match folded.and_then(to_literal) {
+            Some(literal) => Expr::new(ExprKind::Literal(literal), node.span),
+            None => node,
+        }
+    })
+}
+
+/// Whether `expr` calls `name` where it is not shadowed.
+fn calls(expr: &Expr, name: &str) -> bool {
+    match &expr.kind {
+        ExprKind::Call { name: callee, .. } if callee == name => true,
+        ExprKind::Let {
+            name: bound, body, ..
+        } if bound == name => false,
+        ExprKind::Lambda { params, .. } if params.iter().any(|param| param == name) => false,
+        _ => children(expr).into_iter().any(|child| calls(child, name)),
+    }
+}
+
+/// Direct subexpressions, in evaluation order.
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Var(_) => vec![],
+        ExprKind::Unary { operand, .. } => vec![operand],
+        ExprKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+        ExprKind::Call { args, .. } => args.iter().collect(),
+        ExprKind::Apply { callee, args } => std::iter::once(&**callee).chain(args).collect(),
+        ExprKind::Let { value, body, .. } => vec![value, body],
+        ExprKind::Lambda { body, .. } => vec![body],
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => vec![cond, then_branch, else_branch],
+        ExprKind::Convert { expr, .. } | ExprKind::Field { expr, .. } => vec![expr],
+        ExprKind::Record { fields } => fields.iter().map(|(_, value)| value).collect(),
+        ExprKind::Match { scrutinee, arms } => std::iter::once(&**scrutinee)
+            .chain(arms.iter().map(|arm| &arm.body))
+            .collect(),
+    }
+}
+
+/// Drop operations that leave a number or bool unchanged: `x + 0`,
+/// `x * 1`, `x - 0`, `x ^ 1` and `!!x`.
+///
+/// `x + 0` needs `x` to be an int, since `-0.0 + 0` is `0.0`. `x / 1` stays
+/// since it turns an int into a float, and `--x` stays since negating
+/// `i64::MIN` overflows.
+pub fn eliminate_identities(expr: &Expr) -> Expr {
+    rewrite(expr, &Scope::new(), &drop_identity)
+}
+
+fn drop_identity(node: Expr, scope: &Scope) -> Expr {
+    let is = |e: &Expr, k: Known| known(e, scope) == Some(k);
+    let is_number = |e: &Expr| known(e, scope).is_some_and(Known::is_number);
+    let kept = match &node.kind {
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            operand,
+        } => match &operand.kind {
+            ExprKind::Unary {
+                op: UnaryOp::Not,
+                operand: inner,
+            } if is(inner, Known::Bool) => inner,
+            _ => return node,
+        },
+        ExprKind::Binary { op, lhs, rhs } => match op {
+            BinOp::Add if is_int(rhs, 0) && is(lhs, Known::Int) => lhs,
+            BinOp::Add if is_int(lhs, 0) && is(rhs, Known::Int) => rhs,
+            BinOp::Sub if is_int(rhs, 0) && is_number(lhs) => lhs,
+            BinOp::Mul if is_int(rhs, 1) && is_number(lhs) => lhs,
+            BinOp::Mul if is_int(lhs, 1) && is_number(rhs) => rhs,
+            BinOp::Pow if is_int(rhs, 1) && is_number(lhs) => lhs,
+            _ => return node,
+        },
+        _ => return node,
+    };
+    (**kept).clone()
+}
+
+/// Swap expensive operations on numbers for cheaper exact ones: `x ^ 2`
+/// to `x * x`, `2 * x` to `x + x` and division by a power of two to
+/// multiplication.
+pub fn reduce_strength(expr: &Expr) -> Expr {
+    rewrite(expr, &Scope::new(), &cheapen)
+}
+
+fn cheapen(node: Expr, scope: &Scope) -> Expr {
+    let ExprKind::Binary { op, lhs, rhs } = &node.kind else {
+        return node;
+    };
+    let is_number = |e: &Expr| known(e, scope).is_some_and(Known::is_number);
+    let is_var = |e: &Expr| matches!(e.kind, ExprKind::Var(_)) && is_number(e);
+    let binary = |op, lhs: &Expr, rhs: &Expr| {
+        let (lhs, rhs) = (Box::new(lhs.clone()), Box::new(rhs.clone()));
+        Expr::new(ExprKind::Binary { op, lhs, rhs }, node.span)
+    };
+
+    match op {
+        // Only variables are duplicated, since they are cheap to read twice
+        BinOp::Pow if is_var(lhs) && is_int(rhs, 2) => binary(BinOp::Mul, lhs, lhs),
+        BinOp::Mul if is_var(lhs) && is_int(rhs, 2) => binary(BinOp::Add, lhs, lhs),
+        BinOp::Mul if is_var(rhs) && is_int(lhs, 2) => binary(BinOp::Add, rhs, rhs),
+        // `/` always yields a float, and so does multiplying by one
+        BinOp::Div if is_number(lhs) => match rhs.kind {
+            ExprKind::Literal(Literal::Int(_) | Literal::Float(_)) => {
+                match literal(rhs).and_then(|d| d.as_number(rhs.span).ok()) {
+                    Some(d) if has_exact_reciprocal(d) => {
+                        let reciprocal = ExprKind::Literal(Literal::Float(1.0 / d));
+                        binary(BinOp::Mul, lhs, &Expr::new(reciprocal, rhs.span))
+                    }
+                    _ => node,
+                }
+            }
+            _ => node,
+        },
+        _ => node,
+    }
+}
+
+/// Powers of two whose reciprocal is also a normal float, so `x / d`
+/// and `x * (1 / d)` round identically.
+fn has_exact_reciprocal(d: f64) -> bool {
+    const MANTISSA: u64 = (1 << 52) - 1;
+    d.is_normal() && d.to_bits() & MANTISSA == 0 && (1.0 / d).is_normal()
+}
+
+// ------------------------------------------------
+/// Bind repeated subexpressions to a `let` and reuse the variable.
+///
+/// A repeat is only shared when its first occurrence is the first thing
+/// evaluated under the node the `let` wraps, so the order in which errors
+/// surface does not change. Lambda bodies and `let` bodies are handled as
+/// separate scopes.
+///
+/// Calls are only shared when they cannot reach a host function, whose
+/// results may change from call to call: calls to pure builtins, with
+/// none of their names bound in `expr`.
+pub fn eliminate_common_subexpressions(expr: &Expr) -> Expr {
+    share_repeats(expr, &HashSet::new())
+}
+
+/// Common subexpression elimination where the builtins named in `globals`
+/// may have been redefined.
+fn share_repeats(expr: &Expr, globals: &HashSet<String>) -> Expr {
+    let mut shadowed = globals.clone();
+    binders(expr, &mut shadowed);
+    let mut cse = Cse {
+        used: expr.names(),
+        fresh: HashSet::new(),
+        shadowed,
+        next: 0,
+    };
+
+    let mut expr = expr.clone();
+    cse.region(&mut expr);
+    expr
+}
+
+fn size(expr: &Expr) -> usize {
+    1 + children(expr).into_iter().map(size).sum::<usize>()
+}
+
+/// Names bound by every `let` and lambda in `expr`.
+fn binders(expr: &Expr, out: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::Let { name, .. } => {
+            out.insert(name.clone());
+        }
+        ExprKind::Lambda { params, .. } => out.extend(params.iter().cloned()),
+        _ => {}
+    }
+    for child in children(expr) {
+        binders(child, out);
+    }
+}
+
+struct Cse {
+    /// Every name in the input, so fresh names never collide
+    used: HashSet<String>,
+    /// Names of the `let`s this pass introduced
+    fresh: HashSet<String>,
+    /// Names that may not refer to the builtins they are named after
+    shadowed: HashSet<String>,
+    next: usize,
+}
+
+impl Cse {
+    fn fresh_name(&mut self) -> String {
+        loop {
+            let name = format!("_cse{}", self.next);
+            self.next += 1;
+            if !self.used.contains(&name) {
+                self.fresh.insert(name.clone());
+                return name;
+            }
+        }
+    }
+
+    /// Whether `expr` can only call builtins that run no other code, so
+    /// evaluating it twice gives the same result.
+    fn is_pure(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Apply { .. } => false,
+            ExprKind::Call { name, .. }
+                if self.shadowed.contains(name) || !is_pure_builtin(name) =>
+            {
+                false
+            }
+            _ => children(expr).into_iter().all(|child| self.is_pure(child)),
+        }
+    }
+
+    /// Children in evaluation order that share the scope of `expr`.
+    fn children<'a>(&self, expr: &'a Expr) -> Vec<&'a Expr> {
+        match &expr.kind {
+            ExprKind::Unary { operand, .. } => vec![operand],
+            ExprKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            ExprKind::Call { args, .. } => args.iter().collect(),
+            // Our own lets bind fresh names, so their bodies share the scope
+            ExprKind::Let { name, value, body } if self.fresh.contains(name) => {
+                vec![value, body]
+            }
+            ExprKind::Let { value, .. } => vec![value],
+            ExprKind::Convert { expr, .. } | ExprKind::Field { expr, .. } => vec![expr],
+            ExprKind::Record { fields } => fields.iter().map(|(_, value)| value).collect(),
+            // Only the condition is always evaluated, and it comes first
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => vec![cond, then_branch, else_branch],
+            ExprKind::Apply { .. } | ExprKind::Match { .. } => children(expr),
+            _ => vec![],
+        }
+    }
+
+    fn collect<'a>(
+        &self,
+        expr: &'a Expr,
+        path: &mut Vec<usize>,
+        out: &mut Vec<(Vec<usize>, &'a Expr)>,
+    ) {
+        out.push((path.clone(), expr));
+        for (i, child) in self.children(expr).into_iter().enumerate() {
+            path.push(i);
+            self.collect(child, path, out);
+            path.pop();
+        }
+    }
+
+    /// Share repeats within one scope, then handle the scopes nested in it.
+    fn region(&mut self, expr: &mut Expr) {
+        while self.hoist_one(expr) {}
+        self.nested_regions(expr);
+    }
+
+    fn nested_regions(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Literal(_) | ExprKind::Var(_) => {}
+            ExprKind::Unary { operand, .. } => self.nested_regions(operand),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.nested_regions(lhs);
+                self.nested_regions(rhs);
+            }
+            ExprKind::Call { args, .. } => args.iter_mut().for_each(|arg| self.nested_regions(arg)),
+            ExprKind::Apply { callee, args } => {
+                self.nested_regions(callee);
+                args.iter_mut().for_each(|arg| self.nested_regions(arg));
+            }
+            ExprKind::Let { name, value, body } => {
+                self.nested_regions(value);
+                if self.fresh.contains(name) {
+                    self.nested_regions(body);
+                } else {
+                    self.region(body);
+                }
+            }
+            ExprKind::Lambda { body, .. } => self.region(body),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.nested_regions(cond);
+                self.nested_regions(then_branch);
+                self.nested_regions(else_branch);
+            }
+            ExprKind::Convert { expr, .. } | ExprKind::Field { expr, .. } => {
+                self.nested_regions(expr)
+            }
+            ExprKind::Record { fields } => fields
+                .iter_mut()
+                .for_each(|(_, value)| self.nested_regions(value)),
+            ExprKind::Match { scrutinee, arms } => {
+                self.nested_regions(scrutinee);
+                arms.iter_mut()
+                    .for_each(|arm| self.nested_regions(&mut arm.body));
+            }
+        }
+    }
+
+    /// Find the largest shareable repeat and bind it; false if none.
+    fn hoist_one(&mut self, root: &mut Expr) -> bool {
+        let mut nodes = Vec::new();
+        self.collect(root, &mut Vec::new(), &mut nodes);
+
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (i, (_, node)) in nodes.iter().enumerate() {
+            let candidate = matches!(
+                node.kind,
+                ExprKind::Unary { .. } | ExprKind::Binary { .. } | ExprKind::Call { .. }
+            ) && self.is_pure(node);
+            let seen = groups.iter().any(|group| nodes[group[0]].1 == *node);
+            if candidate && !seen {
+                let group: Vec<usize> = (i..nodes.len()).filter(|&j| nodes[j].1 == *node).collect();
+                if group.len() > 1 {
+                    groups.push(group);
+                }
+            }
+        }
+        groups.sort_by_key(|group| std::cmp::Reverse(size(nodes[group[0]].1)));
+
+        for group in groups {
+            let paths: Vec<&Vec<usize>> = group.iter().map(|&i| &nodes[i].0).collect();
+            let common = paths.iter().map(|path| path.len()).min().map_or(0, |len| {
+                (0..len)
+                    .take_while(|&k| paths.iter().all(|p| p[k] == paths[0][k]))
+                    .count()
+            });
+
+            // The first occurrence must be evaluated before anything else under the `let`
+            if paths[0][common..].iter().any(|&i| i != 0) {
+                continue;
+            }
+
+            let shared = nodes[group[0]].1.clone();
+            let lca: Vec<usize> = paths[0][..common].to_vec();
+            let relative: Vec<Vec<usize>> = paths.iter().map(|p| p[common..].to_vec()).collect();
+            self.bind(root, &lca, &relative, shared);
+            return true;
+        }
+        false
+    }
+
+    /// Replace each occurrence under `lca` with a fresh variable bound around it.
+    fn bind(&mut self, root: &mut Expr, lca: &[usize], occurrences: &[Vec<usize>], shared: Expr) {
+        let name = self.fresh_name();
+        let target = node_at(root, lca);
+        for path in occurrences {
+            let node = node_at(target, path);
+            *node = Expr::new(ExprKind::Var(name.clone()), node.span);
+        }
+
+        let span = target.span;
+        let body = std::mem::replace(target, Expr::int(0));
+        *target = Expr::new(
+            ExprKind::Let {
+                name,
+                value: Box::new(shared),
+                body: Box::new(body),
+            },
+            span,
+        );
+    }
+}
+
+/// The node reached by following child indices as numbered by `Cse::children`.
+fn node_at<'a>(mut expr: &'a mut Expr, path: &[usize]) -> &'a mut Expr {
+    for &i in path {
+        expr = match &mut expr.kind {
+            ExprKind::Unary { operand, .. } => operand,
+            ExprKind::Binary { lhs, rhs, .. } => {
+                if i == 0 {
+                    lhs
+                } else {
+                    rhs
+                }
+            }
+            ExprKind::Call { args, .. } => &mut args[i],
+            ExprKind::Apply { callee, args } => match i {
+                0 => callee,
+                _ => &mut args[i - 1],
+            },
+            ExprKind::Let { value, body, .. } => {
+                if i == 0 {
+                    value
+                } else {
+                    body
+                }
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => match i {
+                0 => cond,
+                1 => then_branch,
+                _ => else_branch,
+            },
+            ExprKind::Convert { expr, .. } | ExprKind::Field { expr, .. } => expr,
+            ExprKind::Record { fields } => &mut fields[i].1,
+            ExprKind::Match { scrutinee, arms } => match i {
+                0 => scrutinee,
+                _ => &mut arms[i - 1].body,
+            },
+            _ => unreachable!("paths only lead through nodes with children"),
+        };
+    }
+    expr
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{env::Bindings, error::EvalError, eval::Interpreter, value::Value};
+
+    fn optimized(src: &str) -> String {
+        let expr: Expr = src.parse().unwrap();
+        Optimizer::new().optimize(&expr).to_string()
+    }
+
+    fn with(pass: fn(&Expr) -> Expr, src: &str) -> String {
+        pass(&src.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(with(fold_constants, "1 + 2 * 3"), "7");
+        assert_eq!(with(fold_constants, "x * (2 ^ 3 - 1)"), "x * 7");
+        assert_eq!(with(fold_constants, "-(4 - 6)"), "2");
+        assert_eq!(with(fold_constants, "let n = 4 in n * x + n"), "4 * x + 4");
+        // Calls may be shadowed by user functions, so they stay
+        assert_eq!(with(fold_constants, "sqrt(4)"), "sqrt(4)");
+    }
+
+    #[test]
+    fn test_folding_typed_literals() {
+        assert_eq!(with(fold_constants, "7 / 2 + 1"), "4.5");
+        assert_eq!(with(fold_constants, "2 ^ 10 - 1.5"), "1022.5");
+        assert_eq!(with(fold_constants, r#""ab" + "c""#), r#""abc""#);
+        assert_eq!(with(fold_constants, "1 < 2 && !false"), "true");
+        assert_eq!(with(fold_constants, "1 == 1.0"), "true");
+        // Decided by the left side, even though the right side is unknown
+        assert_eq!(with(fold_constants, "false && x"), "false");
+        assert_eq!(with(fold_constants, "true && x"), "true && x");
+        assert_eq!(with(fold_constants, "if 2 > 1 then a else 1 / 0"), "a");
+        assert_eq!(
+            with(fold_constants, "let t = true in if t then x else y"),
+            "x"
+        );
+        // Overflow and type errors are left for the evaluator
+        assert_eq!(
+            with(fold_constants, "9223372036854775807 + 1"),
+            "9223372036854775807 + 1"
+        );
+        assert_eq!(with(fold_constants, "-true"), "-true");
+        // Complex results fold when one imaginary literal can hold them
+        assert_eq!(with(fold_constants, "2i * 2 + 1i"), "5i");
+        assert_eq!(with(fold_constants, "1i * 1i"), "1i * 1i");
+    }
+
+    #[test]
+    fn test_folding_match() {
+        assert_eq!(
+            with(
+                fold_constants,
+                "match 2 * 3 { 0..5 => a, 6 => b, _ => 1 / 0 }"
+            ),
+            "b"
+        );
+        assert_eq!(
+            with(fold_constants, "match x { 1 => 2 + 2, _ => y }"),
+            "match x { 1 => 4, _ => y }"
+        );
+        assert_eq!(
+            with(fold_constants, "match 7 { 1 => a }"),
+            "match 7 { 1 => a }"
+        );
+        assert_eq!(with(fold_constants, "{ a: 1 + 1 }.a"), "{ a: 2 }.a");
+    }
+
+    #[test]
+    fn test_folding_quantities() {
+        assert_eq!(with(fold_constants, "3 m + 50 cm"), "3.5 m");
+        assert_eq!(with(fold_constants, "20 C -> F"), "68 F");
+        assert_eq!(with(fold_constants, "6 m / 2 m"), "3.0");
+        // No named unit for m/s, so the literal stays
+        assert_eq!(with(fold_constants, "6 m / 2 s"), "6 m / 2 s");
+        assert_eq!(with(fold_constants, "3 m + 2 s"), "3 m + 2 s");
+    }
+
+    #[test]
+    fn test_folding_respects_shadowing() {
+        assert_eq!(
+            with(fold_constants, "let n = 1 in (let n = x in n) + n"),
+            "(let n = x in n) + 1"
+        );
+        assert_eq!(with(fold_constants, "let n = 1 in |n| n + 1"), "|n| n + 1");
+        assert_eq!(
+            with(fold_constants, "let f = 1 in f(2)"),
+            "let f = 1 in f(2)"
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero_is_not_folded() {
+        assert_eq!(with(fold_constants, "1 / 0"), "1 / 0");
+        assert_eq!(optimized("x / (2 - 2)"), "x / 0");
+
+        let expr: Expr = "1 + 2 / (1 - 1)".parse().unwrap();
+        let interp = Interpreter::new();
+        let err = interp.eval(&Optimizer::new().optimize(&expr)).unwrap_err();
+        assert!(matches!(err, EvalError::DivisionByZero { .. }));
+        assert_eq!(Err(err), interp.eval(&expr));
+    }
+
+    #[test]
+    fn test_identity_elimination() {
+        assert_eq!(
+            with(eliminate_identities, "let x = 7 in x * 1 + 0"),
+            "let x = 7 in x"
+        );
+        assert_eq!(with(eliminate_identities, "0 + 1 * (2 - 3)"), "2 - 3");
+        assert_eq!(
+            with(eliminate_identities, "let x = -y < 0 in (x - 0) ^ 1 * 1"),
+            "let x = -y < 0 in (x - 0) ^ 1 * 1"
+        );
+        assert_eq!(
+            with(eliminate_identities, "let x = 1.5 in (x - 0) ^ 1 * 1"),
+            "let x = 1.5 in x"
+        );
+        assert_eq!(with(eliminate_identities, "!!(a < b)"), "a < b");
+        // `-0.0 + 0` is `0.0`, so only ints lose a `+ 0`
+        assert_eq!(
+            with(eliminate_identities, "let x = 0.5 in x + 0"),
+            "let x = 0.5 in x + 0"
+        );
+        // Nothing is known about free variables, which may not be numbers
+        assert_eq!(with(eliminate_identities, "x * 1 + 0"), "x * 1 + 0");
+        assert_eq!(with(eliminate_identities, "!!x"), "!!x");
+        assert_eq!(
+            with(eliminate_identities, "let n = 1 in |x| x * n"),
+            "let n = 1 in |x| x * n"
+        );
+        // Not identities: these could hide an error in `x`
+        assert_eq!(with(eliminate_identities, "x * 0"), "x * 0");
+        assert_eq!(with(eliminate_identities, "0 - x"), "0 - x");
+        assert_eq!(with(eliminate_identities, "--x"), "--x");
+        // ...or change an int into a float
+        assert_eq!(with(eliminate_identities, "x / 1"), "x / 1");
+        assert_eq!(with(eliminate_identities, "x * 1.0"), "x * 1.0");
+    }
+
+    #[test]
+    fn test_strength_reduction() {
+        let reduced = |body: &str| {
+            let src = format!("let x = 3 in let y = 0.5 in {}", body);
+            let out = with(reduce_strength, &src);
+            out[src.len() - body.len()..].to_string()
+        };
+        assert_eq!(reduced("x ^ 2"), "x * x");
+        assert_eq!(reduced("2 * y"), "y + y");
+        assert_eq!(reduced("x / 4"), "x * 0.25");
+        assert_eq!(reduced("x / 0.5"), "x * 2.0");
+        assert_eq!(reduced("x / 3"), "x / 3");
+        assert_eq!(reduced("(x + y) ^ 2"), "(x + y) ^ 2");
+        // `x ^ 2.0` is a float even for an int `x`
+        assert_eq!(reduced("x ^ 2.0"), "x ^ 2.0");
+        // `2 * s` would repeat a string `s`
+        assert_eq!(with(reduce_strength, "2 * s + v / 4"), "2 * s + v / 4");
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        assert_eq!(
+            with(eliminate_common_subexpressions, "(a + b) * (a + b)"),
+            "let _cse0 = a + b in _cse0 * _cse0"
+        );
+        assert_eq!(
+            with(
+                eliminate_common_subexpressions,
+                "sqrt(x * y) + sqrt(x * y) / 2"
+            ),
+            "let _cse0 = sqrt(x * y) in _cse0 + _cse0 / 2"
+        );
+        // Fresh names avoid those already in use
+        assert_eq!(
+            with(eliminate_common_subexpressions, "-_cse0 * -_cse0"),
+            "let _cse1 = -_cse0 in _cse1 * _cse1"
+        );
+    }
+
+    #[test]
+    fn test_cse_leaves_impure_calls_alone() {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicI64, Ordering},
+        };
+
+        use crate::registry::FunctionRegistry;
+
+        // Any of these may reach a host function, which may change
+        for src in [
+            "f(x) + f(x)",
+            "tick() * tick()",
+            "(g)(1) + (g)(1)",
+            "sum(s) + sum(s)",
+            "sqrt(f(x)) - sqrt(f(x))",
+            "(|sqrt| sqrt(x) + sqrt(x))(f)",
+        ] {
+            let expr: Expr = src.parse().unwrap();
+            assert_eq!(eliminate_common_subexpressions(&expr), expr, "{}", src);
+        }
+
+        let calls = Arc::new(AtomicI64::new(0));
+        let counter = Arc::clone(&calls);
+        let mut registry = FunctionRegistry::new();
+        registry.register("tick", move || counter.fetch_add(1, Ordering::SeqCst));
+        let interp = Interpreter::new().with_registry(registry);
+        let expr = Optimizer::new().optimize(&"tick() * 10 + tick()".parse().unwrap());
+        assert_eq!(interp.eval(&expr).unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // A global may redefine a builtin too
+        let f = Value::Int(0);
+        let optimizer = Optimizer::new().with_vars([("sqrt", &f)]);
+        let expr: Expr = "sqrt(x) + sqrt(x)".parse().unwrap();
+        assert_eq!(optimizer.optimize(&expr), expr);
+        assert_eq!(
+            Optimizer::new().optimize(&expr).to_string(),
+            "let _cse0 = sqrt(x) in _cse0 + _cse0"
+        );
+    }
+
+    #[test]
+    fn test_cse_keeps_error_order() {
+        // Hoisting `a + b` would evaluate it before `x`
+        assert_eq!(
+            with(eliminate_common_subexpressions, "x * (a + b) + (a + b)"),
+            "x * (a + b) + (a + b)"
+        );
+    }
+
+    #[test]
+    fn test_cse_leaves_conditional_code_alone() {
+        // Hoisting would evaluate `x / y` even when `y == 0`
+        assert_eq!(
+            with(
+                eliminate_common_subexpressions,
+                "if y == 0 then 0 else x / y + x / y"
+            ),
+            "if y == 0 then 0 else let _cse0 = x / y in _cse0 + _cse0"
+        );
+        assert_eq!(
+            with(
+                eliminate_common_subexpressions,
+                "if c then x / y else x / y"
+            ),
+            "if c then x / y else x / y"
+        );
+        assert_eq!(
+            with(eliminate_common_subexpressions, "y > 0 && x / y > x / y"),
+            "y > 0 && (let _cse0 = x / y in _cse0 > _cse0)"
+        );
+        // The condition always runs first, so a repeat of it can be shared
+        assert_eq!(
+            with(eliminate_common_subexpressions, "if -x > 0 then -x else 0"),
+            "let _cse0 = -x in if _cse0 > 0 then _cse0 else 0"
+        );
+    }
+
+    #[test]
+    fn test_cse_respects_scopes() {
+        // The inner `a + b` refers to a different `a`
+        assert_eq!(
+            with(
+                eliminate_common_subexpressions,
+                "(a + b) + (let a = 1 in a + b)"
+            ),
+            "a + b + (let a = 1 in a + b)"
+        );
+        assert_eq!(
+            with(eliminate_common_subexpressions, "|v| (v - 1) * (v - 1)"),
+            "|v| let _cse0 = v - 1 in _cse0 * _cse0"
+        );
+    }
+
+    #[test]
+    fn test_passes_can_be_toggled() {
+        let expr: Expr = "x * 1 + 2 * 3".parse().unwrap();
+        let x = Value::Int(5);
+        let only_folding = Optimizer::new()
+            .with_vars([("x", &x)])
+            .identity_elimination(false)
+            .strength_reduction(false)
+            .common_subexpressions(false);
+        assert_eq!(only_folding.optimize(&expr).to_string(), "x * 1 + 6");
+
+        let nothing = only_folding.clone().constant_folding(false);
+        assert_eq!(nothing.optimize(&expr), expr);
+        let all = only_folding
+            .identity_elimination(true)
+            .strength_reduction(true)
+            .common_subexpressions(true);
+        assert_eq!(all.optimize(&expr).to_string(), "x + 6");
+        assert_eq!(optimized("x * 1 + 2 * 3"), "x * 1 + 6");
+    }
+
+    #[test]
+    fn test_passes_feed_each_other() {
+        let x = Value::Int(3);
+        let optimizer = Optimizer::new().with_vars([("x", &x)]);
+        let optimized = |src: &str| optimizer.optimize(&src.parse().unwrap()).to_string();
+        assert_eq!(optimized("let k = 1 in x * k + (3 - 3)"), "x");
+        assert_eq!(
+            optimized("(x ^ (4 - 2)) * (x ^ 2)"),
+            "let _cse0 = x * x in _cse0 * _cse0"
+        );
+        // Known through a `let` as well
+        assert_eq!(
+            optimized("|y| let z = y * 1 < 0 in !!z && !!!!z"),
+            "|y| let z = y * 1 < 0 in z && z"
+        );
+    }
+
+    #[test]
+    fn test_optimize_stmt() {
+        let stmt: Stmt = "fn f(x) = let y = x < 1 in !!y && y".parse().unwrap();
+        assert_eq!(
+            Optimizer::new().optimize_stmt(&stmt).to_string(),
+            "fn f(x) = let y = x < 1 in y && y"
+        );
+        // The body may run after `x` changes
+        let x = Value::Int(1);
+        let stmt: Stmt = "fn f(y) = x * 1 + y * 1".parse().unwrap();
+        assert_eq!(
+            Optimizer::new().with_vars([("x", &x)]).optimize_stmt(&stmt),
+            stmt
+        );
+        let stmt: Stmt = "z = x * 1".parse().unwrap();
+        assert_eq!(
+            Optimizer::new()
+                .with_vars([("x", &x)])
+                .optimize_stmt(&stmt)
+                .to_string(),
+            "z = x"
+        );
+    }
+
+    #[test]
+    fn test_semantics_preserved() {
+        let rows: Vec<Bindings> = [
+            (3.0.into(), 4.0.into()),
+            ((-1.5).into(), 0.0.into()),
+            (0.0.into(), (-0.25).into()),
+            (7.into(), 2.into()),
+            (i64::MAX.into(), (-1).into()),
+            ((-0.0).into(), (-0.0).into()),
+            ("s".into(), true.into()),
+            (vec![Value::Int(1)].into(), "t".into()),
+        ]
+        .into_iter()
+        .map(|(x, y): (Value, Value)| [("x".to_string(), x), ("y".to_string(), y)].into())
+        .collect();
+        let sources = [
+            "x * 1 + 0 * y",
+            "(x + y) * (x + y) - (x + y)",
+            "x ^ 2 + 2 * y + x / 8",
+            "let k = 2 in k * x + k ^ 2",
+            "1 / y + (x + 1) * (x + 1)",
+            "x / (y - y) + 1 * 2",
+            "sqrt(x * x) + sqrt(x * x)",
+            "z * (x + y) + (x + y)",
+            "let f = |v| v * 1 + v * 1 in f(x) + f(y)",
+            "max(x ^ 2, y ^ 2) % (3 - 1)",
+            "x / 1 + x * 1.0 + --x",
+            "if y == 0 then 0 else x / y + x / y",
+            "x > y && x / y > 1 || !(x != x)",
+            "let big = 2 ^ 62 in x * big + (x * big)",
+            "match x * 2 { 0 => y, ..0 => x * 2 + x * 2, _ => { a: x * 2, b: y }.a }",
+            "(x + 1i) ^ 2 * 1 + 2i * 2 - sqrt(-y) / 1",
+            "!!x || !!(y == 0)",
+            "x * 2 + (y - 0) ^ 1",
+            "let z = x + 0 in if y then z else 0 + x",
+            "|v| v * 1",
+        ];
+
+        for src in sources {
+            let expr: Expr = src.parse().unwrap();
+            for row in &rows {
+                let interp = Interpreter::with_bindings(row);
+                let vars = row.iter().map(|(name, value)| (name.as_str(), value));
+                for optimizer in [Optimizer::new(), Optimizer::new().with_vars(vars)] {
+                    let opt = optimizer.optimize(&expr);
+                    let (before, after) = (interp.eval(&expr), interp.eval(&opt));
+                    // `-0.0 == 0.0`, so compare the text
+                    assert_eq!(
+                        format!("{:?}", before),
+                        format!("{:?}", after),
+                        "{} => {}",
+                        src,
+                        opt
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimized_output_round_trips() {
+        let expr: Expr = "(a - 2 * 3) * (a - 2 * 3) / 2".parse().unwrap();
+        let opt = Optimizer::new().optimize(&expr);
+        assert_eq!(opt.to_string(), "(let _cse0 = a - 6 in _cse0 * _cse0) / 2");
+        let reparsed: Expr = opt.to_string().parse().unwrap();
+        assert_eq!(reparsed, opt);
+    }
+}
@@ -0,0 +1,1356 @@
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Pattern, Stmt, UnaryOp},
+    error::ParseError,
+    lexer::{Lexer, Span, Token, TokenKind},
+    units::{self, Unit},
+};
+
+/// How deeply a parser lets expressions nest unless told otherwise. The
+/// parser, evaluators and printers all recurse once per level, so this
+/// keeps them well inside a thread's stack whatever the input.
+pub const MAX_NESTING: usize = 100;
+
+// ------------------------------------------------
+/// Precedence-climbing (Pratt) parser over a token vector.
+///
+/// Errors are collected rather than returned at the first one: bad
+/// characters are skipped by the lexer and a missing or rejected operand
+/// is replaced by a placeholder, so one pass reports every independent
+/// mistake, each once.
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    eof: Span,
+    errors: Vec<ParseError>,
+    /// Characters the lexer rejected and left out of `tokens`
+    dropped: Vec<Span>,
+    max_nesting: usize,
+    /// Nesting of the expression being parsed
+    depth: usize,
+}
+
+impl Parser {
+    pub fn new(src: &str) -> Self {
+        let (tokens, errors) = lex(src);
+        Parser::from_tokens(tokens, errors, Span::new(src.len(), src.len()))
+    }
+
+    /// Parse tokens that did not come straight from the lexer, e.g. ones
+    /// rewritten by the spreadsheet. `errors` are lexical errors found
+    /// along the way and `eof` is where the input ends.
+    pub(crate) fn from_tokens(tokens: Vec<Token>, errors: Vec<ParseError>, eof: Span) -> Self {
+        let dropped = errors
+            .iter()
+            .filter(|err| matches!(err, ParseError::UnexpectedChar { .. }))
+            .map(ParseError::span)
+            .collect();
+        Parser {
+            tokens,
+            pos: 0,
+            eof,
+            errors,
+            dropped,
+            max_nesting: MAX_NESTING,
+            depth: 0,
+        }
+    }
+
+    /// Reject expressions nested more than `max` deep, counting brackets,
+    /// operators and other subexpressions alike.
+    pub fn with_max_nesting(mut self, max: usize) -> Self {
+        self.max_nesting = max;
+        self
+    }
+
+    /// Fail once `extra` more levels below the current one would nest too
+    /// deeply; every later stage recurses as deeply as the tree does.
+    fn check_nesting(&self, extra: usize) -> Result<(), ParseError> {
+        if self.depth + extra <= self.max_nesting {
+            return Ok(());
+        }
+        let span = self.peek().map_or(self.eof, |t| t.span);
+        Err(ParseError::TooDeep {
+            max: self.max_nesting,
+            span,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken {
+                expected: expected.to_string(),
+                found: token.kind.to_string(),
+                span: token.span,
+            },
+            None => ParseError::UnexpectedEof {
+                expected: expected.to_string(),
+                span: self.eof,
+            },
+        }
+    }
+
+    /// Consume a token of the given kind or fail with `expected`.
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<Span, ParseError> {
+        match self.peek() {
+            Some(token) if token.kind == kind => {
+                let span = token.span;
+                self.pos += 1;
+                Ok(span)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// Whether a rejected character was dropped just before the next
+    /// token; what is missing there has been reported already.
+    fn follows_dropped(&self) -> bool {
+        let after = self
+            .pos
+            .checked_sub(1)
+            .map_or(0, |i| self.tokens[i].span.end);
+        let before = self.peek().map_or(self.eof.start, |t| t.span.start);
+        self.dropped
+            .iter()
+            .any(|span| after <= span.start && span.end <= before)
+    }
+
+    /// Record an error unless one was already reported at the same place.
+    fn report(&mut self, err: ParseError) {
+        if self.errors.iter().all(|e| e.span() != err.span()) {
+            self.errors.push(err);
+        }
+    }
+
+    /// Run `rule` over the whole input and return every error found.
+    fn run<T>(
+        mut self,
+        rule: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, Vec<ParseError>> {
+        let result = rule(&mut self).and_then(|value| {
+            self.finish()?;
+            Ok(value)
+        });
+
+        match result {
+            Ok(value) if self.errors.is_empty() => Ok(value),
+            Ok(_) => Err(self.sorted_errors()),
+            Err(err) => {
+                self.report(err);
+                Err(self.sorted_errors())
+            }
+        }
+    }
+
+    fn sorted_errors(mut self) -> Vec<ParseError> {
+        self.errors.sort_by_key(|e| e.span().start);
+        self.errors
+    }
+
+    /// Parse a complete input; trailing tokens are an error.
+    pub fn parse(self) -> Result<Expr, ParseError> {
+        self.parse_all().map_err(first)
+    }
+
+    /// Like `parse`, but reports all errors instead of the first.
+    pub fn parse_all(self) -> Result<Expr, Vec<ParseError>> {
+        self.run(|p| p.expr(0))
+    }
+
+    /// Parse a complete statement: `name = expr` or a bare expression.
+    pub fn parse_stmt(self) -> Result<Stmt, ParseError> {
+        self.parse_stmt_all().map_err(first)
+    }
+
+    /// Like `parse_stmt`, but reports all errors instead of the first.
+    pub fn parse_stmt_all(self) -> Result<Stmt, Vec<ParseError>> {
+        self.run(Parser::stmt)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, ParseError> {
+        let is_assign = matches!(
+            (self.tokens.first(), self.tokens.get(1)),
+            (
+                Some(Token {
+                    kind: TokenKind::Ident(_),
+                    ..
+                }),
+                Some(Token {
+                    kind: TokenKind::Assign,
+                    ..
+                })
+            )
+        );
+
+        let stmt = if self.peek().is_some_and(|t| t.kind == TokenKind::Fn) {
+            self.advance();
+            let (name, _) = self.ident("function name")?;
+            self.expect(TokenKind::LParen, "`(`")?;
+            let params = self.params(TokenKind::RParen, "`,` or `)`")?;
+            self.expect(TokenKind::Assign, "`=`")?;
+            let body = self.expr(0)?;
+            Stmt::FnDef { name, params, body }
+        } else if is_assign {
+            let name = self.ident("variable name")?.0;
+            self.expect(TokenKind::Assign, "`=`")?;
+            let value = self.expr(0)?;
+            Stmt::Assign { name, value }
+        } else {
+            Stmt::Expr(self.expr(0)?)
+        };
+        Ok(stmt)
+    }
+
+    fn finish(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(_) => Err(self.error("operator or end of input")),
+            None => Ok(()),
+        }
+    }
+
+    /// Comma-separated parameter names up to and including `close`.
+    fn params(&mut self, close: TokenKind, expected: &str) -> Result<Vec<String>, ParseError> {
+        let mut params = Vec::new();
+        if self.peek().is_some_and(|t| t.kind == close) {
+            self.advance();
+            return Ok(params);
+        }
+
+        loop {
+            params.push(self.ident("parameter name")?.0);
+            if self.peek().is_some_and(|t| t.kind == TokenKind::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        self.expect(close, expected)?;
+        Ok(params)
+    }
+
+    /// Consume an identifier, returning its name and span.
+    fn ident(&mut self, expected: &str) -> Result<(String, Span), ParseError> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Ident(name),
+                span,
+            }) => {
+                let ident = (name.clone(), *span);
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    /// Consume the name of a unit, as after `->`.
+    fn unit(&mut self) -> Result<(&'static Unit, Span), ParseError> {
+        let (name, span) = self.ident("unit")?;
+        match units::lookup(&name) {
+            Some(unit) => Ok((unit, span)),
+            None => Err(ParseError::UnknownUnit { name, span }),
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinOp> {
+        let op = match self.peek()?.kind {
+            TokenKind::Plus => BinOp::Add,
+            TokenKind::Minus => BinOp::Sub,
+            TokenKind::Star => BinOp::Mul,
+            TokenKind::Slash => BinOp::Div,
+            TokenKind::Percent => BinOp::Rem,
+            TokenKind::Caret => BinOp::Pow,
+            TokenKind::EqEq => BinOp::Eq,
+            TokenKind::NotEq => BinOp::Ne,
+            TokenKind::Lt => BinOp::Lt,
+            TokenKind::Le => BinOp::Le,
+            TokenKind::Gt => BinOp::Gt,
+            TokenKind::Ge => BinOp::Ge,
+            TokenKind::AndAnd => BinOp::And,
+            TokenKind::OrOr => BinOp::Or,
+            TokenKind::DotDot => BinOp::Range,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Parse operators binding at least as tightly as `min_prec`.
+    fn expr(&mut self, min_prec: u8) -> Result<Expr, ParseError> {
+        self.check_nesting(1)?;
+        self.depth += 1;
+        let result = self.chain(min_prec);
+        self.depth -= 1;
+        result
+    }
+
+    /// The body of `expr`: an operand followed by operators, each of
+    /// which nests the expression so far one level deeper.
+    fn chain(&mut self, min_prec: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.prefix()?;
+        let mut nesting = 0;
+
+        loop {
+            if let Some(op) = self.binary_op() {
+                let prec = op.precedence();
+                if prec < min_prec {
+                    break;
+                }
+                nesting += 1;
+                self.check_nesting(nesting)?;
+                self.advance();
+
+                let next_min = if op.is_right_assoc() { prec } else { prec + 1 };
+                let rhs = self.expr(next_min)?;
+                lhs = Expr::binary(op, lhs, rhs);
+            } else if min_prec == 0 && self.peek().is_some_and(|t| t.kind == TokenKind::Arrow) {
+                // `->` binds more loosely than any operator
+                nesting += 1;
+                self.check_nesting(nesting)?;
+                self.advance();
+                let (unit, span) = self.unit()?;
+                let span = lhs.span.to(span);
+                lhs = Expr::new(
+                    ExprKind::Convert {
+                        expr: Box::new(lhs),
+                        unit,
+                    },
+                    span,
+                );
+            } else if self.follows_dropped()
+                && self.peek().is_some_and(|t| can_start_operand(&t.kind))
+            {
+                // The dropped character probably stood for an operator;
+                // skip the operand after it rather than report it too
+                self.prefix()?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(lhs)
+    }
+
+    /// Parse an operand, skipping stray tokens in front of it.
+    ///
+    /// When no operand can be found a `NaN` placeholder is returned; it is
+    /// never seen by callers because the missing operand is reported.
+    fn prefix(&mut self) -> Result<Expr, ParseError> {
+        let mut reported = false;
+        loop {
+            let placeholder = match self.peek() {
+                None => Some(self.eof),
+                Some(token) if can_start_operand(&token.kind) => None,
+                // Leave closing tokens for the construct that expects them
+                Some(token) if is_closing(&token.kind) => Some(token.span),
+                Some(_) => {
+                    if !reported {
+                        self.report(self.error("operand"));
+                        reported = true;
+                    }
+                    self.advance();
+                    continue;
+                }
+            };
+
+            if let Some(span) = placeholder {
+                if !reported && !self.follows_dropped() {
+                    self.report(self.error("operand"));
+                }
+                return Ok(Expr::new(ExprKind::Literal(Literal::Float(f64::NAN)), span));
+            }
+            let operand = self.operand()?;
+            return self.postfix(operand);
+        }
+    }
+
+    /// Parse any `.name` accesses and `(args)` calls after an operand, as
+    /// in `make_adder(1)(2).total`; they bind tightest.
+    fn postfix(&mut self, mut expr: Expr) -> Result<Expr, ParseError> {
+        let mut nesting = 0;
+        loop {
+            let is_field = match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::Dot) => true,
+                Some(TokenKind::LParen) => false,
+                _ => return Ok(expr),
+            };
+            nesting += 1;
+            self.check_nesting(nesting)?;
+            self.advance();
+            let start = expr.span;
+            let inner = Box::new(expr);
+            let (kind, end) = if is_field {
+                let (name, span) = self.ident("field name")?;
+                (ExprKind::Field { expr: inner, name }, span)
+            } else {
+                let (args, close) = self.items(TokenKind::RParen, "`,` or `)`")?;
+                (
+                    ExprKind::Apply {
+                        callee: inner,
+                        args,
+                    },
+                    close,
+                )
+            };
+            expr = Expr::new(kind, start.to(end));
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, ParseError> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error("operand"));
+        };
+
+        let literal = match &token.kind {
+            TokenKind::Int(n) => Some(Literal::Int(*n)),
+            TokenKind::BigInt(n) => Some(Literal::BigInt(n.clone())),
+            TokenKind::Float(n) => Some(Literal::Float(*n)),
+            TokenKind::Imaginary(n) => Some(Literal::Imaginary(*n)),
+            TokenKind::Str(s) => Some(Literal::Str(s.clone())),
+            TokenKind::True => Some(Literal::Bool(true)),
+            TokenKind::False => Some(Literal::Bool(false)),
+            // Already reported, so stand in for it as for a missing operand
+            TokenKind::Invalid => Some(Literal::Float(f64::NAN)),
+            _ => None,
+        };
+        if let Some(literal) = literal {
+            self.advance();
+            // A number directly followed by a unit is a quantity: `3 m`
+            if let (
+                Some(n),
+                Some(Token {
+                    kind: TokenKind::Ident(name),
+                    span,
+                }),
+            ) = (literal.as_f64(), self.peek())
+                && let Some(unit) = units::lookup(name)
+            {
+                let span = token.span.to(*span);
+                self.advance();
+                return Ok(Expr::new(
+                    ExprKind::Literal(Literal::Quantity(n, unit)),
+                    span,
+                ));
+            }
+            return Ok(Expr::new(ExprKind::Literal(literal), token.span));
+        }
+
+        match token.kind {
+            TokenKind::Ident(name) => {
+                self.advance();
+                if self.peek().is_some_and(|t| t.kind == TokenKind::LParen) {
+                    self.call(name, token.span)
+                } else {
+                    Ok(Expr::new(ExprKind::Var(name), token.span))
+                }
+            }
+            TokenKind::Minus | TokenKind::Bang => {
+                self.advance();
+                let op = if token.kind == TokenKind::Minus {
+                    UnaryOp::Neg
+                } else {
+                    UnaryOp::Not
+                };
+                let operand = self.expr(op.precedence())?;
+                let span = token.span.to(operand.span);
+                let operand = Box::new(operand);
+                Ok(Expr::new(ExprKind::Unary { op, operand }, span))
+            }
+            TokenKind::Let => {
+                self.advance();
+                let (name, _) = self.ident("variable name")?;
+                self.expect(TokenKind::Assign, "`=`")?;
+                let value = Box::new(self.expr(0)?);
+                self.expect(TokenKind::In, "`in`")?;
+                let body = Box::new(self.expr(0)?);
+                let span = token.span.to(body.span);
+                Ok(Expr::new(ExprKind::Let { name, value, body }, span))
+            }
+            TokenKind::If => {
+                self.advance();
+                let cond = Box::new(self.expr(0)?);
+                self.expect(TokenKind::Then, "`then`")?;
+                let then_branch = Box::new(self.expr(0)?);
+                self.expect(TokenKind::Else, "`else`")?;
+                let else_branch = Box::new(self.expr(0)?);
+                let span = token.span.to(else_branch.span);
+                let kind = ExprKind::If {
+                    cond,
+                    then_branch,
+                    else_branch,
+                };
+                Ok(Expr::new(kind, span))
+            }
+            TokenKind::Pipe | TokenKind::OrOr => {
+                self.advance();
+                // `||` in operand position is a lambda without parameters
+                let params = if token.kind == TokenKind::Pipe {
+                    self.params(TokenKind::Pipe, "`,` or `|`")?
+                } else {
+                    Vec::new()
+                };
+                let body = Box::new(self.expr(0)?);
+                let span = token.span.to(body.span);
+                Ok(Expr::new(ExprKind::Lambda { params, body }, span))
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let mut inner = self.expr(0)?;
+                let close = self.expect(TokenKind::RParen, "`)`")?;
+                inner.span = token.span.to(close);
+                Ok(inner)
+            }
+            // `[a, b]` is short for `list(a, b)`
+            TokenKind::LBracket => {
+                self.advance();
+                let (args, close) = self.items(TokenKind::RBracket, "`,` or `]`")?;
+                let name = "list".to_string();
+                Ok(Expr::new(
+                    ExprKind::Call { name, args },
+                    token.span.to(close),
+                ))
+            }
+            TokenKind::LBrace => {
+                self.advance();
+                let (fields, close) = self.record_fields()?;
+                Ok(Expr::new(ExprKind::Record { fields }, token.span.to(close)))
+            }
+            TokenKind::Match => {
+                self.advance();
+                let scrutinee = Box::new(self.expr(0)?);
+                self.expect(TokenKind::LBrace, "`{`")?;
+                let (arms, close) = self.arms()?;
+                let span = token.span.to(close);
+                Ok(Expr::new(ExprKind::Match { scrutinee, arms }, span))
+            }
+            _ => Err(self.error("operand")),
+        }
+    }
+
+    /// Parse `name(arg, ...)`; the name has already been consumed.
+    fn call(&mut self, name: String, name_span: Span) -> Result<Expr, ParseError> {
+        self.expect(TokenKind::LParen, "`(`")?;
+        let (args, close) = self.items(TokenKind::RParen, "`,` or `)`")?;
+        Ok(Expr::new(
+            ExprKind::Call { name, args },
+            name_span.to(close),
+        ))
+    }
+
+    /// Parse `name: value` pairs up to and including `}`, returning them
+    /// with the span of `}`; the `{` has already been consumed.
+    fn record_fields(&mut self) -> Result<(Vec<(String, Expr)>, Span), ParseError> {
+        let mut fields: Vec<(String, Expr)> = Vec::new();
+        if self.peek().is_some_and(|t| t.kind == TokenKind::RBrace) {
+            let span = self.expect(TokenKind::RBrace, "`}`")?;
+            return Ok((fields, span));
+        }
+
+        loop {
+            let (name, span) = self.ident("field name")?;
+            self.expect(TokenKind::Colon, "`:`")?;
+            let value = self.expr(0)?;
+            if fields.iter().any(|(field, _)| *field == name) {
+                self.report(ParseError::DuplicateField {
+                    name: name.clone(),
+                    span,
+                });
+            }
+            fields.push((name, value));
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::Comma) => {
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+
+        let span = self.expect(TokenKind::RBrace, "`,` or `}`")?;
+        Ok((fields, span))
+    }
+
+    /// Parse `pattern => body` arms up to and including `}`, returning them
+    /// with the span of `}`; the `{` has already been consumed.
+    fn arms(&mut self) -> Result<(Vec<MatchArm>, Span), ParseError> {
+        let mut arms = Vec::new();
+        if self.peek().is_some_and(|t| t.kind == TokenKind::RBrace) {
+            let span = self.expect(TokenKind::RBrace, "`}`")?;
+            return Ok((arms, span));
+        }
+
+        loop {
+            let start = self.pos;
+            let pattern = self.pattern()?;
+            let span = self.tokens[start].span.to(self.tokens[self.pos - 1].span);
+            self.expect(TokenKind::FatArrow, "`=>`")?;
+            let body = self.expr(0)?;
+            arms.push(MatchArm {
+                pattern,
+                body,
+                span,
+            });
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::Comma) => {
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+
+        let span = self.expect(TokenKind::RBrace, "`,` or `}`")?;
+        Ok((arms, span))
+    }
+
+    /// Parse `_`, a literal, or a range with constant numeric bounds.
+    ///
+    /// Literals are strings, bools, and numbers that fit an `i64` or `f64`;
+    /// quantities and bigger integers are not patterns.
+    fn pattern(&mut self) -> Result<Pattern, ParseError> {
+        match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Ident(name)) if name == "_" => {
+                self.advance();
+                return Ok(Pattern::Wildcard);
+            }
+            Some(TokenKind::DotDot) => {
+                self.advance();
+                let end = Some(self.number("number")?);
+                return Ok(Pattern::Range { start: None, end });
+            }
+            _ => {}
+        }
+
+        let literal = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Str(s)) => Literal::Str(s.clone()),
+            Some(TokenKind::True) => Literal::Bool(true),
+            Some(TokenKind::False) => Literal::Bool(false),
+            _ => {
+                let start = self.number("pattern")?;
+                if self.peek().is_none_or(|t| t.kind != TokenKind::DotDot) {
+                    return Ok(Pattern::Literal(start));
+                }
+                self.advance();
+                let end = match self.peek().map(|t| &t.kind) {
+                    Some(TokenKind::Int(_) | TokenKind::Float(_) | TokenKind::Minus) => {
+                        Some(self.number("number")?)
+                    }
+                    _ => None,
+                };
+                let start = Some(start);
+                return Ok(Pattern::Range { start, end });
+            }
+        };
+        self.advance();
+        Ok(Pattern::Literal(literal))
+    }
+
+    /// Consume a number literal, optionally negated, as in a pattern.
+    fn number(&mut self, expected: &str) -> Result<Literal, ParseError> {
+        let negate = self.peek().is_some_and(|t| t.kind == TokenKind::Minus);
+        if negate {
+            self.advance();
+        }
+        let literal = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Int(n)) if negate => Literal::Int(-n),
+            Some(TokenKind::Int(n)) => Literal::Int(*n),
+            Some(TokenKind::Float(n)) if negate => Literal::Float(-n),
+            Some(TokenKind::Float(n)) => Literal::Float(*n),
+            _ => return Err(self.error(expected)),
+        };
+        self.advance();
+        Ok(literal)
+    }
+
+    /// Parse comma-separated expressions up to and including `close`,
+    /// returning them with the span of `close`.
+    fn items(&mut self, close: TokenKind, expected: &str) -> Result<(Vec<Expr>, Span), ParseError> {
+        let mut items = Vec::new();
+        if self.peek().is_some_and(|t| t.kind == close) {
+            let span = self.expect(close, expected)?;
+            return Ok((items, span));
+        }
+
+        loop {
+            items.push(self.expr(0)?);
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::Comma) => {
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+
+        let span = self.expect(close, expected)?;
+        Ok((items, span))
+    }
+}
+
+/// Tokenize `src`, collecting lexical errors instead of stopping at one.
+/// A rejected literal leaves an `Invalid` token behind, so the parser
+/// does not report a missing operand there as well; an unexpected
+/// character leaves nothing, and the parser passes over the gap.
+pub(crate) fn lex(src: &str) -> (Vec<Token>, Vec<ParseError>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for token in Lexer::new(src) {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(err) => {
+                if matches!(
+                    err,
+                    ParseError::InvalidNumber { .. }
+                        | ParseError::UnterminatedString { .. }
+                        | ParseError::InvalidEscape { .. }
+                ) {
+                    tokens.push(Token::new(TokenKind::Invalid, err.span()));
+                }
+                errors.push(err);
+            }
+        }
+    }
+    (tokens, errors)
+}
+
+fn first(errors: Vec<ParseError>) -> ParseError {
+    errors
+        .into_iter()
+        .next()
+        .expect("failed parses report at least one error")
+}
+
+fn can_start_operand(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Invalid
+            | TokenKind::Int(_)
+            | TokenKind::BigInt(_)
+            | TokenKind::Float(_)
+            | TokenKind::Imaginary(_)
+            | TokenKind::Str(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Ident(_)
+            | TokenKind::Minus
+            | TokenKind::Bang
+            | TokenKind::Let
+            | TokenKind::If
+            | TokenKind::Pipe
+            | TokenKind::OrOr
+            | TokenKind::LParen
+            | TokenKind::LBracket
+            | TokenKind::LBrace
+            | TokenKind::Match
+    )
+}
+
+fn is_closing(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::RParen
+            | TokenKind::RBracket
+            | TokenKind::RBrace
+            | TokenKind::Comma
+            | TokenKind::In
+            | TokenKind::Assign
+            | TokenKind::Then
+            | TokenKind::Else
+    )
+}
+
+/// Parse a single expression.
+pub fn parse(src: &str) -> Result<Expr, ParseError> {
+    Parser::new(src).parse()
+}
+
+/// Parse a single expression, reporting every error found.
+pub fn parse_all(src: &str) -> Result<Expr, Vec<ParseError>> {
+    Parser::new(src).parse_all()
+}
+
+/// Parse a single statement.
+pub fn parse_stmt(src: &str) -> Result<Stmt, ParseError> {
+    Parser::new(src).parse_stmt()
+}
+
+/// Parse a single statement, reporting every error found.
+pub fn parse_stmt_all(src: &str) -> Result<Stmt, Vec<ParseError>> {
+    Parser::new(src).parse_stmt_all()
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{env::Bindings, eval::Interpreter, typecheck::TypeChecker, vm::compile};
+
+    fn num(n: i64) -> Expr {
+        Expr::int(n)
+    }
+
+    fn bin(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::binary(op, lhs, rhs)
+    }
+
+    fn neg(operand: Expr) -> Expr {
+        Expr::unary(UnaryOp::Neg, operand)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse("1 + 2 * 3").unwrap(),
+            bin(BinOp::Add, num(1), bin(BinOp::Mul, num(2), num(3)))
+        );
+        assert_eq!(
+            parse("1 * 2 + 3").unwrap(),
+            bin(BinOp::Add, bin(BinOp::Mul, num(1), num(2)), num(3))
+        );
+        assert_eq!(
+            parse("7 % 4 * 2").unwrap(),
+            bin(BinOp::Mul, bin(BinOp::Rem, num(7), num(4)), num(2))
+        );
+    }
+
+    #[test]
+    fn test_left_assoc() {
+        assert_eq!(
+            parse("10 - 4 - 3").unwrap(),
+            bin(BinOp::Sub, bin(BinOp::Sub, num(10), num(4)), num(3))
+        );
+    }
+
+    #[test]
+    fn test_power_right_assoc() {
+        assert_eq!(
+            parse("2 ^ 3 ^ 2").unwrap(),
+            bin(BinOp::Pow, num(2), bin(BinOp::Pow, num(3), num(2)))
+        );
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(
+            parse("-2 ^ 2").unwrap(),
+            neg(bin(BinOp::Pow, num(2), num(2)))
+        );
+        assert_eq!(
+            parse("-a * b").unwrap(),
+            bin(BinOp::Mul, neg(Expr::var("a")), Expr::var("b"))
+        );
+        assert_eq!(parse("--1").unwrap(), neg(neg(num(1))));
+        assert_eq!(
+            parse("2 ^ -1").unwrap(),
+            bin(BinOp::Pow, num(2), neg(num(1)))
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            parse("1 + 2.5").unwrap(),
+            bin(BinOp::Add, num(1), Expr::float(2.5))
+        );
+        assert_eq!(
+            parse(r#"true == "yes""#).unwrap(),
+            bin(
+                BinOp::Eq,
+                Expr::literal(Literal::Bool(true)),
+                Expr::literal(Literal::Str("yes".to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn test_comparison_and_logic_precedence() {
+        let var = Expr::var;
+        // `||` < `&&` < comparisons < arithmetic
+        assert_eq!(
+            parse("a || b && c").unwrap(),
+            bin(BinOp::Or, var("a"), bin(BinOp::And, var("b"), var("c")))
+        );
+        assert_eq!(
+            parse("x + 1 < y && y <= 2").unwrap(),
+            bin(
+                BinOp::And,
+                bin(BinOp::Lt, bin(BinOp::Add, var("x"), num(1)), var("y")),
+                bin(BinOp::Le, var("y"), num(2))
+            )
+        );
+        assert_eq!(
+            parse("!a == b").unwrap(),
+            bin(BinOp::Eq, Expr::unary(UnaryOp::Not, var("a")), var("b"))
+        );
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!(
+            parse("if x > 0 then x else -x").unwrap(),
+            Expr::if_else(
+                bin(BinOp::Gt, Expr::var("x"), num(0)),
+                Expr::var("x"),
+                neg(Expr::var("x"))
+            )
+        );
+        // Like `let`, the else branch extends as far right as possible
+        assert_eq!(
+            parse("1 + if c then 2 else 3 + 4").unwrap(),
+            bin(
+                BinOp::Add,
+                num(1),
+                Expr::if_else(Expr::var("c"), num(2), bin(BinOp::Add, num(3), num(4)))
+            )
+        );
+
+        let err = parse("if x then 1").unwrap_err();
+        assert_eq!(err.to_string(), "expected `else`, found end of input");
+        let errors = parse_all("if then 1 else").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_quantities_and_conversion() {
+        let m = units::lookup("m").unwrap();
+        let cm = units::lookup("cm").unwrap();
+        let expr = parse("3 m + 2.5 m").unwrap();
+        assert_eq!(
+            expr,
+            bin(
+                BinOp::Add,
+                Expr::literal(Literal::Quantity(3.0, m)),
+                Expr::literal(Literal::Quantity(2.5, m))
+            )
+        );
+        assert_eq!(expr.span, Span::new(0, 11));
+
+        // `->` applies to the whole expression before it
+        let expr = parse("x * 2 m -> cm").unwrap();
+        assert_eq!(
+            expr,
+            Expr::convert(
+                bin(
+                    BinOp::Mul,
+                    Expr::var("x"),
+                    Expr::literal(Literal::Quantity(2.0, m))
+                ),
+                cm
+            )
+        );
+        assert_eq!(expr.span, Span::new(0, 13));
+        assert_eq!(
+            parse("f(a -> m) + 1").unwrap(),
+            bin(
+                BinOp::Add,
+                Expr::call("f", vec![Expr::convert(Expr::var("a"), m)]),
+                num(1)
+            )
+        );
+
+        // Only a known unit right after a number makes a quantity
+        assert!(parse("3 x").is_err());
+        assert_eq!(
+            parse("3 * m").unwrap(),
+            bin(BinOp::Mul, num(3), Expr::var("m"))
+        );
+
+        let err = parse("3 m -> parsecs").unwrap_err();
+        assert_eq!(err.to_string(), "unknown unit 'parsecs'");
+        assert_eq!(err.span(), Span::new(7, 14));
+        let err = parse("3 m ->").unwrap_err();
+        assert_eq!(err.to_string(), "expected unit, found end of input");
+    }
+
+    #[test]
+    fn test_parentheses() {
+        assert_eq!(
+            parse("(1 + 2) * 3").unwrap(),
+            bin(BinOp::Mul, bin(BinOp::Add, num(1), num(2)), num(3))
+        );
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            parse("max(1, x + 2)").unwrap(),
+            Expr::call("max", vec![num(1), bin(BinOp::Add, Expr::var("x"), num(2))])
+        );
+        assert_eq!(parse("f()").unwrap(), Expr::call("f", vec![]));
+        assert_eq!(
+            parse("sin(cos(x))").unwrap(),
+            Expr::call("sin", vec![Expr::call("cos", vec![Expr::var("x")])])
+        );
+    }
+
+    #[test]
+    fn test_ranges_and_list_literals() {
+        // `..` binds looser than arithmetic, tighter than comparison
+        assert_eq!(
+            parse("1..n + 1").unwrap(),
+            bin(
+                BinOp::Range,
+                num(1),
+                bin(BinOp::Add, Expr::var("n"), num(1))
+            )
+        );
+        assert_eq!(parse("1..n + 1").unwrap().to_string(), "1..n + 1");
+        assert_eq!(
+            parse("[1, x]").unwrap(),
+            Expr::call("list", vec![num(1), Expr::var("x")])
+        );
+        assert_eq!(parse("[]").unwrap(), Expr::call("list", vec![]));
+        assert_eq!(parse("[1, 2]").unwrap().to_string(), "list(1, 2)");
+        assert_eq!(parse("[1, 2]").unwrap().span, Span::new(0, 6));
+        assert_eq!(
+            parse("[1, 2").unwrap_err().to_string(),
+            "expected `,` or `]`, found end of input"
+        );
+    }
+
+    #[test]
+    fn test_let() {
+        assert_eq!(
+            parse("let x = 3 in x * x").unwrap(),
+            Expr::let_in("x", num(3), bin(BinOp::Mul, Expr::var("x"), Expr::var("x")))
+        );
+        // The body extends as far right as possible
+        assert_eq!(
+            parse("1 + let x = 2 in x + 3").unwrap(),
+            bin(
+                BinOp::Add,
+                num(1),
+                Expr::let_in("x", num(2), bin(BinOp::Add, Expr::var("x"), num(3)))
+            )
+        );
+    }
+
+    #[test]
+    fn test_let_errors() {
+        let err = parse("let 3 = x in x").unwrap_err();
+        assert_eq!(err.to_string(), "expected variable name, found number `3`");
+
+        let err = parse("let x = 3 x").unwrap_err();
+        assert_eq!(err.to_string(), "expected `in`, found identifier `x`");
+    }
+
+    #[test]
+    fn test_lambda() {
+        assert_eq!(
+            parse("|x, y| x + y").unwrap(),
+            Expr::lambda(
+                vec!["x".to_string(), "y".to_string()],
+                bin(BinOp::Add, Expr::var("x"), Expr::var("y"))
+            )
+        );
+        assert_eq!(
+            parse("fold(xs, 0, |acc, x| acc + x)").unwrap(),
+            Expr::call(
+                "fold",
+                vec![
+                    Expr::var("xs"),
+                    num(0),
+                    Expr::lambda(
+                        vec!["acc".to_string(), "x".to_string()],
+                        bin(BinOp::Add, Expr::var("acc"), Expr::var("x"))
+                    ),
+                ]
+            )
+        );
+        assert_eq!(parse("||42").unwrap(), Expr::lambda(vec![], num(42)));
+        assert_eq!(
+            parse("(|x| x)(1)").unwrap(),
+            Expr::apply(
+                Expr::lambda(vec!["x".to_string()], Expr::var("x")),
+                vec![num(1)]
+            )
+        );
+        assert_eq!(
+            parse("make_adder(1)(2).y").unwrap(),
+            Expr::field(
+                Expr::apply(Expr::call("make_adder", vec![num(1)]), vec![num(2)]),
+                "y"
+            )
+        );
+        for src in [
+            "(|x, y| x + y)(1, 2)",
+            "f(1)(2)(3)",
+            "{ g: h }.g(1)",
+            "(-1)(2)",
+        ] {
+            assert_eq!(parse(src).unwrap().to_string(), src);
+        }
+        assert_eq!(
+            parse("|| a || b").unwrap(),
+            Expr::lambda(vec![], bin(BinOp::Or, Expr::var("a"), Expr::var("b")))
+        );
+
+        let err = parse("|x y| x").unwrap_err();
+        assert_eq!(err.to_string(), "expected `,` or `|`, found identifier `y`");
+    }
+
+    #[test]
+    fn test_fn_definition() {
+        assert_eq!(
+            parse_stmt("fn sq(x) = x * x").unwrap(),
+            Stmt::FnDef {
+                name: "sq".to_string(),
+                params: vec!["x".to_string()],
+                body: bin(BinOp::Mul, Expr::var("x"), Expr::var("x")),
+            }
+        );
+        assert!(parse_stmt("fn (x) = x").is_err());
+        assert!(parse_stmt("fn f(x) x").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested =
+            |open: &str, n: usize, close: &str| format!("{}1{}", open.repeat(n), close.repeat(n));
+        let too_deep = |src: &str| {
+            matches!(
+                parse(src).unwrap_err(),
+                ParseError::TooDeep {
+                    max: MAX_NESTING,
+                    ..
+                }
+            )
+        };
+
+        // Every way of nesting is cut off long before the stack runs out
+        assert!(too_deep(&nested("(", 10_000, ")")));
+        assert!(too_deep(&nested("-", 50_000, "")));
+        assert!(too_deep(&nested("f(", 10_000, ")")));
+        assert!(too_deep(&nested("let x = ", 10_000, " in x")));
+        assert!(too_deep(&format!("1{}", " + 1".repeat(100_000))));
+        assert!(too_deep(&nested("2 ^ ", 100_000, "")));
+        assert!(too_deep(&format!("r{}", ".a".repeat(100_000))));
+
+        // Up to the limit, everything downstream copes too
+        assert!(too_deep(&nested("-", MAX_NESTING, "")));
+        let expr = parse(&nested("-", MAX_NESTING - 1, "")).unwrap();
+        assert_eq!(Interpreter::new().eval(&expr).unwrap(), -1);
+        assert_eq!(compile(&expr).eval(&Bindings::new()).unwrap(), -1);
+        assert!(TypeChecker::new().check(&expr).is_ok());
+        assert_eq!(expr.to_string().parse::<Expr>().unwrap(), expr);
+
+        let parse_with = |src: &str, max| Parser::new(src).with_max_nesting(max).parse();
+        assert!(parse_with("((1))", 3).is_ok());
+        let err = parse_with("1 + (2 * (3 - 4))", 3).unwrap_err();
+        assert_eq!(err.to_string(), "expression nested more than 3 deep");
+        assert_eq!(err.span(), Span::new(7, 8));
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            parse_stmt("x = 5").unwrap(),
+            Stmt::Assign {
+                name: "x".to_string(),
+                value: num(5),
+            }
+        );
+        assert_eq!(
+            parse_stmt("x + 5").unwrap(),
+            Stmt::Expr(bin(BinOp::Add, Expr::var("x"), num(5)))
+        );
+
+        // Assignment is only a statement, never part of an expression
+        assert!(parse("x = 5").is_err());
+        assert!(parse_stmt("1 + x = 5").is_err());
+    }
+
+    #[test]
+    fn test_spans() {
+        let expr = parse("foo(1, 2) + -x").unwrap();
+        assert_eq!(expr.span, Span::new(0, 14));
+        let ExprKind::Binary { lhs, rhs, .. } = &expr.kind else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(lhs.span, Span::new(0, 9));
+        assert_eq!(rhs.span, Span::new(12, 14));
+    }
+
+    #[test]
+    fn test_missing_operand() {
+        let err = parse("1 + * 2").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::UnexpectedToken {
+                expected: "operand".to_string(),
+                found: "`*`".to_string(),
+                span: Span::new(4, 5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unclosed_paren() {
+        let err = parse("(1 + 2").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedEof { .. }));
+        assert_eq!(err.span(), Span::new(6, 6));
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let err = parse("1 2").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedToken { .. }));
+        assert_eq!(err.span(), Span::new(2, 3));
+    }
+
+    #[test]
+    fn test_reports_several_errors() {
+        let errors = parse_all("1 + * 2 + (3 *)").unwrap_err();
+        let spans: Vec<Span> = errors.iter().map(|e| e.span()).collect();
+        assert_eq!(spans, vec![Span::new(4, 5), Span::new(14, 15)]);
+        assert!(errors.iter().all(|e| e.label() == "expected operand"));
+    }
+
+    #[test]
+    fn test_recovery_skips_runs_of_stray_tokens() {
+        // One report for the run `* /`, then the unclosed paren
+        let errors = parse_all("(1 + * / 2").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span(), Span::new(5, 6));
+        assert!(matches!(errors[1], ParseError::UnexpectedEof { .. }));
+    }
+
+    #[test]
+    fn test_lexer_errors_are_collected() {
+        let errors = parse_stmt_all("x = 1 $ + # 2").unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec!["unexpected character '$'", "unexpected character '#'"]
+        );
+
+        // Nothing missing around a dropped character is reported again
+        for src in ["1 $ 2", "1 + $", "f(1 $ 2, 3)", "(1 @ x.y)"] {
+            let errors = parse_all(src).unwrap_err();
+            assert_eq!(errors.len(), 1, "{}: {:?}", src, errors);
+        }
+        let errors = parse_all("1 $ 2 + * 3").unwrap_err();
+        let spans: Vec<Span> = errors.iter().map(|e| e.span()).collect();
+        assert_eq!(spans, vec![Span::new(2, 3), Span::new(8, 9)]);
+    }
+
+    #[test]
+    fn test_bad_literal_is_reported_once() {
+        for src in [r#""a\q""#, r#""a\q" / 3"#, r#"1 + "abc"#, r#"f("\z", 2)"#] {
+            let errors = parse_all(src).unwrap_err();
+            assert_eq!(errors.len(), 1, "{}: {:?}", src, errors);
+        }
+        // Mistakes after it are still found
+        let errors = parse_all(r#""a\q" + * 2"#).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].span(), Span::new(8, 9));
+    }
+
+    #[test]
+    fn test_no_duplicate_report_at_same_place() {
+        let errors = parse_all("(1 +").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "expected operand, found end of input"
+        );
+    }
+
+    #[test]
+    fn test_records_and_fields() {
+        let record = Expr::record(vec![
+            ("price".to_string(), num(10)),
+            ("qty".to_string(), num(3)),
+        ]);
+        assert_eq!(parse("{ price: 10, qty: 3 }").unwrap(), record);
+        assert_eq!(parse("{}").unwrap(), Expr::record(Vec::new()));
+        assert_eq!(
+            parse("-r.a.b ^ 2").unwrap(),
+            neg(bin(
+                BinOp::Pow,
+                Expr::field(Expr::field(Expr::var("r"), "a"), "b"),
+                num(2)
+            ))
+        );
+        assert_eq!(
+            parse("{ price: 10, qty: 3 }.qty").unwrap(),
+            Expr::field(record, "qty")
+        );
+        assert_eq!(parse("f(x).y").unwrap().span, Span::new(0, 6));
+
+        let errors = parse_all("{ a: 1, b: 2, a: 3 }").unwrap_err();
+        assert_eq!(
+            errors,
+            [ParseError::DuplicateField {
+                name: "a".to_string(),
+                span: Span::new(14, 15),
+            }]
+        );
+        assert!(parse("{ a: 1, }").is_err());
+        assert!(parse("r.1").is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let expr = parse(r#"match x { 0 => "none", 1..10 => "few", -5.5 => "odd", _ => "many" }"#)
+            .unwrap();
+        let ExprKind::Match { scrutinee, arms } = &expr.kind else {
+            panic!("not a match: {:?}", expr);
+        };
+        assert_eq!(**scrutinee, Expr::var("x"));
+        let patterns: Vec<&Pattern> = arms.iter().map(|arm| &arm.pattern).collect();
+        assert_eq!(
+            patterns,
+            [
+                &Pattern::Literal(Literal::Int(0)),
+                &Pattern::Range {
+                    start: Some(Literal::Int(1)),
+                    end: Some(Literal::Int(10)),
+                },
+                &Pattern::Literal(Literal::Float(-5.5)),
+                &Pattern::Wildcard,
+            ]
+        );
+        assert_eq!(arms[1].span, Span::new(23, 28));
+
+        let expr = parse("match n { ..-1 => 0, 5.. => 1, true => 2 }").unwrap();
+        let ExprKind::Match { arms, .. } = &expr.kind else {
+            panic!("not a match: {:?}", expr);
+        };
+        assert_eq!(
+            arms[0].pattern,
+            Pattern::Range {
+                start: None,
+                end: Some(Literal::Int(-1)),
+            }
+        );
+        assert_eq!(
+            arms[1].pattern,
+            Pattern::Range {
+                start: Some(Literal::Int(5)),
+                end: None,
+            }
+        );
+        assert_eq!(arms[2].pattern, Pattern::Literal(Literal::Bool(true)));
+
+        assert!(parse("match x { y => 1 }").is_err());
+        assert!(parse("match x { 1 -> 2 }").is_err());
+        assert!(parse("match x { 1 => 2").is_err());
+        assert!(parse("match d { 1 m => 1, _ => 0 }").is_err());
+        assert!(parse("match n { 99999999999999999999 => 1, _ => 0 }").is_err());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let err = parse("   ").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedEof { .. }));
+    }
+}
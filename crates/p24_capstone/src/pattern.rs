@@ -0,0 +1,423 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{
+    ast::{Expr, ExprKind, Literal, MatchArm, Pattern, Stmt},
+    lexer::Span,
+};
+
+// ------------------------------------------------
+/// A `match` that runs but probably not as meant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchWarning {
+    pub kind: MatchWarningKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchWarningKind {
+    /// Some values fail with `EvalError::NoMatch`; `missing` describes
+    /// each stretch of them, like `` `5..10` `` or `` `_` ``, with the
+    /// pattern in backticks
+    NonExhaustive { missing: Vec<String> },
+    /// An arm that earlier arms leave no values for
+    Unreachable,
+}
+
+impl MatchWarning {
+    /// Short description for the caret line of a diagnostic.
+    pub fn label(&self) -> String {
+        match &self.kind {
+            MatchWarningKind::NonExhaustive { missing } => {
+                format!("{} not covered", list(missing))
+            }
+            MatchWarningKind::Unreachable => "never reached".to_string(),
+        }
+    }
+}
+
+impl Display for MatchWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            MatchWarningKind::NonExhaustive { missing } => {
+                let verb = if missing.len() == 1 { "is" } else { "are" };
+                write!(
+                    f,
+                    "match is not exhaustive: {} {} not covered",
+                    list(missing),
+                    verb
+                )
+            }
+            MatchWarningKind::Unreachable => write!(f, "unreachable match arm"),
+        }
+    }
+}
+
+impl Expr {
+    /// Warnings for every `match` in the expression, outermost first: a
+    /// match some values get through, at the match, and each arm no value
+    /// can reach, at the arm.
+    ///
+    /// Numeric patterns cover real numbers, so only ranges reaching from
+    /// end to end cover all of them; strings need a `_`.
+    pub fn match_warnings(&self) -> Vec<MatchWarning> {
+        let mut warnings = Vec::new();
+        collect(self, &mut warnings);
+        warnings
+    }
+}
+
+impl Stmt {
+    /// Warnings for every `match` in the statement's expression.
+    pub fn match_warnings(&self) -> Vec<MatchWarning> {
+        match self {
+            Stmt::Assign { value: expr, .. }
+            | Stmt::FnDef { body: expr, .. }
+            | Stmt::Expr(expr) => expr.match_warnings(),
+        }
+    }
+}
+
+fn collect(expr: &Expr, warnings: &mut Vec<MatchWarning>) {
+    if let ExprKind::Match { arms, .. } = &expr.kind {
+        check(arms, expr.span, warnings);
+    }
+    for child in expr.children() {
+        collect(child, warnings);
+    }
+}
+
+fn check(arms: &[MatchArm], span: Span, warnings: &mut Vec<MatchWarning>) {
+    let domain = Domain::of(arms);
+    let mut covered = Coverage::default();
+    for arm in arms {
+        if covered.is_exhaustive(domain) || covered.covers(&arm.pattern) {
+            warnings.push(MatchWarning {
+                kind: MatchWarningKind::Unreachable,
+                span: arm.span,
+            });
+        }
+        covered.add(&arm.pattern);
+    }
+    if !covered.is_exhaustive(domain) {
+        warnings.push(MatchWarning {
+            kind: MatchWarningKind::NonExhaustive {
+                missing: covered.missing(domain),
+            },
+            span,
+        });
+    }
+}
+
+// ------------------------------------------------
+/// The values a match's patterns are written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Domain {
+    Bool,
+    Number,
+    /// Strings, or a mix of kinds, which only `_` covers
+    Other,
+}
+
+impl Domain {
+    fn of(arms: &[MatchArm]) -> Domain {
+        let mut patterns = arms
+            .iter()
+            .map(|arm| &arm.pattern)
+            .filter(|pattern| **pattern != Pattern::Wildcard)
+            .peekable();
+        if patterns.peek().is_none() {
+            return Domain::Other;
+        }
+        let domains: Vec<Domain> = patterns
+            .map(|pattern| match pattern {
+                Pattern::Literal(Literal::Bool(_)) => Domain::Bool,
+                Pattern::Literal(Literal::Int(_) | Literal::Float(_)) | Pattern::Range { .. } => {
+                    Domain::Number
+                }
+                _ => Domain::Other,
+            })
+            .collect();
+        match domains[0] {
+            first if domains.iter().all(|&domain| domain == first) => first,
+            _ => Domain::Other,
+        }
+    }
+}
+
+/// The values earlier arms have taken.
+#[derive(Debug, Default)]
+struct Coverage {
+    wildcard: bool,
+    /// Whether `false` and `true` are taken
+    bools: [bool; 2],
+    /// Half-open ranges of numbers, sorted and merged
+    ranges: Vec<(f64, f64)>,
+    numbers: Vec<f64>,
+    strings: Vec<String>,
+}
+
+impl Coverage {
+    fn is_exhaustive(&self, domain: Domain) -> bool {
+        self.wildcard
+            || match domain {
+                Domain::Bool => self.bools == [true, true],
+                Domain::Number => self.covers_range(f64::NEG_INFINITY, f64::INFINITY),
+                Domain::Other => false,
+            }
+    }
+
+    /// Whether every value `pattern` matches is already taken; a range
+    /// holding no numbers at all counts too.
+    fn covers(&self, pattern: &Pattern) -> bool {
+        match pattern {
+            Pattern::Wildcard => false,
+            Pattern::Literal(Literal::Bool(b)) => self.bools[usize::from(*b)],
+            Pattern::Literal(Literal::Str(s)) => self.strings.contains(s),
+            Pattern::Literal(literal) => {
+                number(literal).is_some_and(|n| self.covers_range(n, next_up(n)))
+            }
+            Pattern::Range { start, end } => {
+                let (start, end) = bounds(start, end);
+                start >= end || self.covers_range(start, end)
+            }
+        }
+    }
+
+    /// Whether ranges and numbers taken so far leave no gap in `start..end`.
+    fn covers_range(&self, start: f64, end: f64) -> bool {
+        self.gaps().into_iter().all(|(a, b, open)| {
+            let a = if open { next_up(a) } else { a };
+            a.max(start) >= b.min(end)
+        })
+    }
+
+    fn add(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.wildcard = true,
+            Pattern::Literal(Literal::Bool(b)) => self.bools[usize::from(*b)] = true,
+            Pattern::Literal(Literal::Str(s)) => self.strings.push(s.clone()),
+            Pattern::Literal(literal) => self.numbers.extend(number(literal)),
+            Pattern::Range { start, end } => {
+                let (start, end) = bounds(start, end);
+                if start < end {
+                    self.add_range(start, end);
+                }
+            }
+        }
+    }
+
+    fn add_range(&mut self, start: f64, end: f64) {
+        self.ranges.push((start, end));
+        self.ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(self.ranges.len());
+        for &(a, b) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if a <= last.1 => last.1 = last.1.max(b),
+                _ => merged.push((a, b)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// Descriptions of every stretch of values not taken yet.
+    fn missing(&self, domain: Domain) -> Vec<String> {
+        match domain {
+            // The first one not taken
+            Domain::Bool => vec![format!("`{}`", self.bools[0])],
+            Domain::Number => self
+                .gaps()
+                .into_iter()
+                .map(|(start, end, open)| match open {
+                    true => format!("`{}` other than `{}`", range(start, end), start),
+                    false => format!("`{}`", range(start, end)),
+                })
+                .collect(),
+            Domain::Other => vec!["`_`".to_string()],
+        }
+    }
+
+    /// The numbers between the ranges as `(start, end, open)`, split at
+    /// each literal taken inside them; `open` leaves out `start`, which an
+    /// arm took.
+    fn gaps(&self) -> Vec<(f64, f64, bool)> {
+        let mut numbers = self.numbers.clone();
+        numbers.sort_by(f64::total_cmp);
+        numbers.dedup();
+
+        let mut gaps = Vec::new();
+        let mut from = f64::NEG_INFINITY;
+        let ends = self
+            .ranges
+            .iter()
+            .copied()
+            .chain([(f64::INFINITY, f64::INFINITY)]);
+        for (a, b) in ends {
+            let (mut start, mut open) = (from, false);
+            for &n in numbers.iter().filter(|&&n| from <= n && n < a) {
+                if start < n {
+                    gaps.push((start, n, open));
+                }
+                (start, open) = (n, true);
+            }
+            // Nothing lies strictly between a number and the next float
+            if start < a && !(open && next_up(start) >= a) {
+                gaps.push((start, a, open));
+            }
+            from = b;
+        }
+        gaps
+    }
+}
+
+/// The parser only takes numbers that fit an `i64` or `f64` as patterns.
+fn number(literal: &Literal) -> Option<f64> {
+    match literal {
+        Literal::Int(n) => Some(*n as f64),
+        Literal::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// A range pattern's bounds, endless ones as infinities.
+fn bounds(start: &Option<Literal>, end: &Option<Literal>) -> (f64, f64) {
+    (
+        start.as_ref().and_then(number).unwrap_or(f64::NEG_INFINITY),
+        end.as_ref().and_then(number).unwrap_or(f64::INFINITY),
+    )
+}
+
+/// The least float above `n`, so `n..next_up(n)` holds `n` alone.
+fn next_up(n: f64) -> f64 {
+    match n {
+        0.0 => f64::from_bits(1),
+        n if n.is_infinite() => n,
+        n if n > 0.0 => f64::from_bits(n.to_bits() + 1),
+        n => f64::from_bits(n.to_bits() - 1),
+    }
+}
+
+/// Descriptions joined as in "`a`, `b` and `c`".
+fn list(missing: &[String]) -> String {
+    match missing {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
+
+/// `start..end` as written in a pattern, or `_` for every number.
+fn range(start: f64, end: f64) -> String {
+    let bound = |n: f64| match n.is_finite() {
+        true => n.to_string(),
+        false => String::new(),
+    };
+    match (bound(start), bound(end)) {
+        (start, end) if start.is_empty() && end.is_empty() => "_".to_string(),
+        (start, end) => format!("{}..{}", start, end),
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(src: &str) -> Vec<String> {
+        let expr: Expr = src.parse().unwrap();
+        expr.match_warnings()
+            .iter()
+            .map(|w| format!("{}..{}: {}", w.span.start, w.span.end, w))
+            .collect()
+    }
+
+    #[test]
+    fn test_exhaustive_matches() {
+        for src in [
+            "match x { _ => 1 }",
+            "match b { true => 1, false => 0 }",
+            "match n { ..0 => 1, 0..10 => 2, 10.. => 3 }",
+            "match n { 0 => 1, 5..7 => 2, ..7.5 => 3, 7.5.. => 4 }",
+            r#"match s { "a" => 1, "b" => 2, _ => 3 }"#,
+            "match { a: 1 }.a { 1 => match b { true => 1, _ => 2 }, _ => 3 }",
+        ] {
+            assert_eq!(warnings(src), Vec::<String>::new(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_missing_values() {
+        assert_eq!(
+            warnings("match n { ..0 => 1, 5..10 => 2, 10.. => 3 }"),
+            ["0..43: match is not exhaustive: `0..5` is not covered"]
+        );
+        assert_eq!(
+            warnings("match n { 0 => 1, 1..2.5 => 2 }")[0],
+            "0..31: match is not exhaustive: `..0`, `0..1` other than `0` \
+             and `2.5..` are not covered"
+        );
+        assert_eq!(
+            warnings(r#"match n { 0 => "none", 1..10 => "few" }"#)[0],
+            "0..39: match is not exhaustive: `..0`, `0..1` other than `0` \
+             and `10..` are not covered"
+        );
+        assert_eq!(
+            warnings("match n { ..100 => 1 }")[0],
+            "0..22: match is not exhaustive: `100..` is not covered"
+        );
+        assert_eq!(
+            warnings("match n { 1 => 1, 2 => 2 }")[0],
+            "0..26: match is not exhaustive: `..1`, `1..2` other than `1` \
+             and `2..` other than `2` are not covered"
+        );
+        // Only the number itself lies between it and the range after it
+        assert_eq!(
+            warnings("match n { ..5 => 1, 5 => 2, 5.000000000000001.. => 3 }"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            warnings("match b { true => 1 }")[0],
+            "0..21: match is not exhaustive: `false` is not covered"
+        );
+        assert_eq!(
+            warnings(r#"match s { "a" => 1, 2 => 2 }"#)[0],
+            "0..28: match is not exhaustive: `_` is not covered"
+        );
+        assert_eq!(
+            warnings("1 + match n {}")[0],
+            "4..14: match is not exhaustive: `_` is not covered"
+        );
+    }
+
+    #[test]
+    fn test_unreachable_arms() {
+        assert_eq!(
+            warnings("match n { _ => 1, 2 => 2 }"),
+            ["18..19: unreachable match arm"]
+        );
+        assert_eq!(
+            warnings("match n { 0..10 => 1, 3 => 2, 2..5 => 3, 5..5 => 4, 0 => 5, _ => 6 }"),
+            [
+                "22..23: unreachable match arm",
+                "30..34: unreachable match arm",
+                "41..45: unreachable match arm",
+                "52..53: unreachable match arm",
+            ]
+        );
+        assert_eq!(
+            warnings("match b { false => 0, true => 1, _ => 2 }"),
+            ["33..34: unreachable match arm"]
+        );
+        assert_eq!(
+            warnings(r#"match s { "a" => 1, "a" => 2, _ => 3 }"#),
+            ["20..23: unreachable match arm"]
+        );
+        let expr: Expr = "match n { ..0 => 1, 0.. => 2, _ => 3 }".parse().unwrap();
+        assert_eq!(
+            expr.match_warnings(),
+            [MatchWarning {
+                kind: MatchWarningKind::Unreachable,
+                span: Span::new(30, 31),
+            }]
+        );
+    }
+}
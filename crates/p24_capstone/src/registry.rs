@@ -0,0 +1,322 @@
+use std::{
+    any::{Any, TypeId, type_name},
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    sync::Arc,
+};
+
+use p14_operator_arithmetic::Vec2;
+use p23_extend_sum::Money;
+
+use crate::{
+    error::EvalError,
+    eval::{expect_arity, is_builtin},
+    lexer::Span,
+    units::Quantity,
+    value::Value,
+};
+
+// ------------------------------------------------
+/// Type-erased body of a native function; arguments are already counted.
+type Thunk = dyn Fn(&[Value], Span) -> Result<Value, EvalError> + Send + Sync;
+
+/// A Rust function callable from expressions, with its parameter types.
+#[derive(Clone)]
+pub struct NativeFn {
+    name: String,
+    params: Vec<&'static str>,
+    func: Arc<Thunk>,
+}
+
+impl NativeFn {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Parameter types as the language names them, like `float` or `int`.
+    pub fn params(&self) -> &[&'static str] {
+        &self.params
+    }
+
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Check the argument count, then convert each argument to the type
+    /// the Rust function takes.
+    pub fn call(&self, args: &[Value], span: Span) -> Result<Value, EvalError> {
+        expect_arity(&self.name, args, self.params.len(), span)?;
+        (self.func)(args, span)
+    }
+}
+
+/// Like a lambda's display, e.g. `<native clamp(float, float, float)>`.
+impl Display for NativeFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native {}({})>", self.name, self.params.join(", "))
+    }
+}
+
+impl Debug for NativeFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// ------------------------------------------------
+/// The language's name for a parameter type, or `None` if arguments
+/// cannot be converted to it.
+fn param_type<T: Any>() -> Option<&'static str> {
+    let id = TypeId::of::<T>();
+    let name = if id == TypeId::of::<f64>() {
+        "float"
+    } else if id == TypeId::of::<i64>() {
+        "int"
+    } else if id == TypeId::of::<bool>() {
+        "bool"
+    } else if id == TypeId::of::<String>() {
+        "string"
+    } else if id == TypeId::of::<Vec2>() {
+        "vector"
+    } else if id == TypeId::of::<Quantity>() {
+        "quantity"
+    } else if id == TypeId::of::<Money>() {
+        "money"
+    } else if id == TypeId::of::<Vec<Value>>() {
+        "list"
+    } else if id == TypeId::of::<Value>() {
+        "value"
+    } else {
+        return None;
+    };
+    Some(name)
+}
+
+/// Convert an argument to `T` by boxing the matching Rust value as
+/// `std::any::Any` and downcasting it.
+///
+/// `float` parameters accept ints and rationals, as everywhere else in the
+/// language, and `int` parameters rationals that are whole numbers.
+fn marshal<T: Any>(value: &Value, span: Span) -> Result<T, EvalError> {
+    let expected = param_type::<T>().expect("checked when the function was registered");
+    let boxed: Box<dyn Any> = match (expected, value) {
+        ("float", _) => Box::new(value.as_number(span)?),
+        ("int", _) => Box::new(value.as_int(span)?),
+        ("bool", Value::Bool(b)) => Box::new(*b),
+        ("string", Value::Str(s)) => Box::new(s.to_string()),
+        ("vector", Value::Vector(v)) => Box::new(*v),
+        ("quantity", Value::Quantity(q)) => Box::new(*q),
+        ("money", Value::Money(m)) => Box::new(*m),
+        ("list", Value::List(items)) => Box::new(items.to_vec()),
+        ("value", _) => Box::new(value.clone()),
+        _ => return Err(value.mismatch(expected, span)),
+    };
+    Ok(*boxed
+        .downcast::<T>()
+        .expect("each parameter type maps to exactly one Rust type"))
+}
+
+/// Rust closures that can become a `NativeFn`, implemented for `Fn`s of
+/// up to five parameters returning anything convertible into a `Value`.
+///
+/// `Args` is the tuple of parameter types; it only exists to tell the
+/// implementations apart.
+pub trait IntoNative<Args>: Send + Sync + 'static {
+    /// Parameter types, or the Rust type name of an unsupported one.
+    fn param_types() -> Vec<Result<&'static str, &'static str>>;
+
+    /// Call with exactly as many arguments as there are parameters.
+    fn call_native(&self, args: &[Value], span: Span) -> Result<Value, EvalError>;
+}
+
+macro_rules! impl_into_native {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: Into<Value>,
+            $($arg: Any,)*
+        {
+            fn param_types() -> Vec<Result<&'static str, &'static str>> {
+                vec![$(param_type::<$arg>().ok_or(type_name::<$arg>())),*]
+            }
+
+            #[allow(non_snake_case, unused_variables)]
+            fn call_native(&self, args: &[Value], span: Span) -> Result<Value, EvalError> {
+                let [$($arg),*] = args else {
+                    unreachable!("arity is checked before the call");
+                };
+                Ok(self($(marshal::<$arg>($arg, span)?),*).into())
+            }
+        }
+    };
+}
+
+impl_into_native!();
+impl_into_native!(A);
+impl_into_native!(A, B);
+impl_into_native!(A, B, C);
+impl_into_native!(A, B, C, D);
+impl_into_native!(A, B, C, D, E);
+
+// ------------------------------------------------
+/// Host functions made available to expressions by name.
+///
+/// Calls look for a user-defined function first, then a builtin, then a
+/// registered function; builtin names cannot be registered, so the
+/// optimizer may still fold builtin calls. Functions must be `Send + Sync`,
+/// so one registry can serve many threads behind an `Arc`.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, NativeFn>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        FunctionRegistry {
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Register `func` under `name`, replacing any function registered
+    /// before under that name, e.g.
+    /// `registry.register("clamp", |x: f64, lo: f64, hi: f64| x.clamp(lo, hi))`.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is a builtin, or `func` takes a parameter type other than
+    /// `f64`, `i64`, `bool`, `String`, `Vec2`, `Quantity`, `Money`,
+    /// `Vec<Value>` or `Value`.
+    pub fn register<Args, F>(&mut self, name: impl Into<String>, func: F) -> &mut Self
+    where
+        F: IntoNative<Args>,
+    {
+        let name = name.into();
+        assert!(
+            !is_builtin(&name),
+            "cannot register '{}': it is a builtin",
+            name
+        );
+        let params = F::param_types()
+            .into_iter()
+            .map(|param| {
+                param.unwrap_or_else(|rust_type| {
+                    panic!(
+                        "'{}' takes a {}, which expressions cannot pass",
+                        name, rust_type
+                    )
+                })
+            })
+            .collect();
+
+        let native = NativeFn {
+            name: name.clone(),
+            params,
+            func: Arc::new(move |args, span| func.call_native(args, span)),
+        };
+        self.functions.insert(name, native);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeFn> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Registered names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> FunctionRegistry {
+        let mut registry = FunctionRegistry::new();
+        registry
+            .register("clamp", |x: f64, lo: f64, hi: f64| x.clamp(lo, hi))
+            .register("tax_rate", |region: String| match region.as_str() {
+                "eu" => 0.2,
+                _ => 0.1,
+            })
+            .register("repeat", |s: String, n: i64| s.repeat(n.max(0) as usize))
+            .register("answer", || 42_i64)
+            .register("count", |items: Vec<Value>| items.len() as i64);
+        registry
+    }
+
+    #[test]
+    fn test_calls_marshal_arguments() {
+        let registry = registry();
+        let span = Span::default();
+        let clamp = registry.get("clamp").unwrap();
+        assert_eq!(clamp.params(), ["float", "float", "float"]);
+        assert_eq!(clamp.to_string(), "<native clamp(float, float, float)>");
+
+        let args = [Value::Int(12), Value::Float(0.0), Value::Int(10)];
+        assert_eq!(clamp.call(&args, span).unwrap(), 10.0);
+        let repeat = registry.get("repeat").unwrap();
+        let args = [Value::from("ab"), Value::Int(3)];
+        assert_eq!(repeat.call(&args, span).unwrap(), Value::from("ababab"));
+        assert_eq!(registry.get("answer").unwrap().call(&[], span).unwrap(), 42);
+        assert_eq!(
+            registry.names(),
+            ["answer", "clamp", "count", "repeat", "tax_rate"]
+        );
+    }
+
+    #[test]
+    fn test_arity_and_type_errors() {
+        let registry = registry();
+        let span = Span::new(0, 5);
+        let clamp = registry.get("clamp").unwrap();
+        assert_eq!(
+            clamp.call(&[Value::Int(1)], span).unwrap_err().to_string(),
+            "function 'clamp' expects 3 argument(s), got 1"
+        );
+        let args = [Value::Int(1), Value::Bool(true), Value::Int(2)];
+        assert_eq!(
+            clamp.call(&args, span).unwrap_err().to_string(),
+            "type mismatch: expected number, found bool"
+        );
+        // No silent truncation of floats passed as ints
+        let repeat = registry.get("repeat").unwrap();
+        let args = [Value::from("ab"), Value::Float(2.5)];
+        assert_eq!(
+            repeat.call(&args, span).unwrap_err(),
+            EvalError::TypeMismatch {
+                expected: "int".to_string(),
+                found: "float".to_string(),
+                span,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot register 'sqrt': it is a builtin")]
+    fn test_builtins_cannot_be_replaced() {
+        FunctionRegistry::new().register("sqrt", |x: f64| x);
+    }
+
+    #[test]
+    #[should_panic(expected = "'half' takes a f32, which expressions cannot pass")]
+    fn test_unsupported_parameter_types_panic() {
+        FunctionRegistry::new().register("half", |x: f32| x as f64 / 2.0);
+    }
+}
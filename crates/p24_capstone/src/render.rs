@@ -0,0 +1,794 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, MatchArm, Pattern, UnaryOp},
+    units::Unit,
+};
+
+// ------------------------------------------------
+impl Expr {
+    /// The expression typeset as LaTeX math, for use between `$` signs.
+    pub fn latex(&self) -> Latex<'_> {
+        Latex(self)
+    }
+
+    /// The expression typeset as a presentation MathML `<math>` element.
+    pub fn mathml(&self) -> MathMl<'_> {
+        MathMl(self)
+    }
+}
+
+/// Variable names written as a Greek letter.
+const GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("theta", "θ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("phi", "φ"),
+    ("omega", "ω"),
+];
+
+/// Builtins typeset as named operators, like `\sin x`.
+const OPERATORS: &[&str] = &["sin", "cos", "tan", "exp", "ln", "min", "max"];
+
+// ------------------------------------------------
+// Parentheses follow the same precedence rules as `Display`, except that
+// fractions, ranges and `if` typeset as self-contained blocks, and the
+// parts of a fraction or exponent are grouped by their position.
+
+/// Precedence of the node as typeset.
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Binary {
+            op: BinOp::Div | BinOp::Range,
+            ..
+        }
+        | ExprKind::If { .. } => u8::MAX,
+        _ => expr.precedence(),
+    }
+}
+
+/// Whether the operand of `op` needs parentheses.
+fn unary_parens(op: UnaryOp, operand: &Expr) -> bool {
+    precedence(operand) < op.precedence()
+}
+
+/// Whether the left and right operands of `op` need parentheses.
+fn binary_parens(op: BinOp, lhs: &Expr, rhs: &Expr) -> (bool, bool) {
+    let prec = op.precedence();
+    match op {
+        BinOp::Div | BinOp::Range => (false, false),
+        // A fraction or `if` raised to a power is still ambiguous
+        BinOp::Pow => (lhs.precedence() <= prec, false),
+        _ => (
+            precedence(lhs) < prec || (precedence(lhs) == prec && op.is_right_assoc()),
+            precedence(rhs) < prec || (precedence(rhs) == prec && !op.is_right_assoc()),
+        ),
+    }
+}
+
+/// Whether the operand of `->` needs parentheses.
+fn convert_parens(expr: &Expr) -> bool {
+    precedence(expr) == 0 && !matches!(expr.kind, ExprKind::Convert { .. })
+}
+
+/// The number of a quantity, whole amounts without `.0` as `Display`
+/// prints them.
+fn amount(n: f64) -> Literal {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        Literal::Int(n as i64)
+    } else {
+        Literal::Float(n)
+    }
+}
+
+/// Whether the record of a field access, or a computed callee, needs
+/// parentheses; a number would read as having a decimal point.
+fn field_parens(expr: &Expr) -> bool {
+    precedence(expr) < u8::MAX || matches!(expr.kind, ExprKind::Literal(_))
+}
+
+/// The branches of an `if` chain as `(condition, value)` pairs, with no
+/// condition on the final `else`.
+fn cases(expr: &Expr) -> Vec<(Option<&Expr>, &Expr)> {
+    let mut cases = Vec::new();
+    let mut expr = expr;
+    while let ExprKind::If {
+        cond,
+        then_branch,
+        else_branch,
+    } = &expr.kind
+    {
+        cases.push((Some(&**cond), &**then_branch));
+        expr = else_branch;
+    }
+    cases.push((None, expr));
+    cases
+}
+
+// ------------------------------------------------
+/// An `Expr` printed as LaTeX math: `/` becomes `\frac`, `^` a
+/// superscript and `if` chains and `match`es a `cases` block, with the
+/// fewest parentheses that keep the structure.
+#[derive(Debug, Clone, Copy)]
+pub struct Latex<'a>(&'a Expr);
+
+impl Latex<'_> {
+    fn operand(f: &mut Formatter<'_>, expr: &Expr, parens: bool) -> std::fmt::Result {
+        if parens {
+            write!(f, "\\left({}\\right)", Latex(expr))
+        } else {
+            write!(f, "{}", Latex(expr))
+        }
+    }
+
+    fn list(f: &mut Formatter<'_>, items: &[Expr]) -> std::fmt::Result {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", Latex(item))?;
+        }
+        Ok(())
+    }
+
+    fn number(f: &mut Formatter<'_>, literal: &Literal) -> std::fmt::Result {
+        match literal {
+            Literal::Float(n) if n.is_infinite() => {
+                write!(f, "{}\\infty", if *n < 0.0 { "-" } else { "" })
+            }
+            Literal::Float(n) if n.is_nan() => write!(f, "\\mathrm{{NaN}}"),
+            _ => write!(f, "{}", literal),
+        }
+    }
+
+    fn name(f: &mut Formatter<'_>, name: &str) -> std::fmt::Result {
+        if GREEK.iter().any(|(greek, _)| *greek == name) {
+            write!(f, "\\{}", name)
+        } else if name.chars().count() == 1 {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "\\mathit{{{}}}", name.replace('_', "\\_"))
+        }
+    }
+
+    /// When a `match` arm is taken, as the second column of `cases`.
+    fn condition(f: &mut Formatter<'_>, scrutinee: &Expr, arm: &MatchArm) -> std::fmt::Result {
+        let bound = |literal: &Literal| Expr::literal(literal.clone());
+        match &arm.pattern {
+            Pattern::Wildcard => write!(f, "\\text{{otherwise}}"),
+            Pattern::Literal(literal) => write!(
+                f,
+                "\\text{{if }} {} = {}",
+                Latex(scrutinee),
+                Latex(&bound(literal))
+            ),
+            Pattern::Range { start, end } => {
+                write!(f, "\\text{{if }} ")?;
+                if let Some(start) = start {
+                    write!(f, "{} \\leq ", Latex(&bound(start)))?;
+                }
+                write!(f, "{}", Latex(scrutinee))?;
+                if let Some(end) = end {
+                    write!(f, " < {}", Latex(&bound(end)))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn unit(f: &mut Formatter<'_>, unit: &Unit) -> std::fmt::Result {
+        match unit.symbol {
+            "C" | "F" => write!(f, "{{}}^{{\\circ}}\\mathrm{{{}}}", unit.symbol),
+            symbol => write!(f, "\\mathrm{{{}}}", symbol),
+        }
+    }
+
+    fn text(f: &mut Formatter<'_>, s: &str) -> std::fmt::Result {
+        write!(f, "\\text{{\"")?;
+        for c in s.chars() {
+            match c {
+                '\\' => write!(f, "\\textbackslash{{}}")?,
+                '~' => write!(f, "\\textasciitilde{{}}")?,
+                '^' => write!(f, "\\textasciicircum{{}}")?,
+                '{' | '}' | '$' | '&' | '#' | '_' | '%' => write!(f, "\\{}", c)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"}}")
+    }
+
+    fn call(f: &mut Formatter<'_>, name: &str, args: &[Expr]) -> std::fmt::Result {
+        match (name, args) {
+            ("sqrt", [x]) => write!(f, "\\sqrt{{{}}}", Latex(x)),
+            ("abs", [x]) => write!(f, "\\left|{}\\right|", Latex(x)),
+            ("floor", [x]) => write!(f, "\\left\\lfloor {}\\right\\rfloor", Latex(x)),
+            ("ceil", [x]) => write!(f, "\\left\\lceil {}\\right\\rceil", Latex(x)),
+            ("list", _) => {
+                write!(f, "\\left[")?;
+                Latex::list(f, args)?;
+                write!(f, "\\right]")
+            }
+            _ => {
+                if OPERATORS.contains(&name) {
+                    write!(f, "\\{}", name)?;
+                } else if name == "log10" {
+                    write!(f, "\\log_{{10}}")?;
+                } else {
+                    write!(f, "\\operatorname{{{}}}", name.replace('_', "\\_"))?;
+                }
+                write!(f, "\\left(")?;
+                Latex::list(f, args)?;
+                write!(f, "\\right)")
+            }
+        }
+    }
+}
+
+impl Display for Latex<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0.kind {
+            ExprKind::Literal(
+                literal @ (Literal::Int(_) | Literal::BigInt(_) | Literal::Float(_)),
+            ) => Latex::number(f, literal),
+            ExprKind::Literal(Literal::Bool(b)) => write!(f, "\\mathrm{{{}}}", b),
+            ExprKind::Literal(Literal::Str(s)) => Latex::text(f, s),
+            ExprKind::Literal(Literal::Quantity(n, unit)) => {
+                Latex::number(f, &amount(*n))?;
+                write!(f, "\\,")?;
+                Latex::unit(f, unit)
+            }
+            ExprKind::Literal(Literal::Imaginary(n)) => {
+                Latex::number(f, &amount(*n))?;
+                write!(f, "i")
+            }
+            ExprKind::Var(name) => Latex::name(f, name),
+            ExprKind::Unary { op, operand } => {
+                match op {
+                    UnaryOp::Neg => write!(f, "-")?,
+                    UnaryOp::Not => write!(f, "\\lnot ")?,
+                }
+                Latex::operand(f, operand, unary_parens(*op, operand))
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (lhs_parens, rhs_parens) = binary_parens(*op, lhs, rhs);
+                let symbol = match op {
+                    BinOp::Div => return write!(f, "\\frac{{{}}}{{{}}}", Latex(lhs), Latex(rhs)),
+                    BinOp::Range => {
+                        return write!(f, "\\left[{}, {}\\right)", Latex(lhs), Latex(rhs));
+                    }
+                    BinOp::Pow => {
+                        Latex::operand(f, lhs, lhs_parens)?;
+                        return write!(f, "^{{{}}}", Latex(rhs));
+                    }
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                    BinOp::Mul => "\\cdot",
+                    BinOp::Rem => "\\bmod",
+                    BinOp::Eq => "=",
+                    BinOp::Ne => "\\neq",
+                    BinOp::Lt => "<",
+                    BinOp::Le => "\\leq",
+                    BinOp::Gt => ">",
+                    BinOp::Ge => "\\geq",
+                    BinOp::And => "\\land",
+                    BinOp::Or => "\\lor",
+                };
+                Latex::operand(f, lhs, lhs_parens)?;
+                write!(f, " {} ", symbol)?;
+                Latex::operand(f, rhs, rhs_parens)
+            }
+            ExprKind::Call { name, args } => Latex::call(f, name, args),
+            ExprKind::Apply { callee, args } => {
+                Latex::operand(f, callee, field_parens(callee))?;
+                write!(f, "\\left(")?;
+                Latex::list(f, args)?;
+                write!(f, "\\right)")
+            }
+            ExprKind::Let { name, value, body } => {
+                write!(f, "\\text{{let }} ")?;
+                Latex::name(f, name)?;
+                write!(f, " = {} \\text{{ in }} {}", Latex(value), Latex(body))
+            }
+            ExprKind::Lambda { params, body } => {
+                match params.as_slice() {
+                    [param] => Latex::name(f, param)?,
+                    _ => {
+                        write!(f, "\\left(")?;
+                        for (i, param) in params.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, ", ")?;
+                            }
+                            Latex::name(f, param)?;
+                        }
+                        write!(f, "\\right)")?;
+                    }
+                }
+                write!(f, " \\mapsto {}", Latex(body))
+            }
+            ExprKind::If { .. } => {
+                write!(f, "\\begin{{cases}}")?;
+                for (i, (cond, value)) in cases(self.0).into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " \\\\")?;
+                    }
+                    match cond {
+                        Some(cond) => {
+                            write!(f, " {} & \\text{{if }} {}", Latex(value), Latex(cond))?
+                        }
+                        None => write!(f, " {} & \\text{{otherwise}}", Latex(value))?,
+                    }
+                }
+                write!(f, " \\end{{cases}}")
+            }
+            ExprKind::Convert { expr, unit } => {
+                Latex::operand(f, expr, convert_parens(expr))?;
+                write!(f, " \\to ")?;
+                Latex::unit(f, unit)
+            }
+            ExprKind::Record { fields } => {
+                write!(f, "\\left\\{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    write!(f, "{}", if i > 0 { ", " } else { " " })?;
+                    Latex::name(f, name)?;
+                    write!(f, ": {}", Latex(value))?;
+                }
+                write!(f, " \\right\\}}")
+            }
+            ExprKind::Field { expr, name } => {
+                Latex::operand(f, expr, field_parens(expr))?;
+                write!(f, ".")?;
+                Latex::name(f, name)
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                write!(f, "\\begin{{cases}}")?;
+                for (i, arm) in arms.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " \\\\")?;
+                    }
+                    write!(f, " {} & ", Latex(&arm.body))?;
+                    Latex::condition(f, scrutinee, arm)?;
+                }
+                write!(f, " \\end{{cases}}")
+            }
+        }
+    }
+}
+
+// ------------------------------------------------
+/// An `Expr` printed as a presentation MathML `<math>` element, laid out
+/// like `Latex`: `<mfrac>` for `/`, `<msup>` for `^` and a brace before
+/// a table of cases for `if` chains.
+#[derive(Debug, Clone, Copy)]
+pub struct MathMl<'a>(&'a Expr);
+
+impl Display for MathMl<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>",
+            Node(self.0)
+        )
+    }
+}
+
+/// One node as a single MathML element, so it can fill any slot of its
+/// parent's.
+struct Node<'a>(&'a Expr);
+
+impl Node<'_> {
+    fn operand(f: &mut Formatter<'_>, expr: &Expr, parens: bool) -> std::fmt::Result {
+        if parens {
+            write!(f, "<mrow><mo>(</mo>{}<mo>)</mo></mrow>", Node(expr))
+        } else {
+            write!(f, "{}", Node(expr))
+        }
+    }
+
+    /// `items` separated by commas between the fences `open` and `close`.
+    fn fenced(f: &mut Formatter<'_>, open: &str, items: &[&Expr], close: &str) -> std::fmt::Result {
+        write!(f, "<mrow><mo>{}</mo>", open)?;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(f, "<mo>,</mo>")?;
+            }
+            write!(f, "{}", Node(item))?;
+        }
+        write!(f, "<mo>{}</mo></mrow>", close)
+    }
+
+    fn number(f: &mut Formatter<'_>, literal: &Literal) -> std::fmt::Result {
+        let text = match literal {
+            Literal::Float(n) if n.is_infinite() => format!("{}∞", if *n < 0.0 { "-" } else { "" }),
+            Literal::Float(n) if n.is_nan() => "NaN".to_string(),
+            _ => literal.to_string(),
+        };
+        match text.strip_prefix('-') {
+            Some(digits) => write!(f, "<mrow><mo>−</mo><mn>{}</mn></mrow>", digits),
+            None => write!(f, "<mn>{}</mn>", text),
+        }
+    }
+
+    fn name(f: &mut Formatter<'_>, name: &str) -> std::fmt::Result {
+        match GREEK.iter().find(|(greek, _)| *greek == name) {
+            Some((_, letter)) => write!(f, "<mi>{}</mi>", letter),
+            None => write!(f, "<mi>{}</mi>", name),
+        }
+    }
+
+    /// When a `match` arm is taken, as the second column of its table.
+    fn condition(f: &mut Formatter<'_>, scrutinee: &Expr, arm: &MatchArm) -> std::fmt::Result {
+        let bound = |literal: &Literal| Expr::literal(literal.clone());
+        match &arm.pattern {
+            Pattern::Wildcard => write!(f, "<mtext>otherwise</mtext>"),
+            Pattern::Literal(literal) => write!(
+                f,
+                "<mrow><mtext>if </mtext><mrow>{}<mo>=</mo>{}</mrow></mrow>",
+                Node(scrutinee),
+                Node(&bound(literal))
+            ),
+            Pattern::Range { start, end } => {
+                write!(f, "<mrow><mtext>if </mtext><mrow>")?;
+                if let Some(start) = start {
+                    write!(f, "{}<mo>≤</mo>", Node(&bound(start)))?;
+                }
+                write!(f, "{}", Node(scrutinee))?;
+                if let Some(end) = end {
+                    write!(f, "<mo>&lt;</mo>{}", Node(&bound(end)))?;
+                }
+                write!(f, "</mrow></mrow>")
+            }
+        }
+    }
+
+    fn unit(f: &mut Formatter<'_>, unit: &Unit) -> std::fmt::Result {
+        let degree = if matches!(unit.symbol, "C" | "F") {
+            "°"
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "<mi mathvariant=\"normal\">{}{}</mi>",
+            degree, unit.symbol
+        )
+    }
+
+    fn text(f: &mut Formatter<'_>, s: &str) -> std::fmt::Result {
+        write!(f, "<mtext>")?;
+        for c in s.chars() {
+            match c {
+                '&' => write!(f, "&amp;")?,
+                '<' => write!(f, "&lt;")?,
+                '>' => write!(f, "&gt;")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "</mtext>")
+    }
+
+    fn call(f: &mut Formatter<'_>, name: &str, args: &[Expr]) -> std::fmt::Result {
+        let args: Vec<&Expr> = args.iter().collect();
+        match (name, args.as_slice()) {
+            ("sqrt", [x]) => write!(f, "<msqrt>{}</msqrt>", Node(x)),
+            ("abs", _) => Node::fenced(f, "|", &args, "|"),
+            ("floor", _) => Node::fenced(f, "⌊", &args, "⌋"),
+            ("ceil", _) => Node::fenced(f, "⌈", &args, "⌉"),
+            ("list", _) => Node::fenced(f, "[", &args, "]"),
+            _ => {
+                write!(f, "<mrow>")?;
+                if name == "log10" {
+                    write!(f, "<msub><mi>log</mi><mn>10</mn></msub>")?;
+                } else {
+                    write!(f, "<mi>{}</mi>", name)?;
+                }
+                write!(f, "<mo>&#x2061;</mo>")?;
+                Node::fenced(f, "(", &args, ")")?;
+                write!(f, "</mrow>")
+            }
+        }
+    }
+}
+
+impl Display for Node<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0.kind {
+            ExprKind::Literal(
+                literal @ (Literal::Int(_) | Literal::BigInt(_) | Literal::Float(_)),
+            ) => Node::number(f, literal),
+            ExprKind::Literal(Literal::Bool(b)) => write!(f, "<mtext>{}</mtext>", b),
+            ExprKind::Literal(Literal::Str(s)) => Node::text(f, &format!("\"{}\"", s)),
+            ExprKind::Literal(Literal::Quantity(n, unit)) => {
+                write!(f, "<mrow>")?;
+                Node::number(f, &amount(*n))?;
+                write!(f, "<mspace width=\"0.17em\"/>")?;
+                Node::unit(f, unit)?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Literal(Literal::Imaginary(n)) => {
+                write!(f, "<mrow>")?;
+                Node::number(f, &amount(*n))?;
+                write!(f, "<mi>i</mi></mrow>")
+            }
+            ExprKind::Var(name) => Node::name(f, name),
+            ExprKind::Unary { op, operand } => {
+                let symbol = match op {
+                    UnaryOp::Neg => "−",
+                    UnaryOp::Not => "¬",
+                };
+                write!(f, "<mrow><mo>{}</mo>", symbol)?;
+                Node::operand(f, operand, unary_parens(*op, operand))?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (lhs_parens, rhs_parens) = binary_parens(*op, lhs, rhs);
+                let symbol = match op {
+                    BinOp::Div => {
+                        return write!(f, "<mfrac>{}{}</mfrac>", Node(lhs), Node(rhs));
+                    }
+                    BinOp::Range => return Node::fenced(f, "[", &[lhs, rhs], ")"),
+                    BinOp::Pow => {
+                        write!(f, "<msup>")?;
+                        Node::operand(f, lhs, lhs_parens)?;
+                        return write!(f, "{}</msup>", Node(rhs));
+                    }
+                    BinOp::Add => "+",
+                    BinOp::Sub => "−",
+                    BinOp::Mul => "⋅",
+                    BinOp::Rem => "mod",
+                    BinOp::Eq => "=",
+                    BinOp::Ne => "≠",
+                    BinOp::Lt => "&lt;",
+                    BinOp::Le => "≤",
+                    BinOp::Gt => "&gt;",
+                    BinOp::Ge => "≥",
+                    BinOp::And => "∧",
+                    BinOp::Or => "∨",
+                };
+                write!(f, "<mrow>")?;
+                Node::operand(f, lhs, lhs_parens)?;
+                write!(f, "<mo>{}</mo>", symbol)?;
+                Node::operand(f, rhs, rhs_parens)?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Call { name, args } => Node::call(f, name, args),
+            ExprKind::Apply { callee, args } => {
+                write!(f, "<mrow>")?;
+                Node::operand(f, callee, field_parens(callee))?;
+                write!(f, "<mo>&#x2061;</mo>")?;
+                Node::fenced(f, "(", &args.iter().collect::<Vec<_>>(), ")")?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Let { name, value, body } => {
+                write!(f, "<mrow><mtext>let </mtext>")?;
+                Node::name(f, name)?;
+                write!(
+                    f,
+                    "<mo>=</mo>{}<mtext> in </mtext>{}</mrow>",
+                    Node(value),
+                    Node(body)
+                )
+            }
+            ExprKind::Lambda { params, body } => {
+                write!(f, "<mrow>")?;
+                match params.as_slice() {
+                    [param] => Node::name(f, param)?,
+                    _ => {
+                        write!(f, "<mrow><mo>(</mo>")?;
+                        for (i, param) in params.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, "<mo>,</mo>")?;
+                            }
+                            Node::name(f, param)?;
+                        }
+                        write!(f, "<mo>)</mo></mrow>")?;
+                    }
+                }
+                write!(f, "<mo>↦</mo>{}</mrow>", Node(body))
+            }
+            ExprKind::If { .. } => {
+                write!(f, "<mrow><mo>{{</mo><mtable columnalign=\"left\">")?;
+                for (cond, value) in cases(self.0) {
+                    write!(f, "<mtr><mtd>{}</mtd><mtd>", Node(value))?;
+                    match cond {
+                        Some(cond) => write!(f, "<mrow><mtext>if </mtext>{}</mrow>", Node(cond))?,
+                        None => write!(f, "<mtext>otherwise</mtext>")?,
+                    }
+                    write!(f, "</mtd></mtr>")?;
+                }
+                write!(f, "</mtable></mrow>")
+            }
+            ExprKind::Convert { expr, unit } => {
+                write!(f, "<mrow>")?;
+                Node::operand(f, expr, convert_parens(expr))?;
+                write!(f, "<mo>→</mo>")?;
+                Node::unit(f, unit)?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Record { fields } => {
+                write!(f, "<mrow><mo>{{</mo>")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "<mo>,</mo>")?;
+                    }
+                    write!(f, "<mrow>")?;
+                    Node::name(f, name)?;
+                    write!(f, "<mo>:</mo>{}</mrow>", Node(value))?;
+                }
+                write!(f, "<mo>}}</mo></mrow>")
+            }
+            ExprKind::Field { expr, name } => {
+                write!(f, "<mrow>")?;
+                Node::operand(f, expr, field_parens(expr))?;
+                write!(f, "<mo>.</mo>")?;
+                Node::name(f, name)?;
+                write!(f, "</mrow>")
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                write!(f, "<mrow><mo>{{</mo><mtable columnalign=\"left\">")?;
+                for arm in arms {
+                    write!(f, "<mtr><mtd>{}</mtd><mtd>", Node(&arm.body))?;
+                    Node::condition(f, scrutinee, arm)?;
+                    write!(f, "</mtd></mtr>")?;
+                }
+                write!(f, "</mtable></mrow>")
+            }
+        }
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    fn latex(src: &str) -> String {
+        parse(src).unwrap().latex().to_string()
+    }
+
+    /// The MathML inside the `<math>` element.
+    fn mathml(src: &str) -> String {
+        let math = parse(src).unwrap().mathml().to_string();
+        let inner = math
+            .strip_prefix("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">")
+            .and_then(|rest| rest.strip_suffix("</math>"))
+            .expect("a single math element");
+        inner.to_string()
+    }
+
+    #[test]
+    fn test_latex_fractions_and_powers() {
+        assert_eq!(latex("(a + b) / 2"), "\\frac{a + b}{2}");
+        assert_eq!(latex("x ^ (n - 1)"), "x^{n - 1}");
+        assert_eq!(latex("(a / b) ^ 2"), "\\left(\\frac{a}{b}\\right)^{2}");
+        assert_eq!(latex("(2 ^ 3) ^ 2"), "\\left(2^{3}\\right)^{2}");
+        assert_eq!(latex("2 ^ 3 ^ 2"), "2^{3^{2}}");
+        assert_eq!(latex("(-2) ^ 2"), "\\left(-2\\right)^{2}");
+        assert_eq!(latex("-(a / b)"), "-\\frac{a}{b}");
+    }
+
+    #[test]
+    fn test_latex_minimal_parens() {
+        assert_eq!(latex("1 + 2 * 3"), "1 + 2 \\cdot 3");
+        assert_eq!(latex("(1 + 2) * 3"), "\\left(1 + 2\\right) \\cdot 3");
+        assert_eq!(latex("a - (b - c)"), "a - \\left(b - c\\right)");
+        assert_eq!(latex("(a - b) - c"), "a - b - c");
+        assert_eq!(latex("a / b * c"), "\\frac{a}{b} \\cdot c");
+        assert_eq!(
+            latex("x % 2 == 0 && !done"),
+            "x \\bmod 2 = 0 \\land \\lnot \\mathit{done}"
+        );
+    }
+
+    #[test]
+    fn test_latex_names_and_calls() {
+        assert_eq!(
+            latex("2 * pi * radius"),
+            "2 \\cdot \\pi \\cdot \\mathit{radius}"
+        );
+        assert_eq!(latex("sqrt(x ^ 2 + 1)"), "\\sqrt{x^{2} + 1}");
+        assert_eq!(latex("sin(x) ^ 2"), "\\sin\\left(x\\right)^{2}");
+        assert_eq!(latex("abs(a - b)"), "\\left|a - b\\right|");
+        assert_eq!(latex("log10(x)"), "\\log_{10}\\left(x\\right)");
+        assert_eq!(
+            latex("net_price(x, 2)"),
+            "\\operatorname{net\\_price}\\left(x, 2\\right)"
+        );
+        assert_eq!(latex("[1, 2]"), "\\left[1, 2\\right]");
+        assert_eq!(latex("1..n + 1"), "\\left[1, n + 1\\right)");
+    }
+
+    #[test]
+    fn test_latex_other_forms() {
+        assert_eq!(
+            latex("if x < 0 then -x else if x == 0 then 1 else x"),
+            "\\begin{cases} -x & \\text{if } x < 0 \\\\ 1 & \\text{if } x = 0 \\\\ \
+             x & \\text{otherwise} \\end{cases}"
+        );
+        assert_eq!(
+            latex("|x, y| x * y"),
+            "\\left(x, y\\right) \\mapsto x \\cdot y"
+        );
+        assert_eq!(
+            latex("let r = 2 in r * r"),
+            "\\text{let } r = 2 \\text{ in } r \\cdot r"
+        );
+        assert_eq!(
+            latex("20 C -> F"),
+            "20\\,{}^{\\circ}\\mathrm{C} \\to {}^{\\circ}\\mathrm{F}"
+        );
+        assert_eq!(latex("\"50% & up\""), "\\text{\"50\\% \\& up\"}");
+        assert_eq!(
+            latex("(3 + 4i) * -2.5i"),
+            "\\left(3 + 4i\\right) \\cdot -2.5i"
+        );
+    }
+
+    #[test]
+    fn test_mathml() {
+        assert_eq!(
+            mathml("(a + b) / 2"),
+            "<mfrac><mrow><mi>a</mi><mo>+</mo><mi>b</mi></mrow><mn>2</mn></mfrac>"
+        );
+        assert_eq!(
+            mathml("(x - 1) ^ 2"),
+            "<msup><mrow><mo>(</mo><mrow><mi>x</mi><mo>−</mo><mn>1</mn></mrow><mo>)</mo></mrow>\
+             <mn>2</mn></msup>"
+        );
+        assert_eq!(mathml("-3"), "<mrow><mo>−</mo><mn>3</mn></mrow>");
+        assert_eq!(
+            mathml("a < b"),
+            "<mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>"
+        );
+        assert_eq!(mathml("sqrt(pi)"), "<msqrt><mi>π</mi></msqrt>");
+        assert_eq!(
+            mathml("max(a, 1)"),
+            "<mrow><mi>max</mi><mo>&#x2061;</mo>\
+             <mrow><mo>(</mo><mi>a</mi><mo>,</mo><mn>1</mn><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            mathml("if c then 1 else 0"),
+            "<mrow><mo>{</mo><mtable columnalign=\"left\">\
+             <mtr><mtd><mn>1</mn></mtd><mtd><mrow><mtext>if </mtext><mi>c</mi></mrow></mtd></mtr>\
+             <mtr><mtd><mn>0</mn></mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable></mrow>"
+        );
+        assert_eq!(mathml("\"a<b\""), "<mtext>\"a&lt;b\"</mtext>");
+        assert_eq!(
+            mathml("2 - 4i"),
+            "<mrow><mn>2</mn><mo>−</mo><mrow><mn>4</mn><mi>i</mi></mrow></mrow>"
+        );
+    }
+
+    #[test]
+    fn test_records_and_match() {
+        assert_eq!(
+            latex("{ price: 10, n: 3 }.n * (a + b).x"),
+            "\\left\\{ \\mathit{price}: 10, n: 3 \\right\\}.n \\cdot \\left(a + b\\right).x"
+        );
+        assert_eq!(
+            latex("match n { 0 => a, 1..10 => b, 10.. => c, _ => d }"),
+            "\\begin{cases} a & \\text{if } n = 0 \\\\ b & \\text{if } 1 \\leq n < 10 \\\\ \
+             c & \\text{if } 10 \\leq n \\\\ d & \\text{otherwise} \\end{cases}"
+        );
+        assert_eq!(mathml("r.x"), "<mrow><mi>r</mi><mo>.</mo><mi>x</mi></mrow>");
+        assert_eq!(
+            mathml("{ a: 1 }"),
+            "<mrow><mo>{</mo><mrow><mi>a</mi><mo>:</mo><mn>1</mn></mrow><mo>}</mo></mrow>"
+        );
+        assert_eq!(
+            mathml("match n { ..0 => 1, _ => 0 }"),
+            "<mrow><mo>{</mo><mtable columnalign=\"left\">\
+             <mtr><mtd><mn>1</mn></mtd><mtd><mrow><mtext>if </mtext>\
+             <mrow><mi>n</mi><mo>&lt;</mo><mn>0</mn></mrow></mrow></mtd></mtr>\
+             <mtr><mtd><mn>0</mn></mtd><mtd><mtext>otherwise</mtext></mtd></mtr></mtable></mrow>"
+        );
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    mem,
+    sync::Arc,
+};
+
+use crate::{
+    ast::{Expr, Stmt},
+    diagnostic::Diagnostic,
+    error::CalcError,
+    eval::Interpreter,
+    exact::NumericMode,
+    optimize::Optimizer,
+    parser::{Parser, parse_all},
+    trace::Tracer,
+    typecheck::TypeChecker,
+    value::Value,
+};
+
+// ------------------------------------------------
+/// How many previous results stay reachable as `_1`, `_2`, ...
+pub const HISTORY_SIZE: usize = 10;
+
+const HELP: &str = "\
+Enter an expression to evaluate it, e.g. `sqrt(2) * 3`.
+Assign variables with `x = 5`; bind locally with `let x = 3 in x * x`.
+Define functions with `fn sq(x) = x * x` or lambdas like `|x, y| x + y`.
+Branch with `if x > 0 then x else -x`; combine conditions with `&&`, `||`, `!`.
+Vectors: `vec(3, 4) * 2`, `dot(a, b)`, `length(v)`, `normalize(v)`.
+Units: `3 m + 50 cm`, `10 m / 4 s`; convert with `20 C -> F`.
+Exact mode (`:mode exact`): `1/3 + 1/6` is `1/2`; `float(x)` converts.
+Sequences: `1..10`, `range(0, 100, 5)`, `[1, 2]`, `take(fib(), 10)`;
+  total with `sum` or `product`, e.g. `sum([money(9.99), money(0.01)])`.
+Complex numbers: `(3 + 4i) * 2i`, `sqrt(-4)`, `abs(z)`, `arg(z)`, `conj(z)`;
+  convert with `polar(z)` and `rect(r, theta)`.
+Records: `{ price: 10, qty: 3 }.qty`; branch on values with
+  `match n { 0 => \"none\", 1..10 => \"few\", _ => \"many\" }`.
+Previous results: `_` (or `_1`) is the last one, `_2` the one before, ...;
+  assignments and definitions are not results, so they do not count.
+Commands:
+  :vars        list variables
+  :type e      show the type of expression e without running it
+  :trace e     evaluate e and show every step with its result
+  :profile e   evaluate e and show calls and time per function
+  :mode m      compute in float (the default) or exact mode; no m shows it
+  :clear       forget all variables and history
+  :help        show this message
+  :quit        leave the calculator";
+
+/// Whether the driver loop should keep reading lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Continue,
+    Quit,
+}
+
+/// Line-oriented driver around an `Interpreter`.
+///
+/// Input comes from any `BufRead` and output goes to any `Write`, so the
+/// same loop serves stdin, script files and in-memory buffers in tests.
+#[derive(Debug, Default)]
+pub struct Repl {
+    interp: Interpreter,
+    history: VecDeque<Value>,
+    interactive: bool,
+    /// Set in `--dump-optimized` mode
+    optimizer: Option<Optimizer>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            interp: Interpreter::new(),
+            history: VecDeque::new(),
+            interactive: false,
+            optimizer: None,
+        }
+    }
+
+    /// Interactive sessions print a prompt before each line; scripts
+    /// instead prefix errors with their line number.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Optimize each statement before running it and print the result.
+    pub fn dump_optimized(mut self, dump: bool) -> Self {
+        self.optimizer = dump.then(Optimizer::new);
+        self
+    }
+
+    /// Start out computing in `mode`; `:mode` changes it later.
+    pub fn numeric_mode(mut self, mode: NumericMode) -> Self {
+        self.set_mode(mode);
+        self
+    }
+
+    fn set_mode(&mut self, mode: NumericMode) {
+        self.interp = mem::take(&mut self.interp).with_mode(mode);
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    /// Most recent result first.
+    pub fn history(&self) -> impl Iterator<Item = &Value> {
+        self.history.iter()
+    }
+
+    /// Run until end of input or `:quit`; returns how many lines failed.
+    pub fn run<R, W>(&mut self, input: R, out: &mut W) -> io::Result<usize>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let mut failures = 0;
+        let mut lines = input.lines().enumerate();
+
+        loop {
+            if self.interactive {
+                write!(out, "> ")?;
+                out.flush()?;
+            }
+
+            let Some((index, line)) = lines.next() else {
+                break;
+            };
+            let line = line?;
+
+            match self.handle_line(&line, out) {
+                Ok(Control::Continue) => {}
+                Ok(Control::Quit) => break,
+                Err(reports) => {
+                    failures += 1;
+                    for report in reports {
+                        if self.interactive {
+                            writeln!(out, "error: {}", report)?;
+                        } else {
+                            writeln!(out, "line {}: error: {}", index + 1, report)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Handle one line of input; the error holds one report per problem.
+    fn handle_line<W: Write>(&mut self, line: &str, out: &mut W) -> Result<Control, Vec<String>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(Control::Continue);
+        }
+
+        if let Some(command) = line.strip_prefix(':') {
+            return self.command(command.trim(), out);
+        }
+
+        let parser = Parser::new(line).with_max_nesting(self.interp.limits().nesting());
+        let mut stmt = parser.parse_stmt_all().map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|e| report(line, &CalcError::Parse(e)))
+                .collect::<Vec<String>>()
+        })?;
+        for warning in stmt.match_warnings() {
+            let diagnostic = Diagnostic::from(&warning);
+            writeln!(out, "{}\n{}", diagnostic, diagnostic.render(line))
+                .map_err(|e| vec![e.to_string()])?;
+        }
+        if let Some(optimizer) = &self.optimizer {
+            stmt = optimizer
+                .clone()
+                .for_mode(self.interp.mode())
+                .with_vars(self.interp.vars())
+                .optimize_stmt(&stmt);
+            writeln!(out, "optimized: {}", stmt).map_err(|e| vec![e.to_string()])?;
+        }
+        let value = self
+            .interp
+            .exec(&stmt)
+            .map_err(|e| vec![report(line, &CalcError::Eval(e))])?;
+        writeln!(out, "{}", value).map_err(|e| vec![e.to_string()])?;
+        // Names already reach what assignments and definitions bind
+        if matches!(stmt, Stmt::Expr(_)) {
+            self.record(value);
+        }
+        Ok(Control::Continue)
+    }
+
+    fn command<W: Write>(&mut self, command: &str, out: &mut W) -> Result<Control, Vec<String>> {
+        let (command, arg) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(command, arg)| (command, arg.trim()));
+        let written = match command {
+            "help" | "h" => writeln!(out, "{}", HELP),
+            // `_1` is always `_` again
+            "vars" => self
+                .interp
+                .vars()
+                .into_iter()
+                .filter(|(name, _)| *name != "_1")
+                .try_for_each(|(name, value)| writeln!(out, "{} = {}", name, value)),
+            "clear" => {
+                self.interp.clear();
+                self.history.clear();
+                Ok(())
+            }
+            "type" | "t" => return self.show_type(arg, out),
+            "trace" | "profile" => return self.observe(command, arg, out),
+            "mode" | "m" if arg.is_empty() => writeln!(out, "{}", self.interp.mode()),
+            "mode" | "m" => {
+                let mode = arg.parse().map_err(|e| vec![e])?;
+                self.set_mode(mode);
+                Ok(())
+            }
+            "quit" | "q" => return Ok(Control::Quit),
+            other => return Err(vec![format!("unknown command ':{}' (try :help)", other)]),
+        };
+        written.map_err(|e| vec![e.to_string()])?;
+        Ok(Control::Continue)
+    }
+
+    /// Type-check `src` against the current variables without running
+    /// it; errors are rendered under `src` itself.
+    fn show_type<W: Write>(&self, src: &str, out: &mut W) -> Result<Control, Vec<String>> {
+        let expr = parse_reported(src)?;
+        let ty = TypeChecker::from_interpreter(&self.interp)
+            .check(&expr)
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(|e| {
+                        let diagnostic = Diagnostic::from(e);
+                        format!("{}\n{}", diagnostic, diagnostic.render(src))
+                    })
+                    .collect::<Vec<String>>()
+            })?;
+        writeln!(out, "{}", ty).map_err(|e| vec![e.to_string()])?;
+        Ok(Control::Continue)
+    }
+
+    /// Evaluate `src` under a `Tracer` and show its trace tree or profile.
+    /// The trace is shown even when evaluation fails, followed by the
+    /// error; the result is not recorded in the history.
+    fn observe<W: Write>(
+        &self,
+        command: &str,
+        src: &str,
+        out: &mut W,
+    ) -> Result<Control, Vec<String>> {
+        let expr = parse_reported(src)?;
+        let tracer = Arc::new(Tracer::new());
+        let result = self
+            .interp
+            .clone()
+            .with_observer(tracer.clone())
+            .eval(&expr);
+
+        let written = match command {
+            "trace" => tracer
+                .traces()
+                .iter()
+                .try_for_each(|trace| writeln!(out, "{}", trace)),
+            _ => write!(out, "{}", tracer.profile()),
+        };
+        written.map_err(|e| vec![e.to_string()])?;
+        result.map_err(|e| vec![report(src, &CalcError::Eval(e))])?;
+        Ok(Control::Continue)
+    }
+
+    /// Push a result into the history and rebind `_`, `_1`, `_2`, ...
+    fn record(&mut self, value: Value) {
+        self.interp.set_var("_", value.clone());
+        self.history.push_front(value);
+        self.history.truncate(HISTORY_SIZE);
+
+        for (i, previous) in self.history.iter().enumerate() {
+            self.interp.set_var(format!("_{}", i + 1), previous.clone());
+        }
+    }
+}
+
+/// Parse a command's expression, with one report per parse error.
+fn parse_reported(src: &str) -> Result<Expr, Vec<String>> {
+    parse_all(src).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|e| report(src, &CalcError::Parse(e)))
+            .collect()
+    })
+}
+
+/// Error message followed by the offending line with carets under it.
+fn report(line: &str, err: &CalcError) -> String {
+    format!("{}\n{}", err, Diagnostic::from(err).render(line))
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use p20_io_bufread_seek::MemBuffer;
+
+    use super::*;
+
+    fn run_script(script: &str) -> (String, usize) {
+        let input = MemBuffer::from_str(script).unwrap();
+        let mut out = Vec::new();
+        let failures = Repl::new().run(input, &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), failures)
+    }
+
+    #[test]
+    fn test_evaluates_each_line() {
+        let (out, failures) = run_script("1 + 2\n2 ^ 10\n");
+        assert_eq!(out, "3\n1024\n");
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn test_runaway_recursion_is_an_error() {
+        let (out, failures) = run_script("fn f(n) = f(n)\nf(1)\n");
+        assert_eq!(
+            out,
+            "<fn f(n)>\n\
+             line 2: error: evaluation error: evaluation limit exceeded: evaluation nested more than 128 deep\n\
+             f(1)\n    ^ stopped here\n"
+        );
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_history_variables() {
+        let (out, _) = run_script("2\n3\n_ * 10\n_2 + _3\n_1\n");
+        assert_eq!(out, "2\n3\n30\n5\n5\n");
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut repl = Repl::new();
+        let script: String = (1..=HISTORY_SIZE + 5).map(|n| format!("{}\n", n)).collect();
+        let input = MemBuffer::from_str(&script).unwrap();
+        repl.run(input, &mut Vec::new()).unwrap();
+
+        assert_eq!(repl.history().count(), HISTORY_SIZE);
+        assert_eq!(
+            repl.history().next(),
+            Some(&Value::Int((HISTORY_SIZE + 5) as i64))
+        );
+    }
+
+    #[test]
+    fn test_errors_keep_going() {
+        let (out, failures) = run_script("1 +\nfoo\n4\n");
+        assert_eq!(
+            out,
+            "line 1: error: parse error: expected operand, found end of input\n\
+             1 +\n\
+             \x20  ^ expected operand\n\
+             line 2: error: evaluation error: unknown variable 'foo'\n\
+             foo\n\
+             ^^^ not defined\n\
+             4\n"
+        );
+        assert_eq!(failures, 2);
+    }
+
+    #[test]
+    fn test_reports_every_parse_error() {
+        let (out, failures) = run_script("1 + * 2 + (3 *)\n");
+        assert_eq!(
+            out,
+            "line 1: error: parse error: expected operand, found `*`\n\
+             1 + * 2 + (3 *)\n\
+             \x20   ^ expected operand\n\
+             line 1: error: parse error: expected operand, found `)`\n\
+             1 + * 2 + (3 *)\n\
+             \x20             ^ expected operand\n"
+        );
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let (out, failures) = run_script("# a comment\n\n   \n6 * 7\n");
+        assert_eq!(out, "42\n");
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn test_vars_and_clear() {
+        let (out, _) = run_script("5\n:vars\n:clear\n:vars\n_\n");
+        assert_eq!(
+            out,
+            "5\n_ = 5\nline 5: error: evaluation error: unknown variable '_'\n_\n^ not defined\n"
+        );
+    }
+
+    #[test]
+    fn test_assignments_persist() {
+        let (out, _) = run_script("rate = 0.2\nprice = 50\nprice * (1 + rate)\n:vars\n");
+        assert_eq!(
+            out,
+            "0.2\n50\n60.0\n_ = 60.0\nprice = 50\nrate = 0.2\n"
+        );
+    }
+
+    #[test]
+    fn test_functions() {
+        let (out, failures) = run_script("fn sq(x) = x * x\nmap(list(1, 2, 3), sq)\n|x| x\n");
+        assert_eq!(out, "<fn sq(x)>\n[1, 4, 9]\n<lambda |x|>\n");
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn test_definitions_stay_out_of_history() {
+        let (out, failures) = run_script("7\nfn sq(a) = a * a\nn = 3\nsq(_)\n_2\n");
+        assert_eq!(out, "7\n<fn sq(a)>\n3\n49\n7\n");
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn test_dump_optimized() {
+        let input = MemBuffer::from_str("x = 4\nx * 1 + 2 * 3\n(x + 1) ^ 2 / 2\nx = -0.0\nx + 0\n")
+            .unwrap();
+        let mut out = Vec::new();
+        Repl::new()
+            .dump_optimized(true)
+            .run(input, &mut out)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "optimized: x = 4\n4\n\
+             optimized: x + 6\n10\n\
+             optimized: (x + 1) ^ 2 * 0.5\n12.5\n\
+             optimized: x = -0.0\n-0.0\n\
+             optimized: x + 0\n0.0\n"
+        );
+    }
+
+    #[test]
+    fn test_exact_mode() {
+        let script = ":mode\n:mode exact\n1/3 + 1/6\n0.1 + 0.2 == 0.3\n2 ^ 70\nfloat(1/8)\n\
+                      :dump\n:mode fast\n:mode float\n1/4\n";
+        let (out, failures) = run_script(script);
+        assert_eq!(
+            out,
+            "float\n1/2\ntrue\n1180591620717411303424\n0.125\n\
+             line 7: error: unknown command ':dump' (try :help)\n\
+             line 8: error: unknown numeric mode 'fast' (expected 'float' or 'exact')\n\
+             0.25\n"
+        );
+        assert_eq!(failures, 2);
+
+        // Folding would compute `1 / 3` as a float, so it is skipped
+        let input = MemBuffer::from_str("1 / 3 + x * 1\n").unwrap();
+        let mut out = Vec::new();
+        let mut repl = Repl::new()
+            .dump_optimized(true)
+            .numeric_mode(NumericMode::Exact);
+        repl.interp.set_var("x", Value::Int(0));
+        repl.run(input, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "optimized: 1 / 3 + x\n1/3\n"
+        );
+    }
+
+    #[test]
+    fn test_trace_and_profile_commands() {
+        let script = "fn sq(x) = x * x\n:trace sq(2) + 1\n:profile sq(sq(2))\n:trace 1 / 0\n";
+        let (out, failures) = run_script(script);
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.by_ref().take(7).collect::<Vec<_>>(),
+            [
+                "<fn sq(x)>",
+                "sq(2) + 1 => 5",
+                "  sq(2) => 4",
+                "    2 => 2",
+                "    x * x => 4",
+                "      x => 2",
+                "      x => 2",
+            ]
+        );
+        assert_eq!(lines.next(), Some("  1 => 1"));
+        assert!(lines.next().unwrap().starts_with("function"));
+        let sq_row: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+        assert_eq!(sq_row[..3], ["sq", "2", "0"]);
+        assert_eq!(
+            lines.collect::<Vec<_>>()[..4],
+            [
+                "1 / 0 => error: division by zero",
+                "  1 => 1",
+                "  0 => 0",
+                "line 4: error: evaluation error: division by zero",
+            ]
+        );
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_help_and_unknown_command() {
+        let (out, failures) = run_script(":help\n:bogus\n");
+        assert!(out.starts_with("Enter an expression"));
+        assert!(out.contains(":vars"));
+        assert!(out.ends_with("error: unknown command ':bogus' (try :help)\n"));
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_match_warnings() {
+        let script = "fn size(n) = match n { ..10 => 1, 20.. => 2 }\n\
+                      size(15)\n\
+                      match true { _ => 1, false => 0 }\n";
+        let (out, failures) = run_script(script);
+        assert_eq!(
+            out,
+            "warning: match is not exhaustive: `10..20` is not covered\n\
+             fn size(n) = match n { ..10 => 1, 20.. => 2 }\n\
+             \x20            ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^ `10..20` not covered\n\
+             <fn size(n)>\n\
+             line 2: error: evaluation error: no match arm for 15\n\
+             size(15)\n\
+             \x20       ^ no arm matches\n\
+             warning: unreachable match arm\n\
+             match true { _ => 1, false => 0 }\n\
+             \x20                    ^^^^^ never reached\n\
+             1\n"
+        );
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_type_command() {
+        let script = "fn inc(x) = x + 1\n:type map(list(1, 2), |x| x > 1)\n:type inc\n\
+                      :type inc(true)\n";
+        let (out, failures) = run_script(script);
+        assert_eq!(
+            out,
+            "<fn inc(x)>\n[bool]\nnumber -> number\n\
+             line 4: error: type error: type mismatch: expected number, found bool\n\
+             inc(true)\n\
+             \x20   ^^^^ expected number\n"
+        );
+        assert_eq!(failures, 1);
+    }
+
+    #[test]
+    fn test_quit_stops_reading() {
+        let (out, _) = run_script("1\n:quit\n2\n");
+        assert_eq!(out, "1\n");
+    }
+
+    #[test]
+    fn test_interactive_prompts() {
+        let input = MemBuffer::from_str("1 + 1\nnope\n").unwrap();
+        let mut out = Vec::new();
+        Repl::new().interactive(true).run(input, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "> 2\n> error: evaluation error: unknown variable 'nope'\nnope\n^^^^ not defined\n> "
+        );
+    }
+}
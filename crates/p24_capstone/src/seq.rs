@@ -0,0 +1,391 @@
+use std::{
+    fmt::{Display, Formatter},
+    iter,
+    sync::Arc,
+};
+
+use p09_iterator_core::Fibonacci;
+use p23_extend_sum::{Money, Scalar};
+
+use crate::{
+    ast::BinOp,
+    error::EvalError,
+    eval::{Interpreter, apply_binary},
+    lexer::Span,
+    limits::Meter,
+    value::{Lambda, Value},
+};
+
+// ------------------------------------------------
+/// Terms p09's `Fibonacci` yields before working out the next one
+/// overflows its `u64`; all of them fit in an `i64`.
+const FIB_TERMS: usize = 92;
+
+/// Items produced one at a time; the first error ends the sequence.
+pub(crate) type Items<'a> = Box<dyn Iterator<Item = Result<Value, EvalError>> + 'a>;
+
+/// A lazy sequence: a recipe for its items, run again each time it is
+/// read.
+///
+/// `take`, `map` and `filter` on a sequence only wrap the recipe, so they
+/// work on sequences without an end; `sum`, `product`, `fold`, `len` and
+/// `collect` read every item, and refuse those.
+#[derive(Debug, Clone)]
+pub enum Seq {
+    /// `start`, `start + step`, ... up to but excluding `end`
+    Range {
+        start: i64,
+        end: i64,
+        step: i64,
+    },
+    /// 0, 1, 1, 2, 3, 5, ... from p09's `Fibonacci`
+    Fib,
+    /// The first `n` items
+    Take(Arc<Seq>, usize),
+    Map(Arc<Seq>, Arc<Lambda>),
+    Filter(Arc<Seq>, Arc<Lambda>),
+}
+
+impl Seq {
+    /// Whether reading every item finishes.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Seq::Range { .. } | Seq::Take(..) => true,
+            Seq::Fib => false,
+            Seq::Map(seq, _) | Seq::Filter(seq, _) => seq.is_finite(),
+        }
+    }
+
+    /// The items, produced on demand. Each generated item counts as a
+    /// step, so even `filter` over `fib()` that never matches is bounded
+    /// by `EvalLimits`; the numbers follow `interp`'s `NumericMode`.
+    pub(crate) fn items<'a>(
+        &'a self,
+        interp: &'a Interpreter,
+        span: Span,
+        meter: &'a Meter,
+    ) -> Items<'a> {
+        let mode = interp.mode();
+        let generated = move |n: i64| meter.step(span).map(|()| mode.number(Value::Int(n)));
+        match self {
+            Seq::Range { start, end, step } => {
+                let (end, step) = (*end, *step);
+                let ints = iter::successors(Some(*start), move |n| n.checked_add(step))
+                    .take_while(move |&n| if step > 0 { n < end } else { n > end });
+                Box::new(ints.map(generated))
+            }
+            Seq::Fib => {
+                let fibs = Fibonacci::new().take(FIB_TERMS).map(|n| n as i64);
+                let overflow = iter::once(Err(EvalError::IntegerOverflow { span }));
+                Box::new(fibs.map(generated).chain(overflow))
+            }
+            Seq::Take(seq, n) => Box::new(seq.items(interp, span, meter).take(*n)),
+            Seq::Map(seq, f) => Box::new(
+                seq.items(interp, span, meter)
+                    .map(move |item| interp.call_in(f, vec![item?], span, meter)),
+            ),
+            Seq::Filter(seq, f) => {
+                Box::new(seq.items(interp, span, meter).filter_map(move |item| {
+                    let kept = item.and_then(|item| {
+                        let keep = interp.call_in(f, vec![item.clone()], span, meter)?;
+                        Ok(keep.as_bool(span)?.then_some(item))
+                    });
+                    kept.transpose()
+                }))
+            }
+        }
+    }
+
+    /// The number of items, when it is known without running the recipe.
+    pub(crate) fn known_len(&self) -> Option<usize> {
+        match self {
+            Seq::Range { start, end, step } => {
+                let (start, end, step) = (*start as i128, *end as i128, *step as i128);
+                let distance = if step > 0 { end - start } else { start - end };
+                if distance <= 0 {
+                    return Some(0);
+                }
+                usize::try_from((distance - 1) / step.abs() + 1).ok()
+            }
+            // Only the terms before `Fibonacci` overflows exist
+            Seq::Take(seq, n) if matches!(**seq, Seq::Fib) => (*n <= FIB_TERMS).then_some(*n),
+            Seq::Take(seq, n) => seq.known_len().map(|len| len.min(*n)),
+            Seq::Fib | Seq::Map(..) | Seq::Filter(..) => None,
+        }
+    }
+
+    /// Fails unless the function `name`, which reads every item, would
+    /// finish.
+    pub(crate) fn expect_finite(&self, name: &str, span: Span) -> Result<(), EvalError> {
+        if self.is_finite() {
+            return Ok(());
+        }
+        Err(EvalError::InfiniteSequence {
+            name: name.to_string(),
+            span,
+        })
+    }
+
+    /// Every item, for the function `name` that builds a list of them.
+    /// Fails as soon as there are more than `EvalLimits::max_len` of
+    /// them, rather than after building the whole list.
+    pub(crate) fn collect(
+        &self,
+        name: &str,
+        interp: &Interpreter,
+        span: Span,
+        meter: &Meter,
+    ) -> Result<Vec<Value>, EvalError> {
+        self.expect_finite(name, span)?;
+        let mut items = Vec::new();
+        for item in self.items(interp, span, meter) {
+            items.push(item?);
+            meter.check_len(items.len(), span)?;
+        }
+        Ok(items)
+    }
+}
+
+/// Prints the recipe, like `take(fib(), 5)`; the items are not known
+/// without running it.
+impl Display for Seq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Seq::Range {
+                start,
+                end,
+                step: 1,
+            } => write!(f, "{}..{}", start, end),
+            Seq::Range { start, end, step } => write!(f, "range({}, {}, {})", start, end, step),
+            Seq::Fib => write!(f, "fib()"),
+            Seq::Take(seq, n) => write!(f, "take({}, {})", seq, n),
+            Seq::Map(seq, func) => write!(f, "map({}, {})", seq, func),
+            Seq::Filter(seq, func) => write!(f, "filter({}, {})", seq, func),
+        }
+    }
+}
+
+/// Recipes compare structurally, with functions by identity as in `Value`.
+impl PartialEq for Seq {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Seq::Range { start, end, step },
+                Seq::Range {
+                    start: s,
+                    end: e,
+                    step: st,
+                },
+            ) => (start, end, step) == (s, e, st),
+            (Seq::Fib, Seq::Fib) => true,
+            (Seq::Take(a, n), Seq::Take(b, m)) => n == m && a == b,
+            (Seq::Map(a, f), Seq::Map(b, g)) | (Seq::Filter(a, f), Seq::Filter(b, g)) => {
+                Arc::ptr_eq(f, g) && a == b
+            }
+            _ => false,
+        }
+    }
+}
+
+// ------------------------------------------------
+/// `sum` (for `BinOp::Add`) or `product` (for `BinOp::Mul`) of `items`,
+/// read one at a time so a long sequence is never held in memory.
+///
+/// Money adds up through p23's `Sum` for `Money`, and once a float turns
+/// up among numbers the rest go through its `Sum` and `Product` for
+/// `Scalar`. Until then ints stay exact, failing on overflow, rationals
+/// stay exact, and anything else combines with the operator itself, so
+/// vectors and quantities can be summed too.
+pub(crate) fn total(
+    op: BinOp,
+    items: impl IntoIterator<Item = Result<Value, EvalError>>,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let mut items = items.into_iter();
+    let Some(first) = items.next().transpose()? else {
+        return Ok(Value::Int(if op == BinOp::Add { 0 } else { 1 }));
+    };
+
+    if op == BinOp::Add && matches!(first, Value::Money(_)) {
+        // `Money`'s `+` does not check for overflow, so keep a tally that does
+        let mut cents = 0_i64;
+        let amounts = iter::once(Ok(first)).chain(items).map(|item| {
+            let money = match item? {
+                Value::Money(money) => money,
+                other => return Err(other.mismatch("money", span)),
+            };
+            cents = cents
+                .checked_add(money.cents())
+                .ok_or(EvalError::IntegerOverflow { span })?;
+            Ok(money)
+        });
+        return amounts.sum::<Result<Money, _>>().map(Value::Money);
+    }
+
+    let is_number =
+        |item: &Value| matches!(item, Value::Int(_) | Value::Float(_) | Value::Rational(_));
+    let mut acc = first;
+    while let Some(item) = items.next() {
+        let item = item?;
+        let is_float = matches!(acc, Value::Float(_)) || matches!(item, Value::Float(_));
+        if is_float && is_number(&acc) && is_number(&item) {
+            let scalars = [acc, item]
+                .into_iter()
+                .map(Ok)
+                .chain(items)
+                .map(|item| item?.as_number(span).map(Scalar::new));
+            let total: Scalar = match op {
+                BinOp::Add => scalars.sum::<Result<_, _>>()?,
+                _ => scalars.product::<Result<_, _>>()?,
+            };
+            return Ok(Value::Float(total.0));
+        }
+        acc = apply_binary(op, &acc, &item, [span; 3])?;
+    }
+    Ok(acc)
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{EvalLimits, Limit};
+
+    fn run(seq: &Seq) -> Result<Vec<Value>, EvalError> {
+        let meter = Meter::new(EvalLimits::none());
+        seq.collect("test", &Interpreter::new(), Span::default(), &meter)
+    }
+
+    #[test]
+    fn test_ranges() {
+        let seq = Seq::Range {
+            start: 0,
+            end: 10,
+            step: 4,
+        };
+        assert_eq!(run(&seq).unwrap(), [0, 4, 8].map(Value::Int));
+        let down = Seq::Range {
+            start: 3,
+            end: 0,
+            step: -1,
+        };
+        assert_eq!(run(&down).unwrap(), [3, 2, 1].map(Value::Int));
+        let empty = Seq::Range {
+            start: 5,
+            end: 5,
+            step: 1,
+        };
+        assert!(run(&empty).unwrap().is_empty());
+        // Stops instead of wrapping around
+        let top = Seq::Range {
+            start: i64::MAX - 1,
+            end: i64::MAX,
+            step: 5,
+        };
+        assert_eq!(run(&top).unwrap(), [Value::Int(i64::MAX - 1)]);
+        assert_eq!(seq.to_string(), "range(0, 10, 4)");
+    }
+
+    #[test]
+    fn test_fib_ends_before_overflow() {
+        let fib = Arc::new(Seq::Fib);
+        assert!(!fib.is_finite());
+        assert_eq!(
+            run(&fib).unwrap_err().to_string(),
+            "'test' needs a sequence with an end; limit it with take()"
+        );
+
+        let first = run(&Seq::Take(Arc::clone(&fib), 8)).unwrap();
+        assert_eq!(first, [0, 1, 1, 2, 3, 5, 8, 13].map(Value::Int));
+        let all = run(&Seq::Take(Arc::clone(&fib), FIB_TERMS)).unwrap();
+        assert_eq!(all.last(), Some(&Value::Int(4_660_046_610_375_530_309)));
+        assert_eq!(
+            run(&Seq::Take(fib, FIB_TERMS + 1)),
+            Err(EvalError::IntegerOverflow {
+                span: Span::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_known_len() {
+        let range = |start, end, step| Seq::Range { start, end, step };
+        assert_eq!(range(0, 10, 4).known_len(), Some(3));
+        assert_eq!(range(3, 0, -1).known_len(), Some(3));
+        assert_eq!(range(5, 5, 1).known_len(), Some(0));
+        assert_eq!(range(0, 5, -1).known_len(), Some(0));
+        assert_eq!(range(i64::MAX - 1, i64::MAX, 5).known_len(), Some(1));
+        assert_eq!(range(i64::MIN, i64::MAX, i64::MAX).known_len(), Some(3));
+        for seq in [range(0, 10, 4), range(10, -7, -3), range(-3, 3, 1)] {
+            assert_eq!(seq.known_len(), Some(run(&seq).unwrap().len()));
+        }
+
+        let fib = Arc::new(Seq::Fib);
+        assert_eq!(Seq::Take(Arc::clone(&fib), 5).known_len(), Some(5));
+        assert_eq!(Seq::Take(Arc::clone(&fib), FIB_TERMS + 1).known_len(), None);
+        assert_eq!(Seq::Take(Arc::new(range(0, 3, 1)), 5).known_len(), Some(3));
+        assert_eq!(fib.known_len(), None);
+    }
+
+    #[test]
+    fn test_collect_stops_past_max_len() {
+        // Room for one item past the limit, so reaching it means the
+        // rest were never generated
+        let meter = Meter::new(EvalLimits::none().max_len(1000).max_steps(1001));
+        let huge = Seq::Range {
+            start: 1,
+            end: 10_000_000_000,
+            step: 1,
+        };
+        let err = huge
+            .collect("collect", &Interpreter::new(), Span::default(), &meter)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EvalError::LimitExceeded {
+                limit: Limit::Len(1000),
+                ..
+            }
+        ));
+
+        let meter = Meter::new(EvalLimits::none().max_len(3));
+        let three = Seq::Take(Arc::new(Seq::Fib), 3);
+        let items = three.collect("collect", &Interpreter::new(), Span::default(), &meter);
+        assert_eq!(items.unwrap(), [0, 1, 1].map(Value::Int));
+    }
+
+    #[test]
+    fn test_totals() {
+        let span = Span::default();
+        let ints = [2, 3, 4].map(Value::Int);
+        assert_eq!(
+            total(BinOp::Add, ints.clone().map(Ok), span).unwrap(),
+            Value::Int(9)
+        );
+        assert_eq!(
+            total(BinOp::Mul, ints.map(Ok), span).unwrap(),
+            Value::Int(24)
+        );
+        assert_eq!(total(BinOp::Add, [], span).unwrap(), Value::Int(0));
+        assert_eq!(total(BinOp::Mul, [], span).unwrap(), Value::Int(1));
+
+        let mixed = [Value::Int(2), Value::Float(0.5), Value::Int(3)];
+        assert_eq!(
+            total(BinOp::Add, mixed.clone().map(Ok), span).unwrap(),
+            Value::Float(5.5)
+        );
+        assert_eq!(
+            total(BinOp::Mul, mixed.map(Ok), span).unwrap(),
+            Value::Float(3.0)
+        );
+
+        let prices = [1999, 1, 500].map(|cents| Value::Money(Money::new(cents)));
+        assert_eq!(
+            total(BinOp::Add, prices.map(Ok), span).unwrap(),
+            Value::Money(Money::new(2500))
+        );
+        let huge = [i64::MAX, 1].map(|cents| Value::Money(Money::new(cents)));
+        assert!(total(BinOp::Add, huge.map(Ok), span).is_err());
+        assert!(total(BinOp::Mul, [i64::MAX, 2].map(|n| Ok(Value::Int(n))), span).is_err());
+    }
+}
@@ -0,0 +1,650 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque, hash_map::Entry},
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+use p15_operator_index::Grid2D;
+
+use crate::{
+    ast::Expr,
+    error::SheetError,
+    eval::Interpreter,
+    lexer::{Span, Token, TokenKind},
+    limits::EvalLimits,
+    memo::MemoCache,
+    parser::{self, Parser},
+    registry::FunctionRegistry,
+    value::Value,
+};
+
+// ------------------------------------------------
+/// Zero-based position of a cell, written `B12`: column letters, then the
+/// row counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellRef {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl CellRef {
+    pub fn new(row: usize, col: usize) -> Self {
+        CellRef { row, col }
+    }
+
+    /// Parse a name like `B12`. Columns are uppercase, so `b12`, `A0` and
+    /// `log10` are not cells.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let split = name.find(|c: char| !c.is_ascii_uppercase())?;
+        let (letters, digits) = name.split_at(split);
+        if letters.is_empty()
+            || digits.starts_with('0')
+            || !digits.bytes().all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        // Bijective base 26: A..Z, then AA..AZ, BA..
+        let col = letters.bytes().try_fold(0usize, |acc, b| {
+            acc.checked_mul(26)?.checked_add(usize::from(b - b'A') + 1)
+        })?;
+        let row: usize = digits.parse().ok()?;
+        Some(CellRef::new(row - 1, col - 1))
+    }
+
+    fn index(self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+}
+
+impl Display for CellRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut letters = Vec::new();
+        let mut n = self.col + 1;
+        while n > 0 {
+            n -= 1;
+            letters.push(char::from(b'A' + (n % 26) as u8));
+            n /= 26;
+        }
+        let letters: String = letters.into_iter().rev().collect();
+        write!(f, "{}{}", letters, self.row + 1)
+    }
+}
+
+// ------------------------------------------------
+/// What was entered in a cell.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Content {
+    #[default]
+    Empty,
+    Value(Value),
+    Formula(Formula),
+}
+
+/// A parsed formula and the cells it reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    pub src: String,
+    pub expr: Expr,
+    pub refs: BTreeSet<CellRef>,
+}
+
+/// A cell's content and the value last computed from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub content: Content,
+    pub value: Result<Value, SheetError>,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            content: Content::Empty,
+            value: Ok(Value::Int(0)),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// A `Grid2D` of cells holding values or formulas over other cells, such
+/// as `A1 + B2` or `sum(A1:A10)`.
+///
+/// Editing a cell recomputes it and every cell depending on it, directly
+/// or not, each after all of its inputs; other cells are left alone.
+/// Empty cells read as 0. An edit that would create a reference cycle is
+/// rejected and leaves the sheet unchanged.
+#[derive(Debug, Clone)]
+pub struct Sheet {
+    cells: Grid2D<Cell>,
+    /// Cells whose formulas refer to the key
+    dependents: HashMap<CellRef, BTreeSet<CellRef>>,
+    /// Applied to each formula separately
+    limits: EvalLimits,
+    registry: Arc<FunctionRegistry>,
+    memo: Option<Arc<MemoCache>>,
+}
+
+impl Sheet {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Sheet {
+            cells: Grid2D::new(rows, cols, Cell::default()),
+            dependents: HashMap::new(),
+            limits: EvalLimits::default(),
+            registry: Arc::default(),
+            memo: None,
+        }
+    }
+
+    /// Evaluate every formula under `limits`; a formula that exceeds them
+    /// gets an error value like any other failing formula.
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Let formulas call the host functions in `registry`.
+    pub fn with_registry(mut self, registry: impl Into<Arc<FunctionRegistry>>) -> Self {
+        self.registry = registry.into();
+        self
+    }
+
+    /// Remember formula results in `memo`, so recomputing a formula whose
+    /// inputs have held the same values before is a lookup.
+    pub fn with_memo(mut self, memo: Arc<MemoCache>) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    pub fn rows(&self) -> usize {
+        self.cells.rows()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cells.cols()
+    }
+
+    pub fn cell(&self, at: CellRef) -> Option<&Cell> {
+        self.cells.get(at.row, at.col)
+    }
+
+    /// The value of the cell at `at`, unless it is outside the grid or
+    /// failed to compute.
+    pub fn value(&self, at: CellRef) -> Option<&Value> {
+        self.cell(at)?.value.as_ref().ok()
+    }
+
+    /// Cells whose formulas refer to `at` directly.
+    pub fn dependents(&self, at: CellRef) -> impl Iterator<Item = CellRef> + '_ {
+        self.dependents.get(&at).into_iter().flatten().copied()
+    }
+
+    /// Store a plain value. Like the other edits, returns the cells it
+    /// recomputed in evaluation order.
+    pub fn set_value(
+        &mut self,
+        at: CellRef,
+        value: impl Into<Value>,
+    ) -> Result<Vec<CellRef>, SheetError> {
+        self.set(at, Content::Value(value.into()))
+    }
+
+    /// Parse `src` as a formula and store it, unless it is invalid or
+    /// would close a reference cycle.
+    pub fn set_formula(&mut self, at: CellRef, src: &str) -> Result<Vec<CellRef>, SheetError> {
+        let formula = self.parse_formula(at, src)?;
+        self.set(at, Content::Formula(formula))
+    }
+
+    pub fn clear(&mut self, at: CellRef) -> Result<Vec<CellRef>, SheetError> {
+        self.set(at, Content::Empty)
+    }
+
+    fn set(&mut self, at: CellRef, content: Content) -> Result<Vec<CellRef>, SheetError> {
+        self.check_in_range(at)?;
+        let refs = match &content {
+            Content::Formula(formula) => formula.refs.clone(),
+            _ => BTreeSet::new(),
+        };
+        for &input in &refs {
+            self.check_in_range(input)?;
+        }
+        if let Some(path) = self.find_cycle(at, &refs) {
+            return Err(SheetError::Cycle { path });
+        }
+
+        let old_refs: Vec<CellRef> = self.refs(at).collect();
+        for input in old_refs {
+            if let Some(dependents) = self.dependents.get_mut(&input) {
+                dependents.remove(&at);
+            }
+        }
+        for &input in &refs {
+            self.dependents.entry(input).or_default().insert(at);
+        }
+        self.cells[at.index()].content = content;
+
+        let order = self.recompute_order(at);
+        for &cell in &order {
+            self.cells[cell.index()].value = self.compute(cell);
+        }
+        Ok(order)
+    }
+
+    fn check_in_range(&self, cell: CellRef) -> Result<(), SheetError> {
+        match self.cell(cell) {
+            Some(_) => Ok(()),
+            None => Err(SheetError::OutOfRange { cell }),
+        }
+    }
+
+    /// Cells the formula at `at` refers to; none for other content.
+    fn refs(&self, at: CellRef) -> impl Iterator<Item = CellRef> + '_ {
+        let refs = match self.cell(at).map(|cell| &cell.content) {
+            Some(Content::Formula(formula)) => Some(formula.refs.iter().copied()),
+            _ => None,
+        };
+        refs.into_iter().flatten()
+    }
+
+    /// The chain of references that giving `at` the references `refs`
+    /// would close into a cycle, if any.
+    fn find_cycle(&self, at: CellRef, refs: &BTreeSet<CellRef>) -> Option<Vec<CellRef>> {
+        // The cell that refers to each visited cell, for rebuilding the path
+        let mut referrer: HashMap<CellRef, CellRef> = HashMap::new();
+        let mut stack = Vec::new();
+        for &input in refs {
+            referrer.insert(input, at);
+            stack.push(input);
+        }
+
+        while let Some(cell) = stack.pop() {
+            if cell == at {
+                let mut path = vec![at];
+                let mut current = referrer[&at];
+                while current != at {
+                    path.push(current);
+                    current = referrer[&current];
+                }
+                path.push(at);
+                path.reverse();
+                return Some(path);
+            }
+            for input in self.refs(cell) {
+                if let Entry::Vacant(entry) = referrer.entry(input) {
+                    entry.insert(cell);
+                    stack.push(input);
+                }
+            }
+        }
+        None
+    }
+
+    /// `at` and every cell depending on it, each after all of its inputs.
+    fn recompute_order(&self, at: CellRef) -> Vec<CellRef> {
+        let mut affected = BTreeSet::from([at]);
+        let mut stack = vec![at];
+        while let Some(cell) = stack.pop() {
+            for dependent in self.dependents(cell) {
+                if affected.insert(dependent) {
+                    stack.push(dependent);
+                }
+            }
+        }
+
+        // Kahn's algorithm: a cell is ready once none of its inputs are pending
+        let mut waiting: HashMap<CellRef, usize> = affected
+            .iter()
+            .map(|&cell| {
+                let inputs = self.refs(cell).filter(|input| affected.contains(input));
+                (cell, inputs.count())
+            })
+            .collect();
+        let mut ready = VecDeque::from([at]);
+        let mut order = Vec::with_capacity(affected.len());
+        while let Some(cell) = ready.pop_front() {
+            order.push(cell);
+            for dependent in self.dependents(cell) {
+                let count = waiting
+                    .get_mut(&dependent)
+                    .expect("dependents are affected");
+                *count -= 1;
+                if *count == 0 {
+                    ready.push_back(dependent);
+                }
+            }
+        }
+        order
+    }
+
+    fn compute(&self, at: CellRef) -> Result<Value, SheetError> {
+        let formula = match &self.cells[at.index()].content {
+            Content::Empty => return Ok(Value::Int(0)),
+            Content::Value(value) => return Ok(value.clone()),
+            Content::Formula(formula) => formula,
+        };
+
+        let mut interp = Interpreter::new()
+            .with_limits(self.limits)
+            .with_registry(Arc::clone(&self.registry));
+        if let Some(memo) = &self.memo {
+            interp = interp.with_memo(Arc::clone(memo));
+        }
+        for &input in &formula.refs {
+            match &self.cells[input.index()].value {
+                Ok(value) => interp.set_var(input.to_string(), value.clone()),
+                Err(_) => return Err(SheetError::BadInput { cell: at, input }),
+            }
+        }
+        interp
+            .eval(&formula.expr)
+            .map_err(|source| SheetError::Eval { cell: at, source })
+    }
+
+    fn parse_formula(&self, at: CellRef, src: &str) -> Result<Formula, SheetError> {
+        let (tokens, errors) = parser::lex(src);
+        let tokens = self.expand_ranges(tokens)?;
+        let eof = Span::new(src.len(), src.len());
+        let expr = Parser::from_tokens(tokens, errors, eof)
+            .with_max_nesting(self.limits.nesting())
+            .parse_all()
+            .map_err(|errors| SheetError::Parse { cell: at, errors })?;
+
+        // Only free variables are references; `let A1 = 2 in A1` reads nothing
+        let refs = expr
+            .names()
+            .iter()
+            .filter(|name| expr.depends_on(name))
+            .filter_map(|name| CellRef::from_name(name))
+            .collect();
+        Ok(Formula {
+            src: src.to_string(),
+            expr,
+            refs,
+        })
+    }
+
+    /// Rewrite each range `A1:B2` into `list(A1, B1, A2, B2)`, row by row.
+    /// Every token of the expansion carries the span of the whole range,
+    /// so errors point at it. Both corners must be inside the grid.
+    fn expand_ranges(&self, tokens: Vec<Token>) -> Result<Vec<Token>, SheetError> {
+        let mut expanded = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            let Some((from, to)) = range_at(&tokens[i..]) else {
+                expanded.push(tokens[i].clone());
+                i += 1;
+                continue;
+            };
+            self.check_in_range(from)?;
+            self.check_in_range(to)?;
+
+            let span = tokens[i].span.to(tokens[i + 2].span);
+            let token = |kind| Token::new(kind, span);
+            expanded.push(token(TokenKind::Ident("list".to_string())));
+            expanded.push(token(TokenKind::LParen));
+            let rows = from.row.min(to.row)..=from.row.max(to.row);
+            let cols = from.col.min(to.col)..=from.col.max(to.col);
+            for (n, row) in rows.enumerate() {
+                for (m, col) in cols.clone().enumerate() {
+                    if n + m > 0 {
+                        expanded.push(token(TokenKind::Comma));
+                    }
+                    let name = CellRef::new(row, col).to_string();
+                    expanded.push(token(TokenKind::Ident(name)));
+                }
+            }
+            expanded.push(token(TokenKind::RParen));
+            i += 3;
+        }
+        Ok(expanded)
+    }
+}
+
+/// The corners of a range starting at the front of `tokens`.
+fn range_at(tokens: &[Token]) -> Option<(CellRef, CellRef)> {
+    match tokens {
+        [from, colon, to, ..] if colon.kind == TokenKind::Colon => match (&from.kind, &to.kind) {
+            (TokenKind::Ident(from), TokenKind::Ident(to)) => {
+                Some((CellRef::from_name(from)?, CellRef::from_name(to)?))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EvalError;
+
+    fn at(name: &str) -> CellRef {
+        CellRef::from_name(name).unwrap()
+    }
+
+    fn cells(names: &[&str]) -> Vec<CellRef> {
+        names.iter().map(|name| at(name)).collect()
+    }
+
+    #[test]
+    fn test_cell_names() {
+        for (name, row, col) in [
+            ("A1", 0, 0),
+            ("Z9", 8, 25),
+            ("AA10", 9, 26),
+            ("AZ1", 0, 51),
+            ("BA3", 2, 52),
+        ] {
+            let cell = CellRef::from_name(name).unwrap();
+            assert_eq!(cell, CellRef::new(row, col), "{}", name);
+            assert_eq!(cell.to_string(), name);
+        }
+        for name in ["a1", "A0", "A01", "A", "12", "A1B", "log10", ""] {
+            assert_eq!(CellRef::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_values_and_formulas() {
+        let mut sheet = Sheet::new(10, 5);
+        sheet.set_value(at("A1"), 2).unwrap();
+        sheet.set_formula(at("B1"), "A1 * 3 + C1").unwrap();
+        assert_eq!(sheet.value(at("B1")), Some(&Value::Int(6)));
+        assert_eq!(sheet.value(at("C1")), Some(&Value::Int(0)));
+
+        sheet.set_value(at("C1"), 0.5).unwrap();
+        assert_eq!(sheet.value(at("B1")), Some(&Value::Float(6.5)));
+
+        sheet.clear(at("A1")).unwrap();
+        assert_eq!(sheet.value(at("B1")), Some(&Value::Float(0.5)));
+        assert_eq!(sheet.cell(at("A1")).unwrap().content, Content::Empty);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut sheet = Sheet::new(10, 3);
+        for row in 0..4 {
+            sheet
+                .set_value(CellRef::new(row, 0), row as i64 + 1)
+                .unwrap();
+        }
+        sheet.set_formula(at("A5"), "sum(A1:A4)").unwrap();
+        assert_eq!(sheet.value(at("A5")), Some(&Value::Int(10)));
+
+        // Corners in either order; blocks are read row by row
+        sheet.set_formula(at("B1"), "A2:A1").unwrap();
+        assert_eq!(sheet.value(at("B1")).unwrap().to_string(), "[1, 2]");
+        sheet
+            .set_formula(at("C1"), "len(A1:B3) + sum(A3:A4)")
+            .unwrap();
+        assert_eq!(sheet.value(at("C1")), Some(&Value::Int(13)));
+
+        sheet.set_value(at("A4"), 10).unwrap();
+        assert_eq!(sheet.value(at("A5")), Some(&Value::Int(16)));
+        assert_eq!(sheet.value(at("C1")), Some(&Value::Int(19)));
+    }
+
+    #[test]
+    fn test_recomputes_only_dependents_in_order() {
+        let mut sheet = Sheet::new(5, 5);
+        sheet.set_value(at("A1"), 1).unwrap();
+        // A3 comes before A2 in the grid but must be computed first
+        sheet.set_formula(at("A3"), "A1 + 1").unwrap();
+        sheet.set_formula(at("A2"), "A3 * 2").unwrap();
+        sheet.set_formula(at("B1"), "A1 + A2 + A3").unwrap();
+        sheet.set_formula(at("C1"), "5 * 2").unwrap();
+
+        let order = sheet.set_value(at("A1"), 10).unwrap();
+        assert_eq!(order, cells(&["A1", "A3", "A2", "B1"]));
+        assert_eq!(sheet.value(at("B1")), Some(&Value::Int(43)));
+
+        // Editing a leaf touches nothing else
+        assert_eq!(sheet.set_formula(at("C1"), "B1").unwrap(), cells(&["C1"]));
+        let dependents: Vec<CellRef> = sheet.dependents(at("A3")).collect();
+        assert_eq!(dependents, cells(&["B1", "A2"]));
+    }
+
+    #[test]
+    fn test_replacing_a_formula_drops_old_references() {
+        let mut sheet = Sheet::new(3, 3);
+        sheet.set_formula(at("B1"), "A1 + 1").unwrap();
+        sheet.set_formula(at("B1"), "C1 + 1").unwrap();
+        assert_eq!(sheet.set_value(at("A1"), 5).unwrap(), cells(&["A1"]));
+        assert_eq!(sheet.set_value(at("C1"), 5).unwrap(), cells(&["C1", "B1"]));
+
+        // Bound names are not references
+        sheet
+            .set_formula(at("B2"), "let A1 = 2 in A1 * C1")
+            .unwrap();
+        assert_eq!(sheet.value(at("B2")), Some(&Value::Int(10)));
+        assert_eq!(sheet.dependents(at("A1")).count(), 0);
+    }
+
+    #[test]
+    fn test_cycles_are_rejected() {
+        let mut sheet = Sheet::new(3, 3);
+        sheet.set_formula(at("A1"), "B1").unwrap();
+        sheet.set_formula(at("B1"), "C1 + 1").unwrap();
+
+        let err = sheet.set_formula(at("C1"), "A1 * 2").unwrap_err();
+        assert_eq!(
+            err,
+            SheetError::Cycle {
+                path: cells(&["C1", "A1", "B1", "C1"])
+            }
+        );
+        assert_eq!(err.to_string(), "reference cycle: C1 -> A1 -> B1 -> C1");
+        // The sheet is unchanged and still works
+        assert_eq!(sheet.cell(at("C1")).unwrap().content, Content::Empty);
+        assert_eq!(
+            sheet.set_value(at("C1"), 1).unwrap(),
+            cells(&["C1", "B1", "A1"])
+        );
+        assert_eq!(sheet.value(at("A1")), Some(&Value::Int(2)));
+
+        let err = sheet.set_formula(at("C3"), "sum(A3:C3)").unwrap_err();
+        assert_eq!(err.to_string(), "reference cycle: C3 -> C3");
+    }
+
+    #[test]
+    fn test_errors_propagate_and_recover() {
+        let mut sheet = Sheet::new(3, 3);
+        sheet.set_formula(at("B1"), "1 / A1").unwrap();
+        sheet.set_formula(at("C1"), "B1 + 1").unwrap();
+        assert!(matches!(
+            sheet.cell(at("B1")).unwrap().value,
+            Err(SheetError::Eval {
+                source: EvalError::DivisionByZero { .. },
+                ..
+            })
+        ));
+        let err = sheet.cell(at("C1")).unwrap().value.clone().unwrap_err();
+        assert_eq!(err.to_string(), "C1 depends on B1, which has an error");
+        assert_eq!(sheet.value(at("C1")), None);
+
+        sheet.set_value(at("A1"), 4).unwrap();
+        assert_eq!(sheet.value(at("C1")), Some(&Value::Float(1.25)));
+    }
+
+    #[test]
+    fn test_limits_apply_per_formula() {
+        let mut sheet = Sheet::new(3, 3).with_limits(EvalLimits::none().max_len(4));
+        sheet.set_value(at("A1"), "ab").unwrap();
+        sheet.set_formula(at("B1"), "A1 + A1").unwrap();
+        sheet.set_formula(at("C1"), "B1 + A1").unwrap();
+        assert_eq!(sheet.value(at("B1")), Some(&Value::from("abab")));
+        assert!(matches!(
+            sheet.cell(at("C1")).unwrap().value,
+            Err(SheetError::Eval {
+                source: EvalError::LimitExceeded { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_registered_functions() {
+        let mut registry = FunctionRegistry::new();
+        registry.register("tax_rate", |region: String| match region.as_str() {
+            "eu" => 0.25,
+            _ => 0.0,
+        });
+        let mut sheet = Sheet::new(2, 2).with_registry(registry);
+        sheet.set_value(at("A1"), 100).unwrap();
+        sheet.set_value(at("B1"), "eu").unwrap();
+        sheet
+            .set_formula(at("A2"), "A1 * (1 + tax_rate(B1))")
+            .unwrap();
+        assert_eq!(sheet.value(at("A2")), Some(&Value::Float(125.0)));
+    }
+
+    #[test]
+    fn test_memoized_recalculation() {
+        let memo = Arc::new(MemoCache::default());
+        let mut sheet = Sheet::new(3, 2).with_memo(Arc::clone(&memo));
+        sheet.set_value(at("A1"), 20).unwrap();
+        sheet.set_value(at("B1"), 1).unwrap();
+        sheet
+            .set_formula(at("A2"), "fold(range(0, A1), 0, |acc, i| acc + i * i)")
+            .unwrap();
+        sheet.set_formula(at("A3"), "A2 + B1").unwrap();
+        assert_eq!(sheet.value(at("A3")), Some(&Value::Int(2471)));
+
+        sheet.set_value(at("A1"), 10).unwrap();
+        let hits = memo.stats().hits;
+        sheet.set_value(at("A1"), 20).unwrap();
+        assert_eq!(memo.stats().hits, hits + 2, "A2 and A3 both seen before");
+        assert_eq!(sheet.value(at("A3")), Some(&Value::Int(2471)));
+        sheet.set_value(at("B1"), 2).unwrap();
+        assert_eq!(sheet.value(at("A3")), Some(&Value::Int(2472)));
+    }
+
+    #[test]
+    fn test_rejected_edits() {
+        let mut sheet = Sheet::new(10, 5);
+        let err = sheet.set_formula(at("A1"), "1 + * 2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot parse the formula in A1: expected operand, found `*`"
+        );
+        assert!(std::error::Error::source(&err).is_some());
+
+        let err = sheet.set_formula(at("A1"), "Z99 + 1").unwrap_err();
+        assert_eq!(err, SheetError::OutOfRange { cell: at("Z99") });
+        assert_eq!(err.to_string(), "cell Z99 is outside the sheet");
+        let err = sheet.set_formula(at("A1"), "sum(A1:A1000000)").unwrap_err();
+        assert_eq!(
+            err,
+            SheetError::OutOfRange {
+                cell: at("A1000000")
+            }
+        );
+        assert!(sheet.set_value(at("F1"), 1).is_err());
+
+        // A colon outside a range is still a syntax error
+        assert!(matches!(
+            sheet.set_formula(at("A1"), "x:A2").unwrap_err(),
+            SheetError::Parse { .. }
+        ));
+    }
+}
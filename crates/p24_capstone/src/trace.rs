@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use p07_drop_destructor::TimerGuard;
+
+use crate::{
+    ast::{Expr, ExprKind},
+    error::EvalError,
+    lexer::Span,
+    value::Value,
+};
+
+// ------------------------------------------------
+/// Notified around every node the interpreter evaluates: `enter`, then
+/// the node's subexpressions and the bodies of any functions it calls,
+/// then `exit` with the node's result, even when that is an error.
+///
+/// Clones of an interpreter share its observer and may run on several
+/// threads, so observers take `&self`. Only the tree walker reports
+/// nodes; `CompiledExpr` has none to report.
+pub trait Observer: Debug + Send + Sync {
+    fn enter(&self, expr: &Expr);
+    fn exit(&self, expr: &Expr, result: Result<&Value, &EvalError>);
+}
+
+// ------------------------------------------------
+/// One evaluated node of a trace.
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    /// The node printed as source
+    pub expr: String,
+    pub span: Span,
+    /// The function called, for calls
+    pub function: Option<String>,
+    /// Values of the node's own subexpressions, in the order evaluated
+    pub inputs: Vec<Value>,
+    pub output: Result<Value, EvalError>,
+    pub elapsed: Duration,
+    /// Nodes evaluated meanwhile: the subexpressions, and the bodies of
+    /// functions called
+    pub children: Vec<TraceNode>,
+}
+
+impl TraceNode {
+    fn write_tree(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        write!(f, "{:indent$}{} => ", "", self.expr, indent = depth * 2)?;
+        match &self.output {
+            Ok(value) => write!(f, "{}", value)?,
+            Err(e) => write!(f, "error: {}", e)?,
+        }
+        if f.alternate() {
+            write!(f, " ({:?})", self.elapsed)?;
+        }
+        for child in &self.children {
+            writeln!(f)?;
+            child.write_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line per node, `expr => output`, indented two spaces per level;
+/// the alternate form `{:#}` adds each node's time.
+impl Display for TraceNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+// ------------------------------------------------
+/// An `Observer` recording each top-level evaluation as a tree of
+/// `TraceNode`s, with every node timed by p07's `TimerGuard`.
+///
+/// Nodes are recorded one evaluation at a time; evaluations running at
+/// once on several threads would interleave theirs.
+#[derive(Default)]
+pub struct Tracer {
+    state: Mutex<TraceState>,
+}
+
+#[derive(Default)]
+struct TraceState {
+    /// Nodes entered but not yet exited, innermost last
+    open: Vec<OpenNode>,
+    finished: Vec<TraceNode>,
+}
+
+struct OpenNode {
+    expr: String,
+    span: Span,
+    function: Option<String>,
+    /// Addresses of the node's subexpressions, which tell its inputs apart
+    /// from the other nodes evaluated meanwhile
+    operands: Vec<usize>,
+    inputs: Vec<Value>,
+    children: Vec<TraceNode>,
+    timer: TimerGuard,
+}
+
+/// Identity of a node in the tree being evaluated; the tree is borrowed
+/// for the whole evaluation, so nodes stay put.
+fn address(expr: &Expr) -> usize {
+    expr as *const Expr as usize
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Tracer::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, TraceState> {
+        // A panicking observer callback leaves nothing half-updated
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// One tree per evaluation finished so far, oldest first.
+    pub fn traces(&self) -> Vec<TraceNode> {
+        self.state().finished.clone()
+    }
+
+    /// The traces so far, leaving none recorded.
+    pub fn take(&self) -> Vec<TraceNode> {
+        std::mem::take(&mut self.state().finished)
+    }
+
+    /// Time per function over the traces so far.
+    pub fn profile(&self) -> Profile {
+        Profile::of(&self.state().finished)
+    }
+}
+
+impl Observer for Tracer {
+    fn enter(&self, expr: &Expr) {
+        let function = match &expr.kind {
+            ExprKind::Call { name, .. } => Some(name.clone()),
+            ExprKind::Apply { callee, .. } => Some(callee.to_string()),
+            _ => None,
+        };
+        let text = expr.to_string();
+        let operands = expr.children().into_iter().map(address).collect();
+        self.state().open.push(OpenNode {
+            timer: TimerGuard::quiet(&text),
+            expr: text,
+            span: expr.span,
+            function,
+            operands,
+            inputs: Vec::new(),
+            children: Vec::new(),
+        });
+    }
+
+    fn exit(&self, expr: &Expr, result: Result<&Value, &EvalError>) {
+        let mut state = self.state();
+        let Some(open) = state.open.pop() else {
+            return;
+        };
+        let node = TraceNode {
+            elapsed: open.timer.elapsed(),
+            expr: open.expr,
+            span: open.span,
+            function: open.function,
+            inputs: open.inputs,
+            output: result.cloned().map_err(EvalError::clone),
+            children: open.children,
+        };
+        match state.open.last_mut() {
+            Some(parent) => {
+                if let Ok(value) = result
+                    && parent.operands.contains(&address(expr))
+                {
+                    parent.inputs.push(value.clone());
+                }
+                parent.children.push(node);
+            }
+            None => state.finished.push(node),
+        }
+    }
+}
+
+impl Debug for Tracer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("traces", &self.state().finished.len())
+            .finish_non_exhaustive()
+    }
+}
+
+// ------------------------------------------------
+/// Calls and time per function, busiest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub entries: Vec<ProfileEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileEntry {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    /// Time inside the calls, including functions they call in turn; a
+    /// recursive function counts its inner calls again
+    pub total: Duration,
+    /// Time inside the calls but outside any call nested in them
+    pub own: Duration,
+}
+
+impl Profile {
+    /// Add up the call nodes in `traces`.
+    pub fn of(traces: &[TraceNode]) -> Profile {
+        let mut entries = HashMap::new();
+        for trace in traces {
+            Profile::visit(trace, &mut entries);
+        }
+        let mut entries: Vec<ProfileEntry> = entries.into_values().collect();
+        entries.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+        Profile { entries }
+    }
+
+    /// Record the calls under `node`; returns the time spent in the
+    /// outermost ones.
+    fn visit(node: &TraceNode, entries: &mut HashMap<String, ProfileEntry>) -> Duration {
+        let nested: Duration = node
+            .children
+            .iter()
+            .map(|child| Profile::visit(child, entries))
+            .sum();
+        let Some(name) = &node.function else {
+            return nested;
+        };
+        let entry = entries.entry(name.clone()).or_insert_with(|| ProfileEntry {
+            name: name.clone(),
+            calls: 0,
+            errors: 0,
+            total: Duration::ZERO,
+            own: Duration::ZERO,
+        });
+        entry.calls += 1;
+        entry.errors += u64::from(node.output.is_err());
+        entry.total += node.elapsed;
+        entry.own += node.elapsed.saturating_sub(nested);
+        node.elapsed
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProfileEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+/// A table with a header row and one row per function.
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {:>8} {:>8} {:>12} {:>12}",
+            "function", "calls", "errors", "total", "own"
+        )?;
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:<16} {:>8} {:>8} {:>12} {:>12}",
+                entry.name,
+                entry.calls,
+                entry.errors,
+                format!("{:.1?}", entry.total),
+                format!("{:.1?}", entry.own)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::eval::Interpreter;
+
+    fn trace(src: &str) -> (Result<Value, crate::CalcError>, Arc<Tracer>) {
+        let tracer = Arc::new(Tracer::new());
+        let mut interp = Interpreter::new().with_observer(tracer.clone());
+        interp.exec_str("fn sq(x) = x * x").unwrap();
+        (interp.eval_str(src), tracer)
+    }
+
+    #[test]
+    fn test_trace_tree() {
+        let (result, tracer) = trace("sq(1 + 2) - 4");
+        assert_eq!(result.unwrap(), 5);
+        let traces = tracer.take();
+        assert_eq!(traces.len(), 1, "the definition evaluates no nodes");
+        assert_eq!(
+            traces[0].to_string(),
+            "sq(1 + 2) - 4 => 5\n\
+             \x20 sq(1 + 2) => 9\n\
+             \x20   1 + 2 => 3\n\
+             \x20     1 => 1\n\
+             \x20     2 => 2\n\
+             \x20   x * x => 9\n\
+             \x20     x => 3\n\
+             \x20     x => 3\n\
+             \x20 4 => 4"
+        );
+        // The body of `sq` is nested, but only the argument is an input
+        let call = &traces[0].children[0];
+        assert_eq!(call.function.as_deref(), Some("sq"));
+        assert_eq!(call.inputs, [Value::Int(3)]);
+        assert_eq!(traces[0].inputs, [Value::Int(9), Value::Int(4)]);
+        assert!(traces[0].elapsed >= call.elapsed);
+        assert!(format!("{:#}", traces[0]).starts_with("sq(1 + 2) - 4 => 5 ("));
+        assert!(tracer.traces().is_empty());
+    }
+
+    #[test]
+    fn test_trace_records_errors() {
+        let (result, tracer) = trace("1 + sq(true)");
+        assert!(result.is_err());
+        let traces = tracer.traces();
+        assert_eq!(
+            traces[0].to_string(),
+            "1 + sq(true) => error: type mismatch: expected number, found bool\n\
+             \x20 1 => 1\n\
+             \x20 sq(true) => error: type mismatch: expected number, found bool\n\
+             \x20   true => true\n\
+             \x20   x * x => error: type mismatch: expected number, found bool\n\
+             \x20     x => true\n\
+             \x20     x => true"
+        );
+        assert_eq!(traces[0].inputs, [Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_profile() {
+        let (result, tracer) = trace("sum(map(list(1, 2, 3), |n| sq(n))) + sq(sqrt(4))");
+        assert_eq!(result.unwrap(), 18.0);
+        let profile = tracer.profile();
+        let names: Vec<&str> = {
+            let mut names: Vec<&str> = profile.entries.iter().map(|e| e.name.as_str()).collect();
+            names.sort_unstable();
+            names
+        };
+        assert_eq!(names, ["list", "map", "sq", "sqrt", "sum"]);
+        let sq = profile.get("sq").unwrap();
+        assert_eq!((sq.calls, sq.errors), (4, 0));
+        assert!(sq.own <= sq.total);
+        let map = profile.get("map").unwrap();
+        assert!(map.total >= map.own, "map's time includes the calls to sq");
+        assert!(profile.to_string().starts_with("function"));
+        assert_eq!(profile.to_string().lines().count(), 6);
+    }
+}
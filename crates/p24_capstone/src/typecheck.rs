@@ -0,0 +1,1410 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Display, Formatter},
+    mem,
+    sync::Arc,
+};
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, Pattern, Stmt, UnaryOp},
+    env::Bindings,
+    error::TypeError,
+    eval::{Interpreter, numeric_builtin},
+    lexer::Span,
+    registry::FunctionRegistry,
+    value::{Lambda, Value},
+};
+
+// ------------------------------------------------
+/// Static type of an expression.
+///
+/// Ints and floats are both `Number`, since they mix freely at runtime.
+/// `Var`s stand for types not pinned down yet; in a finished type they
+/// mean "any type", and print as `a`, `b`, ...
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Number,
+    Bool,
+    Str,
+    Vector,
+    Quantity,
+    Money,
+    List(Box<Type>),
+    /// Field names and their types; records of the same fields are one type
+    Record(BTreeMap<String, Type>),
+    Function(Vec<Type>, Box<Type>),
+    Var(u32),
+}
+
+impl Type {
+    pub fn list(item: Type) -> Self {
+        Type::List(Box::new(item))
+    }
+
+    pub fn function(params: Vec<Type>, ret: Type) -> Self {
+        Type::Function(params, Box::new(ret))
+    }
+
+    /// The type of a runtime value. Functions, empty lists and sequences
+    /// get type variables, which `TypeChecker::declare` makes generic;
+    /// `TypeChecker::from_interpreter` infers functions' types from their
+    /// definitions instead.
+    ///
+    /// Sequences are lists as far as types go; only when their items are
+    /// produced differs.
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Int(_) | Value::Float(_) | Value::Rational(_) | Value::Complex(_) => {
+                Type::Number
+            }
+            Value::Bool(_) => Type::Bool,
+            Value::Str(_) => Type::Str,
+            Value::Vector(_) => Type::Vector,
+            Value::Quantity(_) => Type::Quantity,
+            Value::Money(_) => Type::Money,
+            Value::List(items) => Type::list(items.first().map_or(Type::Var(0), Type::of)),
+            Value::Seq(_) => Type::list(Type::Var(0)),
+            Value::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Type::of(value)))
+                    .collect(),
+            ),
+            Value::Function(lambda) => {
+                let arity = lambda.params.len() as u32;
+                Type::function((0..arity).map(Type::Var).collect(), Type::Var(arity))
+            }
+        }
+    }
+
+    /// Rename type variables to `a`, `b`, ... in order of appearance.
+    pub fn normalized(&self) -> Type {
+        let mut names = HashMap::new();
+        self.map_vars(&mut |id| {
+            let next = names.len() as u32;
+            Type::Var(*names.entry(id).or_insert(next))
+        })
+    }
+
+    fn map_vars(&self, f: &mut impl FnMut(u32) -> Type) -> Type {
+        match self {
+            Type::List(item) => Type::list(item.map_vars(f)),
+            Type::Record(fields) => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, ty)| (name.clone(), ty.map_vars(f)))
+                    .collect(),
+            ),
+            Type::Function(params, ret) => Type::function(
+                params.iter().map(|param| param.map_vars(f)).collect(),
+                ret.map_vars(f),
+            ),
+            Type::Var(id) => f(*id),
+            other => other.clone(),
+        }
+    }
+
+    fn vars(&self, out: &mut Vec<u32>) {
+        match self {
+            Type::List(item) => item.vars(out),
+            Type::Record(fields) => fields.values().for_each(|ty| ty.vars(out)),
+            Type::Function(params, ret) => {
+                params.iter().for_each(|param| param.vars(out));
+                ret.vars(out);
+            }
+            Type::Var(id) if !out.contains(id) => out.push(*id),
+            _ => {}
+        }
+    }
+
+    fn kind(&self) -> Option<Kind> {
+        let kind = match self {
+            Type::Number => Kind::Number,
+            Type::Bool => Kind::Bool,
+            Type::Str => Kind::Str,
+            Type::Vector => Kind::Vector,
+            Type::Quantity => Kind::Quantity,
+            Type::Money => Kind::Money,
+            Type::List(_) => Kind::List,
+            Type::Record(_) => Kind::Record,
+            Type::Function(..) => Kind::Function,
+            Type::Var(_) => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Lists as `[number]`, records as `{ price: number }`, functions as
+/// `(number, bool) -> string`.
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "string"),
+            Type::Vector => write!(f, "vector"),
+            Type::Quantity => write!(f, "quantity"),
+            Type::Money => write!(f, "money"),
+            Type::List(item) => write!(f, "[{}]", item),
+            Type::Record(fields) if fields.is_empty() => write!(f, "{{}}"),
+            Type::Record(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, ty)| format!("{}: {}", name, ty))
+                    .collect();
+                write!(f, "{{ {} }}", fields.join(", "))
+            }
+            Type::Function(params, ret) => match params.as_slice() {
+                [param] if !matches!(param, Type::Function(..)) => {
+                    write!(f, "{} -> {}", param, ret)
+                }
+                _ => {
+                    let params: Vec<String> = params.iter().map(Type::to_string).collect();
+                    write!(f, "({}) -> {}", params.join(", "), ret)
+                }
+            },
+            Type::Var(id @ 0..26) => write!(f, "{}", (b'a' + *id as u8) as char),
+            Type::Var(id) => write!(f, "t{}", id),
+        }
+    }
+}
+
+/// Outermost constructor of a type, for operator rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Bool,
+    Str,
+    Vector,
+    Quantity,
+    Money,
+    List,
+    Record,
+    Function,
+}
+
+const ADDABLE: &[Kind] = &[
+    Kind::Number,
+    Kind::Str,
+    Kind::Vector,
+    Kind::Quantity,
+    Kind::Money,
+];
+const SUBTRACTABLE: &[Kind] = &[Kind::Number, Kind::Vector, Kind::Quantity, Kind::Money];
+const ORDERED: &[Kind] = &[Kind::Number, Kind::Str, Kind::Quantity, Kind::Money];
+const REMAINDER: &[Kind] = &[Kind::Number, Kind::Quantity];
+const SIZED: &[Kind] = &[Kind::Str, Kind::List];
+
+// ------------------------------------------------
+/// A requirement on types that may not be known yet when it arises, so
+/// it is kept until they are.
+#[derive(Debug, Clone)]
+enum Constraint {
+    /// The first operand's type must be one of `allowed`; the operands
+    /// are already unified with each other
+    OneOf {
+        op: &'static str,
+        operands: Vec<Type>,
+        allowed: &'static [Kind],
+        span: Span,
+    },
+    /// `result` is the type of `lhs * rhs` or `lhs / rhs`, which depends
+    /// on both operand types
+    Scale {
+        op: BinOp,
+        lhs: Type,
+        rhs: Type,
+        result: Type,
+        span: Span,
+    },
+    /// `result` is the type of field `name` of `record`
+    Field {
+        record: Type,
+        name: String,
+        result: Type,
+        span: Span,
+    },
+    /// `result` comes from `*` or `/` between quantities: a quantity, or
+    /// a number when the dimensions cancel
+    Measure { result: Type, span: Span },
+}
+
+impl Constraint {
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Constraint::OneOf { span, .. }
+            | Constraint::Scale { span, .. }
+            | Constraint::Field { span, .. }
+            | Constraint::Measure { span, .. } => span,
+        }
+    }
+
+    fn map_types(&self, f: &mut impl FnMut(&Type) -> Type) -> Constraint {
+        match self {
+            Constraint::OneOf {
+                op,
+                operands,
+                allowed,
+                span,
+            } => Constraint::OneOf {
+                op,
+                operands: operands.iter().map(&mut *f).collect(),
+                allowed,
+                span: *span,
+            },
+            Constraint::Scale {
+                op,
+                lhs,
+                rhs,
+                result,
+                span,
+            } => Constraint::Scale {
+                op: *op,
+                lhs: f(lhs),
+                rhs: f(rhs),
+                result: f(result),
+                span: *span,
+            },
+            Constraint::Field {
+                record,
+                name,
+                result,
+                span,
+            } => Constraint::Field {
+                record: f(record),
+                name: name.clone(),
+                result: f(result),
+                span: *span,
+            },
+            Constraint::Measure { result, span } => Constraint::Measure {
+                result: f(result),
+                span: *span,
+            },
+        }
+    }
+
+    fn vars(&self, out: &mut Vec<u32>) {
+        match self {
+            Constraint::OneOf { operands, .. } => operands.iter().for_each(|ty| ty.vars(out)),
+            Constraint::Scale {
+                lhs, rhs, result, ..
+            } => {
+                lhs.vars(out);
+                rhs.vars(out);
+                result.vars(out);
+            }
+            Constraint::Field { record, result, .. } => {
+                record.vars(out);
+                result.vars(out);
+            }
+            Constraint::Measure { result, .. } => result.vars(out),
+        }
+    }
+}
+
+/// A type generic in `vars`, like `|x| x`'s `a -> a`. Constraints on the
+/// generic variables are copied to every use.
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<u32>,
+    ty: Type,
+    constraints: Vec<Constraint>,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Self {
+        Scheme {
+            vars: Vec::new(),
+            ty,
+            constraints: Vec::new(),
+        }
+    }
+
+    /// `ty` with all its type variables generic.
+    fn generic(ty: Type) -> Self {
+        let mut vars = Vec::new();
+        ty.vars(&mut vars);
+        Scheme {
+            vars,
+            ty,
+            constraints: Vec::new(),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// Hindley–Milner-style type inference for expressions and statements,
+/// run before evaluation so bad formulas can be rejected up front.
+///
+/// `let`-bound values and `fn` definitions are generic, so `let id = |x|
+/// x in ...` may be used at several types. Operators are overloaded as at
+/// runtime; when an operand's type is not known yet, its operator is
+/// checked once it is. Three deliberate simplifications:
+/// - lists must hold one type, and both branches of an `if` must agree
+/// - `*` and `/` between two quantities may cancel to a number, so their
+///   result may be used as a number, and is otherwise taken as a quantity
+/// - complex numbers are numbers, so `%` or `<` on one is only caught
+///   when it runs
+///
+/// Every error found is reported, sorted by position.
+#[derive(Debug, Clone, Default)]
+pub struct TypeChecker {
+    globals: HashMap<String, Scheme>,
+    registry: Arc<FunctionRegistry>,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        TypeChecker {
+            globals: HashMap::new(),
+            registry: Arc::default(),
+        }
+    }
+
+    /// A checker knowing the type of each binding's value.
+    pub fn with_bindings(bindings: &Bindings) -> Self {
+        let mut checker = TypeChecker::new();
+        for (name, value) in bindings {
+            checker.declare(name.clone(), Type::of(value));
+        }
+        checker
+    }
+
+    /// A checker for expressions run by `interp`: its globals and its
+    /// registered functions.
+    ///
+    /// Functions get the type `check_stmt` infers from their definitions,
+    /// so `fn sq(x) = x * x` still rejects `sq("a")`. A function calling
+    /// another is checked again once the callee's type is known.
+    pub fn from_interpreter(interp: &Interpreter) -> Self {
+        let mut checker = TypeChecker::new().with_registry(Arc::clone(interp.registry()));
+        let vars = interp.vars();
+        for &(name, value) in &vars {
+            checker.declare(name, Type::of(value));
+        }
+
+        let functions: Vec<(&str, &Lambda)> = vars
+            .iter()
+            .filter_map(|&(name, value)| match value {
+                Value::Function(lambda) => Some((name, &**lambda)),
+                _ => None,
+            })
+            .collect();
+        for _ in 0..functions.len() {
+            let mut changed = false;
+            for &(name, lambda) in &functions {
+                let Some(scheme) = checker.lambda_scheme(lambda) else {
+                    continue;
+                };
+                changed |= checker.type_of(name) != Some(scheme.ty.normalized());
+                checker.globals.insert(name.to_string(), scheme);
+            }
+            if !changed {
+                break;
+            }
+        }
+        checker
+    }
+
+    /// The scheme of a function value, inferred as for its definition:
+    /// locals it captured have their values' types, and a named function
+    /// may call itself. `None` if the definition does not check.
+    fn lambda_scheme(&self, lambda: &Lambda) -> Option<Scheme> {
+        let mut infer = Infer::new(self);
+        let mut scope = lambda.captured.as_deref();
+        while let Some(env) = scope {
+            for (name, value) in env.locals() {
+                let local = (name.to_string(), Scheme::generic(Type::of(value)));
+                infer.locals.insert(0, local);
+            }
+            scope = env.parent().map(|parent| &**parent);
+        }
+        let ty = infer.definition(lambda.name.as_deref(), &lambda.params, &lambda.body);
+        infer.finish(ty).ok().map(|(_, scheme)| scheme)
+    }
+
+    /// Check calls to the host functions in `registry`. Their results can
+    /// be of any type.
+    pub fn with_registry(mut self, registry: impl Into<Arc<FunctionRegistry>>) -> Self {
+        self.registry = registry.into();
+        self
+    }
+
+    /// Give the global `name` type `ty`; its type variables are generic.
+    pub fn declare(&mut self, name: impl Into<String>, ty: Type) {
+        self.globals.insert(name.into(), Scheme::generic(ty));
+    }
+
+    /// The type of the global `name`, if it has one.
+    pub fn type_of(&self, name: &str) -> Option<Type> {
+        self.globals.get(name).map(|scheme| scheme.ty.normalized())
+    }
+
+    /// Infer the type of `expr`, or report every type error in it.
+    pub fn check(&self, expr: &Expr) -> Result<Type, Vec<TypeError>> {
+        let mut infer = Infer::new(self);
+        let ty = infer.infer(expr);
+        infer.finish(ty).map(|(ty, _)| ty)
+    }
+
+    /// Check a statement; assignments and definitions declare their name
+    /// with the inferred type when it checks.
+    pub fn check_stmt(&mut self, stmt: &Stmt) -> Result<Type, Vec<TypeError>> {
+        let mut infer = Infer::new(self);
+        let (name, ty) = match stmt {
+            Stmt::Expr(expr) => return self.check(expr),
+            Stmt::Assign { name, value } => (name, infer.infer(value)),
+            Stmt::FnDef { name, params, body } => {
+                (name, infer.definition(Some(name), params, body))
+            }
+        };
+        let (ty, scheme) = infer.finish(ty)?;
+        self.globals.insert(name.clone(), scheme);
+        Ok(ty)
+    }
+}
+
+// ------------------------------------------------
+/// State of one inference run.
+struct Infer<'a> {
+    checker: &'a TypeChecker,
+    /// What each type variable has been bound to so far
+    subst: Vec<Option<Type>>,
+    /// `let` and parameter bindings, innermost last
+    locals: Vec<(String, Scheme)>,
+    pending: Vec<Constraint>,
+    errors: Vec<TypeError>,
+}
+
+impl<'a> Infer<'a> {
+    fn new(checker: &'a TypeChecker) -> Self {
+        Infer {
+            checker,
+            subst: Vec::new(),
+            locals: Vec::new(),
+            pending: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() as u32 - 1)
+    }
+
+    /// Follow variable bindings at the top of `ty` only.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(id) = ty {
+            match &self.subst[id as usize] {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Apply every binding made so far, all the way down.
+    fn resolve(&self, ty: &Type) -> Type {
+        ty.map_vars(&mut |id| match &self.subst[id as usize] {
+            Some(bound) => self.resolve(bound),
+            None => Type::Var(id),
+        })
+    }
+
+    fn occurs(&self, id: u32, ty: &Type) -> bool {
+        let mut vars = Vec::new();
+        self.resolve(ty).vars(&mut vars);
+        vars.contains(&id)
+    }
+
+    /// Make `found` the same type as `expected`, reporting a mismatch at
+    /// `span` if it cannot be.
+    fn unify(&mut self, expected: &Type, found: &Type, span: Span) {
+        if let Err(infinite) = self.unify_inner(expected, found) {
+            let err = if infinite {
+                TypeError::InfiniteType { span }
+            } else {
+                TypeError::Mismatch {
+                    expected: self.resolve(expected),
+                    found: self.resolve(found),
+                    span,
+                }
+            };
+            self.errors.push(err);
+        }
+    }
+
+    /// `Err(true)` when the types could only match by containing themselves.
+    fn unify_inner(&mut self, a: &Type, b: &Type) -> Result<(), bool> {
+        match (self.shallow(a), self.shallow(b)) {
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(id), other) | (other, Type::Var(id)) => {
+                if self.occurs(id, &other) {
+                    return Err(true);
+                }
+                self.subst[id as usize] = Some(other);
+                Ok(())
+            }
+            (Type::List(x), Type::List(y)) => self.unify_inner(&x, &y),
+            (Type::Record(xs), Type::Record(ys)) if xs.keys().eq(ys.keys()) => {
+                for (x, y) in xs.values().zip(ys.values()) {
+                    self.unify_inner(x, y)?;
+                }
+                Ok(())
+            }
+            (Type::Function(xs, x), Type::Function(ys, y)) if xs.len() == ys.len() => {
+                for (x, y) in xs.iter().zip(&ys) {
+                    self.unify_inner(x, y)?;
+                }
+                self.unify_inner(&x, &y)
+            }
+            (x, y) if x == y => Ok(()),
+            _ => Err(false),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme, at: Option<Span>) -> Type {
+        let fresh: HashMap<u32, Type> = scheme.vars.iter().map(|&id| (id, self.fresh())).collect();
+        let mut rename = |id: u32| fresh.get(&id).cloned().unwrap_or(Type::Var(id));
+        for constraint in &scheme.constraints {
+            let mut constraint = constraint.map_types(&mut |ty| ty.map_vars(&mut rename));
+            if let Some(at) = at {
+                *constraint.span_mut() = at;
+            }
+            self.constrain(constraint);
+        }
+        scheme.ty.map_vars(&mut rename)
+    }
+
+    /// Make the variables of `ty` that no local mentions generic, taking
+    /// the pending constraints on them along.
+    fn generalize(&mut self, ty: &Type) -> Scheme {
+        self.solve();
+        let ty = self.resolve(ty);
+        let mut in_scope = Vec::new();
+        for (_, scheme) in &self.locals {
+            let mut vars = Vec::new();
+            self.resolve(&scheme.ty).vars(&mut vars);
+            in_scope.extend(vars.into_iter().filter(|id| !scheme.vars.contains(id)));
+        }
+        let mut vars = Vec::new();
+        ty.vars(&mut vars);
+        vars.retain(|id| !in_scope.contains(id));
+        let generic: HashSet<u32> = vars.iter().copied().collect();
+
+        let mut constraints = Vec::new();
+        for constraint in mem::take(&mut self.pending) {
+            let constraint = constraint.map_types(&mut |ty| self.resolve(ty));
+            let mut mentioned = Vec::new();
+            constraint.vars(&mut mentioned);
+            if mentioned.iter().any(|id| generic.contains(id)) {
+                constraints.push(constraint);
+            } else {
+                self.pending.push(constraint);
+            }
+        }
+        Scheme {
+            vars,
+            ty,
+            constraints,
+        }
+    }
+
+    /// Check `constraint` now if its types are known, else later.
+    fn constrain(&mut self, constraint: Constraint) {
+        if let Some(constraint) = self.try_solve(constraint) {
+            self.pending.push(constraint);
+        }
+    }
+
+    /// Check pending constraints until no more can be decided.
+    fn solve(&mut self) {
+        loop {
+            let before = self.pending.len();
+            for constraint in mem::take(&mut self.pending) {
+                if let Some(constraint) = self.try_solve(constraint) {
+                    self.pending.push(constraint);
+                }
+            }
+            if self.pending.len() == before {
+                break;
+            }
+        }
+    }
+
+    /// `Some` with the constraint if it cannot be decided yet.
+    fn try_solve(&mut self, constraint: Constraint) -> Option<Constraint> {
+        match &constraint {
+            Constraint::OneOf {
+                op,
+                operands,
+                allowed,
+                span,
+            } => {
+                let Some(kind) = self.shallow(&operands[0]).kind() else {
+                    return Some(constraint);
+                };
+                if !allowed.contains(&kind) {
+                    self.no_operator(op, operands, *span);
+                }
+            }
+            Constraint::Scale {
+                op,
+                lhs,
+                rhs,
+                result,
+                span,
+            } => {
+                let (l, r) = (self.shallow(lhs).kind(), self.shallow(rhs).kind());
+                let scalable =
+                    |kind: Option<Kind>| kind.is_none_or(|kind| SUBTRACTABLE.contains(&kind));
+                let symbol = op.symbol();
+                if !scalable(l) || !scalable(r) {
+                    self.no_operator(symbol, &[lhs.clone(), rhs.clone()], *span);
+                    return None;
+                }
+                let (Some(l), Some(r)) = (l, r) else {
+                    return Some(constraint);
+                };
+                let ty = match (op, l, r) {
+                    (_, Kind::Number, Kind::Number) => Type::Number,
+                    (BinOp::Mul, Kind::Vector, Kind::Vector | Kind::Number)
+                    | (BinOp::Mul, Kind::Number, Kind::Vector)
+                    | (BinOp::Div, Kind::Vector, Kind::Number) => Type::Vector,
+                    (_, Kind::Quantity, Kind::Number) | (_, Kind::Number, Kind::Quantity) => {
+                        Type::Quantity
+                    }
+                    (BinOp::Mul, Kind::Money, Kind::Number)
+                    | (BinOp::Mul, Kind::Number, Kind::Money) => Type::Money,
+                    // The dimensions may cancel, leaving a plain number
+                    (_, Kind::Quantity, Kind::Quantity) => {
+                        let result = result.clone();
+                        self.constrain(Constraint::Measure {
+                            result,
+                            span: *span,
+                        });
+                        return None;
+                    }
+                    _ => {
+                        self.no_operator(symbol, &[lhs.clone(), rhs.clone()], *span);
+                        return None;
+                    }
+                };
+                self.unify(&ty, result, *span);
+            }
+            Constraint::Field {
+                record,
+                name,
+                result,
+                span,
+            } => match self.shallow(record) {
+                Type::Var(_) => return Some(constraint),
+                Type::Record(fields) => match fields.get(name) {
+                    Some(ty) => self.unify(ty, result, *span),
+                    None => self.errors.push(TypeError::NoField {
+                        name: name.clone(),
+                        record: self.resolve(record),
+                        span: *span,
+                    }),
+                },
+                _ => self.no_operator(".", std::slice::from_ref(record), *span),
+            },
+            Constraint::Measure { result, span } => match self.shallow(result).kind() {
+                None => return Some(constraint),
+                Some(Kind::Number | Kind::Quantity) => {}
+                Some(_) => self.errors.push(TypeError::Mismatch {
+                    expected: Type::Quantity,
+                    found: self.resolve(result),
+                    span: *span,
+                }),
+            },
+        }
+        None
+    }
+
+    /// Take products of quantities whose type is still open as quantities,
+    /// so that uses needing anything but a number or quantity are caught.
+    fn default_measures(&mut self) {
+        self.solve();
+        let open: Vec<Type> = self
+            .pending
+            .iter()
+            .filter_map(|constraint| match constraint {
+                Constraint::Measure { result, .. } => Some(result.clone()),
+                _ => None,
+            })
+            .collect();
+        for result in open {
+            self.unify(&Type::Quantity, &result, Span::default());
+        }
+        self.solve();
+    }
+
+    fn no_operator(&mut self, op: &'static str, operands: &[Type], span: Span) {
+        let operands = operands.iter().map(|ty| self.resolve(ty)).collect();
+        self.errors
+            .push(TypeError::NoOperator { op, operands, span });
+    }
+
+    /// Settle what can be settled and produce the final type and its
+    /// generic scheme, or the errors sorted by position.
+    fn finish(mut self, ty: Type) -> Result<(Type, Scheme), Vec<TypeError>> {
+        self.default_measures();
+        let scheme = self.generalize(&ty);
+        if self.errors.is_empty() {
+            return Ok((scheme.ty.normalized(), scheme));
+        }
+        let mut errors = self.errors;
+        errors.sort_by_key(|err| (err.span().start, err.span().end));
+        errors.dedup();
+        Err(errors)
+    }
+
+    // ------------------------------------------------
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Int(_)
+                | Literal::BigInt(_)
+                | Literal::Float(_)
+                | Literal::Imaginary(_) => Type::Number,
+                Literal::Bool(_) => Type::Bool,
+                Literal::Str(_) => Type::Str,
+                Literal::Quantity(..) => Type::Quantity,
+            },
+            ExprKind::Var(name) => match self.lookup(name) {
+                Some(scheme) => {
+                    let at = self.use_site(name, expr.span);
+                    self.instantiate(&scheme, at)
+                }
+                None if matches!(name.as_str(), "pi" | "e") => Type::Number,
+                None => {
+                    self.errors.push(TypeError::UnknownVariable {
+                        name: name.clone(),
+                        span: expr.span,
+                    });
+                    self.fresh()
+                }
+            },
+            ExprKind::Unary { op, operand } => {
+                let ty = self.infer(operand);
+                let errors = self.errors.len();
+                match op {
+                    UnaryOp::Neg => self.constrain(Constraint::OneOf {
+                        op: "-",
+                        operands: vec![ty.clone()],
+                        allowed: SUBTRACTABLE,
+                        span: expr.span,
+                    }),
+                    UnaryOp::Not => self.unify(&Type::Bool, &ty, operand.span),
+                }
+                self.unless_failed(errors, ty)
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (l, r) = (self.infer(lhs), self.infer(rhs));
+                self.binary(*op, l, r, [lhs.span, rhs.span, expr.span])
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let ty = self.infer(cond);
+                self.unify(&Type::Bool, &ty, cond.span);
+                let then_ty = self.infer(then_branch);
+                let else_ty = self.infer(else_branch);
+                self.unify(&then_ty, &else_ty, else_branch.span);
+                then_ty
+            }
+            ExprKind::Convert { expr: inner, .. } => {
+                let ty = self.infer(inner);
+                self.unify(&Type::Quantity, &ty, inner.span);
+                Type::Quantity
+            }
+            ExprKind::Call { name, args } => {
+                let args: Vec<(Type, Span)> =
+                    args.iter().map(|arg| (self.infer(arg), arg.span)).collect();
+                self.call(name, &args, expr.span)
+            }
+            ExprKind::Apply { callee, args } => {
+                let callee_ty = self.infer(callee);
+                let args: Vec<(Type, Span)> =
+                    args.iter().map(|arg| (self.infer(arg), arg.span)).collect();
+                self.apply(&callee.to_string(), callee_ty, &args, expr.span)
+            }
+            ExprKind::Let { name, value, body } => {
+                let ty = self.infer(value);
+                let scheme = self.generalize(&ty);
+                self.locals.push((name.clone(), scheme));
+                let ty = self.infer(body);
+                self.locals.pop();
+                ty
+            }
+            ExprKind::Lambda { params, body } => self.lambda(params, body),
+            ExprKind::Record { fields } => Type::Record(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), self.infer(value)))
+                    .collect(),
+            ),
+            ExprKind::Field { expr: record, name } => {
+                let ty = self.infer(record);
+                let result = self.fresh();
+                self.constrain(Constraint::Field {
+                    record: ty,
+                    name: name.clone(),
+                    result: result.clone(),
+                    span: expr.span,
+                });
+                result
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let ty = self.infer(scrutinee);
+                let mut result: Option<Type> = None;
+                for arm in arms {
+                    if let Some(pattern) = pattern_type(&arm.pattern) {
+                        self.unify(&ty, &pattern, arm.span);
+                    }
+                    let body = self.infer(&arm.body);
+                    match &result {
+                        Some(first) => self.unify(first, &body, arm.body.span),
+                        None => result = Some(body),
+                    }
+                }
+                result.unwrap_or_else(|| self.fresh())
+            }
+        }
+    }
+
+    /// The type of `fn name(params) = body`. The function is in scope, not
+    /// yet generic, in its own body.
+    fn definition(&mut self, name: Option<&str>, params: &[String], body: &Expr) -> Type {
+        let Some(name) = name else {
+            return self.lambda(params, body);
+        };
+        let this = self.fresh();
+        self.locals
+            .push((name.to_string(), Scheme::mono(this.clone())));
+        let ty = self.lambda(params, body);
+        self.unify(&this, &ty, body.span);
+        self.locals.pop();
+        ty
+    }
+
+    fn lambda(&mut self, params: &[String], body: &Expr) -> Type {
+        let params: Vec<Type> = params
+            .iter()
+            .map(|param| {
+                let ty = self.fresh();
+                self.locals.push((param.clone(), Scheme::mono(ty.clone())));
+                ty
+            })
+            .collect();
+        let ret = self.infer(body);
+        self.locals.truncate(self.locals.len() - params.len());
+        Type::function(params, ret)
+    }
+
+    /// Where to report errors in the constraints `name`'s scheme brings
+    /// along: at its definition for a local, which is in the same source,
+    /// but at this use of `span` for a global defined elsewhere.
+    fn use_site(&self, name: &str, span: Span) -> Option<Span> {
+        let local = self.locals.iter().any(|(local, _)| local == name);
+        (!local).then_some(span)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Scheme> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, scheme)| scheme.clone())
+            .or_else(|| self.checker.globals.get(name).cloned())
+    }
+
+    /// Mirrors `apply_binary`; `spans` are the operands' and the whole
+    /// expression's.
+    fn binary(&mut self, op: BinOp, l: Type, r: Type, spans: [Span; 3]) -> Type {
+        let [lhs_span, rhs_span, span] = spans;
+        let errors = self.errors.len();
+        let allowed = match op {
+            BinOp::Eq | BinOp::Ne => return Type::Bool,
+            BinOp::Range => {
+                self.unify(&Type::Number, &l, lhs_span);
+                self.unify(&Type::Number, &r, rhs_span);
+                return Type::list(Type::Number);
+            }
+            BinOp::And | BinOp::Or => {
+                self.unify(&Type::Bool, &l, lhs_span);
+                self.unify(&Type::Bool, &r, rhs_span);
+                return Type::Bool;
+            }
+            BinOp::Mul | BinOp::Div => {
+                let result = self.fresh();
+                self.constrain(Constraint::Scale {
+                    op,
+                    lhs: l,
+                    rhs: r,
+                    result: result.clone(),
+                    span,
+                });
+                return result;
+            }
+            BinOp::Pow => {
+                self.unify(&Type::Number, &r, rhs_span);
+                self.constrain(Constraint::OneOf {
+                    op: op.symbol(),
+                    operands: vec![l.clone()],
+                    allowed: REMAINDER,
+                    span,
+                });
+                return self.unless_failed(errors, l);
+            }
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => ORDERED,
+            BinOp::Add => ADDABLE,
+            BinOp::Sub => SUBTRACTABLE,
+            BinOp::Rem => REMAINDER,
+        };
+        // The rest take two operands of one type
+        self.unify(&l, &r, rhs_span);
+        self.constrain(Constraint::OneOf {
+            op: op.symbol(),
+            operands: vec![l.clone(), r],
+            allowed,
+            span,
+        });
+        if matches!(op, BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge) {
+            return Type::Bool;
+        }
+        self.unless_failed(errors, l)
+    }
+
+    /// `ty`, or an unknown type if errors were reported since there were
+    /// `errors` of them, so one mistake is not reported again further out.
+    fn unless_failed(&mut self, errors: usize, ty: Type) -> Type {
+        if self.errors.len() > errors {
+            self.fresh()
+        } else {
+            ty
+        }
+    }
+
+    /// A call of a local or global function, a builtin or a registered
+    /// function, in the order the interpreter looks for them.
+    /// Apply a function value of type `callee`, known as `name` in errors.
+    fn apply(&mut self, name: &str, callee: Type, args: &[(Type, Span)], span: Span) -> Type {
+        let ret = self.fresh();
+        match self.shallow(&callee) {
+            Type::Function(params, _) if params.len() != args.len() => {
+                self.errors.push(TypeError::ArityMismatch {
+                    name: name.to_string(),
+                    expected: params.len(),
+                    found: args.len(),
+                    span,
+                });
+            }
+            Type::Function(params, callee_ret) => {
+                for (param, (arg, arg_span)) in params.iter().zip(args) {
+                    self.unify(param, arg, *arg_span);
+                }
+                self.unify(&callee_ret, &ret, span);
+            }
+            _ => {
+                let arg_types = args.iter().map(|(ty, _)| ty.clone()).collect();
+                self.unify(&Type::function(arg_types, ret.clone()), &callee, span);
+            }
+        }
+        ret
+    }
+
+    fn call(&mut self, name: &str, args: &[(Type, Span)], span: Span) -> Type {
+        if let Some(scheme) = self.lookup(name) {
+            let at = self.use_site(name, span);
+            let callee = self.instantiate(&scheme, at);
+            return self.apply(name, callee, args, span);
+        }
+
+        let Some((params, ret)) = self.builtin(name, args.len()) else {
+            self.errors.push(TypeError::UnknownFunction {
+                name: name.to_string(),
+                span,
+            });
+            return self.fresh();
+        };
+        if params.len() != args.len() {
+            self.errors.push(TypeError::ArityMismatch {
+                name: name.to_string(),
+                expected: params.len(),
+                found: args.len(),
+                span,
+            });
+            return ret;
+        }
+        for (param, (arg, arg_span)) in params.iter().zip(args) {
+            self.unify(param, arg, *arg_span);
+        }
+        match name {
+            "len" => self.constrain(Constraint::OneOf {
+                op: "len",
+                operands: vec![args[0].0.clone()],
+                allowed: SIZED,
+                span,
+            }),
+            "sum" => self.constrain(Constraint::OneOf {
+                op: "sum",
+                operands: vec![ret.clone()],
+                allowed: ADDABLE,
+                span,
+            }),
+            _ => {}
+        }
+        ret
+    }
+
+    /// Parameter and result types of a builtin or registered function;
+    /// `argc` only matters for the variadic `list` and `range`.
+    fn builtin(&mut self, name: &str, argc: usize) -> Option<(Vec<Type>, Type)> {
+        use Type::{Bool, Number, Vector};
+
+        let signature = match name {
+            "list" => {
+                let item = self.fresh();
+                (vec![item.clone(); argc], Type::list(item))
+            }
+            "len" => (vec![self.fresh()], Number),
+            "int" | "float" => (vec![Number], Number),
+            "vec" => (vec![Number, Number], Vector),
+            "dot" => (vec![Vector, Vector], Number),
+            "length" => (vec![Vector], Number),
+            "normalize" => (vec![Vector], Vector),
+            "sum" => {
+                let item = self.fresh();
+                (vec![Type::list(item.clone())], item)
+            }
+            "product" => (vec![Type::list(Number)], Number),
+            "range" => (vec![Number; argc.clamp(1, 3)], Type::list(Number)),
+            "fib" => (vec![], Type::list(Number)),
+            "take" => {
+                let a = Type::list(self.fresh());
+                (vec![a.clone(), Number], a)
+            }
+            "collect" => {
+                let a = Type::list(self.fresh());
+                (vec![a.clone()], a)
+            }
+            "money" => (vec![Number], Type::Money),
+            "map" => {
+                let (a, b) = (self.fresh(), self.fresh());
+                let f = Type::function(vec![a.clone()], b.clone());
+                (vec![Type::list(a), f], Type::list(b))
+            }
+            "filter" => {
+                let a = self.fresh();
+                let f = Type::function(vec![a.clone()], Bool);
+                (vec![Type::list(a.clone()), f], Type::list(a))
+            }
+            "fold" => {
+                let (a, b) = (self.fresh(), self.fresh());
+                let f = Type::function(vec![b.clone(), a.clone()], b.clone());
+                (vec![Type::list(a), b.clone(), f], b)
+            }
+            "sqrt" | "abs" | "arg" | "conj" | "re" | "im" => (vec![Number], Number),
+            "rect" => (vec![Number, Number], Number),
+            "polar" => {
+                let fields = [("r", Number), ("theta", Number)];
+                let fields = fields.map(|(name, ty)| (name.to_string(), ty));
+                (vec![Number], Type::Record(fields.into()))
+            }
+            _ => {
+                if let Some((arity, _)) = numeric_builtin(name) {
+                    return Some((vec![Number; arity], Number));
+                }
+                let native = self.checker.registry.get(name)?.clone();
+                let params = native
+                    .params()
+                    .iter()
+                    .map(|param| match *param {
+                        "float" | "int" => Number,
+                        "bool" => Bool,
+                        "string" => Type::Str,
+                        "vector" => Vector,
+                        "quantity" => Type::Quantity,
+                        "money" => Type::Money,
+                        "list" => Type::list(self.fresh()),
+                        _ => self.fresh(),
+                    })
+                    .collect();
+                (params, self.fresh())
+            }
+        };
+        Some(signature)
+    }
+}
+
+/// The type of value `pattern` can match; `None` for `_`, which matches any.
+fn pattern_type(pattern: &Pattern) -> Option<Type> {
+    let literal = match pattern {
+        Pattern::Wildcard => return None,
+        Pattern::Literal(literal) => literal,
+        Pattern::Range { .. } => return Some(Type::Number),
+    };
+    Some(match literal {
+        Literal::Int(_) | Literal::BigInt(_) | Literal::Float(_) | Literal::Imaginary(_) => {
+            Type::Number
+        }
+        Literal::Bool(_) => Type::Bool,
+        Literal::Str(_) => Type::Str,
+        Literal::Quantity(..) => Type::Quantity,
+    })
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_of(src: &str) -> String {
+        let expr: Expr = src.parse().unwrap();
+        match TypeChecker::new().check(&expr) {
+            Ok(ty) => ty.to_string(),
+            Err(errors) => panic!("{}: {:?}", src, errors),
+        }
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        let expr: Expr = src.parse().unwrap();
+        TypeChecker::new()
+            .check(&expr)
+            .unwrap_err()
+            .iter()
+            .map(|err| format!("{}..{}: {}", err.span().start, err.span().end, err))
+            .collect()
+    }
+
+    #[test]
+    fn test_infers_simple_types() {
+        assert_eq!(type_of("1 + 2.5 * 3"), "number");
+        assert_eq!(type_of(r#""a" + "b""#), "string");
+        assert_eq!(type_of("1 < 2 && !false"), "bool");
+        assert_eq!(type_of("vec(1, 2) * 3 + vec(0, 1)"), "vector");
+        assert_eq!(type_of("3 m * 2 -> cm"), "quantity");
+        assert_eq!(type_of("list(1, 2, 3)"), "[number]");
+        assert_eq!(type_of("[1..10, range(0, 9, 3)]"), "[[number]]");
+        assert_eq!(type_of("take(map(fib(), |n| n > 2), 5)"), "[bool]");
+        assert_eq!(type_of("sum([money(2), money(0.5) * 3])"), "money");
+        assert_eq!(type_of(r#"if true then "yes" else "no""#), "string");
+        assert_eq!(type_of("sqrt(2) + pi"), "number");
+    }
+
+    #[test]
+    fn test_infers_lambdas() {
+        assert_eq!(type_of("|x| x"), "a -> a");
+        assert_eq!(type_of("|x, y| x && y"), "(bool, bool) -> bool");
+        assert_eq!(type_of("|f, x| f(f(x))"), "(a -> a, a) -> a");
+        assert_eq!(type_of("|x| x + 1"), "number -> number");
+        assert_eq!(type_of(r#"|s| s + "!""#), "string -> string");
+        assert_eq!(type_of("|xs| map(xs, |x| x > 0)"), "[number] -> [bool]");
+        assert_eq!(type_of("(|x, y| x && y)(true, false)"), "bool");
+        assert_eq!(type_of("|f| f(1)(\"a\")"), "(number -> string -> a) -> a");
+        assert_eq!(
+            type_of("|xs, init| fold(xs, init, |acc, x| acc + len(x))"),
+            "([a], number) -> number"
+        );
+    }
+
+    #[test]
+    fn test_let_polymorphism() {
+        assert_eq!(
+            type_of(r#"let id = |x| x in if id(true) then id(1) else 2"#),
+            "number"
+        );
+        // Operators on generic values are checked at each use
+        assert_eq!(type_of(r#"let twice = |x| x + x in twice("ab")"#), "string");
+        assert_eq!(
+            errors("let twice = |x| x + x in twice(true)"),
+            ["16..21: `+` does not apply to bool and bool"]
+        );
+    }
+
+    #[test]
+    fn test_scaling_waits_for_operand_types() {
+        assert_eq!(type_of("|v| v * 2"), "a -> b");
+        assert_eq!(type_of("let f = |v| v * 2 in f(vec(1, 2))"), "vector");
+        assert_eq!(type_of("let f = |v| v * 2 in f(3)"), "number");
+        assert_eq!(type_of("10 m / 2 s"), "quantity");
+        assert_eq!(
+            errors(r#""ab" * 2"#),
+            ["0..8: `*` does not apply to string and number"]
+        );
+    }
+
+    #[test]
+    fn test_quantity_products_stay_numeric() {
+        assert_eq!(type_of("3 m * 2 s"), "quantity");
+        for src in [
+            "if 3 m * 2 s then 1 else 2",
+            "len(2 m * 3 m)",
+            "(1 m / 1 s).a",
+            r#"(3 m * 2 s) + "x""#,
+            "let area = 2 m * 3 m in area && true",
+        ] {
+            assert!(!errors(src).is_empty(), "{}", src);
+        }
+        assert_eq!(
+            errors("len(2 m * 3 m)"),
+            ["0..14: `len` does not apply to quantity"]
+        );
+    }
+
+    #[test]
+    fn test_accepts_what_evaluates() {
+        let sources = [
+            "let x = 10 in let f = |y| x * y in f(3) - 1",
+            "fold(map(list(1, 2, 3), |x| x * x), 0, |a, b| a + b)",
+            "filter(list(1, 5, 10), |x| x % 2 == 1)",
+            "sum(list(vec(1, 2), vec(3, 4))) / 2",
+            "length(normalize(vec(3, 4)) * 5)",
+            "(3 km + 200 m) / 2 -> m",
+            "10 m / 4 s * 2 s",
+            "(3 m / 1 m) + 1",
+            "let speed = |d, t| d / t in speed(3 km, 2 s) * 1 s -> m",
+            r#"len("abc") + len(list(true)) == 4 || "a" < "b""#,
+            "let apply = |f, x| f(x) in apply(|n| n > 2, 3) && apply(|s| s, true)",
+            "if 2 ^ 10 > 1000 then int(2.5) else float(3)",
+            "sum(take(filter(fib(), |n| n % 2 == 0), 5)) + product(1..4)",
+            "let z = sqrt(-4) * (3 + 4i) in abs(z) + arg(conj(z)) + polar(z).theta",
+            "re(rect(2, 0.5)) - im(1i ^ 0.5)",
+        ];
+        for src in sources {
+            let expr: Expr = src.parse().unwrap();
+            assert!(Interpreter::new().eval(&expr).is_ok(), "{}", src);
+            assert!(TypeChecker::new().check(&expr).is_ok(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_reports_every_error_with_spans() {
+        assert_eq!(
+            errors(r#"(1 + "a") * 2 + (if 3 then 1 else 2)"#),
+            [
+                "5..8: type mismatch: expected number, found string",
+                "20..21: type mismatch: expected bool, found number",
+            ]
+        );
+        assert_eq!(
+            errors("foo(1) + bar + sqrt(1, 2)"),
+            [
+                "0..6: unknown function 'foo'",
+                "9..12: unknown variable 'bar'",
+                "15..25: function 'sqrt' expects 1 argument(s), got 2",
+            ]
+        );
+        assert_eq!(
+            errors("-true + len(5)"),
+            [
+                "0..5: `-` does not apply to bool",
+                "8..14: `len` does not apply to number",
+            ]
+        );
+        assert_eq!(errors("|f| f(f)"), ["4..8: recursive type"]);
+    }
+
+    #[test]
+    fn test_records_and_match() {
+        assert_eq!(
+            type_of(r#"{ price: 10, name: "tea" }"#),
+            "{ name: string, price: number }"
+        );
+        assert_eq!(type_of("{ price: 10, qty: 3 }.qty * 2"), "number");
+        // Fields of an unknown record are checked once it is known
+        assert_eq!(type_of("|order| order.price"), "a -> b");
+        assert_eq!(
+            type_of("let total = |o| o.price * o.qty in total({ price: 2, qty: 3 })"),
+            "number"
+        );
+        assert_eq!(
+            type_of(r#"|n| match n { 0 => "none", 1..10 => "few", _ => "many" }"#),
+            "number -> string"
+        );
+        assert_eq!(
+            errors(r#"{ a: 1 }.b + (match true { 1 => 2, _ => "x" })"#),
+            [
+                "0..10: { a: number } has no field 'b'",
+                "27..28: type mismatch: expected bool, found number",
+                "40..43: type mismatch: expected number, found string",
+            ]
+        );
+        assert_eq!(errors("(1).a"), ["0..5: `.` does not apply to number"]);
+    }
+
+    #[test]
+    fn test_declared_globals_and_statements() {
+        let mut checker = TypeChecker::new();
+        checker.declare("price", Type::Number);
+        checker.declare("name", Type::Str);
+        let stmt: Stmt = "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            checker.check_stmt(&stmt).unwrap(),
+            Type::function(vec![Type::Number], Type::Number)
+        );
+        let stmt: Stmt = "total = fact(3) * price".parse().unwrap();
+        assert_eq!(checker.check_stmt(&stmt).unwrap(), Type::Number);
+        assert_eq!(checker.type_of("total"), Some(Type::Number));
+
+        let expr: Expr = "total + name".parse().unwrap();
+        assert_eq!(
+            checker.check(&expr).unwrap_err(),
+            [TypeError::Mismatch {
+                expected: Type::Number,
+                found: Type::Str,
+                span: Span::new(8, 12),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_from_interpreter() {
+        let mut registry = FunctionRegistry::new();
+        registry.register(
+            "tax_rate",
+            |region: String| if region == "eu" { 0.2 } else { 0.1 },
+        );
+        let mut interp = Interpreter::new().with_registry(registry);
+        interp.exec_str("rate = 0.5").unwrap();
+        interp.exec_str("fn add(a, b) = a + b").unwrap();
+
+        let checker = TypeChecker::from_interpreter(&interp);
+        let expr: Expr = r#"add(rate, tax_rate("eu")) + add(1, 2)"#.parse().unwrap();
+        assert!(checker.check(&expr).is_ok());
+        let expr: Expr = "add(1) + tax_rate(2)".parse().unwrap();
+        assert_eq!(checker.check(&expr).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_from_interpreter_infers_function_types() {
+        let mut interp = Interpreter::new();
+        for line in [
+            "fn sq(x) = x * x",
+            "fn inc(x) = x + 1",
+            "fn twice(x) = inc(inc(x))",
+            "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1)",
+            "shout = let s = \"s\" in |x| x + s",
+        ] {
+            interp.exec_str(line).unwrap();
+        }
+
+        let checker = TypeChecker::from_interpreter(&interp);
+        let shown = |name| checker.type_of(name).unwrap().to_string();
+        assert_eq!(shown("twice"), "number -> number");
+        assert_eq!(shown("fact"), "number -> number");
+        assert_eq!(shown("shout"), "string -> string");
+
+        let expr: Expr = "sq(\"a\")".parse().unwrap();
+        let errors = checker.check(&expr).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span(), Span::new(0, 7));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::{Div, Mul},
+};
+
+use p03_conversion_from_into::{Celsius, Fahrenheit};
+use p17_display_debug::Measurement;
+
+// ------------------------------------------------
+/// SI base unit of each dimension, in the order `Dim` stores exponents.
+const BASE_UNITS: [&str; 4] = ["kg", "m", "s", "K"];
+
+/// Exponents of mass, length, time and temperature, e.g. `m/s^2` is
+/// `[0, 1, -2, 0]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Dim(pub [i32; 4]);
+
+impl Dim {
+    pub const NONE: Dim = Dim([0, 0, 0, 0]);
+    pub const MASS: Dim = Dim([1, 0, 0, 0]);
+    pub const LENGTH: Dim = Dim([0, 1, 0, 0]);
+    pub const TIME: Dim = Dim([0, 0, 1, 0]);
+    pub const TEMPERATURE: Dim = Dim([0, 0, 0, 1]);
+
+    pub fn is_none(self) -> bool {
+        self == Dim::NONE
+    }
+
+    pub fn pow(self, n: i32) -> Dim {
+        Dim(self.0.map(|e| e * n))
+    }
+}
+
+impl Mul for Dim {
+    type Output = Dim;
+
+    fn mul(self, rhs: Dim) -> Dim {
+        Dim(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Div for Dim {
+    type Output = Dim;
+
+    fn div(self, rhs: Dim) -> Dim {
+        Dim(std::array::from_fn(|i| self.0[i] - rhs.0[i]))
+    }
+}
+
+/// In SI base units, like `kg*m/s^2`; `dimensionless` for plain numbers.
+impl Display for Dim {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_none() {
+            return write!(f, "dimensionless");
+        }
+        let part = |unit: &str, exp: i32| match exp {
+            1 => unit.to_string(),
+            _ => format!("{}^{}", unit, exp),
+        };
+        let (mut num, mut den) = (Vec::new(), Vec::new());
+        for (unit, &exp) in BASE_UNITS.iter().zip(&self.0) {
+            if exp > 0 {
+                num.push(part(unit, exp));
+            } else if exp < 0 {
+                den.push(part(unit, -exp));
+            }
+        }
+
+        if num.is_empty() {
+            write!(f, "1")?;
+        } else {
+            write!(f, "{}", num.join("*"))?;
+        }
+        if !den.is_empty() {
+            write!(f, "/{}", den.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+// ------------------------------------------------
+/// A named unit: `v` of it is `v * scale + offset` in SI base units.
+#[derive(Debug, PartialEq)]
+pub struct Unit {
+    pub symbol: &'static str,
+    pub dim: Dim,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+/// Symbols are unique, so they identify a unit.
+impl Hash for Unit {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.symbol.hash(state);
+    }
+}
+
+const fn unit(symbol: &'static str, dim: Dim, scale: f64) -> Unit {
+    Unit {
+        symbol,
+        dim,
+        scale,
+        offset: 0.0,
+    }
+}
+
+/// Units that may follow a number literal, as in `3 m` or `20 C`.
+const UNITS: &[Unit] = &[
+    unit("m", Dim::LENGTH, 1.0),
+    unit("km", Dim::LENGTH, 1000.0),
+    unit("cm", Dim::LENGTH, 0.01),
+    unit("mm", Dim::LENGTH, 0.001),
+    unit("ft", Dim::LENGTH, 0.3048),
+    unit("kg", Dim::MASS, 1.0),
+    unit("g", Dim::MASS, 0.001),
+    unit("lb", Dim::MASS, 0.453_592_37),
+    unit("s", Dim::TIME, 1.0),
+    unit("ms", Dim::TIME, 0.001),
+    unit("min", Dim::TIME, 60.0),
+    unit("h", Dim::TIME, 3600.0),
+    unit("N", Dim([1, 1, -2, 0]), 1.0),
+    unit("J", Dim([1, 2, -2, 0]), 1.0),
+    unit("W", Dim([1, 2, -3, 0]), 1.0),
+    unit("K", Dim::TEMPERATURE, 1.0),
+    Unit {
+        symbol: "C",
+        dim: Dim::TEMPERATURE,
+        scale: 1.0,
+        offset: 273.15,
+    },
+    Unit {
+        symbol: "F",
+        dim: Dim::TEMPERATURE,
+        scale: 5.0 / 9.0,
+        offset: 273.15 - 32.0 * 5.0 / 9.0,
+    },
+];
+
+/// The unit written `symbol`, if there is one.
+pub fn lookup(symbol: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.symbol == symbol)
+}
+
+// ------------------------------------------------
+/// An amount with a dimension: in a named unit, or in SI base units when
+/// `unit` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub dim: Dim,
+    pub unit: Option<&'static Unit>,
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: &'static Unit) -> Self {
+        Quantity {
+            value,
+            dim: unit.dim,
+            unit: Some(unit),
+        }
+    }
+
+    /// An amount in SI base units, shown in a named unit when one of
+    /// scale 1 matches, e.g. `N` for `kg*m/s^2`.
+    pub fn from_si(value: f64, dim: Dim) -> Self {
+        let unit = UNITS
+            .iter()
+            .find(|unit| unit.dim == dim && unit.scale == 1.0 && unit.offset == 0.0);
+        Quantity { value, dim, unit }
+    }
+
+    /// Whether the unit's zero is not SI's, as with `C` and `F`, so that
+    /// only differences of it have a meaning that survives conversion.
+    pub fn has_offset(&self) -> bool {
+        self.unit.is_some_and(|unit| unit.offset != 0.0)
+    }
+
+    /// The amount in SI base units.
+    pub fn si(&self) -> f64 {
+        match self.unit {
+            Some(unit) => self.value * unit.scale + unit.offset,
+            None => self.value,
+        }
+    }
+
+    /// The amount in `target`, which must have the same dimension.
+    pub fn value_in(&self, target: &Unit) -> f64 {
+        match (self.unit.map(|unit| unit.symbol), target.symbol) {
+            // Between the two everyday scales, use p03's conversions
+            (Some("C"), "F") => Fahrenheit::from(Celsius::new(self.value)).0,
+            (Some("F"), "C") => Celsius::from(Fahrenheit::new(self.value)).0,
+            _ if self.unit == Some(target) => self.value,
+            _ => (self.si() - target.offset) / target.scale,
+        }
+    }
+
+    /// The amount expressed in the same unit as `other`.
+    pub fn value_like(&self, other: &Quantity) -> f64 {
+        match other.unit {
+            Some(unit) => self.value_in(unit),
+            None => self.si(),
+        }
+    }
+
+    /// p17's `Measurement`, for quantities in a named unit.
+    pub fn measurement(&self) -> Option<Measurement> {
+        self.unit
+            .map(|unit| Measurement::new(self.value, unit.symbol))
+    }
+}
+
+impl Display for Quantity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.measurement() {
+            Some(measurement) => Display::fmt(&measurement, f),
+            None => write!(f, "{} {}", self.value, self.dim),
+        }
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(value: f64, symbol: &str) -> Quantity {
+        Quantity::new(value, lookup(symbol).unwrap())
+    }
+
+    #[test]
+    fn test_dim_display() {
+        assert_eq!(Dim::LENGTH.to_string(), "m");
+        assert_eq!((Dim::LENGTH / Dim::TIME.pow(2)).to_string(), "m/s^2");
+        assert_eq!((Dim::MASS * Dim::LENGTH.pow(2)).to_string(), "kg*m^2");
+        assert_eq!((Dim::NONE / Dim::TIME).to_string(), "1/s");
+        assert_eq!((Dim::LENGTH / Dim::LENGTH).to_string(), "dimensionless");
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(q(20.0, "C").value_in(lookup("F").unwrap()), 68.0);
+        assert_eq!(q(212.0, "F").value_in(lookup("C").unwrap()), 100.0);
+        assert!((q(0.0, "C").value_in(lookup("K").unwrap()) - 273.15).abs() < 1e-9);
+        assert!((q(32.0, "F").value_in(lookup("K").unwrap()) - 273.15).abs() < 1e-9);
+        assert_eq!(q(1.5, "km").value_in(lookup("m").unwrap()), 1500.0);
+        assert_eq!(q(2.0, "h").value_like(&q(1.0, "min")), 120.0);
+    }
+
+    #[test]
+    fn test_offsets() {
+        assert!(q(20.0, "C").has_offset());
+        assert!(q(20.0, "F").has_offset());
+        assert!(!q(20.0, "K").has_offset());
+        assert!(!Quantity::from_si(1.0, Dim::TEMPERATURE).has_offset());
+    }
+
+    #[test]
+    fn test_display_uses_named_units() {
+        assert_eq!(q(3.0, "m").to_string(), "3 m");
+        assert_eq!(format!("{:.1}", q(20.0, "C")), "20.0 C");
+        let force = Quantity::from_si(9.5, Dim::MASS * Dim::LENGTH / Dim::TIME.pow(2));
+        assert_eq!(force.to_string(), "9.5 N");
+        let speed = Quantity::from_si(2.5, Dim::LENGTH / Dim::TIME);
+        assert_eq!(speed.unit, None);
+        assert_eq!(speed.to_string(), "2.5 m/s");
+    }
+}
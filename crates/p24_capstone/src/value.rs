@@ -0,0 +1,470 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+use p14_operator_arithmetic::Vec2;
+use p23_extend_sum::Money;
+
+use crate::{
+    ast::{Expr, Literal, write_float, write_quoted},
+    complex::Complex,
+    env::Environment,
+    error::EvalError,
+    exact::Rational,
+    lexer::Span,
+    seq::Seq,
+    units::Quantity,
+};
+
+// ------------------------------------------------
+/// A user-defined function: `fn name(params) = body` or `|params| body`.
+///
+/// Lambdas keep the local scope they were created in, so they can refer
+/// to `let` bindings after those have gone out of scope.
+#[derive(Debug)]
+pub struct Lambda {
+    /// `None` for anonymous lambdas
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    pub body: Expr,
+    pub captured: Option<Arc<Environment>>,
+}
+
+impl Display for Lambda {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}({})>", name, self.params.join(", ")),
+            None => write!(f, "<lambda |{}|>", self.params.join(", ")),
+        }
+    }
+}
+
+// ------------------------------------------------
+/// Result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    /// An exact number, from `NumericMode::Exact`
+    Rational(Arc<Rational>),
+    Complex(Complex),
+    Bool(bool),
+    Str(Arc<str>),
+    Vector(Vec2),
+    Quantity(Quantity),
+    /// An amount of p23's `Money`, kept in whole cents
+    Money(Money),
+    List(Arc<Vec<Value>>),
+    /// Named fields, kept in name order
+    Record(Arc<BTreeMap<String, Value>>),
+    /// Items produced on demand; see `Seq`
+    Seq(Arc<Seq>),
+    Function(Arc<Lambda>),
+}
+
+impl Value {
+    /// Short name of the value's type, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Rational(_) => "rational",
+            Value::Complex(_) => "complex",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Vector(_) => "vector",
+            Value::Quantity(_) => "quantity",
+            Value::Money(_) => "money",
+            Value::List(_) => "list",
+            Value::Record(_) => "record",
+            Value::Seq(_) => "sequence",
+            Value::Function(_) => "function",
+        }
+    }
+
+    pub fn list(items: Vec<Value>) -> Self {
+        Value::List(Arc::new(items))
+    }
+
+    pub fn record(fields: impl IntoIterator<Item = (String, Value)>) -> Self {
+        Value::Record(Arc::new(fields.into_iter().collect()))
+    }
+
+    pub fn seq(seq: Seq) -> Self {
+        Value::Seq(Arc::new(seq))
+    }
+
+    /// Any number as a float; ints are widened and rationals rounded.
+    pub fn as_number(&self, span: Span) -> Result<f64, EvalError> {
+        match self {
+            Value::Int(n) => Ok(*n as f64),
+            Value::Float(n) => Ok(*n),
+            Value::Rational(r) => Ok(r.to_f64()),
+            other => Err(other.mismatch("number", span)),
+        }
+    }
+
+    /// Conditions must be bools; numbers are not truthy.
+    pub fn as_bool(&self, span: Span) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            other => Err(other.mismatch("bool", span)),
+        }
+    }
+
+    /// Ints, and rationals that are whole numbers; floats are not
+    /// truncated silently.
+    pub fn as_int(&self, span: Span) -> Result<i64, EvalError> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            Value::Rational(r) if r.is_integer() => {
+                r.to_i64().ok_or(EvalError::IntegerOverflow { span })
+            }
+            other => Err(other.mismatch("int", span)),
+        }
+    }
+
+    pub fn as_vector(&self, span: Span) -> Result<Vec2, EvalError> {
+        match self {
+            Value::Vector(v) => Ok(*v),
+            other => Err(other.mismatch("vector", span)),
+        }
+    }
+
+    pub fn as_list(&self, span: Span) -> Result<&[Value], EvalError> {
+        match self {
+            Value::List(items) => Ok(items),
+            other => Err(other.mismatch("list", span)),
+        }
+    }
+
+    pub fn as_function(&self, span: Span) -> Result<&Arc<Lambda>, EvalError> {
+        match self {
+            Value::Function(lambda) => Ok(lambda),
+            other => Err(other.mismatch("function", span)),
+        }
+    }
+
+    /// The language's `==`: numbers compare by numeric value (exactly
+    /// between ints and rationals, and a complex number equals a real one
+    /// when its imaginary part is zero),
+    /// quantities of one dimension by amount (so `1 km == 1000 m`), lists
+    /// element-wise, records field by field, and values of otherwise
+    /// different types are unequal.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f64 == *b,
+            (Value::Rational(a), Value::Int(b)) | (Value::Int(b), Value::Rational(a)) => {
+                **a == Rational::from(*b)
+            }
+            (Value::Rational(a), Value::Float(b)) | (Value::Float(b), Value::Rational(a)) => {
+                a.to_f64() == *b
+            }
+            (Value::Complex(c), n @ (Value::Int(_) | Value::Float(_) | Value::Rational(_)))
+            | (n @ (Value::Int(_) | Value::Float(_) | Value::Rational(_)), Value::Complex(c)) => {
+                c.im == 0.0 && n.as_number(Span::default()).is_ok_and(|n| n == c.re)
+            }
+            (Value::Quantity(a), Value::Quantity(b)) => a.dim == b.dim && a.si() == b.si(),
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y))
+            }
+            (Value::Record(a), Value::Record(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|((k, x), (l, y))| k == l && x.equals(y))
+            }
+            _ => self == other,
+        }
+    }
+
+    pub(crate) fn mismatch(&self, expected: &str, span: Span) -> EvalError {
+        EvalError::TypeMismatch {
+            expected: expected.to_string(),
+            found: self.type_name().to_string(),
+            span,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<Rational> for Value {
+    fn from(r: Rational) -> Self {
+        Value::Rational(Arc::new(r))
+    }
+}
+
+impl From<Complex> for Value {
+    fn from(c: Complex) -> Self {
+        Value::Complex(c)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<Vec2> for Value {
+    fn from(v: Vec2) -> Self {
+        Value::Vector(v)
+    }
+}
+
+/// Dimensionless quantities are plain floats.
+impl From<Quantity> for Value {
+    fn from(q: Quantity) -> Self {
+        if q.dim.is_none() {
+            Value::Float(q.si())
+        } else {
+            Value::Quantity(q)
+        }
+    }
+}
+
+impl From<Money> for Value {
+    fn from(money: Money) -> Self {
+        Value::Money(money)
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Int(n) => Value::Int(*n),
+            // Only exact mode evaluates these, as `NumericMode::big_int` checks
+            Literal::BigInt(n) => Value::from(Rational::integer(n.clone())),
+            Literal::Float(n) => Value::Float(*n),
+            Literal::Bool(b) => Value::Bool(*b),
+            Literal::Str(s) => Value::from(s.as_str()),
+            Literal::Quantity(n, unit) => Value::Quantity(Quantity::new(*n, unit)),
+            Literal::Imaginary(n) => Value::Complex(Complex::new(0.0, *n)),
+        }
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::list(items)
+    }
+}
+
+/// Structural equality: `Int(1)` and `Float(1.0)` differ, unlike the
+/// language's `==` (see `Value::equals`). Functions compare by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::Complex(a), Value::Complex(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Quantity(a), Value::Quantity(b)) => a == b,
+            (Value::Money(a), Value::Money(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Record(a), Value::Record(b)) => a == b,
+            (Value::Seq(a), Value::Seq(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq<i64> for Value {
+    fn eq(&self, other: &i64) -> bool {
+        matches!(self, Value::Int(n) if n == other)
+    }
+}
+
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        matches!(self, Value::Float(n) if n == other)
+    }
+}
+
+impl PartialEq<bool> for Value {
+    fn eq(&self, other: &bool) -> bool {
+        matches!(self, Value::Bool(b) if b == other)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write_float(f, *n),
+            Value::Rational(r) => write!(f, "{}", r),
+            Value::Complex(c) => write!(f, "{}", c),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write_quoted(f, s),
+            Value::Vector(v) => {
+                write!(f, "vec(")?;
+                write_float(f, v.x)?;
+                write!(f, ", ")?;
+                write_float(f, v.y)?;
+                write!(f, ")")
+            }
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::Money(money) => {
+                let cents = money.cents();
+                let sign = if cents < 0 { "-" } else { "" };
+                let abs = cents.unsigned_abs();
+                write!(f, "money({}{}.{:02})", sign, abs / 100, abs % 100)
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Record(fields) if fields.is_empty() => write!(f, "{{}}"),
+            Value::Record(fields) => {
+                write!(f, "{{ ")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Seq(seq) => write!(f, "{}", seq),
+            Value::Function(lambda) => write!(f, "{}", lambda),
+        }
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Float(2.5).to_string(), "2.5");
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        assert_eq!(Value::from("a\"b").to_string(), r#""a\"b""#);
+        let list = Value::list(vec![1.into(), Value::list(vec![2.0.into(), true.into()])]);
+        assert_eq!(list.to_string(), "[1, [2.0, true]]");
+        let v = Value::from(Vec2::new(1.0, -0.5));
+        assert_eq!(v.to_string(), "vec(1.0, -0.5)");
+        assert_eq!(Value::from(Money::new(1205)).to_string(), "money(12.05)");
+        assert_eq!(Value::from(Money::new(-7)).to_string(), "money(-0.07)");
+    }
+
+    #[test]
+    fn test_function_display() {
+        let body: Expr = "x * x".parse().unwrap();
+        let named = Lambda {
+            name: Some("sq".to_string()),
+            params: vec!["x".to_string()],
+            body: body.clone(),
+            captured: None,
+        };
+        assert_eq!(named.to_string(), "<fn sq(x)>");
+
+        let anonymous = Lambda {
+            name: None,
+            params: vec!["x".to_string(), "y".to_string()],
+            body,
+            captured: None,
+        };
+        assert_eq!(anonymous.to_string(), "<lambda |x, y|>");
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let list = Value::list(vec![]);
+        let err = list.as_number(Span::new(1, 3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "type mismatch: expected number, found list"
+        );
+        assert_eq!(err.span(), Span::new(1, 3));
+    }
+
+    #[test]
+    fn test_compare_with_primitives() {
+        assert_eq!(Value::Float(3.0), 3.0);
+        assert_eq!(Value::Int(3), 3);
+        assert_eq!(Value::Bool(true), true);
+        assert_ne!(Value::Int(3), 3.0);
+        assert_ne!(Value::list(vec![3.0.into()]), 3.0);
+    }
+
+    #[test]
+    fn test_equals_coerces_numbers_only() {
+        assert!(Value::Int(2).equals(&Value::Float(2.0)));
+        assert_ne!(Value::Int(2), Value::Float(2.0));
+        assert!(!Value::Int(1).equals(&Value::Bool(true)));
+        assert!(!Value::from("1").equals(&Value::Int(1)));
+
+        let ints = Value::list(vec![1.into(), 2.into()]);
+        let floats = Value::list(vec![1.0.into(), 2.0.into()]);
+        assert!(ints.equals(&floats));
+        assert!(!Value::Float(f64::NAN).equals(&Value::Float(f64::NAN)));
+    }
+
+    #[test]
+    fn test_records() {
+        let record = |qty: Value| {
+            Value::record([
+                ("qty".to_string(), qty),
+                ("price".to_string(), Value::Int(10)),
+            ])
+        };
+        assert_eq!(record(3.into()).to_string(), "{ price: 10, qty: 3 }");
+        assert_eq!(Value::record([]).to_string(), "{}");
+        assert_eq!(record(3.into()).type_name(), "record");
+        assert!(record(3.into()).equals(&record(3.0.into())));
+        assert_ne!(record(3.into()), record(3.0.into()));
+        assert!(!record(3.into()).equals(&Value::record([])));
+    }
+
+    #[test]
+    fn test_rationals() {
+        let half = Value::from(Rational::from_decimal(0.5).unwrap());
+        assert_eq!(half.to_string(), "1/2");
+        assert!(half.equals(&Value::Float(0.5)));
+        assert_eq!(half.as_number(Span::default()), Ok(0.5));
+        assert!(half.as_int(Span::default()).is_err());
+
+        let seven = Value::from(Rational::from(7));
+        assert_eq!(seven.to_string(), "7");
+        assert!(seven.equals(&Value::Int(7)));
+        assert_ne!(seven, Value::Int(7));
+        assert_eq!(seven.as_int(Span::default()), Ok(7));
+    }
+}
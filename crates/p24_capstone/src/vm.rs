@@ -0,0 +1,1545 @@
+use std::{
+    cell::OnceCell,
+    io::{self, Read, Write},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use p10_iterator_collect::Stack;
+
+use crate::{
+    ast::{BinOp, Expr, ExprKind, Literal, Pattern, UnaryOp},
+    codec::{self, Decoder, Encoder, Kind},
+    env::{Bindings, Environment},
+    error::{CodecError, EvalError, ParseError},
+    eval::{
+        BuiltinFn, Interpreter, apply_binary, apply_unary, convert, field, matches_pattern,
+        numeric_builtin,
+    },
+    exact::{BigInt, NumericMode},
+    lexer::Span,
+    limits::{EvalLimits, Meter},
+    registry::{FunctionRegistry, NativeFn},
+    units::Unit,
+    value::{Lambda, Value},
+};
+
+// ------------------------------------------------
+/// One stack-machine instruction.
+///
+/// Spans travel with the instructions that can fail, so errors point at
+/// the same source text as the tree walker's.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Push a literal value
+    Literal(Value),
+    /// Push an integer literal too big for an `i64`, in exact mode
+    BigInt {
+        value: BigInt,
+        span: Span,
+    },
+    /// Push the free variable in the given slot
+    Global {
+        slot: usize,
+        span: Span,
+    },
+    /// Push a `let`-bound local, counted from the outermost one
+    Local(usize),
+    Unary {
+        op: UnaryOp,
+        span: Span,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Span,
+        rhs: Span,
+        span: Span,
+    },
+    /// Re-express the top of the stack in another unit
+    Convert {
+        unit: &'static Unit,
+        span: Span,
+    },
+    /// Pop `argc` arguments and call the callee with them
+    Call {
+        callee: Callee,
+        argc: usize,
+        span: Span,
+    },
+    /// Move the top of the stack into a new local
+    Bind,
+    /// Drop the innermost local
+    Unbind,
+    /// Push a closure over the current locals; the index is into the lambda table
+    Lambda(usize),
+    /// Continue at the given instruction
+    Jump(usize),
+    /// Pop a bool and jump to `target` if it equals `when`
+    JumpIf {
+        when: bool,
+        target: usize,
+        span: Span,
+    },
+    /// Pop one value per field name, in order, and push them as a record
+    Record(Vec<String>),
+    /// Replace a record on top of the stack with one of its fields
+    Field {
+        name: String,
+        record: Span,
+        span: Span,
+    },
+    /// Replace the top of the stack with whether it matches the pattern
+    Test(Pattern),
+    /// Fail with the top of the stack as the value no `match` arm took
+    NoMatch {
+        span: Span,
+    },
+}
+
+/// Where a call finds its function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Callee {
+    /// A `let`-bound local holding a function value
+    Local(usize),
+    /// A free name: a function in the bindings, otherwise a builtin
+    Global(usize),
+    /// A function value pushed before the arguments, as for `f(1)(2)`
+    Stack,
+}
+
+/// A free name the compiled code refers to.
+#[derive(Debug, Clone)]
+struct Slot {
+    name: String,
+    /// Used when the bindings have no such name
+    constant: Option<Value>,
+    /// Fast path for numeric builtins called by this name
+    builtin: Option<(usize, BuiltinFn)>,
+    /// Registered function called by this name
+    native: Option<NativeFn>,
+}
+
+impl Slot {
+    fn new(name: &str) -> Self {
+        let constant = match name {
+            "pi" => Some(Value::Float(std::f64::consts::PI)),
+            "e" => Some(Value::Float(std::f64::consts::E)),
+            _ => None,
+        };
+        Slot {
+            name: name.to_string(),
+            constant,
+            builtin: numeric_builtin(name),
+            native: None,
+        }
+    }
+}
+
+/// Source of a lambda created by `Op::Lambda`.
+#[derive(Debug, Clone)]
+struct LambdaProto {
+    params: Vec<String>,
+    body: Expr,
+    /// Names of the locals in scope, in `Local` order
+    locals: Vec<String>,
+}
+
+// ------------------------------------------------
+/// An expression compiled once to bytecode, ready to run against many
+/// sets of variable bindings.
+///
+/// Results, including errors and their spans, match `Interpreter::eval`
+/// with the same bindings as globals. Lambda bodies are kept as trees and
+/// run by the interpreter when called.
+///
+/// Under `EvalLimits` every instruction run is a step, so step budgets are
+/// comparable to, not identical with, the tree walker's.
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    ops: Vec<Op>,
+    slots: Vec<Slot>,
+    lambdas: Vec<LambdaProto>,
+    limits: EvalLimits,
+    registry: Arc<FunctionRegistry>,
+    mode: NumericMode,
+    /// Whole source, where running out of steps or time is reported
+    span: Span,
+}
+
+impl CompiledExpr {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Enforce `limits` on every run, including lambdas it calls.
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> EvalLimits {
+        self.limits
+    }
+
+    /// Make the host functions in `registry` callable by name; each call
+    /// site is resolved against it here rather than on every run.
+    pub fn with_registry(mut self, registry: impl Into<Arc<FunctionRegistry>>) -> Self {
+        self.registry = registry.into();
+        for slot in &mut self.slots {
+            slot.native = self.registry.get(&slot.name).cloned();
+        }
+        self
+    }
+
+    pub fn registry(&self) -> &Arc<FunctionRegistry> {
+        &self.registry
+    }
+
+    /// Run number literals in `mode`, as `Interpreter::with_mode` does.
+    pub fn with_mode(mut self, mode: NumericMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> NumericMode {
+        self.mode
+    }
+
+    /// Names of the free variables and functions, in slot order.
+    pub fn free_names(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|slot| slot.name.as_str())
+    }
+
+    pub fn eval(&self, bindings: &Bindings) -> Result<Value, EvalError> {
+        let globals: Vec<Option<&Value>> = self
+            .slots
+            .iter()
+            .map(|slot| bindings.get(&slot.name))
+            .collect();
+        // Only built when a function value or list builtin is called
+        let interp = OnceCell::new();
+        let interp = || {
+            interp.get_or_init(|| {
+                Interpreter::with_bindings(bindings)
+                    .with_registry(Arc::clone(&self.registry))
+                    .with_mode(self.mode)
+            })
+        };
+        let meter = Meter::new(self.limits);
+
+        let mut stack: Stack<Value> = Stack::new();
+        let mut locals: Vec<Value> = Vec::new();
+
+        let mut pc = 0;
+        while let Some(op) = self.ops.get(pc) {
+            pc += 1;
+            meter.step(self.span)?;
+            match op {
+                Op::Literal(value) => stack.push(self.mode.number(value.clone())),
+                Op::BigInt { value, span } => stack.push(self.mode.big_int(value, *span)?),
+                Op::Global { slot, span } => {
+                    let value = match (globals[*slot], &self.slots[*slot].constant) {
+                        (Some(value), _) => value.clone(),
+                        (None, Some(constant)) => constant.clone(),
+                        (None, None) => {
+                            return Err(EvalError::UnknownVariable {
+                                name: self.slots[*slot].name.clone(),
+                                span: *span,
+                            });
+                        }
+                    };
+                    stack.push(value);
+                }
+                Op::Local(index) => stack.push(locals[*index].clone()),
+                Op::Unary { op, span } => {
+                    let value = pop(&mut stack);
+                    stack.push(apply_unary(*op, &value, *span)?);
+                }
+                Op::Convert { unit, span } => {
+                    let value = pop(&mut stack);
+                    stack.push(convert(&value, unit, *span)?);
+                }
+                Op::Binary { op, lhs, rhs, span } => {
+                    let r = pop(&mut stack);
+                    let l = pop(&mut stack);
+                    let value = apply_binary(*op, &l, &r, [*lhs, *rhs, *span])?;
+                    stack.push(meter.checked(value, *span)?);
+                }
+                Op::Call { callee, argc, span } => {
+                    let mut args: Vec<Value> = (0..*argc).map(|_| pop(&mut stack)).collect();
+                    args.reverse();
+
+                    let result = match *callee {
+                        Callee::Stack => {
+                            let function = pop(&mut stack);
+                            interp().call_in(function.as_function(*span)?, args, *span, &meter)?
+                        }
+                        Callee::Local(index) => interp().call_in(
+                            locals[index].as_function(*span)?,
+                            args,
+                            *span,
+                            &meter,
+                        )?,
+                        Callee::Global(index) => {
+                            let slot = &self.slots[index];
+                            match (globals[index], slot.builtin, &slot.native) {
+                                (Some(value), _, _) => interp().call_in(
+                                    value.as_function(*span)?,
+                                    args,
+                                    *span,
+                                    &meter,
+                                )?,
+                                // Exact mode keeps some exact, which `call_builtin` knows
+                                (None, Some((arity, func)), _)
+                                    if args.len() == arity && self.mode == NumericMode::Float =>
+                                {
+                                    let nums = args
+                                        .iter()
+                                        .map(|arg| arg.as_number(*span))
+                                        .collect::<Result<Vec<f64>, EvalError>>()?;
+                                    Value::Float(func(&nums))
+                                }
+                                (None, _, Some(native)) => native.call(&args, *span)?,
+                                (None, _, None) => {
+                                    interp().call_builtin(&slot.name, args, *span, &meter)?
+                                }
+                            }
+                        }
+                    };
+                    stack.push(meter.checked(result, *span)?);
+                }
+                Op::Bind => locals.push(pop(&mut stack)),
+                Op::Unbind => {
+                    locals.pop();
+                }
+                Op::Lambda(index) => {
+                    let proto = &self.lambdas[*index];
+                    let mut env = Environment::new();
+                    for (name, value) in proto.locals.iter().zip(&locals) {
+                        env.define(name.clone(), value.clone());
+                    }
+                    let lambda = Lambda {
+                        name: None,
+                        params: proto.params.clone(),
+                        body: proto.body.clone(),
+                        captured: (!proto.locals.is_empty()).then(|| Arc::new(env)),
+                    };
+                    stack.push(Value::Function(Arc::new(lambda)));
+                }
+                Op::Jump(target) => pc = *target,
+                Op::JumpIf { when, target, span } => {
+                    if pop(&mut stack).as_bool(*span)? == *when {
+                        pc = *target;
+                    }
+                }
+                Op::Record(names) => {
+                    let mut values: Vec<Value> =
+                        (0..names.len()).map(|_| pop(&mut stack)).collect();
+                    values.reverse();
+                    stack.push(Value::record(names.iter().cloned().zip(values)));
+                }
+                Op::Field { name, record, span } => {
+                    let value = pop(&mut stack);
+                    stack.push(field(&value, name, *record, *span)?);
+                }
+                Op::Test(pattern) => {
+                    let value = pop(&mut stack);
+                    stack.push(Value::Bool(matches_pattern(pattern, &value)));
+                }
+                Op::NoMatch { span } => {
+                    return Err(EvalError::NoMatch {
+                        value: pop(&mut stack).to_string(),
+                        span: *span,
+                    });
+                }
+            }
+        }
+
+        Ok(pop(&mut stack))
+    }
+}
+
+fn pop(stack: &mut Stack<Value>) -> Value {
+    stack.pop().expect("compiled code keeps the stack balanced")
+}
+
+impl FromStr for CompiledExpr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expr: Expr = s.parse()?;
+        Ok(compile(&expr))
+    }
+}
+
+// ------------------------------------------------
+impl CompiledExpr {
+    /// Save the bytecode in the versioned binary format, so a formula can
+    /// be cached on disk and run later without parsing or compiling it;
+    /// returns the number of bytes written.
+    ///
+    /// The numeric mode and limits are saved with the code, so it runs as
+    /// it did before; registered functions are Rust code, and have to be
+    /// attached again with `with_registry`.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<usize> {
+        let mut enc = Encoder::new();
+        enc.span(self.span);
+        enc.bool(self.mode == NumericMode::Exact);
+        encode_limits(&mut enc, &self.limits);
+        enc.usize(self.slots.len());
+        for slot in &self.slots {
+            enc.str(&slot.name);
+        }
+        enc.usize(self.lambdas.len());
+        for proto in &self.lambdas {
+            enc.strs(&proto.params);
+            enc.expr(&proto.body);
+            enc.strs(&proto.locals);
+        }
+        enc.usize(self.ops.len());
+        for op in &self.ops {
+            encode_op(&mut enc, op);
+        }
+        codec::write_framed(Kind::Compiled, &enc.finish(), writer)
+    }
+
+    /// Load bytecode saved by `write_to`, with the numeric mode and limits
+    /// it was saved with and an empty registry.
+    ///
+    /// Besides the checks on the file itself, the code is verified before
+    /// it is returned, so a damaged file cannot make `eval` panic.
+    pub fn read_from<R: Read>(reader: R) -> Result<CompiledExpr, CodecError> {
+        let payload = codec::read_framed(Kind::Compiled, reader)?;
+        let mut dec = Decoder::new(&payload);
+        let span = dec.span()?;
+        let mode = match dec.bool()? {
+            true => NumericMode::Exact,
+            false => NumericMode::Float,
+        };
+        let limits = decode_limits(&mut dec)?;
+        let slots = (0..dec.usize()?)
+            .map(|_| Ok(Slot::new(&dec.string()?)))
+            .collect::<Result<_, CodecError>>()?;
+        let lambdas = (0..dec.usize()?)
+            .map(|_| {
+                Ok(LambdaProto {
+                    params: dec.strings()?,
+                    body: dec.expr()?,
+                    locals: dec.strings()?,
+                })
+            })
+            .collect::<Result<_, CodecError>>()?;
+        let ops = (0..dec.usize()?)
+            .map(|_| decode_op(&mut dec))
+            .collect::<Result<_, CodecError>>()?;
+        dec.finish()?;
+
+        let code = CompiledExpr {
+            ops,
+            slots,
+            lambdas,
+            limits,
+            registry: Arc::default(),
+            mode,
+            span,
+        };
+        code.verify()?;
+        Ok(code)
+    }
+
+    /// Check that every path through the code reaches each instruction with
+    /// the same stack height and number of locals, never indexes past a
+    /// table or pops an empty stack, and ends with exactly one value.
+    ///
+    /// Jumps only go forward, so one pass in order sees every way into an
+    /// instruction before the instruction itself.
+    fn verify(&self) -> Result<(), CodecError> {
+        let mut states: Vec<Option<(usize, usize)>> = vec![None; self.ops.len() + 1];
+        states[0] = Some((0, 0));
+
+        for (at, op) in self.ops.iter().enumerate() {
+            let invalid = |reason: String| CodecError::InvalidCode { at, reason };
+            // Only reachable through a jump that was never emitted
+            let Some((height, locals)) = states[at] else {
+                continue;
+            };
+            let check = |index: usize, len: usize, what: &str| match index < len {
+                true => Ok(()),
+                false => Err(invalid(format!("{} {} out of {}", what, index, len))),
+            };
+
+            let (pops, pushes, locals_after) = match op {
+                Op::Literal(_) | Op::BigInt { .. } => (0, 1, locals),
+                Op::Global { slot, .. } => {
+                    check(*slot, self.slots.len(), "slot")?;
+                    (0, 1, locals)
+                }
+                Op::Local(index) => {
+                    check(*index, locals, "local")?;
+                    (0, 1, locals)
+                }
+                Op::Unary { .. } | Op::Convert { .. } => (1, 1, locals),
+                Op::Binary { .. } => (2, 1, locals),
+                Op::Call { callee, argc, .. } => match *callee {
+                    Callee::Local(index) => {
+                        check(index, locals, "local")?;
+                        (*argc, 1, locals)
+                    }
+                    Callee::Global(index) => {
+                        check(index, self.slots.len(), "slot")?;
+                        (*argc, 1, locals)
+                    }
+                    // The function comes off the stack with the arguments
+                    Callee::Stack => (*argc + 1, 1, locals),
+                },
+                Op::Bind => (1, 0, locals + 1),
+                Op::Unbind => {
+                    check(0, locals, "local")?;
+                    (0, 0, locals - 1)
+                }
+                Op::Lambda(index) => {
+                    check(*index, self.lambdas.len(), "lambda")?;
+                    (0, 1, locals)
+                }
+                Op::Jump(_) => (0, 0, locals),
+                Op::JumpIf { .. } => (1, 0, locals),
+                Op::Record(names) => (names.len(), 1, locals),
+                Op::Field { .. } | Op::Test(_) | Op::NoMatch { .. } => (1, 1, locals),
+            };
+            if height < pops {
+                return Err(invalid(format!("pops {} of {} values", pops, height)));
+            }
+            let after = (height - pops + pushes, locals_after);
+
+            let targets = match op {
+                Op::Jump(target) => vec![*target],
+                Op::JumpIf { target, .. } => vec![*target, at + 1],
+                Op::NoMatch { .. } => vec![],
+                _ => vec![at + 1],
+            };
+            for target in targets {
+                let state = states
+                    .get_mut(target)
+                    .filter(|_| target > at)
+                    .ok_or_else(|| invalid(format!("jumps to {}", target)))?;
+                match state {
+                    None => *state = Some(after),
+                    Some(before) if *before == after => {}
+                    Some(_) => {
+                        return Err(invalid(format!(
+                            "paths into instruction {} disagree",
+                            target
+                        )));
+                    }
+                }
+            }
+        }
+
+        match states[self.ops.len()] {
+            Some((1, 0)) => Ok(()),
+            _ => Err(CodecError::InvalidCode {
+                at: self.ops.len(),
+                reason: "does not end with exactly one value".to_string(),
+            }),
+        }
+    }
+}
+
+fn encode_op(enc: &mut Encoder, op: &Op) {
+    match op {
+        Op::Literal(value) => {
+            enc.u8(0);
+            enc.literal(&literal_of(value));
+        }
+        Op::Global { slot, span } => {
+            enc.u8(1);
+            enc.usize(*slot);
+            enc.span(*span);
+        }
+        Op::Local(index) => {
+            enc.u8(2);
+            enc.usize(*index);
+        }
+        Op::Unary { op, span } => {
+            enc.u8(3);
+            enc.unary_op(*op);
+            enc.span(*span);
+        }
+        Op::Binary { op, lhs, rhs, span } => {
+            enc.u8(4);
+            enc.bin_op(*op);
+            enc.span(*lhs);
+            enc.span(*rhs);
+            enc.span(*span);
+        }
+        Op::Convert { unit, span } => {
+            enc.u8(5);
+            enc.unit(unit);
+            enc.span(*span);
+        }
+        Op::Call { callee, argc, span } => {
+            enc.u8(6);
+            match *callee {
+                Callee::Local(index) => {
+                    enc.u8(0);
+                    enc.usize(index);
+                }
+                Callee::Global(index) => {
+                    enc.u8(1);
+                    enc.usize(index);
+                }
+                Callee::Stack => enc.u8(2),
+            }
+            enc.usize(*argc);
+            enc.span(*span);
+        }
+        Op::Bind => enc.u8(7),
+        Op::Unbind => enc.u8(8),
+        Op::Lambda(index) => {
+            enc.u8(9);
+            enc.usize(*index);
+        }
+        Op::Jump(target) => {
+            enc.u8(10);
+            enc.usize(*target);
+        }
+        Op::JumpIf { when, target, span } => {
+            enc.u8(11);
+            enc.bool(*when);
+            enc.usize(*target);
+            enc.span(*span);
+        }
+        Op::Record(names) => {
+            enc.u8(12);
+            enc.strs(names);
+        }
+        Op::Field { name, record, span } => {
+            enc.u8(13);
+            enc.str(name);
+            enc.span(*record);
+            enc.span(*span);
+        }
+        Op::Test(pattern) => {
+            enc.u8(14);
+            enc.pattern(pattern);
+        }
+        Op::NoMatch { span } => {
+            enc.u8(15);
+            enc.span(*span);
+        }
+        Op::BigInt { value, span } => {
+            enc.u8(16);
+            enc.literal(&Literal::BigInt(value.clone()));
+            enc.span(*span);
+        }
+    }
+}
+
+fn decode_op(dec: &mut Decoder) -> Result<Op, CodecError> {
+    let op = match dec.u8()? {
+        0 => Op::Literal(Value::from(&dec.literal()?)),
+        1 => Op::Global {
+            slot: dec.usize()?,
+            span: dec.span()?,
+        },
+        2 => Op::Local(dec.usize()?),
+        3 => Op::Unary {
+            op: dec.unary_op()?,
+            span: dec.span()?,
+        },
+        4 => Op::Binary {
+            op: dec.bin_op()?,
+            lhs: dec.span()?,
+            rhs: dec.span()?,
+            span: dec.span()?,
+        },
+        5 => Op::Convert {
+            unit: dec.unit()?,
+            span: dec.span()?,
+        },
+        6 => {
+            let callee = match dec.u8()? {
+                0 => Callee::Local(dec.usize()?),
+                1 => Callee::Global(dec.usize()?),
+                2 => Callee::Stack,
+                kind => return Err(dec.corrupt(format!("unknown callee kind {}", kind))),
+            };
+            Op::Call {
+                callee,
+                argc: dec.usize()?,
+                span: dec.span()?,
+            }
+        }
+        7 => Op::Bind,
+        8 => Op::Unbind,
+        9 => Op::Lambda(dec.usize()?),
+        10 => Op::Jump(dec.usize()?),
+        11 => Op::JumpIf {
+            when: dec.bool()?,
+            target: dec.usize()?,
+            span: dec.span()?,
+        },
+        12 => Op::Record(dec.strings()?),
+        13 => Op::Field {
+            name: dec.string()?,
+            record: dec.span()?,
+            span: dec.span()?,
+        },
+        14 => Op::Test(dec.pattern()?),
+        15 => Op::NoMatch { span: dec.span()? },
+        16 => match dec.literal()? {
+            Literal::BigInt(value) => Op::BigInt {
+                value,
+                span: dec.span()?,
+            },
+            _ => return Err(dec.corrupt("expected an integer literal".to_string())),
+        },
+        tag => return Err(dec.corrupt(format!("unknown instruction tag {}", tag))),
+    };
+    Ok(op)
+}
+
+/// Each limit as a flag for whether it is set, then its value.
+fn encode_limits(enc: &mut Encoder, limits: &EvalLimits) {
+    let mut limit = |n: Option<u64>| {
+        enc.bool(n.is_some());
+        enc.i64(n.unwrap_or(0) as i64);
+    };
+    limit(limits.max_steps);
+    limit(limits.max_depth.map(|n| n as u64));
+    limit(limits.max_len.map(|n| n as u64));
+    limit(limits.max_time.map(|time| time.as_secs()));
+    limit(limits.max_nesting.map(|n| n as u64));
+    enc.usize(
+        limits
+            .max_time
+            .map_or(0, |time| time.subsec_nanos() as usize),
+    );
+}
+
+fn decode_limits(dec: &mut Decoder) -> Result<EvalLimits, CodecError> {
+    let mut limit = || -> Result<Option<u64>, CodecError> {
+        let set = dec.bool()?;
+        let n = dec.i64()? as u64;
+        Ok(set.then_some(n))
+    };
+    let size = |n: Option<u64>| n.map(|n| usize::try_from(n).unwrap_or(usize::MAX));
+    let max_steps = limit()?;
+    let max_depth = size(limit()?);
+    let max_len = size(limit()?);
+    let secs = limit()?;
+    let max_nesting = size(limit()?);
+    let nanos = dec.usize()?;
+    if nanos >= 1_000_000_000 {
+        return Err(dec.corrupt(format!("{} nanoseconds is not under a second", nanos)));
+    }
+    Ok(EvalLimits {
+        max_steps,
+        max_depth,
+        max_len,
+        max_time: secs.map(|secs| Duration::new(secs, nanos as u32)),
+        max_nesting,
+    })
+}
+
+/// The literal a `Literal` instruction was compiled from.
+fn literal_of(value: &Value) -> Literal {
+    match value {
+        Value::Int(n) => Literal::Int(*n),
+        Value::Float(n) => Literal::Float(*n),
+        Value::Complex(c) => Literal::Imaginary(c.im),
+        Value::Bool(b) => Literal::Bool(*b),
+        Value::Str(s) => Literal::Str(s.to_string()),
+        Value::Quantity(q) => {
+            let unit = q.unit.expect("quantity literals are written in a unit");
+            Literal::Quantity(q.value, unit)
+        }
+        other => unreachable!("{} is not a literal", other),
+    }
+}
+
+// ------------------------------------------------
+/// Compile `expr` to bytecode.
+pub fn compile(expr: &Expr) -> CompiledExpr {
+    let mut compiler = Compiler {
+        code: CompiledExpr {
+            ops: Vec::new(),
+            slots: Vec::new(),
+            lambdas: Vec::new(),
+            limits: EvalLimits::default(),
+            registry: Arc::default(),
+            mode: NumericMode::default(),
+            span: expr.span,
+        },
+        locals: Vec::new(),
+    };
+    compiler.expr(expr);
+    compiler.code
+}
+
+struct Compiler {
+    code: CompiledExpr,
+    /// Names of the locals in scope, innermost last
+    locals: Vec<String>,
+}
+
+impl Compiler {
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(Literal::BigInt(value)) => self.emit(Op::BigInt {
+                value: value.clone(),
+                span: expr.span,
+            }),
+            ExprKind::Literal(literal) => self.emit(Op::Literal(Value::from(literal))),
+            ExprKind::Var(name) => match self.local(name) {
+                Some(index) => self.emit(Op::Local(index)),
+                None => {
+                    let slot = self.slot(name);
+                    self.emit(Op::Global {
+                        slot,
+                        span: expr.span,
+                    });
+                }
+            },
+            ExprKind::Unary { op, operand } => {
+                self.expr(operand);
+                self.emit(Op::Unary {
+                    op: *op,
+                    span: operand.span,
+                });
+            }
+            ExprKind::Binary { op, lhs, rhs } if op.is_short_circuit() => {
+                // Either operand can decide the result; each must be a bool
+                let decides = *op == BinOp::Or;
+                self.expr(lhs);
+                let lhs_jump = self.jump_if(decides, lhs.span);
+                self.expr(rhs);
+                let rhs_jump = self.jump_if(decides, rhs.span);
+                self.emit(Op::Literal(Value::Bool(!decides)));
+                let end = self.jump();
+                self.patch(lhs_jump);
+                self.patch(rhs_jump);
+                self.emit(Op::Literal(Value::Bool(decides)));
+                self.patch(end);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.emit(Op::Binary {
+                    op: *op,
+                    lhs: lhs.span,
+                    rhs: rhs.span,
+                    span: expr.span,
+                });
+            }
+            ExprKind::Call { name, args } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                let callee = match self.local(name) {
+                    Some(index) => Callee::Local(index),
+                    None => Callee::Global(self.slot(name)),
+                };
+                self.emit(Op::Call {
+                    callee,
+                    argc: args.len(),
+                    span: expr.span,
+                });
+            }
+            ExprKind::Apply { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Op::Call {
+                    callee: Callee::Stack,
+                    argc: args.len(),
+                    span: expr.span,
+                });
+            }
+            ExprKind::Let { name, value, body } => {
+                self.expr(value);
+                self.emit(Op::Bind);
+                self.locals.push(name.clone());
+                self.expr(body);
+                self.locals.pop();
+                self.emit(Op::Unbind);
+            }
+            ExprKind::Lambda { params, body } => {
+                self.code.lambdas.push(LambdaProto {
+                    params: params.clone(),
+                    body: (**body).clone(),
+                    locals: self.locals.clone(),
+                });
+                self.emit(Op::Lambda(self.code.lambdas.len() - 1));
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                let to_else = self.jump_if(false, cond.span);
+                self.expr(then_branch);
+                let to_end = self.jump();
+                self.patch(to_else);
+                self.expr(else_branch);
+                self.patch(to_end);
+            }
+            ExprKind::Convert { expr, unit } => {
+                self.expr(expr);
+                self.emit(Op::Convert {
+                    unit,
+                    span: expr.span,
+                });
+            }
+            ExprKind::Record { fields } => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+                let names = fields.iter().map(|(name, _)| name.clone()).collect();
+                self.emit(Op::Record(names));
+            }
+            ExprKind::Field { expr: record, name } => {
+                self.expr(record);
+                self.emit(Op::Field {
+                    name: name.clone(),
+                    record: record.span,
+                    span: expr.span,
+                });
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                // The value tested is kept in a local named by a keyword, so
+                // no variable in the arms can refer to it
+                self.expr(scrutinee);
+                self.emit(Op::Bind);
+                self.locals.push("match".to_string());
+                let value = self.locals.len() - 1;
+                let mut to_end = Vec::new();
+                for arm in arms {
+                    self.emit(Op::Local(value));
+                    self.emit(Op::Test(arm.pattern.clone()));
+                    let to_next = self.jump_if(false, arm.span);
+                    self.expr(&arm.body);
+                    to_end.push(self.jump());
+                    self.patch(to_next);
+                }
+                self.emit(Op::Local(value));
+                self.emit(Op::NoMatch { span: expr.span });
+                for at in to_end {
+                    self.patch(at);
+                }
+                self.locals.pop();
+                self.emit(Op::Unbind);
+            }
+        }
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.code.ops.push(op);
+    }
+
+    /// Emit a `Jump` to be patched later; returns its index.
+    fn jump(&mut self) -> usize {
+        self.emit(Op::Jump(usize::MAX));
+        self.code.ops.len() - 1
+    }
+
+    /// Emit a `JumpIf` to be patched later; returns its index.
+    fn jump_if(&mut self, when: bool, span: Span) -> usize {
+        self.emit(Op::JumpIf {
+            when,
+            target: usize::MAX,
+            span,
+        });
+        self.code.ops.len() - 1
+    }
+
+    /// Point the jump at `at` to the next instruction emitted.
+    fn patch(&mut self, at: usize) {
+        let here = self.code.ops.len();
+        match &mut self.code.ops[at] {
+            Op::Jump(target) | Op::JumpIf { target, .. } => *target = here,
+            other => unreachable!("patching a non-jump {:?}", other),
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local == name)
+    }
+
+    /// Slot index for a free name, added on first use.
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(index) = self.code.slots.iter().position(|slot| slot.name == name) {
+            return index;
+        }
+        self.code.slots.push(Slot::new(name));
+        self.code.slots.len() - 1
+    }
+}
+
+// ------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(pairs: &[(&str, f64)]) -> Bindings {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), Value::Float(value)))
+            .collect()
+    }
+
+    /// Evaluate with both backends and require the same outcome.
+    fn assert_equivalent(src: &str, bindings: &Bindings) {
+        let expr: Expr = src.parse().unwrap();
+        let tree = Interpreter::with_bindings(bindings).eval(&expr);
+        let vm = compile(&expr).eval(bindings);
+        match (&tree, &vm) {
+            // Function values compare by identity, so compare their display
+            (Ok(a), Ok(b)) => assert_eq!(a.to_string(), b.to_string(), "{}", src),
+            _ => assert_eq!(tree, vm, "{}", src),
+        }
+    }
+
+    #[test]
+    fn test_compiles_to_postfix() {
+        let code: CompiledExpr = "1 + 2 * 3".parse().unwrap();
+        let kinds: Vec<&str> = code
+            .ops()
+            .iter()
+            .map(|op| match op {
+                Op::Literal(_) => "num",
+                Op::Binary { op: BinOp::Add, .. } => "add",
+                Op::Binary { op: BinOp::Mul, .. } => "mul",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, vec!["num", "num", "num", "mul", "add"]);
+    }
+
+    #[test]
+    fn test_free_names() {
+        let code: CompiledExpr = "let y = 2 in x * y + sqrt(x) + z".parse().unwrap();
+        let names: Vec<&str> = code.free_names().collect();
+        assert_eq!(names, vec!["x", "sqrt", "z"]);
+    }
+
+    #[test]
+    fn test_compile_once_run_many() {
+        let code: CompiledExpr = "price * qty * (1 + rate)".parse().unwrap();
+        let mut row = bindings(&[("price", 10.0), ("qty", 3.0), ("rate", 0.5)]);
+        assert_eq!(code.eval(&row).unwrap(), 45.0);
+
+        row.insert("qty".to_string(), Value::Float(4.0));
+        assert_eq!(code.eval(&row).unwrap(), 60.0);
+    }
+
+    #[test]
+    fn test_errors_match_tree_walker() {
+        let code: CompiledExpr = "1 + foo".parse().unwrap();
+        assert_eq!(
+            code.eval(&Bindings::new()).unwrap_err(),
+            EvalError::UnknownVariable {
+                name: "foo".to_string(),
+                span: Span::new(4, 7),
+            }
+        );
+
+        let code: CompiledExpr = "1 / (x - 2)".parse().unwrap();
+        assert_eq!(
+            code.eval(&bindings(&[("x", 2.0)])).unwrap_err(),
+            EvalError::DivisionByZero {
+                span: Span::new(0, 11),
+            }
+        );
+    }
+
+    #[test]
+    fn test_exact_mode_matches_tree_walker() {
+        let row: Bindings = [("x".to_string(), Value::Int(3))].into_iter().collect();
+        for src in [
+            "1/3 + x/6",
+            "0.1 * x == 0.3",
+            "2 ^ 80 / x",
+            "let f = |n| n / 4 in f(x) + f(1)",
+            "sum(map(1..x, |n| 1/n))",
+            "float(x / 8) + sqrt(float(4))",
+            "1 / (x - 3)",
+            "123456789012345678901234567890 / x",
+            "round(7/2) + max(1/x, 1/4)",
+        ] {
+            let expr: Expr = src.parse().unwrap();
+            let tree = Interpreter::with_bindings(&row)
+                .with_mode(NumericMode::Exact)
+                .eval(&expr);
+            let vm = compile(&expr).with_mode(NumericMode::Exact).eval(&row);
+            assert_eq!(tree, vm, "{}", src);
+        }
+        let code = compile(&"1/4".parse().unwrap()).with_mode(NumericMode::Exact);
+        assert_eq!(code.eval(&Bindings::new()).unwrap().to_string(), "1/4");
+
+        // Float mode has no room for the big literal in either
+        let expr: Expr = "x + 99999999999999999999".parse().unwrap();
+        let tree = Interpreter::with_bindings(&row).eval(&expr);
+        assert!(matches!(tree, Err(EvalError::IntegerOverflow { .. })));
+        assert_eq!(compile(&expr).eval(&row), tree);
+    }
+
+    #[test]
+    fn test_registered_functions_match_tree_walker() {
+        let mut registry = FunctionRegistry::new();
+        registry
+            .register("clamp", |x: f64, lo: f64, hi: f64| x.clamp(lo, hi))
+            .register("twice", |n: i64| n * 2);
+        let registry = Arc::new(registry);
+
+        let row = bindings(&[("x", 12.0)]);
+        for src in [
+            "clamp(x, 0, 10)",
+            "let f = |v| clamp(v, 0, 1) in f(x)",
+            "twice(3) + twice(x)",
+            "clamp(x)",
+        ] {
+            let expr: Expr = src.parse().unwrap();
+            let tree = Interpreter::with_bindings(&row)
+                .with_registry(Arc::clone(&registry))
+                .eval(&expr);
+            let vm = compile(&expr)
+                .with_registry(Arc::clone(&registry))
+                .eval(&row);
+            assert_eq!(tree, vm, "{}", src);
+        }
+
+        let code: CompiledExpr = "clamp(x, 0, 10)".parse().unwrap();
+        assert!(matches!(
+            code.eval(&row),
+            Err(EvalError::UnknownFunction { .. })
+        ));
+    }
+
+    #[test]
+    fn test_limits_match_tree_walker() {
+        use crate::limits::Limit;
+
+        let mut interp = Interpreter::new();
+        interp
+            .exec_str("fn down(n) = if n == 0 then 0 else down(n - 1)")
+            .unwrap();
+        let globals: Bindings = interp
+            .vars()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+
+        let limits = EvalLimits::none().max_depth(20).max_len(3);
+        for src in [
+            "down(4)",
+            r#""ab" + "cd""#,
+            "len(list(1, 2, 3, 4))",
+            "map(list(1, 2), |x| list(x, x, x, x))",
+        ] {
+            let expr: Expr = src.parse().unwrap();
+            let tree = Interpreter::with_bindings(&globals)
+                .with_limits(limits)
+                .eval(&expr);
+            let vm = compile(&expr).with_limits(limits).eval(&globals);
+            assert_eq!(tree, vm, "{}", src);
+        }
+
+        // The tree walker also counts frames for the top level, which the
+        // VM runs without recursing, so only compare which limit was hit
+        for src in ["down(19)", "let f = |n| down(n) in f(25)"] {
+            let expr: Expr = src.parse().unwrap();
+            let tree = Interpreter::with_bindings(&globals)
+                .with_limits(limits)
+                .eval(&expr);
+            let vm = compile(&expr).with_limits(limits).eval(&globals);
+            for result in [tree, vm] {
+                assert!(
+                    matches!(
+                        result,
+                        Err(EvalError::LimitExceeded {
+                            limit: Limit::Depth(20),
+                            ..
+                        })
+                    ),
+                    "{}",
+                    src
+                );
+            }
+        }
+
+        // Steps count instructions here, but still stop runaway loops
+        let code: CompiledExpr = "down(1000)".parse().unwrap();
+        let err = code
+            .with_limits(EvalLimits::none().max_steps(500))
+            .eval(&globals)
+            .unwrap_err();
+        assert!(matches!(err, EvalError::LimitExceeded { .. }));
+    }
+
+    #[test]
+    fn test_equivalence_on_examples() {
+        let rows = [
+            bindings(&[]),
+            bindings(&[("x", 3.0), ("y", 4.0)]),
+            bindings(&[("x", -1.5), ("y", 0.0), ("pi", 3.0)]),
+        ];
+        let sources = [
+            "1 + 2 * 3 - 4 / 5",
+            "2 ^ 3 ^ 2 % 7",
+            "-x ^ 2 + -(y)",
+            "sqrt(x^2 + y^2)",
+            "max(x, y) - min(x, y) + abs(x)",
+            "x / y",
+            "x % y",
+            "pi * e",
+            "let x = 10 in x * y",
+            "let a = x in let b = a + 1 in let a = b * 2 in a + b",
+            "(let z = 1 in z) + z",
+            "let f = |n| n * x in f(2) + f(y)",
+            "let k = 2 in map(list(1, 2, 3), |n| n * k)",
+            "filter(list(x, y, 1), |v| v > 0)",
+            "sum(map(1..5, |n| n * x)) + product([x, y, 2])",
+            "collect(take(filter(fib(), |n| n > y), 3))",
+            "if x > y then x else y",
+            "if x then 1 else 2",
+            "x < 1 && y >= 1 || !(x == y)",
+            "x > 0 || 1 / 0 > 0",
+            "x > 0 && 1 / 0 > 0",
+            "(x && true) || y",
+            "1 < 2 && x",
+            "let t = x != y in if t && !t then 0 else let u = 1 in u + x",
+            "if y == 0 then 0 else x / y",
+            "\"a\" + \"b\" < \"abc\"",
+            "9223372036854775807 + 1",
+            "-(2 ^ 63 - 1) - 2",
+            "7 / 2 + 7 % 2 + 2 ^ -1",
+            "int(x) + float(2)",
+            "fold(list(1, 2, 3), x, |a, b| a * b)",
+            "let n = 5 in |v| v + n",
+            "len(list(x, y))",
+            "x * 3 m + 2 m -> cm",
+            "(20 C -> F) - x * 1 F",
+            "if x > y then 1 km else y * 1 m",
+            "x * 1 m + y * 1 s",
+            "let v = vec(x, y) in normalize(v * 2 - vec(1, 1)) / length(v)",
+            "dot(vec(x, 1), -vec(y, x)) + length(vec(x, y) + 1)",
+            "sqrt(1, 2)",
+            "nope(x)",
+            "let f = 3 in f(1)",
+            "list(1) + x",
+            "-list()",
+            "x(1)",
+            "{ a: x, b: { c: y } }.b.c * 2",
+            "{ a: x }.b",
+            "(x).a",
+            "match x { 0 => \"zero\", ..0 => \"neg\", 1..3.5 => \"some\", _ => \"many\" }",
+            "match x > y { true => 1 }",
+            "let v = 1 in let g = match v + x { 4 => |n| v, _ => let w = 2 in |n| n * w + v } in g(1)",
+            "match { p: x }.p { -1.5 => 1 }",
+            "(x + 2i) * (y - 1i) / 3i - 1i ^ x",
+            "sqrt(x - 4) + abs(3 + 4i * y) + arg(x) * 1i",
+            "let p = polar(rect(x, y)) in p.r + re(conj(x + y * 1i))",
+            "(1 + x * 1i) % 2",
+            "1i < x",
+            "(|a, b| a * b)(x, y)",
+            "let adder = |n| |v| v + n in adder(x)(y)",
+            "{ f: |v| v - x }.f(y)",
+            "(x)(1)",
+            "(|v| v)(x, y)",
+        ];
+        for row in &rows {
+            for src in sources {
+                assert_equivalent(src, row);
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_circuit_jumps() {
+        let code: CompiledExpr = "a && b".parse().unwrap();
+        assert!(matches!(
+            code.ops()[1],
+            Op::JumpIf {
+                when: false,
+                target: 6,
+                ..
+            }
+        ));
+        let row: Bindings = [("a".to_string(), Value::Bool(false))].into();
+        // `b` is never read, so it need not be bound
+        assert_eq!(code.eval(&row).unwrap(), false);
+    }
+
+    #[test]
+    fn test_equivalence_with_function_bindings() {
+        let mut interp = Interpreter::new();
+        interp.exec_str("fn sq(v) = v * v").unwrap();
+        interp.exec_str("fn sqrt(v) = -v").unwrap();
+        let mut row = bindings(&[("x", 3.0)]);
+        for name in ["sq", "sqrt"] {
+            row.insert(name.to_string(), interp.get_var(name).unwrap().clone());
+        }
+
+        // A binding shadows the builtin of the same name in both backends
+        assert_equivalent("sqrt(x)", &row);
+        assert_equivalent("sq(x) + sq(2)", &row);
+        assert_equivalent("map(list(1, 2), sq)", &row);
+        assert_eq!(
+            compile(&"sqrt(x)".parse().unwrap()).eval(&row).unwrap(),
+            -3.0
+        );
+    }
+
+    /// Small deterministic generator so the property test needs no crates.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn random_expr(rng: &mut Lcg, depth: u32, locals: &mut Vec<String>) -> Expr {
+        let leaf = depth == 0 || rng.next(4) == 0;
+        if leaf {
+            return match rng.next(4) {
+                0 => Expr::int(rng.next(7) as i64 - 2),
+                1 => Expr::float(rng.next(7) as f64 * 0.5 - 1.0),
+                2 if !locals.is_empty() => {
+                    Expr::var(locals[rng.next(locals.len() as u64) as usize].clone())
+                }
+                _ => Expr::var(["x", "y", "z"][rng.next(3) as usize]),
+            };
+        }
+        match rng.next(8) {
+            0 => Expr::unary(UnaryOp::Neg, random_expr(rng, depth - 1, locals)),
+            1 => {
+                let name = ["a", "b", "x"][rng.next(3) as usize].to_string();
+                let value = random_expr(rng, depth - 1, locals);
+                locals.push(name.clone());
+                let body = random_expr(rng, depth - 1, locals);
+                locals.pop();
+                Expr::let_in(name, value, body)
+            }
+            2 => {
+                let name = ["sqrt", "abs", "max"][rng.next(3) as usize];
+                let argc = if name == "max" { 2 } else { 1 };
+                let args = (0..argc)
+                    .map(|_| random_expr(rng, depth - 1, locals))
+                    .collect();
+                Expr::call(name, args)
+            }
+            3 => Expr::if_else(
+                random_condition(rng, depth - 1, locals),
+                random_expr(rng, depth - 1, locals),
+                random_expr(rng, depth - 1, locals),
+            ),
+            _ => {
+                let ops = [
+                    BinOp::Add,
+                    BinOp::Sub,
+                    BinOp::Mul,
+                    BinOp::Div,
+                    BinOp::Rem,
+                    BinOp::Pow,
+                ];
+                let op = ops[rng.next(ops.len() as u64) as usize];
+                let lhs = random_expr(rng, depth - 1, locals);
+                let rhs = random_expr(rng, depth - 1, locals);
+                Expr::binary(op, lhs, rhs)
+            }
+        }
+    }
+
+    /// Mostly bools, built from comparisons and logic operators.
+    fn random_condition(rng: &mut Lcg, depth: u32, locals: &mut Vec<String>) -> Expr {
+        if depth == 0 || rng.next(3) == 0 {
+            let ops = [BinOp::Lt, BinOp::Le, BinOp::Eq, BinOp::Ne];
+            let op = ops[rng.next(ops.len() as u64) as usize];
+            let lhs = random_expr(rng, depth.saturating_sub(1), locals);
+            let rhs = random_expr(rng, depth.saturating_sub(1), locals);
+            return Expr::binary(op, lhs, rhs);
+        }
+        match rng.next(4) {
+            0 => Expr::unary(UnaryOp::Not, random_condition(rng, depth - 1, locals)),
+            // Occasionally not a bool at all, to exercise the type errors
+            1 => random_expr(rng, depth - 1, locals),
+            _ => {
+                let op = [BinOp::And, BinOp::Or][rng.next(2) as usize];
+                let lhs = random_condition(rng, depth - 1, locals);
+                let rhs = random_condition(rng, depth - 1, locals);
+                Expr::binary(op, lhs, rhs)
+            }
+        }
+    }
+
+    #[test]
+    fn test_equivalence_on_random_exprs() {
+        let mut rng = Lcg(42);
+        let rows = [
+            bindings(&[("x", 1.5), ("y", -2.0), ("z", 0.0)]),
+            bindings(&[("x", 0.0), ("y", 3.0)]),
+            [
+                ("x".to_string(), Value::Int(3)),
+                ("y".to_string(), Value::Int(i64::MAX)),
+                ("z".to_string(), Value::Bool(true)),
+            ]
+            .into(),
+        ];
+        for _ in 0..500 {
+            let expr = random_expr(&mut rng, 5, &mut Vec::new());
+            let code = compile(&expr);
+            for row in &rows {
+                let tree = Interpreter::with_bindings(row).eval(&expr);
+                let vm = code.eval(row);
+                match (&tree, &vm) {
+                    (Ok(Value::Float(a)), Ok(Value::Float(b))) if a.is_nan() => {
+                        assert!(b.is_nan(), "{}", expr)
+                    }
+                    // NaN parts print alike though they never compare equal
+                    (Ok(Value::Complex(_)), _) => {
+                        assert_eq!(format!("{:?}", tree), format!("{:?}", vm), "{}", expr)
+                    }
+                    _ => assert_eq!(tree, vm, "{}", expr),
+                }
+            }
+        }
+    }
+
+    fn reload(code: &CompiledExpr) -> Result<CompiledExpr, CodecError> {
+        let mut bytes = Vec::new();
+        let written = code.write_to(&mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+        CompiledExpr::read_from(bytes.as_slice())
+    }
+
+    #[test]
+    fn test_saved_code_runs_the_same() {
+        let mut rng = Lcg(7);
+        let row = bindings(&[("x", 1.5), ("y", -2.0)]);
+        for _ in 0..200 {
+            let code = compile(&random_expr(&mut rng, 5, &mut Vec::new()));
+            let loaded = reload(&code).unwrap();
+            assert_eq!(loaded.ops(), code.ops());
+            assert_eq!(
+                format!("{:?}", loaded.eval(&row)),
+                format!("{:?}", code.eval(&row))
+            );
+        }
+
+        let code: CompiledExpr = "let f = |n| n * 2 m in f(x) -> cm + \"!\"".parse().unwrap();
+        let loaded = reload(&code).unwrap();
+        let row = bindings(&[("x", 3.0)]);
+        assert_eq!(loaded.eval(&row), code.eval(&row));
+        let code: CompiledExpr = "match { n: x }.n { 1..3 => 1, 3 => 2 }".parse().unwrap();
+        let loaded = reload(&code).unwrap();
+        assert_eq!(loaded.ops(), code.ops());
+        assert_eq!(loaded.eval(&row).unwrap(), 2);
+        assert_eq!(loaded.free_names().collect::<Vec<_>>(), ["x"]);
+        let code: CompiledExpr = "99999999999999999999 - x".parse().unwrap();
+        let loaded = reload(&code).unwrap();
+        assert_eq!(loaded.ops(), code.ops());
+        let ints: Bindings = [("x".to_string(), Value::Int(3))].into_iter().collect();
+        let exact = loaded.with_mode(NumericMode::Exact).eval(&ints).unwrap();
+        assert_eq!(exact.to_string(), "99999999999999999996");
+
+        // The registry is not saved, but can be attached again
+        let mut registry = FunctionRegistry::new();
+        registry.register("twice", |x: f64| x * 2.0);
+        let code: CompiledExpr = "twice(x)".parse().unwrap();
+        let code = code.with_registry(registry.clone());
+        let loaded = reload(&code).unwrap().with_registry(registry);
+        assert_eq!(loaded.eval(&row).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_saved_code_keeps_mode_and_limits() {
+        let none = Bindings::new();
+        let exact = |src: &str| {
+            let code = src.parse::<CompiledExpr>().unwrap();
+            reload(&code.with_mode(NumericMode::Exact)).unwrap()
+        };
+        let loaded = exact("1 / 3 + 1 / 6");
+        assert_eq!(loaded.mode(), NumericMode::Exact);
+        assert_eq!(loaded.eval(&none).unwrap().to_string(), "1/2");
+        let loaded = exact("2 ^ 100");
+        assert_eq!(
+            loaded.eval(&none).unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert!(exact("sqrt(4)").eval(&none).is_err());
+        let code: CompiledExpr = "sqrt(4)".parse().unwrap();
+        assert_eq!(reload(&code).unwrap().eval(&none).unwrap(), 2.0);
+
+        for limits in [
+            EvalLimits::none(),
+            EvalLimits::default(),
+            EvalLimits::untrusted(),
+            EvalLimits::none()
+                .max_steps(u64::MAX)
+                .max_time(Duration::new(3, 999_999_999)),
+        ] {
+            let code: CompiledExpr = "sum(1..x)".parse().unwrap();
+            let loaded = reload(&code.with_limits(limits)).unwrap();
+            assert_eq!(loaded.limits(), limits);
+        }
+        let code: CompiledExpr = "sum(1..10000000)".parse().unwrap();
+        let loaded = reload(&code.with_limits(EvalLimits::untrusted())).unwrap();
+        assert!(matches!(
+            loaded.eval(&none),
+            Err(EvalError::LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_loading_verifies_code() {
+        let code: CompiledExpr = "let a = x in if a > 1 then a else sqrt(a)".parse().unwrap();
+        assert!(reload(&code).is_ok());
+
+        let mut bad = code.clone();
+        bad.ops[2] = Op::Local(1);
+        assert_eq!(
+            reload(&bad).unwrap_err().to_string(),
+            "invalid bytecode at instruction 2: local 1 out of 1"
+        );
+
+        let mut bad = code.clone();
+        bad.ops.push(Op::Literal(Value::Int(1)));
+        assert_eq!(
+            reload(&bad).unwrap_err().to_string(),
+            format!(
+                "invalid bytecode at instruction {}: does not end with exactly one value",
+                bad.ops.len()
+            )
+        );
+
+        let mut bad = code.clone();
+        bad.ops[0] = Op::Jump(0);
+        assert!(matches!(
+            reload(&bad),
+            Err(CodecError::InvalidCode { at: 0, .. })
+        ));
+
+        let mut bad = code;
+        bad.ops.insert(0, Op::Unbind);
+        assert!(reload(&bad).is_err());
+
+        let mut bytes = Vec::new();
+        "x".parse::<Expr>().unwrap().write_to(&mut bytes).unwrap();
+        assert!(matches!(
+            CompiledExpr::read_from(bytes.as_slice()),
+            Err(CodecError::WrongKind {
+                expected: Kind::Compiled,
+                found: Kind::Expr,
+            })
+        ));
+    }
+}